@@ -0,0 +1,57 @@
+# Dumps the 16 kHz network of a Silero VAD (v5) TorchScript checkpoint in the layout read by
+# `load_silero_vad`. Convert the dump with `cargo run --release -- convert <dump> --silero-vad`.
+
+import sys
+import pathlib
+import numpy as np
+
+import torch
+
+def save_scalar(s, name, path):
+    s = np.array([1.0, float(s)]).astype(np.float32)
+    np.save(pathlib.Path(path, f'{name}.npy'), s)
+
+def save_tensor(tensor, name, path):
+    tensor_numpy = tensor.detach().cpu().numpy()
+    tensor_dims = np.array(tensor_numpy.shape)
+    tensor_values = tensor_numpy.flatten()
+    tensor_to_save = np.concatenate((tensor_dims, tensor_values)).astype(np.float32)
+    np.save(pathlib.Path(path, f'{name}.npy'), tensor_to_save)
+
+def save_conv1d(weight, bias, path):
+    pathlib.Path(path).mkdir(parents=True, exist_ok=True)
+    save_tensor(weight, 'weight', path)
+    save_tensor(bias, 'bias', path)
+
+def save_linear(weight, bias, path):
+    pathlib.Path(path).mkdir(parents=True, exist_ok=True)
+    save_tensor(weight.transpose(0, 1), 'weight', path) # Burn linear weights are (in, out)
+    save_tensor(bias, 'bias', path)
+
+def save_silero_vad(state, path):
+    pathlib.Path(path).mkdir(parents=True, exist_ok=True)
+
+    # the learned STFT has no bias
+    stft = state['_model.stft.forward_basis_buffer']
+    save_conv1d(stft, torch.zeros(stft.shape[0]), pathlib.Path(path, 'stft'))
+
+    for i in range(4):
+        prefix = f'_model.encoder.{i}.reparam_conv'
+        save_conv1d(state[f'{prefix}.weight'], state[f'{prefix}.bias'], pathlib.Path(path, 'encoder', f'block_{i}'))
+
+    rnn = '_model.decoder.rnn'
+    save_linear(state[f'{rnn}.weight_ih'], state[f'{rnn}.bias_ih'], pathlib.Path(path, 'decoder', 'rnn', 'input'))
+    save_linear(state[f'{rnn}.weight_hh'], state[f'{rnn}.bias_hh'], pathlib.Path(path, 'decoder', 'rnn', 'hidden'))
+    save_conv1d(state['_model.decoder.decoder.2.weight'], state['_model.decoder.decoder.2.bias'], pathlib.Path(path, 'decoder', 'output'))
+
+    save_scalar(stft.shape[-1], 'n_fft', path)
+    save_scalar(stft.shape[-1] // 2, 'hop_length', path)
+    save_scalar(state[f'{rnn}.weight_hh'].shape[1], 'n_state', path)
+
+if __name__ == "__main__":
+  if len(sys.argv) < 3:
+    print(f"Usage: python3 {sys.argv[0]} <silero_vad.jit> <output_name>")
+    sys.exit(1)
+
+  model = torch.jit.load(sys.argv[1], map_location='cpu')
+  save_silero_vad(model.state_dict(), pathlib.Path(sys.argv[2]))
@@ -0,0 +1,65 @@
+# Dumps the x-vector network of SpeechBrain's `spkrec-xvect-voxceleb` (its `embedding_model.ckpt`)
+# in the layout read by `load_speaker_encoder`. Convert the dump with
+# `cargo run --release -- convert <dump> --speaker-encoder`.
+
+import sys
+import pathlib
+import numpy as np
+
+import torch
+
+def save_scalar(s, name, path):
+    s = np.array([1.0, float(s)]).astype(np.float32)
+    np.save(pathlib.Path(path, f'{name}.npy'), s)
+
+def save_tensor(tensor, name, path):
+    tensor_numpy = tensor.detach().cpu().numpy()
+    tensor_dims = np.array(tensor_numpy.shape)
+    tensor_values = tensor_numpy.flatten()
+    tensor_to_save = np.concatenate((tensor_dims, tensor_values)).astype(np.float32)
+    np.save(pathlib.Path(path, f'{name}.npy'), tensor_to_save)
+
+def save_conv1d(weight, bias, path):
+    pathlib.Path(path).mkdir(parents=True, exist_ok=True)
+    save_tensor(weight, 'weight', path)
+    save_tensor(bias, 'bias', path)
+
+def save_linear(weight, bias, path):
+    pathlib.Path(path).mkdir(parents=True, exist_ok=True)
+    save_tensor(weight.transpose(0, 1), 'weight', path) # Burn linear weights are (in, out)
+    save_tensor(bias, 'bias', path)
+
+def save_batch_norm(state, prefix, path, eps=1e-5):
+    # the running statistics are folded into a scale and shift per channel
+    scale = state[f'{prefix}.weight'] / torch.sqrt(state[f'{prefix}.running_var'] + eps)
+    shift = state[f'{prefix}.bias'] - state[f'{prefix}.running_mean'] * scale
+    save_tensor(scale, 'scale', path)
+    save_tensor(shift, 'shift', path)
+
+def save_speaker_encoder(state, path):
+    pathlib.Path(path).mkdir(parents=True, exist_ok=True)
+
+    # every TDNN layer is a convolution, a leaky ReLU and a batch normalization, followed by
+    # the statistics pooling and the linear embedding layer
+    n_layers = 5
+    for i in range(n_layers):
+        layer_path = pathlib.Path(path, f'layer_{i}')
+        conv = f'blocks.{3 * i}.conv'
+        save_conv1d(state[f'{conv}.weight'], state[f'{conv}.bias'], pathlib.Path(layer_path, 'conv'))
+        save_batch_norm(state, f'blocks.{3 * i + 2}.norm', layer_path)
+
+    embedding = f'blocks.{3 * n_layers + 1}.w'
+    save_linear(state[f'{embedding}.weight'], state[f'{embedding}.bias'], pathlib.Path(path, 'embedding'))
+
+    save_scalar(state['blocks.0.conv.weight'].shape[1], 'n_mels', path)
+    save_scalar(state['blocks.0.conv.weight'].shape[0], 'n_channels', path)
+    save_scalar(state[f'blocks.{3 * (n_layers - 1)}.conv.weight'].shape[0], 'n_pooled', path)
+    save_scalar(state[f'{embedding}.weight'].shape[0], 'n_embedding', path)
+
+if __name__ == "__main__":
+  if len(sys.argv) < 3:
+    print(f"Usage: python3 {sys.argv[0]} <embedding_model.ckpt> <output_name>")
+    sys.exit(1)
+
+  state = torch.load(sys.argv[1], map_location='cpu')
+  save_speaker_encoder(state, pathlib.Path(sys.argv[2]))
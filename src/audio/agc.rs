@@ -0,0 +1,79 @@
+use burn::config::Config;
+
+/// Options of the automatic gain control, see `AutomaticGainControl`.
+#[derive(Config, Debug)]
+pub struct AgcConfig {
+    /// RMS level the gain is adjusted to reach.
+    #[config(default = 0.1)]
+    pub target_rms: f64,
+    /// Time constant in seconds with which the gain drops when the input gets louder.
+    #[config(default = 0.01)]
+    pub attack: f64,
+    /// Time constant in seconds with which the gain recovers when the input gets quieter.
+    #[config(default = 0.5)]
+    pub release: f64,
+    /// Upper limit of the gain in decibels, which keeps background noise in pauses from being
+    /// amplified to the level of speech.
+    #[config(default = 30.0)]
+    pub max_gain_db: f64,
+}
+
+/// Length in seconds of the window the input level is measured over.
+const LEVEL_WINDOW: f64 = 0.05;
+
+/// Automatic gain control for a stream of audio, so speech arrives at a consistent level
+/// regardless of the distance to the microphone. The input level is tracked with a running RMS
+/// and the gain follows the one needed to reach the target, quickly when it has to drop and
+/// slowly when it may rise.
+#[derive(Clone, Debug)]
+pub struct AutomaticGainControl {
+    target_rms: f64,
+    max_gain: f64,
+    level_coefficient: f64,
+    attack_coefficient: f64,
+    release_coefficient: f64,
+    mean_square: f64,
+    gain: f64,
+}
+
+impl AutomaticGainControl {
+    pub fn new(config: &AgcConfig, sample_rate: usize) -> Self {
+        let coefficient = |seconds: f64| (-1.0 / (seconds * sample_rate as f64).max(1.0)).exp();
+
+        Self {
+            target_rms: config.target_rms,
+            max_gain: 10.0f64.powf(config.max_gain_db / 20.0),
+            level_coefficient: coefficient(LEVEL_WINDOW),
+            attack_coefficient: coefficient(config.attack),
+            release_coefficient: coefficient(config.release),
+            mean_square: config.target_rms * config.target_rms,
+            gain: 1.0,
+        }
+    }
+
+    /// Current gain as a linear factor.
+    pub fn gain(&self) -> f64 {
+        self.gain
+    }
+
+    /// Applies the gain to the next chunk of a stream in place. The output is clamped to full
+    /// scale.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let x = *sample as f64;
+            self.mean_square = self.level_coefficient * self.mean_square
+                + (1.0 - self.level_coefficient) * x * x;
+
+            let desired_gain = (self.target_rms / self.mean_square.sqrt().max(f64::EPSILON))
+                .min(self.max_gain);
+            let coefficient = if desired_gain < self.gain {
+                self.attack_coefficient
+            } else {
+                self.release_coefficient
+            };
+            self.gain = coefficient * self.gain + (1.0 - coefficient) * desired_gain;
+
+            *sample = (x * self.gain).clamp(-1.0, 1.0) as f32;
+        }
+    }
+}
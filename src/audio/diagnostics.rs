@@ -0,0 +1,75 @@
+use log::warn;
+
+/// Samples at least this close to full scale are counted as clipped.
+const CLIP_LEVEL: f32 = 0.999;
+
+/// Share of clipped samples above which a recording is reported as clipped.
+const MAX_CLIPPED_FRACTION: f64 = 0.001;
+
+/// Recordings whose peak stays below this level in dBFS are reported as near silent.
+const MIN_PEAK_DB: f64 = -40.0;
+
+/// Level statistics of a waveform, see `measure_levels`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelStats {
+    /// Share of the samples at full scale, between 0 and 1.
+    pub clipped_fraction: f64,
+    /// Peak level in dBFS.
+    pub peak_db: f64,
+    /// RMS level in dBFS.
+    pub rms_db: f64,
+}
+
+impl LevelStats {
+    pub fn is_clipped(&self) -> bool {
+        self.clipped_fraction > MAX_CLIPPED_FRACTION
+    }
+
+    pub fn is_near_silent(&self) -> bool {
+        self.peak_db < MIN_PEAK_DB
+    }
+}
+
+pub fn measure_levels(waveform: &[f32]) -> LevelStats {
+    let to_db = |level: f64| 20.0 * level.max(1e-10).log10();
+
+    if waveform.is_empty() {
+        return LevelStats {
+            clipped_fraction: 0.0,
+            peak_db: to_db(0.0),
+            rms_db: to_db(0.0),
+        };
+    }
+
+    let n_clipped = waveform.iter().filter(|x| x.abs() >= CLIP_LEVEL).count();
+    let peak = waveform.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+    let mean_square =
+        waveform.iter().map(|&x| (x as f64) * (x as f64)).sum::<f64>() / waveform.len() as f64;
+
+    LevelStats {
+        clipped_fraction: n_clipped as f64 / waveform.len() as f64,
+        peak_db: to_db(peak as f64),
+        rms_db: to_db(mean_square.sqrt()),
+    }
+}
+
+/// Logs a warning when the waveform is clipped or near silent, the usual causes of garbled or
+/// empty transcripts. Should be given the audio as recorded, before any normalization.
+pub fn check_levels(waveform: &[f32]) -> LevelStats {
+    let stats = measure_levels(waveform);
+
+    if stats.is_clipped() {
+        warn!(
+            "Input audio is clipped: {:.2}% of the samples are at full scale, lower the input gain",
+            100.0 * stats.clipped_fraction
+        );
+    }
+    if stats.is_near_silent() {
+        warn!(
+            "Input audio is near silent: peak {:.1} dBFS, RMS {:.1} dBFS, check the input device and its gain",
+            stats.peak_db, stats.rms_db
+        );
+    }
+
+    stats
+}
@@ -0,0 +1,141 @@
+use burn::tensor::{backend::Backend, Tensor};
+use flate2::{write::ZlibEncoder, Compression, Crc};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::token;
+
+/// Writes a float32 array of the given shape to a `.npy` file that numpy can load.
+pub fn save_npy<P: AsRef<Path>>(path: P, data: &[f32], shape: &[usize]) -> io::Result<()> {
+    let shape_str = match shape {
+        [n] => format!("({},)", n),
+        _ => format!(
+            "({})",
+            shape.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+        shape_str
+    );
+    // the header is padded so the data starts at a multiple of 64 bytes
+    let prefix_length = 10;
+    let n_padding = 63 - (prefix_length + header.len()) % 64;
+    header.extend(std::iter::repeat(' ').take(n_padding));
+    header.push('\n');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x93NUMPY\x01\x00")?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    for x in data {
+        file.write_all(&x.to_le_bytes())?;
+    }
+    file.flush()
+}
+
+/// Writes a mel spectrogram of shape (n_mels, n_frames) to a `.npy` file, for comparison with
+/// e.g. `whisper.log_mel_spectrogram` of openai/whisper.
+pub fn save_mels_npy<B: Backend, P: AsRef<Path>>(
+    mels: Tensor<B, 2>,
+    path: P,
+) -> token::Result<()> {
+    let shape = mels.dims();
+    let data: Vec<f32> = mels.into_data().convert::<f32>().value;
+    save_npy(path, &data, &shape)?;
+    Ok(())
+}
+
+/// Colors of the heatmap from the lowest to the highest value, roughly matplotlib's magma.
+const COLORMAP: [[f32; 3]; 5] = [
+    [0.0, 0.0, 4.0],
+    [81.0, 18.0, 124.0],
+    [183.0, 55.0, 121.0],
+    [252.0, 137.0, 97.0],
+    [252.0, 253.0, 191.0],
+];
+
+fn colormap(t: f32) -> [u8; 3] {
+    let position = t.clamp(0.0, 1.0) * (COLORMAP.len() - 1) as f32;
+    let i = (position as usize).min(COLORMAP.len() - 2);
+    let fraction = position - i as f32;
+
+    let mut color = [0; 3];
+    for (c, channel) in color.iter_mut().enumerate() {
+        let value = COLORMAP[i][c] + fraction * (COLORMAP[i + 1][c] - COLORMAP[i][c]);
+        *channel = value.round() as u8;
+    }
+    color
+}
+
+/// Renders a mel spectrogram of shape (n_mels, n_frames) as a PNG heatmap, one pixel per frame
+/// and mel bin with the low frequencies at the bottom.
+pub fn save_mels_png<B: Backend, P: AsRef<Path>>(
+    mels: Tensor<B, 2>,
+    path: P,
+) -> token::Result<()> {
+    let [n_mels, n_frames] = mels.dims();
+    if n_mels == 0 || n_frames == 0 {
+        return Err("Can't render an empty mel spectrogram".into());
+    }
+    let data: Vec<f32> = mels.into_data().convert::<f32>().value;
+
+    let min = data.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = data.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let range = (max - min).max(f32::EPSILON);
+
+    let mut pixels = Vec::with_capacity(n_mels * n_frames * 3);
+    for mel in (0..n_mels).rev() {
+        for frame in 0..n_frames {
+            pixels.extend(colormap((data[mel * n_frames + frame] - min) / range));
+        }
+    }
+
+    write_png(path, n_frames, n_mels, &pixels)?;
+    Ok(())
+}
+
+/// Writes an 8 bit RGB image, given row by row from the top.
+fn write_png<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut write_chunk = |kind: &[u8; 4], data: &[u8]| -> io::Result<()> {
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(data);
+
+        file.write_all(&(data.len() as u32).to_be_bytes())?;
+        file.write_all(kind)?;
+        file.write_all(data)?;
+        file.write_all(&crc.sum().to_be_bytes())
+    };
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // bit depth 8, truecolor, default compression, filtering and no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+
+    // every row starts with the type of its filter, none
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks(width * 3) {
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    let image_data = encoder.finish()?;
+
+    write_chunk(b"IHDR", &header)?;
+    write_chunk(b"IDAT", &image_data)?;
+    write_chunk(b"IEND", &[])?;
+    drop(write_chunk);
+    file.flush()
+}
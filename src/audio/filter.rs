@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+
+/// Cutoff in Hz of the high pass removing rumble, well below the fundamental of speech.
+pub const DEFAULT_HIGH_PASS_CUTOFF: f64 = 80.0;
+
+/// Second order IIR filter in direct form I. The filter state is kept so a stream can be
+/// filtered chunk by chunk.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// High pass with a -12 dB per octave slope below `cutoff` Hz. A `q` of 1 / sqrt(2) gives a
+    /// Butterworth response.
+    pub fn high_pass(cutoff: f64, q: f64, sample_rate: usize) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate as f64;
+        let alpha = w0.sin() / (2.0 * q);
+
+        Self::new(
+            [(1.0 + w0.cos()) / 2.0, -(1.0 + w0.cos()), (1.0 + w0.cos()) / 2.0],
+            [1.0 + alpha, -2.0 * w0.cos(), 1.0 - alpha],
+        )
+    }
+
+    /// Shelf amplifying frequencies above `cutoff` Hz by `gain_db`.
+    pub fn high_shelf(gain_db: f64, q: f64, cutoff: f64, sample_rate: usize) -> Self {
+        let a = 10.0f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * cutoff / sample_rate as f64;
+        let alpha = w0.sin() / (2.0 * q);
+        let (cos, sqrt_a) = (w0.cos(), a.sqrt());
+
+        Self::new(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + 2.0 * sqrt_a * alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - 2.0 * sqrt_a * alpha),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + 2.0 * sqrt_a * alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - 2.0 * sqrt_a * alpha,
+            ],
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = (self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1])
+            / self.a[0];
+
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+
+    pub fn filter(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            *sample = self.process(*sample as f64) as f32;
+        }
+    }
+}
+
+/// Removes the DC offset and the low frequency rumble of cheap microphones: a one pole DC
+/// blocker followed by a Butterworth high pass.
+#[derive(Clone, Debug)]
+pub struct HighPassFilter {
+    dc_x: f64,
+    dc_y: f64,
+    dc_pole: f64,
+    high_pass: Biquad,
+}
+
+impl HighPassFilter {
+    pub fn new(cutoff: f64, sample_rate: usize) -> Self {
+        Self {
+            dc_x: 0.0,
+            dc_y: 0.0,
+            // puts the corner of the DC blocker around 5 Hz
+            dc_pole: 1.0 - 2.0 * PI * 5.0 / sample_rate as f64,
+            high_pass: Biquad::high_pass(cutoff, 1.0 / 2.0f64.sqrt(), sample_rate),
+        }
+    }
+
+    /// Filters the next chunk of a stream in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let x = *sample as f64;
+            self.dc_y = x - self.dc_x + self.dc_pole * self.dc_y;
+            self.dc_x = x;
+
+            *sample = self.high_pass.process(self.dc_y) as f32;
+        }
+    }
+}
+
+/// Removes the DC offset of a whole waveform by subtracting its mean, then filters out
+/// frequencies below `cutoff` Hz. See `HighPassFilter`.
+pub fn high_pass(waveform: &mut [f32], sample_rate: usize, cutoff: f64) {
+    if waveform.is_empty() {
+        return;
+    }
+
+    // with the exact offset removed up front the filter doesn't start with a step
+    let mean = waveform.iter().map(|&x| x as f64).sum::<f64>() / waveform.len() as f64;
+    waveform.iter_mut().for_each(|x| *x -= mean as f32);
+
+    HighPassFilter::new(cutoff, sample_rate).process(waveform);
+}
@@ -0,0 +1,87 @@
+use super::filter::Biquad;
+
+/// Integrated loudness in LUFS speech is normalized to by default, the EBU R128 target.
+pub const TARGET_LOUDNESS: f64 = -23.0;
+
+/// Peak level normalized audio may not exceed, which leaves a little headroom below full scale.
+pub const MAX_PEAK: f32 = 0.9;
+
+/// Length in seconds of the gating blocks of ITU-R BS.1770, overlapping by 75 %.
+const BLOCK_DURATION: f64 = 0.4;
+const BLOCK_STEP: f64 = 0.1;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// The two stages of the K-weighting filter, a high shelf boosting what the head amplifies and
+/// a high pass.
+fn k_weighting_filters(sample_rate: usize) -> [Biquad; 2] {
+    [
+        Biquad::high_shelf(4.0, 1.0 / 2.0f64.sqrt(), 1500.0, sample_rate),
+        Biquad::high_pass(38.0, 0.5, sample_rate),
+    ]
+}
+
+/// Integrated loudness of a mono waveform in LUFS as defined by ITU-R BS.1770 and used by EBU
+/// R128. Returns `None` for audio shorter than a gating block or entirely below the absolute
+/// gate, i.e. silence.
+pub fn integrated_loudness(waveform: &[f32], sample_rate: usize) -> Option<f64> {
+    let block_length = (BLOCK_DURATION * sample_rate as f64) as usize;
+    let step = (BLOCK_STEP * sample_rate as f64) as usize;
+    if block_length == 0 || waveform.len() < block_length {
+        return None;
+    }
+
+    let mut filters = k_weighting_filters(sample_rate);
+    let weighted: Vec<f64> = waveform
+        .iter()
+        .map(|&x| filters.iter_mut().fold(x as f64, |x, filter| filter.process(x)))
+        .collect();
+
+    let block_powers: Vec<f64> = (0..=(weighted.len() - block_length) / step)
+        .map(|i| {
+            let block = &weighted[i * step..i * step + block_length];
+            block.iter().map(|x| x * x).sum::<f64>() / block_length as f64
+        })
+        .collect();
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let gated_loudness = |gate: f64| {
+        let gated: Vec<f64> = block_powers
+            .iter()
+            .cloned()
+            .filter(|&power| loudness(power) > gate)
+            .collect();
+        (!gated.is_empty()).then(|| loudness(gated.iter().sum::<f64>() / gated.len() as f64))
+    };
+
+    let relative_gate = gated_loudness(ABSOLUTE_GATE)? + RELATIVE_GATE;
+    gated_loudness(relative_gate.max(ABSOLUTE_GATE))
+}
+
+/// Scales the waveform so its peak reaches `MAX_PEAK`. Silence is left untouched.
+pub fn normalize_peak(waveform: &mut [f32]) {
+    let peak = waveform.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+    if peak > 0.0 {
+        let gain = MAX_PEAK / peak;
+        waveform.iter_mut().for_each(|x| *x *= gain);
+    }
+}
+
+/// Scales the waveform to an integrated loudness of `target` LUFS, without letting its peak
+/// exceed `MAX_PEAK`. Audio too short to measure the loudness of is peak normalized instead
+/// and silence is left untouched.
+pub fn normalize_loudness(waveform: &mut [f32], sample_rate: usize, target: f64) {
+    if waveform.len() < (BLOCK_DURATION * sample_rate as f64) as usize {
+        return normalize_peak(waveform);
+    }
+
+    let loudness = match integrated_loudness(waveform, sample_rate) {
+        Some(loudness) => loudness,
+        None => return,
+    };
+
+    let peak = waveform.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+    let gain = (10.0f64.powf((target - loudness) / 20.0) as f32).min(MAX_PEAK / peak);
+    waveform.iter_mut().for_each(|x| *x *= gain);
+}
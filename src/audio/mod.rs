@@ -0,0 +1,967 @@
+pub mod agc;
+pub mod diagnostics;
+pub mod dump;
+pub mod filter;
+pub mod loudness;
+pub mod network;
+pub mod silero;
+pub mod speaker;
+pub mod vad;
+pub mod wakeword;
+
+use burn::tensor::{self, activation::relu, backend::Backend, Tensor};
+use rubato::{
+    Resampler as _, SincFixedIn, SincInterpolationParameters, SincInterpolationType,
+    WindowFunction,
+};
+use std::{
+    fs::File,
+    io::{self, Read},
+    ops::Range,
+    path::Path,
+};
+use strum_macros::EnumIter;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, SeekMode, SeekTo},
+    io::{MediaSourceStream, ReadOnlySource},
+    meta::MetadataOptions,
+    probe::Hint,
+    units::Time,
+};
+
+use crate::helper::*;
+use crate::token;
+
+/// Sample rate in Hz of the audio Whisper models were trained on.
+pub const SAMPLE_RATE: usize = 16000;
+
+const N_FFT: usize = 400;
+/// Samples between the frames of the mel spectrogram.
+pub const HOP_LENGTH: usize = 160;
+// const N_MELS: usize = 128;
+const WINDOW_LENGTH: usize = N_FFT;
+
+/// Returns the maximum number of waveform samples that can be submitted to `prep_audio`
+/// without receiving more than `n_frame_max` frames.
+pub fn max_waveform_samples(n_frame_max: usize) -> usize {
+    // number of waveform samples must be less than this
+    let n_samples_max = HOP_LENGTH * (n_frame_max + 1) + is_odd(N_FFT) as usize;
+
+    n_samples_max - 1
+}
+
+fn is_odd(x: usize) -> bool {
+    if x % 2 == 0 {
+        false
+    } else {
+        true
+    }
+}
+
+/// Converts interleaved samples of `n_channels` channels to mono, either by averaging all
+/// channels or by keeping only the zero based `channel`.
+pub fn downmix(samples: &[f32], n_channels: usize, channel: Option<usize>) -> Vec<f32> {
+    let n_channels = n_channels.max(1);
+
+    samples
+        .chunks_exact(n_channels)
+        .map(|frame| match channel {
+            Some(channel) => frame[channel.min(n_channels - 1)],
+            None => frame.iter().sum::<f32>() / n_channels as f32,
+        })
+        .collect()
+}
+
+/// Decodes an audio file (wav, mp3, flac, ogg/vorbis or m4a/aac) to mono f32 samples, returning
+/// the samples along with their sample rate. See `downmix` for how `channel` is used.
+pub fn load_audio<P: AsRef<Path>>(
+    path: P,
+    channel: Option<usize>,
+) -> token::Result<(Vec<f32>, usize)> {
+    load_audio_range(path, channel, 0.0, None)
+}
+
+/// Like `load_audio`, but only decodes `duration` seconds of audio starting `start` seconds
+/// into the file, or up to the end of the file without a duration. The file is seeked to the
+/// start where the format allows it, so the audio before it is neither decoded nor kept in
+/// memory.
+pub fn load_audio_range<P: AsRef<Path>>(
+    path: P,
+    channel: Option<usize>,
+    start: f64,
+    duration: Option<f64>,
+) -> token::Result<(Vec<f32>, usize)> {
+    if start < 0.0 || duration.map_or(false, |duration| duration < 0.0) {
+        return Err("Start and duration must not be negative".into());
+    }
+
+    let path = path.as_ref();
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    decode_audio(source, &hint, channel, start, duration)
+}
+
+/// Extensions of the audio files `load_audio` can decode.
+pub const AUDIO_EXTENSIONS: [&str; 8] = ["wav", "mp3", "flac", "ogg", "oga", "m4a", "mp4", "aac"];
+
+/// Returns whether the file at `path` is audio by its extension.
+pub fn is_audio_file<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| {
+            AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        })
+}
+
+/// Returns whether the audio argument is a http(s) URL rather than a path.
+pub fn is_url(audio: &str) -> bool {
+    audio.starts_with("http://") || audio.starts_with("https://")
+}
+
+/// Like `load_audio_range`, but downloads the audio from a http(s) URL. The download is decoded
+/// as it arrives instead of being saved first. The stream can't be seeked, so the audio in
+/// front of `start` is still downloaded.
+pub fn load_audio_url(
+    url: &str,
+    channel: Option<usize>,
+    start: f64,
+    duration: Option<f64>,
+) -> token::Result<(Vec<f32>, usize)> {
+    if start < 0.0 || duration.map_or(false, |duration| duration < 0.0) {
+        return Err("Start and duration must not be negative".into());
+    }
+
+    let response = ureq::get(url).call()?;
+
+    let mut hint = Hint::new();
+    if let Some(mime_type) = response.header("Content-Type") {
+        hint.mime_type(mime_type);
+    }
+    let file_name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .unwrap_or_default();
+    if let Some((_, extension)) = file_name.rsplit_once('.') {
+        hint.with_extension(extension);
+    }
+
+    let source = MediaSourceStream::new(
+        Box::new(ReadOnlySource::new(response.into_reader())),
+        Default::default(),
+    );
+
+    decode_audio(source, &hint, channel, start, duration)
+}
+
+/// Like `load_audio`, but decodes audio that is already in memory, e.g. an upload. The format
+/// is probed from the data, helped by the `extension` of the file it came from if known.
+pub fn load_audio_bytes(
+    bytes: Vec<u8>,
+    extension: Option<&str>,
+    channel: Option<usize>,
+) -> token::Result<(Vec<f32>, usize)> {
+    let source = MediaSourceStream::new(Box::new(io::Cursor::new(bytes)), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    decode_audio(source, &hint, channel, 0.0, None)
+}
+
+fn decode_audio(
+    source: MediaSourceStream,
+    hint: &Hint,
+    channel: Option<usize>,
+    start: f64,
+    duration: Option<f64>,
+) -> token::Result<(Vec<f32>, usize)> {
+    let probed = symphonia::default::get_probe().format(
+        hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No decodable audio track")?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or("Unknown sample rate")? as usize;
+    let time_base = track.codec_params.time_base;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    // samples decoded in front of the start, which are dropped
+    let mut n_skip = (start * sample_rate as f64).round() as usize;
+    if start > 0.0 {
+        let seeked = format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::new(start.trunc() as u64, start.fract()),
+                track_id: Some(track_id),
+            },
+        );
+
+        match seeked {
+            Ok(seeked) => {
+                // seeking lands on the packet holding the start, which may begin before it
+                let n_ts = seeked.required_ts.saturating_sub(seeked.actual_ts);
+                n_skip = match time_base {
+                    Some(time_base) => {
+                        let time = time_base.calc_time(n_ts);
+                        ((time.seconds as f64 + time.frac) * sample_rate as f64).round() as usize
+                    }
+                    None => n_ts as usize,
+                };
+                decoder.reset();
+            }
+            // formats that can't seek are decoded from the beginning instead
+            Err(SymphoniaError::SeekError(_)) | Err(SymphoniaError::Unsupported(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let max_samples = duration.map(|duration| (duration * sample_rate as f64).round() as usize);
+
+    let mut samples = Vec::new();
+    loop {
+        if max_samples.map_or(false, |max_samples| samples.len() >= max_samples) {
+            break;
+        }
+
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // a corrupt packet only loses a few milliseconds of audio
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let n_channels = spec.channels.count();
+        if let Some(channel) = channel.filter(|&channel| channel >= n_channels) {
+            return Err(format!(
+                "Channel {} does not exist, the audio has {} channels",
+                channel, n_channels
+            )
+            .into());
+        }
+
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+
+        let mono = downmix(buffer.samples(), n_channels, channel);
+        let n_skipped = n_skip.min(mono.len());
+        n_skip -= n_skipped;
+        samples.extend_from_slice(&mono[n_skipped..]);
+    }
+
+    if let Some(max_samples) = max_samples {
+        samples.truncate(max_samples);
+    }
+
+    Ok((samples, sample_rate))
+}
+
+/// Sample encodings of raw PCM audio, see `load_pcm`.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum PcmFormat {
+    S16Le,
+    F32Le,
+}
+
+impl PcmFormat {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PcmFormat::S16Le => "s16le",
+            PcmFormat::F32Le => "f32le",
+        }
+    }
+
+    /// Size of a sample in bytes.
+    pub fn sample_size(&self) -> usize {
+        match self {
+            PcmFormat::S16Le => 2,
+            PcmFormat::F32Le => 4,
+        }
+    }
+}
+
+/// Reads raw interleaved little endian PCM of `n_channels` channels until the end of `reader`,
+/// e.g. audio piped from ffmpeg, and converts it to mono f32 samples. A trailing partial frame
+/// is dropped. See `downmix` for how `channel` is used.
+pub fn load_pcm<R: Read>(
+    mut reader: R,
+    format: PcmFormat,
+    n_channels: usize,
+    channel: Option<usize>,
+) -> token::Result<Vec<f32>> {
+    if n_channels == 0 {
+        return Err("Raw audio needs at least one channel".into());
+    }
+    if let Some(channel) = channel.filter(|&channel| channel >= n_channels) {
+        return Err(format!(
+            "Channel {} does not exist, the audio has {} channels",
+            channel, n_channels
+        )
+        .into());
+    }
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let sample_size = format.sample_size();
+    let n_bytes = bytes.len() / (sample_size * n_channels) * sample_size * n_channels;
+    let samples: Vec<f32> = bytes[..n_bytes]
+        .chunks_exact(sample_size)
+        .map(|sample| match format {
+            PcmFormat::S16Le => i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0,
+            PcmFormat::F32Le => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]),
+        })
+        .collect();
+
+    Ok(downmix(&samples, n_channels, channel))
+}
+
+/// Number of input samples resampled at once.
+const RESAMPLER_CHUNK_SIZE: usize = 1024;
+
+/// Band-limited sinc resampler converting mono audio to `SAMPLE_RATE`. Audio can be fed in
+/// chunks of any size, e.g. as it arrives from a microphone, and resampled samples are returned
+/// as soon as a full chunk has been processed.
+pub struct Resampler {
+    resampler: Option<SincFixedIn<f32>>,
+    pending: Vec<f32>,
+    ratio: f64,
+    n_input: usize,
+    n_output: usize,
+    // leading output samples that only hold the delay of the sinc filter
+    delay: usize,
+}
+
+impl Resampler {
+    pub fn new(input_rate: usize) -> token::Result<Self> {
+        let ratio = SAMPLE_RATE as f64 / input_rate as f64;
+
+        let resampler = if input_rate == SAMPLE_RATE {
+            None
+        } else {
+            let params = SincInterpolationParameters {
+                sinc_len: 256,
+                f_cutoff: 0.95,
+                interpolation: SincInterpolationType::Linear,
+                oversampling_factor: 256,
+                window: WindowFunction::BlackmanHarris2,
+            };
+            Some(SincFixedIn::new(ratio, 1.0, params, RESAMPLER_CHUNK_SIZE, 1)?)
+        };
+        let delay = resampler.as_ref().map_or(0, |resampler| resampler.output_delay());
+
+        Ok(Self {
+            resampler,
+            pending: Vec::new(),
+            ratio,
+            n_input: 0,
+            n_output: 0,
+            delay,
+        })
+    }
+
+    /// Resamples `samples`, returning the output samples that are complete so far.
+    pub fn process(&mut self, samples: &[f32]) -> token::Result<Vec<f32>> {
+        let resampler = match self.resampler.as_mut() {
+            Some(resampler) => resampler,
+            None => return Ok(samples.to_vec()),
+        };
+
+        self.n_input += samples.len();
+        self.pending.extend_from_slice(samples);
+
+        let mut output = Vec::new();
+        let mut start = 0;
+        while self.pending.len() - start >= resampler.input_frames_next() {
+            let end = start + resampler.input_frames_next();
+            let resampled = resampler.process(&[&self.pending[start..end]], None)?;
+            output.extend(resampled.into_iter().next().unwrap_or_default());
+            start = end;
+        }
+        self.pending.drain(..start);
+
+        Ok(self.trim(output))
+    }
+
+    /// Resamples the remaining buffered samples and flushes the filter delay.
+    pub fn finish(mut self) -> token::Result<Vec<f32>> {
+        let resampler = match self.resampler.as_mut() {
+            Some(resampler) => resampler,
+            None => return Ok(Vec::new()),
+        };
+
+        let expected = (self.n_input as f64 * self.ratio).ceil() as usize;
+
+        let mut output = Vec::new();
+        let resampled = resampler.process_partial(Some(&[&self.pending[..]]), None)?;
+        output.extend(resampled.into_iter().next().unwrap_or_default());
+        while self.n_output + output.len().saturating_sub(self.delay) < expected {
+            let resampled = resampler.process_partial(None::<&[&[f32]]>, None)?;
+            output.extend(resampled.into_iter().next().unwrap_or_default());
+        }
+
+        let mut output = self.trim(output);
+        output.truncate(expected.saturating_sub(self.n_output - output.len()));
+        Ok(output)
+    }
+
+    fn trim(&mut self, mut output: Vec<f32>) -> Vec<f32> {
+        let n_delay = self.delay.min(output.len());
+        output.drain(..n_delay);
+        self.delay -= n_delay;
+        self.n_output += output.len();
+        output
+    }
+}
+
+/// Resamples a whole mono waveform from `input_rate` to `SAMPLE_RATE`.
+pub fn resample(waveform: &[f32], input_rate: usize) -> token::Result<Vec<f32>> {
+    let mut resampler = Resampler::new(input_rate)?;
+    let mut output = resampler.process(waveform)?;
+    output.extend(resampler.finish()?);
+    Ok(output)
+}
+
+/// Transform an input waveform into a format interpretable by Whisper.
+/// With a waveform size of (n_batch, n_samples) the output will be of size (n_batch, n_mels, n_frame)
+/// where n_mels is the number of mel bins of the model, 80 or 128 for large-v3 and large-v3-turbo,
+/// n_frame = int( ( n_samples_padded - n_fft ) / hop_length ),
+/// n_samples_padded = if n_fft is even: n_samples + n_fft else: n_samples + n_fft - 1,
+/// n_fft = 400,
+/// hop_length = 160.
+pub fn prep_audio<B: Backend>(waveform: Tensor<B, 2>, sample_rate: f64, n_mels: usize) -> Tensor<B, 3> {
+    let device = waveform.device();
+
+    let window = hann_window_device(WINDOW_LENGTH, &device);
+    let (stft_real, stft_imag) = stfft(waveform, N_FFT, HOP_LENGTH, window);
+
+    let magnitudes = stft_real.powf_scalar(2.0) + stft_imag.powf_scalar(2.0);
+    let [n_batch, n_row, n_col] = magnitudes.dims();
+    let magnitudes = magnitudes.slice([0..n_batch, 0..n_row, 0..(n_col - 1)]);
+
+    let mel_spec = get_mel_filters_device(sample_rate, N_FFT, n_mels, false, &device)
+        .unsqueeze()
+        .matmul(magnitudes);
+
+    let log_spec = tensor_log10(tensor_max_scalar(mel_spec, 1.0e-10));
+
+    // the maximum stays on the device instead of being read back, and each waveform of the
+    // batch is normalized by its own maximum
+    let max = log_spec.clone().max_dim(2).max_dim(1);
+    let log_spec = tensor_max(log_spec, max.sub_scalar(8.0));
+    let log_spec = (log_spec + 4.0) / 4.0;
+
+    return log_spec;
+}
+
+/// Log mel spectrogram of a whole `SAMPLE_RATE` waveform with shape (n_mels, n_frames),
+/// normalized over the whole waveform like `whisper.log_mel_spectrogram` of openai/whisper.
+/// See `dump` for writing it out.
+pub fn log_mel_spectrogram<B: Backend>(
+    waveform: &[f32],
+    n_mels: usize,
+    device: &B::Device,
+) -> Tensor<B, 2> {
+    let waveform = Tensor::from_floats(
+        tensor::Data::new(waveform.to_vec(), [waveform.len()].into()),
+        device,
+    );
+
+    prep_audio(waveform.unsqueeze(), SAMPLE_RATE as f64, n_mels).squeeze(0)
+}
+
+/// Log mel spectrogram of a live audio stream computed incrementally. Only the STFT frames
+/// covering newly pushed samples are computed and appended to a rolling buffer of at most
+/// `max_frames` frames, instead of recomputing the spectrogram of the whole buffered audio.
+pub struct MelStream<B: Backend> {
+    filters: Tensor<B, 2>,
+    dft_real: Tensor<B, 2>,
+    dft_imag: Tensor<B, 2>,
+    // samples from the start of the next frame on, reflection padded at the start of the stream
+    signal: Vec<f32>,
+    padded: bool,
+    log_spec: Option<Tensor<B, 2>>,
+    max_frames: usize,
+    /// Number of frames computed and dropped from the buffer since the start of the stream.
+    n_dropped: usize,
+}
+
+impl<B: Backend> MelStream<B> {
+    pub fn new(sample_rate: f64, n_mels: usize, max_frames: usize, device: &B::Device) -> Self {
+        let filters = get_mel_filters_device(sample_rate, N_FFT, n_mels, false, device);
+
+        let n_freq = N_FFT / 2 + 1;
+        let coe = std::f64::consts::PI * 2.0 / N_FFT as f64;
+        let angles = Tensor::arange(0..n_freq as i64, device)
+            .float()
+            .mul_scalar(coe)
+            .unsqueeze::<2>()
+            .transpose()
+            .repeat(1, N_FFT)
+            * Tensor::arange(0..N_FFT as i64, device).float().unsqueeze::<2>();
+        let window = hann_window_device::<B>(WINDOW_LENGTH, device).unsqueeze::<2>();
+
+        Self {
+            filters,
+            dft_real: angles.clone().cos() * window.clone(),
+            dft_imag: angles.sin() * (-window),
+            signal: Vec::new(),
+            padded: false,
+            log_spec: None,
+            max_frames,
+            n_dropped: 0,
+        }
+    }
+
+    /// Appends samples to the stream and computes the frames they complete.
+    pub fn push(&mut self, samples: &[f32]) {
+        self.signal.extend_from_slice(samples);
+
+        // reflection padding centers the first frame on the first sample like `prep_audio`
+        let pad = N_FFT / 2;
+        if !self.padded {
+            if self.signal.len() <= pad {
+                return;
+            }
+            let left_pad: Vec<f32> = self.signal[1..=pad].iter().rev().cloned().collect();
+            self.signal.splice(0..0, left_pad);
+            self.padded = true;
+        }
+
+        if self.signal.len() < N_FFT {
+            return;
+        }
+        let n_frames = (self.signal.len() - N_FFT) / HOP_LENGTH + 1;
+
+        // columns are the windows of consecutive frames
+        let mut windows = vec![0.0; N_FFT * n_frames];
+        for frame in 0..n_frames {
+            let start = frame * HOP_LENGTH;
+            for (i, &sample) in self.signal[start..start + N_FFT].iter().enumerate() {
+                windows[i * n_frames + frame] = sample;
+            }
+        }
+        self.signal.drain(..n_frames * HOP_LENGTH);
+
+        let device = self.filters.device();
+        let windows = Tensor::from_floats(
+            tensor::Data::new(windows, [N_FFT, n_frames].into()),
+            &device,
+        );
+        let real = self.dft_real.clone().matmul(windows.clone());
+        let imag = self.dft_imag.clone().matmul(windows);
+        let magnitudes = real.powf_scalar(2.0) + imag.powf_scalar(2.0);
+
+        let mel_spec = self.filters.clone().matmul(magnitudes);
+        let log_spec = tensor_log10(tensor_max_scalar(mel_spec, 1.0e-10));
+
+        let log_spec = match self.log_spec.take() {
+            Some(previous) => Tensor::cat(vec![previous, log_spec], 1),
+            None => log_spec,
+        };
+        let [n_mels, n_total] = log_spec.dims();
+        self.log_spec = Some(if n_total > self.max_frames {
+            self.n_dropped += n_total - self.max_frames;
+            log_spec.slice([0..n_mels, (n_total - self.max_frames)..n_total])
+        } else {
+            log_spec
+        });
+    }
+
+    /// Number of frames currently buffered.
+    pub fn len(&self) -> usize {
+        self.log_spec.as_ref().map_or(0, |log_spec| log_spec.dims()[1])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops all buffered frames, e.g. once they have been transcribed. Samples of frames not
+    /// computed yet are kept.
+    pub fn clear(&mut self) {
+        self.n_dropped += self.len();
+        self.log_spec = None;
+    }
+
+    /// Normalized mel spectrogram of the buffered frames of size (1, n_mels, n_frame), as
+    /// returned by `prep_audio`.
+    pub fn mels(&self) -> Option<Tensor<B, 3>> {
+        self.log_spec.clone().map(normalize_log_spec)
+    }
+
+    /// Normalized mel spectrogram of the frames `range` counted from the start of the stream,
+    /// as `prep_audio` returns it for the samples `range` spans. Frame `i` is centered on
+    /// sample `i * HOP_LENGTH`. `None` if the start of the range was dropped from the buffer
+    /// already or none of its frames are computed yet.
+    pub fn frames(&self, range: Range<usize>) -> Option<Tensor<B, 3>> {
+        let log_spec = self.log_spec.as_ref()?;
+        let [n_mels, n_frames] = log_spec.dims();
+        if range.start < self.n_dropped {
+            return None;
+        }
+        let start = range.start - self.n_dropped;
+        let end = range.end.saturating_sub(self.n_dropped).min(n_frames);
+        (start < end).then(|| normalize_log_spec(log_spec.clone().slice([0..n_mels, start..end])))
+    }
+}
+
+/// Clamps the log mel spectrogram to 8 below its maximum and scales it, as openai/whisper
+/// does.
+fn normalize_log_spec<B: Backend>(log_spec: Tensor<B, 2>) -> Tensor<B, 3> {
+    let max = log_spec.clone().max_dim(1).max_dim(0);
+    let log_spec = tensor_max(log_spec, max.sub_scalar(8.0));
+
+    ((log_spec + 4.0) / 4.0).unsqueeze()
+}
+
+fn get_mel_filters<B: Backend>(
+    sample_rate: f64,
+    n_fft: usize,
+    n_mels: usize,
+    htk: bool,
+) -> Tensor<B, 2> {
+    get_mel_filters_device(sample_rate, n_fft, n_mels, htk, &B::Device::default())
+}
+
+fn get_mel_filters_device<B: Backend>(
+    sample_rate: f64,
+    n_fft: usize,
+    n_mels: usize,
+    htk: bool,
+    device: &B::Device,
+) -> Tensor<B, 2> {
+    let fmin = 0.0;
+    let fmax = sample_rate * 0.5;
+
+    //let weights = Tensor::zeros([n_mels, 1 + n_fft / 2]);
+
+    // Center freqs of each FFT bin
+    let fftfreqs = fft_frequencies_device(sample_rate, n_fft, device);
+    let [n_ffefreqs] = fftfreqs.dims();
+
+    // 'Center freqs' of mel bands - uniformly spaced between limits
+    let mel_f_size = n_mels + 2;
+    let mel_f = mel_frequencies_device(mel_f_size, fmin, fmax, htk, device);
+
+    // fdiff = np.diff(mel_f)
+    let fdiff = mel_f.clone().slice([1..mel_f_size]) - mel_f.clone().slice([0..(mel_f_size - 1)]);
+
+    // ramps = np.subtract.outer(mel_f, fftfreqs)
+    let ramps = mel_f
+        .clone()
+        .unsqueeze::<2>()
+        .transpose()
+        .repeat(1, n_ffefreqs)
+        - fftfreqs.unsqueeze();
+
+    /*for i in range(n_mels):
+    # lower and upper slopes for all bins
+    lower = -ramps[i] / fdiff[i]
+    upper = ramps[i + 2] / fdiff[i + 1]
+
+    # .. then intersect them with each other and zero
+    weights[i] = np.maximum(0, np.minimum(lower, upper))*/
+
+    // lower and upper slopes for all bins
+    let lower = -ramps.clone().slice([0..n_mels])
+        / fdiff
+            .clone()
+            .slice([0..n_mels])
+            .unsqueeze::<2>()
+            .transpose();
+    let upper = ramps.slice([2..(2 + n_mels)])
+        / fdiff.slice([1..(1 + n_mels)]).unsqueeze::<2>().transpose();
+
+    // .. then intersect them with each other and zero
+    let weights = relu(tensor_min(lower, upper));
+
+    // Slaney-style mel is scaled to be approx constant energy per channel
+    //enorm = 2.0 / (mel_f[2 : n_mels + 2] - mel_f[:n_mels])
+    let enorm = (mel_f.clone().slice([2..(n_mels + 2)]) - mel_f.clone().slice([0..n_mels]))
+        .powf_scalar(-1)
+        * 2.0;
+    //weights *= enorm[:, np.newaxis]
+    let weights = weights * enorm.unsqueeze::<2>().transpose();
+
+    // Only check weights if f_mel[0] is positive
+    /*if not np.all((mel_f[:-2] == 0) | (weights.max(axis=1) > 0)):
+    # This means we have an empty channel somewhere
+    warnings.warn(
+        "Empty filters detected in mel frequency basis. "
+        "Some channels will produce empty responses. "
+        "Try increasing your sampling rate (and fmax) or "
+        "reducing n_mels.",
+        stacklevel=2,
+    )*/
+    if !(all_zeros(mel_f.slice([0..(n_mels - 2)])) || all_zeros(relu(-weights.clone().max_dim(1))))
+    {
+        println!("Empty filters detected in mel frequency basis. \nSome channels will produce empty responses. \nTry increasing your sampling rate (and fmax) or reducing n_mels.");
+    }
+
+    return weights;
+}
+
+fn fft_frequencies<B: Backend>(sample_rate: f64, n_fft: usize) -> Tensor<B, 1> {
+    fft_frequencies_device(sample_rate, n_fft, &B::Device::default())
+}
+
+fn fft_frequencies_device<B: Backend>(
+    sample_rate: f64,
+    n_fft: usize,
+    device: &B::Device,
+) -> Tensor<B, 1> {
+    //return np.fft.rfftfreq(n=n_fft, d=1.0 / sr)
+    Tensor::arange(0..(n_fft / 2 + 1) as i64, device)
+        .float()
+        .mul_scalar(sample_rate / n_fft as f64)
+}
+
+fn test_fft_frequencies<B: Backend>() {
+    let sr = 1000.0; // stating sample rate
+    let n_fft = 100; // stating the window size of fft
+    let fftfreqs = fft_frequencies::<B>(sr, n_fft);
+    println!("{:?}", fftfreqs);
+}
+
+fn test_mel_frequencies<B: Backend>(htk: bool) {
+    let n_mels = 128; // stating the number of Mel bands
+    let fmin = 0.0; // stating the lowest frequency
+    let fmax = 22050.0; // stating the highest frequency
+    let melfreqs = mel_frequencies::<B>(n_mels + 2, fmin, fmax, htk);
+    println!("{:?}", melfreqs);
+}
+
+fn mel_frequencies<B: Backend>(n_mels: usize, fmin: f64, fmax: f64, htk: bool) -> Tensor<B, 1> {
+    mel_frequencies_device(n_mels, fmin, fmax, htk, &B::Device::default())
+}
+
+fn mel_frequencies_device<B: Backend>(
+    n_mels: usize,
+    fmin: f64,
+    fmax: f64,
+    htk: bool,
+    device: &B::Device,
+) -> Tensor<B, 1> {
+    // 'Center freqs' of mel bands - uniformly spaced between limits
+    let min_mel = hz_to_mel(fmin, htk);
+    let max_mel = hz_to_mel(fmax, htk);
+
+    //mels = np.linspace(min_mel, max_mel, n_mels)
+    let mels = Tensor::arange(0..n_mels as i64, device)
+        .float()
+        .mul_scalar((max_mel - min_mel) / (n_mels - 1) as f64)
+        .add_scalar(min_mel);
+
+    //hz: np.ndarray = mel_to_hz(mels, htk=htk)
+    mel_to_hz_tensor(mels, htk)
+}
+
+fn hz_to_mel(freq: f64, htk: bool) -> f64 {
+    if htk {
+        return 2595.0 * (1.0 + freq / 700.0).log10();
+    }
+
+    // Fill in the linear part
+    let f_min = 0.0;
+    let f_sp = 200.0 / 3.0;
+
+    //let mel = (freq - f_min) / f_sp;
+
+    // Fill in the log-scale part
+
+    let min_log_hz = 1000.0; // beginning of log region (Hz)
+    let min_log_mel = (min_log_hz - f_min) / f_sp; // same (Mels)
+    let logstep = (6.4f64).ln() / 27.0; // step size for log region
+
+    /*if frequencies.ndim:
+        # If we have array data, vectorize
+        log_t = frequencies >= min_log_hz
+        mels[log_t] = min_log_mel + np.log(frequencies[log_t] / min_log_hz) / logstep
+    elif frequencies >= min_log_hz:
+        # If we have scalar data, heck directly
+        mels = min_log_mel + np.log(frequencies / min_log_hz) / logstep*/
+
+    let mel = if freq >= min_log_hz {
+        min_log_mel + (freq / min_log_hz).ln() / logstep
+    } else {
+        (freq - f_min) / f_sp
+    };
+
+    return mel;
+}
+
+fn mel_to_hz_tensor<B: Backend>(mel: Tensor<B, 1>, htk: bool) -> Tensor<B, 1> {
+    if htk {
+        return (_10pow(mel / 2595.0) - 1.0) * 700.0;
+    }
+
+    // Fill in the linear scale
+    let f_min = 0.0;
+    let f_sp = 200.0 / 3.0;
+    //let freqs = f_min + f_sp * mel;
+
+    // And now the nonlinear scale
+    let min_log_hz = 1000.0; // beginning of log region (Hz)
+    let min_log_mel = (min_log_hz - f_min) / f_sp; // same (Mels)
+    let logstep = (6.4f64).ln() / 27.0; // step size for log region
+
+    /*if mels.ndim:
+        # If we have vector data, vectorize
+        log_t = mels >= min_log_mel
+        freqs[log_t] = min_log_hz * np.exp(logstep * (mels[log_t] - min_log_mel))
+    elif mels >= min_log_mel:
+        # If we have scalar data, check directly
+        freqs = min_log_hz * np.exp(logstep * (mels - min_log_mel))*/
+
+    let log_t = mel.clone().greater_equal_elem(min_log_mel).float();
+    let freq = log_t.clone() * (((mel.clone() - min_log_mel) * logstep).exp() * min_log_hz)
+        + (-log_t + 1.0) * (mel * f_sp + f_min);
+
+    /*let freq = if mel >= min_log_mel {
+        min_log_hz * (logstep * (mel - min_log_mel)).exp()
+    } else {
+        f_min + f_sp * mel
+    };*/
+
+    return freq;
+}
+
+pub fn hann_window<B: Backend>(window_length: usize) -> Tensor<B, 1> {
+    hann_window_device(window_length, &B::Device::default())
+}
+
+pub fn hann_window_device<B: Backend>(window_length: usize, device: &B::Device) -> Tensor<B, 1> {
+    Tensor::arange(0..window_length as i64, device)
+        .float()
+        .mul_scalar(std::f64::consts::PI / window_length as f64)
+        .sin()
+        .powf_scalar(2.0)
+}
+
+/// Short time Fourier transform that takes a waveform input of size (n_batch, n_sample) and returns (real_part, imaginary_part) frequency spectrums.
+/// The size of each returned tensor is (n_batch, n_freq, n_frame)
+/// where n_freq = int(n_fft / 2 + 1), n_frame = int( ( n_sample_padded - n_fft ) / hop_length ) + 1,
+/// n_sample_padded = if n_fft is even: n_sample + n_fft else: n_sample + n_fft - 1.
+pub fn stfft<B: Backend>(
+    input: Tensor<B, 2>,
+    n_fft: usize,
+    hop_length: usize,
+    window: Tensor<B, 1>,
+) -> (Tensor<B, 3>, Tensor<B, 3>) {
+    let [n_batch, orig_input_size] = input.dims();
+
+    assert!(orig_input_size >= n_fft);
+
+    let device = input.device();
+
+    // add reflection padding to center the windows on the input times
+    let pad = n_fft / 2;
+    let left_pad = reverse(input.clone().slice([0..n_batch, 1..(pad + 1)]), 1);
+    let right_pad = reverse(
+        input.clone().slice([
+            0..n_batch,
+            (orig_input_size - pad - 1)..(orig_input_size - 1),
+        ]),
+        1,
+    );
+    let input = Tensor::cat(vec![left_pad, input, right_pad], 1);
+
+    // pad window to length n_fft
+    let [orig_window_length] = window.dims();
+    let window = if orig_window_length < n_fft {
+        let left_pad = (n_fft - orig_window_length) / 2;
+        let right_pad = n_fft - orig_window_length - left_pad;
+        Tensor::cat(
+            vec![
+                Tensor::zeros([left_pad], &device),
+                window,
+                Tensor::zeros([right_pad], &device),
+            ],
+            0,
+        )
+    } else {
+        window
+    };
+
+    let [_, input_size] = input.dims();
+
+    let n_frame = (input_size - n_fft) / hop_length + 1;
+    let n_freq = n_fft / 2 + 1; // assuming real input there is conjugate symmetry
+
+    // construct matrix of overlapping input windows
+    let num_parts = div_roundup(n_fft, hop_length);
+    let n_hops = div_roundup(input_size, hop_length);
+    let padded_input_size = n_hops * hop_length;
+    let padding = Tensor::zeros([n_batch, padded_input_size - input_size], &device);
+    let template = Tensor::cat(vec![input, padding], 1)
+        .reshape([n_batch, n_hops, hop_length])
+        .transpose();
+    let parts: Vec<_> = (0..num_parts)
+        .into_iter()
+        .map(|i| {
+            template
+                .clone()
+                .slice([0..n_batch, 0..hop_length, i..(n_frame + i)])
+        })
+        .collect();
+    let input_windows = Tensor::cat(parts, 1).slice([0..n_batch, 0..n_fft, 0..n_frame]);
+
+    // construct matrix of wave angles
+    let coe = std::f64::consts::PI * 2.0 / n_fft as f64;
+    let b = Tensor::arange(0..n_freq as i64, &device)
+        .float()
+        .mul_scalar(coe)
+        .unsqueeze::<2>()
+        .transpose()
+        .repeat(1, n_fft)
+        * Tensor::arange(0..n_fft as i64, &device)
+            .float()
+            .unsqueeze::<2>();
+
+    // convolve the input slices with the window and waves
+    let real_part = (b.clone().cos() * window.clone().unsqueeze())
+        .unsqueeze()
+        .matmul(input_windows.clone());
+    let imaginary_part = (b.sin() * (-window).unsqueeze())
+        .unsqueeze()
+        .matmul(input_windows);
+
+    return (real_part, imaginary_part);
+}
+
+fn div_roundup(a: usize, b: usize) -> usize {
+    (a + b - 1) / b
+}
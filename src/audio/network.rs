@@ -0,0 +1,223 @@
+use std::{
+    io::{self, Read},
+    process::{Child, ChildStdout, Command, Stdio},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::{MediaSourceStream, ReadOnlySource},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use super::{downmix, SAMPLE_RATE};
+use crate::token;
+
+/// Number of bytes read from ffmpeg at once, 64 ms of audio.
+const FFMPEG_READ_SIZE: usize = 4096;
+
+/// A live audio stream received over the network: an Icecast/Shoutcast or any other http(s)
+/// audio stream, decoded in process, or an RTSP/RTP stream, e.g. of an IP camera, decoded by an
+/// `ffmpeg` child process, which has to be installed.
+pub struct NetworkSource {
+    inner: Inner,
+    sample_rate: usize,
+    channel: Option<usize>,
+}
+
+enum Inner {
+    Http {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32,
+    },
+    Ffmpeg {
+        child: Child,
+        stdout: ChildStdout,
+        pending: Vec<u8>,
+    },
+}
+
+impl NetworkSource {
+    /// Connects to the stream at `url`. See `downmix` for how `channel` is used.
+    pub fn open(url: &str, channel: Option<usize>) -> token::Result<Self> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            Self::open_http(url, channel)
+        } else if ["rtsp://", "rtsps://", "rtp://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme))
+        {
+            Self::open_ffmpeg(url, channel)
+        } else {
+            Err(format!("Unsupported stream URL: {}", url).into())
+        }
+    }
+
+    fn open_http(url: &str, channel: Option<usize>) -> token::Result<Self> {
+        // no Icy-MetaData header is sent, so Icecast servers don't interleave metadata with the
+        // audio
+        let response = ureq::get(url).call()?;
+
+        let mut hint = Hint::new();
+        if let Some(mime_type) = response.header("Content-Type") {
+            hint.mime_type(mime_type);
+        }
+
+        let source = MediaSourceStream::new(
+            Box::new(ReadOnlySource::new(response.into_reader())),
+            Default::default(),
+        );
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                source,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )?
+            .format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or("No decodable audio track")?;
+        let track_id = track.id;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or("Unknown sample rate")? as usize;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        Ok(Self {
+            inner: Inner::Http {
+                format,
+                decoder,
+                track_id,
+            },
+            sample_rate,
+            channel,
+        })
+    }
+
+    fn open_ffmpeg(url: &str, channel: Option<usize>) -> token::Result<Self> {
+        let mut command = Command::new("ffmpeg");
+        command.args(["-loglevel", "error", "-nostdin"]);
+        if url.starts_with("rtsp") {
+            // interleaving RTP in the RTSP connection gets through firewalls and doesn't drop
+            // packets
+            command.args(["-rtsp_transport", "tcp"]);
+        }
+        command.args(["-i", url, "-vn"]);
+        match channel {
+            Some(channel) => command.args(["-af", &format!("pan=mono|c0=c{}", channel)]),
+            None => command.args(["-ac", "1"]),
+        };
+        command
+            .args(["-ar", &SAMPLE_RATE.to_string(), "-f", "f32le", "-"])
+            .stdout(Stdio::piped());
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("Failed to start ffmpeg, is it installed? {}", e))?;
+        let stdout = child.stdout.take().ok_or("Failed to read the output of ffmpeg")?;
+
+        Ok(Self {
+            inner: Inner::Ffmpeg {
+                child,
+                stdout,
+                pending: Vec::new(),
+            },
+            sample_rate: SAMPLE_RATE,
+            channel,
+        })
+    }
+
+    /// Sample rate in Hz of the samples returned by `next_chunk`.
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// Blocks until the next chunk of mono samples has been received. Returns `None` once the
+    /// stream has ended.
+    pub fn next_chunk(&mut self) -> token::Result<Option<Vec<f32>>> {
+        match &mut self.inner {
+            Inner::Http {
+                format,
+                decoder,
+                track_id,
+            } => loop {
+                let packet = match format.next_packet() {
+                    Ok(packet) => packet,
+                    Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        return Ok(None)
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if packet.track_id() != *track_id {
+                    continue;
+                }
+
+                let decoded = match decoder.decode(&packet) {
+                    Ok(decoded) => decoded,
+                    // a corrupt packet only loses a few milliseconds of audio
+                    Err(SymphoniaError::DecodeError(_)) => continue,
+                    Err(e) => return Err(e.into()),
+                };
+
+                let spec = *decoded.spec();
+                let n_channels = spec.channels.count();
+                if let Some(channel) = self.channel.filter(|&channel| channel >= n_channels) {
+                    return Err(format!(
+                        "Channel {} does not exist, the stream has {} channels",
+                        channel, n_channels
+                    )
+                    .into());
+                }
+
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+
+                return Ok(Some(downmix(buffer.samples(), n_channels, self.channel)));
+            },
+            Inner::Ffmpeg {
+                child,
+                stdout,
+                pending,
+            } => {
+                let mut bytes = [0u8; FFMPEG_READ_SIZE];
+                let n_read = stdout.read(&mut bytes)?;
+                if n_read == 0 {
+                    let status = child.wait()?;
+                    return if status.success() {
+                        Ok(None)
+                    } else {
+                        Err(format!("ffmpeg exited with {}", status).into())
+                    };
+                }
+
+                // reads don't necessarily end on a sample boundary
+                pending.extend_from_slice(&bytes[..n_read]);
+                let n_bytes = pending.len() / 4 * 4;
+                let samples = pending[..n_bytes]
+                    .chunks_exact(4)
+                    .map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]))
+                    .collect();
+                pending.drain(..n_bytes);
+
+                Ok(Some(samples))
+            }
+        }
+    }
+}
+
+impl Drop for NetworkSource {
+    fn drop(&mut self) {
+        if let Inner::Ffmpeg { child, .. } = &mut self.inner {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
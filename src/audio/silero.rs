@@ -0,0 +1,286 @@
+use burn::{
+    config::Config,
+    module::Module,
+    nn::{
+        self,
+        conv::{Conv1d, Conv1dConfig},
+        PaddingConfig1d,
+    },
+    tensor::{
+        self,
+        activation::{relu, sigmoid},
+        backend::Backend,
+        ElementConversion, Tensor,
+    },
+};
+use std::error::Error;
+use std::ops::Range;
+
+use super::vad::{self, VadConfig};
+use super::SAMPLE_RATE;
+use crate::helper::reverse;
+use crate::model::load::{load_conv1d, load_linear, load_usize};
+use crate::token;
+
+/// Number of new samples the network classifies at each step, 32 ms at 16 kHz.
+pub const CHUNK_SIZE: usize = 512;
+
+/// Number of samples of the previous chunk the network sees in front of every chunk.
+const CONTEXT_SIZE: usize = 64;
+
+#[derive(Config, Debug)]
+pub struct SileroVadConfig {
+    /// Length of the filters of the learned STFT.
+    #[config(default = 256)]
+    pub n_fft: usize,
+    #[config(default = 128)]
+    pub hop_length: usize,
+    #[config(default = 128)]
+    pub n_state: usize,
+}
+
+impl SileroVadConfig {
+    pub fn init<B: Backend>(&self, tensor_device_ref: &B::Device) -> SileroVad<B> {
+        let n_freq = self.n_fft / 2 + 1;
+
+        let stft = Conv1dConfig::new(1, 2 * n_freq, self.n_fft)
+            .with_stride(self.hop_length)
+            .init(tensor_device_ref);
+
+        let encoder = [
+            (n_freq, self.n_state, 1),
+            (self.n_state, self.n_state / 2, 2),
+            (self.n_state / 2, self.n_state / 2, 2),
+            (self.n_state / 2, self.n_state, 1),
+        ]
+        .into_iter()
+        .map(|(n_in, n_out, stride)| {
+            Conv1dConfig::new(n_in, n_out, 3)
+                .with_stride(stride)
+                .with_padding(PaddingConfig1d::Explicit(1))
+                .init(tensor_device_ref)
+        })
+        .collect();
+
+        let lstm_input =
+            nn::LinearConfig::new(self.n_state, 4 * self.n_state).init(tensor_device_ref);
+        let lstm_hidden =
+            nn::LinearConfig::new(self.n_state, 4 * self.n_state).init(tensor_device_ref);
+        let output = Conv1dConfig::new(self.n_state, 1, 1).init(tensor_device_ref);
+
+        SileroVad {
+            stft,
+            encoder,
+            lstm_input,
+            lstm_hidden,
+            output,
+            n_freq,
+            n_state: self.n_state,
+        }
+    }
+}
+
+/// The Silero voice activity detection network for 16 kHz audio: a learned STFT followed by a
+/// small convolutional encoder and an LSTM cell that carries state from chunk to chunk.
+#[derive(Module, Debug)]
+pub struct SileroVad<B: Backend> {
+    stft: Conv1d<B>,
+    encoder: Vec<Conv1d<B>>,
+    lstm_input: nn::Linear<B>,
+    lstm_hidden: nn::Linear<B>,
+    output: Conv1d<B>,
+    n_freq: usize,
+    n_state: usize,
+}
+
+/// Hidden and cell state of the LSTM carried between chunks, along with the tail of the
+/// previous chunk.
+#[derive(Clone, Debug)]
+pub struct SileroVadState<B: Backend> {
+    hidden: Tensor<B, 2>,
+    cell: Tensor<B, 2>,
+    context: Vec<f32>,
+}
+
+impl<B: Backend> SileroVad<B> {
+    /// State to start a new stream of audio with.
+    pub fn initial_state(&self) -> SileroVadState<B> {
+        let device = self.devices()[0].clone();
+
+        SileroVadState {
+            hidden: Tensor::zeros([1, self.n_state], &device),
+            cell: Tensor::zeros([1, self.n_state], &device),
+            context: vec![0.0; CONTEXT_SIZE],
+        }
+    }
+
+    /// Takes a chunk of samples of size (n_batch, CONTEXT_SIZE + CHUNK_SIZE) and returns the
+    /// speech probability of each along with the next LSTM hidden and cell states.
+    pub fn forward(
+        &self,
+        x: Tensor<B, 2>,
+        hidden: Tensor<B, 2>,
+        cell: Tensor<B, 2>,
+    ) -> (Tensor<B, 1>, Tensor<B, 2>, Tensor<B, 2>) {
+        let [n_batch, n_samples] = x.dims();
+
+        // reflection padding on the right only, as done by the original model
+        let right_pad = reverse(
+            x.clone()
+                .slice([0..n_batch, (n_samples - CONTEXT_SIZE - 1)..(n_samples - 1)]),
+            1,
+        );
+        let x = Tensor::cat(vec![x, right_pad], 1).unsqueeze_dim::<3>(1);
+
+        let spectrum = self.stft.forward(x);
+        let [_, _, n_frame] = spectrum.dims();
+        let real = spectrum.clone().slice([0..n_batch, 0..self.n_freq, 0..n_frame]);
+        let imag = spectrum.slice([0..n_batch, self.n_freq..2 * self.n_freq, 0..n_frame]);
+        let magnitudes = (real.powf_scalar(2.0) + imag.powf_scalar(2.0)).sqrt();
+
+        let x = self
+            .encoder
+            .iter()
+            .fold(magnitudes, |x, conv| relu(conv.forward(x)));
+        let [_, _, n_frame] = x.dims();
+        let x: Tensor<B, 2> = x
+            .slice([0..n_batch, 0..self.n_state, (n_frame - 1)..n_frame])
+            .squeeze(2);
+
+        // LSTM cell with the gates in PyTorch order: input, forget, cell and output
+        let gates = self.lstm_input.forward(x) + self.lstm_hidden.forward(hidden);
+        let gate = |i: usize| {
+            gates
+                .clone()
+                .slice([0..n_batch, (i * self.n_state)..((i + 1) * self.n_state)])
+        };
+        let input_gate = sigmoid(gate(0));
+        let forget_gate = sigmoid(gate(1));
+        let cell_gate = gate(2).tanh();
+        let output_gate = sigmoid(gate(3));
+
+        let cell = forget_gate * cell + input_gate * cell_gate;
+        let hidden = output_gate * cell.clone().tanh();
+
+        let probability = sigmoid(self.output.forward(relu(hidden.clone()).unsqueeze_dim(2)));
+
+        (probability.reshape([n_batch]), hidden, cell)
+    }
+
+    /// Speech probability of a single chunk of `CHUNK_SIZE` samples of a 16 kHz stream. Shorter
+    /// chunks are padded with silence.
+    pub fn step(&self, chunk: &[f32], state: &mut SileroVadState<B>) -> f32 {
+        let device = self.devices()[0].clone();
+
+        let mut samples = state.context.clone();
+        samples.extend_from_slice(&chunk[..chunk.len().min(CHUNK_SIZE)]);
+        samples.resize(CONTEXT_SIZE + CHUNK_SIZE, 0.0);
+        state.context = samples[samples.len() - CONTEXT_SIZE..].to_vec();
+
+        let x = Tensor::from_floats(
+            tensor::Data::new(samples, [1, CONTEXT_SIZE + CHUNK_SIZE].into()),
+            &device,
+        );
+        let (probability, hidden, cell) =
+            self.forward(x, state.hidden.clone(), state.cell.clone());
+        state.hidden = hidden;
+        state.cell = cell;
+
+        probability.into_scalar().elem()
+    }
+
+    /// Speech probability of every chunk of `CHUNK_SIZE` samples of a 16 kHz waveform.
+    pub fn speech_probabilities(&self, waveform: &[f32]) -> Vec<f32> {
+        let mut state = self.initial_state();
+
+        waveform
+            .chunks(CHUNK_SIZE)
+            .map(|chunk| self.step(chunk, &mut state))
+            .collect()
+    }
+
+    /// Returns the sample ranges of `waveform` that hold speech, sorted and non overlapping.
+    /// Chunks with a speech probability of at least `speech_threshold` are speech, the
+    /// remaining options are applied like for the energy based detection, see
+    /// `vad::speech_segments`.
+    pub fn speech_segments(
+        &self,
+        waveform: &[f32],
+        sample_rate: usize,
+        config: &VadConfig,
+    ) -> token::Result<Vec<Range<usize>>> {
+        if sample_rate != SAMPLE_RATE {
+            return Err(format!("Silero VAD needs {} Hz audio", SAMPLE_RATE).into());
+        }
+
+        let speech_frames: Vec<bool> = self
+            .speech_probabilities(waveform)
+            .into_iter()
+            .map(|probability| probability as f64 >= config.speech_threshold)
+            .collect();
+
+        Ok(vad::segments_from_frames(
+            &speech_frames,
+            CHUNK_SIZE,
+            waveform.len(),
+            sample_rate,
+            config,
+        ))
+    }
+}
+
+/// Loads the weights dumped by `python/dump_silero_vad.py`.
+pub fn load_silero_vad<B: Backend>(
+    path: &str,
+) -> Result<(SileroVad<B>, SileroVadConfig), Box<dyn Error>> {
+    let n_fft = load_usize::<B>("n_fft", path)?;
+    let hop_length = load_usize::<B>("hop_length", path)?;
+    let n_state = load_usize::<B>("n_state", path)?;
+    let config = SileroVadConfig::new()
+        .with_n_fft(n_fft)
+        .with_hop_length(hop_length)
+        .with_n_state(n_state);
+
+    let n_freq = n_fft / 2 + 1;
+    let stft = load_conv1d(
+        &format!("{}/{}", path, "stft"),
+        Conv1dConfig::new(1, 2 * n_freq, n_fft).with_stride(hop_length),
+    )?;
+
+    let encoder = [
+        (n_freq, n_state, 1),
+        (n_state, n_state / 2, 2),
+        (n_state / 2, n_state / 2, 2),
+        (n_state / 2, n_state, 1),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, (n_in, n_out, stride))| {
+        load_conv1d(
+            &format!("{}/encoder/block_{}", path, i),
+            Conv1dConfig::new(n_in, n_out, 3)
+                .with_stride(stride)
+                .with_padding(PaddingConfig1d::Explicit(1)),
+        )
+    })
+    .collect::<Result<_, _>>()?;
+
+    let lstm_input = load_linear(&format!("{}/{}", path, "decoder/rnn/input"))?;
+    let lstm_hidden = load_linear(&format!("{}/{}", path, "decoder/rnn/hidden"))?;
+    let output = load_conv1d(
+        &format!("{}/{}", path, "decoder/output"),
+        Conv1dConfig::new(n_state, 1, 1),
+    )?;
+
+    let vad = SileroVad {
+        stft,
+        encoder,
+        lstm_input,
+        lstm_hidden,
+        output,
+        n_freq,
+        n_state,
+    };
+
+    Ok((vad, config))
+}
@@ -0,0 +1,219 @@
+use burn::{
+    config::Config,
+    module::{Module, Param},
+    nn::{
+        self,
+        conv::{Conv1d, Conv1dConfig},
+        PaddingConfig1d,
+    },
+    tensor::{self, backend::Backend, Tensor},
+};
+use std::error::Error;
+
+use super::{get_mel_filters_device, stfft, HOP_LENGTH, N_FFT, SAMPLE_RATE};
+use crate::helper::{tensor_log10, tensor_max, tensor_max_scalar};
+use crate::model::load::{load_conv1d, load_linear, load_tensor, load_usize};
+
+/// Kernel size and dilation of the TDNN layers.
+const LAYERS: [(usize, usize); 5] = [(5, 1), (3, 2), (3, 3), (1, 1), (1, 1)];
+
+/// Slope of the leaky ReLU after every TDNN layer.
+const LEAKY_SLOPE: f64 = 0.01;
+
+/// Shortest audio an embedding is computed of, 0.5 s, shorter audio is repeated to this length.
+const MIN_SAMPLES: usize = SAMPLE_RATE / 2;
+
+#[derive(Config, Debug)]
+pub struct SpeakerEncoderConfig {
+    #[config(default = 24)]
+    pub n_mels: usize,
+    /// Channels of the TDNN layers but the last.
+    #[config(default = 512)]
+    pub n_channels: usize,
+    /// Channels of the last TDNN layer, whose mean and standard deviation are pooled.
+    #[config(default = 1500)]
+    pub n_pooled: usize,
+    #[config(default = 512)]
+    pub n_embedding: usize,
+}
+
+impl SpeakerEncoderConfig {
+    pub fn init<B: Backend>(&self, tensor_device_ref: &B::Device) -> SpeakerEncoder<B> {
+        let layers = (0..LAYERS.len())
+            .map(|i| {
+                let (n_in, n_out) = self.layer_channels(i);
+                TdnnLayer {
+                    conv: layer_conv_config(i, n_in, n_out).init(tensor_device_ref),
+                    scale: Param::from_tensor(Tensor::ones([n_out], tensor_device_ref)),
+                    shift: Param::from_tensor(Tensor::zeros([n_out], tensor_device_ref)),
+                }
+            })
+            .collect();
+
+        let embedding =
+            nn::LinearConfig::new(2 * self.n_pooled, self.n_embedding).init(tensor_device_ref);
+
+        SpeakerEncoder {
+            layers,
+            embedding,
+            n_mels: self.n_mels,
+        }
+    }
+
+    /// Input and output channels of the TDNN layer `i`.
+    fn layer_channels(&self, i: usize) -> (usize, usize) {
+        let n_in = if i == 0 { self.n_mels } else { self.n_channels };
+        let n_out = if i == LAYERS.len() - 1 {
+            self.n_pooled
+        } else {
+            self.n_channels
+        };
+        (n_in, n_out)
+    }
+}
+
+/// Convolution of the TDNN layer `i`, padded to keep the number of frames.
+fn layer_conv_config(i: usize, n_in: usize, n_out: usize) -> Conv1dConfig {
+    let (kernel_size, dilation) = LAYERS[i];
+    Conv1dConfig::new(n_in, n_out, kernel_size)
+        .with_dilation(dilation)
+        .with_padding(PaddingConfig1d::Explicit(dilation * (kernel_size - 1) / 2))
+}
+
+/// A time delay layer: a dilated convolution over the frames followed by a leaky ReLU and a
+/// batch normalization, folded into a scale and shift per channel.
+#[derive(Module, Debug)]
+pub struct TdnnLayer<B: Backend> {
+    conv: Conv1d<B>,
+    scale: Param<Tensor<B, 1>>,
+    shift: Param<Tensor<B, 1>>,
+}
+
+impl<B: Backend> TdnnLayer<B> {
+    fn forward(&self, x: Tensor<B, 3>) -> Tensor<B, 3> {
+        let x = self.conv.forward(x);
+        let x = x.clone().mask_where(x.clone().lower_elem(0.0), x * LEAKY_SLOPE);
+
+        let [n_channel] = self.scale.dims();
+        let scale = self.scale.val().reshape([1, n_channel, 1]);
+        let shift = self.shift.val().reshape([1, n_channel, 1]);
+        x * scale + shift
+    }
+}
+
+/// The x-vector speaker embedding network of SpeechBrain's `spkrec-xvect-voxceleb`: TDNN
+/// layers over filter bank features, pooled into the mean and standard deviation over time
+/// and projected to an embedding that is close for speech of the same speaker.
+#[derive(Module, Debug)]
+pub struct SpeakerEncoder<B: Backend> {
+    layers: Vec<TdnnLayer<B>>,
+    embedding: nn::Linear<B>,
+    n_mels: usize,
+}
+
+impl<B: Backend> SpeakerEncoder<B> {
+    /// Takes features of size (n_batch, n_mels, n_frames), see `speaker_features`, and returns
+    /// the embeddings of size (n_batch, n_embedding).
+    pub fn forward(&self, features: Tensor<B, 3>) -> Tensor<B, 2> {
+        let x = self.layers.iter().fold(features, |x, layer| layer.forward(x));
+        let [n_batch, n_channel, n_frame] = x.dims();
+
+        // unbiased standard deviation like torch.std
+        let mean = x.clone().mean_dim(2);
+        let std = (x - mean.clone())
+            .powf_scalar(2.0)
+            .sum_dim(2)
+            .div_scalar(n_frame.saturating_sub(1).max(1) as f64)
+            .sqrt();
+        let statistics = Tensor::cat(vec![mean, std], 1).reshape([n_batch, 2 * n_channel]);
+
+        self.embedding.forward(statistics)
+    }
+
+    /// Speaker embedding of a 16 kHz waveform.
+    pub fn embed(&self, waveform: &[f32]) -> Vec<f32> {
+        let device = self.devices()[0].clone();
+
+        let n_samples = waveform.len().max(MIN_SAMPLES);
+        let mut samples: Vec<f32> = waveform.iter().copied().cycle().take(n_samples).collect();
+        samples.resize(n_samples, 0.0);
+
+        let features = speaker_features::<B>(&samples, self.n_mels, &device);
+        self.forward(features.unsqueeze())
+            .into_data()
+            .convert::<f32>()
+            .value
+    }
+}
+
+/// Log mel filter bank energies in dB of a 16 kHz waveform with shape (n_mels, n_frames),
+/// normalized to a mean of zero per band like the features SpeechBrain's x-vectors are
+/// trained on.
+pub fn speaker_features<B: Backend>(
+    waveform: &[f32],
+    n_mels: usize,
+    device: &B::Device,
+) -> Tensor<B, 2> {
+    let waveform: Tensor<B, 1> = Tensor::from_floats(
+        tensor::Data::new(waveform.to_vec(), [waveform.len()].into()),
+        device,
+    );
+
+    let (stft_real, stft_imag) =
+        stfft(waveform.unsqueeze(), N_FFT, HOP_LENGTH, hamming_window(N_FFT, device));
+    let power = stft_real.powf_scalar(2.0) + stft_imag.powf_scalar(2.0);
+    let mel_spec = get_mel_filters_device(SAMPLE_RATE as f64, N_FFT, n_mels, true, device)
+        .unsqueeze()
+        .matmul(power);
+
+    let log_spec = tensor_log10(tensor_max_scalar(mel_spec, 1.0e-10)).mul_scalar(10.0);
+    let max = log_spec.clone().max_dim(2).max_dim(1);
+    let log_spec = tensor_max(log_spec, max.sub_scalar(80.0));
+    let log_spec = log_spec.clone() - log_spec.mean_dim(2);
+
+    log_spec.squeeze(0)
+}
+
+fn hamming_window<B: Backend>(window_length: usize, device: &B::Device) -> Tensor<B, 1> {
+    Tensor::arange(0..window_length as i64, device)
+        .float()
+        .mul_scalar(std::f64::consts::PI * 2.0 / window_length as f64)
+        .cos()
+        .mul_scalar(-0.46)
+        .add_scalar(0.54)
+}
+
+/// Loads the weights dumped by `python/dump_speaker_encoder.py`.
+pub fn load_speaker_encoder<B: Backend>(
+    path: &str,
+) -> Result<(SpeakerEncoder<B>, SpeakerEncoderConfig), Box<dyn Error>> {
+    let config = SpeakerEncoderConfig::new()
+        .with_n_mels(load_usize::<B>("n_mels", path)?)
+        .with_n_channels(load_usize::<B>("n_channels", path)?)
+        .with_n_pooled(load_usize::<B>("n_pooled", path)?)
+        .with_n_embedding(load_usize::<B>("n_embedding", path)?);
+
+    let layers = (0..LAYERS.len())
+        .map(|i| {
+            let layer_path = format!("{}/layer_{}", path, i);
+            let (n_in, n_out) = config.layer_channels(i);
+            let conv = load_conv1d(
+                &format!("{}/conv", layer_path),
+                layer_conv_config(i, n_in, n_out),
+            )?;
+            let scale = Param::from_tensor(load_tensor::<B, 1>("scale", &layer_path)?);
+            let shift = Param::from_tensor(load_tensor::<B, 1>("shift", &layer_path)?);
+            Ok(TdnnLayer { conv, scale, shift })
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+
+    let embedding = load_linear(&format!("{}/{}", path, "embedding"))?;
+
+    let encoder = SpeakerEncoder {
+        layers,
+        embedding,
+        n_mels: config.n_mels,
+    };
+
+    Ok((encoder, config))
+}
@@ -0,0 +1,100 @@
+use burn::config::Config;
+use std::ops::Range;
+
+/// Options of the voice activity detection, see `speech_segments`.
+#[derive(Config, Debug)]
+pub struct VadConfig {
+    /// Length in seconds of the frames the energy is measured over.
+    #[config(default = 0.03)]
+    pub frame_duration: f64,
+    /// Frames louder than the noise floor by this many decibels are considered speech.
+    #[config(default = 12.0)]
+    pub threshold_db: f64,
+    /// Frames quieter than this level in dBFS are never considered speech, which keeps the
+    /// noise of silent recordings from being picked up.
+    #[config(default = -50.0)]
+    pub min_level_db: f64,
+    /// Chunks the Silero model gives at least this speech probability are considered speech,
+    /// see `silero::SileroVad`.
+    #[config(default = 0.5)]
+    pub speech_threshold: f64,
+    /// Speech shorter than this many seconds is dropped.
+    #[config(default = 0.25)]
+    pub min_speech_duration: f64,
+    /// Pauses shorter than this many seconds don't split speech.
+    #[config(default = 0.5)]
+    pub min_silence_duration: f64,
+    /// Seconds of audio kept around every speech segment so word onsets aren't clipped.
+    #[config(default = 0.2)]
+    pub speech_pad: f64,
+}
+
+/// Returns the sample ranges of `waveform` that hold speech, sorted and non overlapping. A frame
+/// is speech when its energy exceeds the noise floor, estimated from the quietest frames, by
+/// `threshold_db`.
+pub fn speech_segments(
+    waveform: &[f32],
+    sample_rate: usize,
+    config: &VadConfig,
+) -> Vec<Range<usize>> {
+    let seconds_to_samples = |seconds: f64| (seconds * sample_rate as f64) as usize;
+    let frame_length = seconds_to_samples(config.frame_duration).max(1);
+
+    let levels: Vec<f64> = waveform.chunks(frame_length).map(level_db).collect();
+    if levels.is_empty() {
+        return Vec::new();
+    }
+
+    // the quietest tenth of the frames is taken as the noise floor
+    let mut sorted_levels = levels.clone();
+    sorted_levels.sort_by(|a, b| a.total_cmp(b));
+    let noise_floor = sorted_levels[sorted_levels.len() / 10];
+    let threshold = (noise_floor + config.threshold_db).max(config.min_level_db);
+
+    let speech_frames: Vec<bool> = levels.iter().map(|&level| level >= threshold).collect();
+
+    segments_from_frames(&speech_frames, frame_length, waveform.len(), sample_rate, config)
+}
+
+/// Turns per frame speech decisions into sample ranges of speech: pauses shorter than
+/// `min_silence_duration` are bridged, speech shorter than `min_speech_duration` is dropped and
+/// the remaining ranges are padded by `speech_pad`.
+pub fn segments_from_frames(
+    speech_frames: &[bool],
+    frame_length: usize,
+    n_samples: usize,
+    sample_rate: usize,
+    config: &VadConfig,
+) -> Vec<Range<usize>> {
+    let seconds_to_samples = |seconds: f64| (seconds * sample_rate as f64) as usize;
+
+    let min_silence = seconds_to_samples(config.min_silence_duration);
+    let mut speech: Vec<Range<usize>> = Vec::new();
+    for (i, _) in speech_frames.iter().enumerate().filter(|(_, &is_speech)| is_speech) {
+        let start = (i * frame_length).min(n_samples);
+        let end = (start + frame_length).min(n_samples);
+        match speech.last_mut() {
+            Some(last) if start - last.end <= min_silence => last.end = end,
+            _ => speech.push(start..end),
+        }
+    }
+
+    let min_speech = seconds_to_samples(config.min_speech_duration);
+    let pad = seconds_to_samples(config.speech_pad);
+    let mut segments: Vec<Range<usize>> = Vec::new();
+    for segment in speech.into_iter().filter(|segment| segment.len() >= min_speech) {
+        let segment = segment.start.saturating_sub(pad)..(segment.end + pad).min(n_samples);
+        match segments.last_mut() {
+            Some(last) if segment.start <= last.end => last.end = segment.end,
+            _ => segments.push(segment),
+        }
+    }
+
+    segments
+}
+
+/// Energy of a frame in dBFS.
+fn level_db(frame: &[f32]) -> f64 {
+    let power = frame.iter().map(|&x| (x as f64).powi(2)).sum::<f64>() / frame.len() as f64;
+    10.0 * power.max(1e-10).log10()
+}
@@ -0,0 +1,214 @@
+use burn::config::Config;
+use std::f32::consts::PI;
+
+use super::{HOP_LENGTH, N_FFT, SAMPLE_RATE};
+
+/// Number of mel bands of the features templates are matched on.
+const N_BANDS: usize = 40;
+
+/// Options of the wake word detection, see `WakeWordDetector`.
+#[derive(Config, Debug)]
+pub struct WakeWordConfig {
+    /// Audio matching a template with at most this average cosine distance between aligned
+    /// frames counts as the wake word. Lower is stricter.
+    #[config(default = 0.3)]
+    pub threshold: f64,
+}
+
+/// Where the wake word was found in some audio.
+#[derive(Debug, Clone, Copy)]
+pub struct WakeWordMatch {
+    /// Average cosine distance between the aligned frames of the template and the audio.
+    pub distance: f64,
+    /// Time in seconds the wake word ends at.
+    pub end: f64,
+}
+
+/// Spots a wake word by matching recordings of it against the audio with dynamic time warping
+/// of log mel features. It runs on the CPU in a fraction of real time and needs no training,
+/// only a few recordings of the wake word spoken by the user, which keeps the model idle
+/// until it is needed.
+pub struct WakeWordDetector {
+    templates: Vec<Vec<Vec<f32>>>,
+    features: FeatureExtractor,
+    config: WakeWordConfig,
+}
+
+impl WakeWordDetector {
+    /// Creates a detector from recordings of the wake word, 16 kHz mono waveforms holding
+    /// little besides the phrase.
+    pub fn new(templates: &[Vec<f32>], config: WakeWordConfig) -> Self {
+        let features = FeatureExtractor::new();
+        let templates = templates
+            .iter()
+            .map(|template| features.compute(template))
+            .filter(|template| !template.is_empty())
+            .collect();
+        Self {
+            templates,
+            features,
+            config,
+        }
+    }
+
+    /// Returns the best match of any template in the 16 kHz `waveform`.
+    pub fn best_match(&self, waveform: &[f32]) -> Option<WakeWordMatch> {
+        let features = self.features.compute(waveform);
+        if features.is_empty() {
+            return None;
+        }
+
+        self.templates
+            .iter()
+            .map(|template| {
+                let (distance, end_frame) = subsequence_dtw(template, &features);
+                WakeWordMatch {
+                    distance,
+                    end: ((end_frame + 1) * HOP_LENGTH) as f64 / SAMPLE_RATE as f64,
+                }
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    /// Returns where the wake word is found in the 16 kHz `waveform`, if it is.
+    pub fn detect(&self, waveform: &[f32]) -> Option<WakeWordMatch> {
+        self.best_match(waveform)
+            .filter(|found| found.distance <= self.config.threshold)
+    }
+}
+
+/// Log mel spectrogram frames, normalized to zero mean per band so the loudness and the
+/// microphone matter less.
+struct FeatureExtractor {
+    /// Hann windowed DFT basis, the real and imaginary parts of every frequency bin.
+    dft: Vec<(Vec<f32>, Vec<f32>)>,
+    /// Triangular mel filters over the frequency bins.
+    filters: Vec<Vec<f32>>,
+}
+
+impl FeatureExtractor {
+    fn new() -> Self {
+        let n_freq = N_FFT / 2 + 1;
+        let window: Vec<f32> = (0..N_FFT)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / N_FFT as f32).cos())
+            .collect();
+        let dft = (0..n_freq)
+            .map(|k| {
+                let angle = |i: usize| 2.0 * PI * (k * i) as f32 / N_FFT as f32;
+                let real = (0..N_FFT).map(|i| angle(i).cos() * window[i]).collect();
+                let imag = (0..N_FFT).map(|i| -angle(i).sin() * window[i]).collect();
+                (real, imag)
+            })
+            .collect();
+
+        // bands evenly spaced on the mel scale between 0 Hz and the Nyquist frequency
+        let hz_to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+        let mel_to_hz = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+        let max_mel = hz_to_mel(SAMPLE_RATE as f32 / 2.0);
+        let edges: Vec<f32> = (0..N_BANDS + 2)
+            .map(|i| mel_to_hz(max_mel * i as f32 / (N_BANDS + 1) as f32))
+            .collect();
+        let bin_hz = SAMPLE_RATE as f32 / N_FFT as f32;
+        let filters = (0..N_BANDS)
+            .map(|band| {
+                let (low, center, high) = (edges[band], edges[band + 1], edges[band + 2]);
+                (0..n_freq)
+                    .map(|bin| {
+                        let hz = bin as f32 * bin_hz;
+                        let rising = (hz - low) / (center - low);
+                        let falling = (high - hz) / (high - center);
+                        rising.min(falling).max(0.0)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self { dft, filters }
+    }
+
+    fn compute(&self, waveform: &[f32]) -> Vec<Vec<f32>> {
+        if waveform.len() < N_FFT {
+            return Vec::new();
+        }
+
+        let mut frames: Vec<Vec<f32>> = waveform
+            .windows(N_FFT)
+            .step_by(HOP_LENGTH)
+            .map(|frame| {
+                let power: Vec<f32> = self
+                    .dft
+                    .iter()
+                    .map(|(real, imag)| {
+                        let re: f32 = real.iter().zip(frame).map(|(b, x)| b * x).sum();
+                        let im: f32 = imag.iter().zip(frame).map(|(b, x)| b * x).sum();
+                        re * re + im * im
+                    })
+                    .collect();
+                self.filters
+                    .iter()
+                    .map(|filter| {
+                        let energy: f32 = filter.iter().zip(&power).map(|(w, p)| w * p).sum();
+                        energy.max(1e-10).ln()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let n_frames = frames.len() as f32;
+        for band in 0..N_BANDS {
+            let mean = frames.iter().map(|frame| frame[band]).sum::<f32>() / n_frames;
+            for frame in &mut frames {
+                frame[band] -= mean;
+            }
+        }
+        frames
+    }
+}
+
+fn cosine_distance(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        1.0
+    } else {
+        (1.0 - dot / denominator) as f64
+    }
+}
+
+/// Aligns the whole `template` with the part of `features` it fits best, which may start and
+/// end anywhere. Returns the cost of the alignment averaged over its steps and the frame of
+/// `features` it ends at.
+fn subsequence_dtw(template: &[Vec<f32>], features: &[Vec<f32>]) -> (f64, usize) {
+    let m = features.len();
+    // accumulated cost and number of steps of the best path to every cell of the last row
+    let mut previous: Vec<(f64, usize)> = features
+        .iter()
+        .map(|frame| (cosine_distance(&template[0], frame), 1))
+        .collect();
+
+    for template_frame in &template[1..] {
+        let mut current: Vec<(f64, usize)> = Vec::with_capacity(m);
+        for j in 0..m {
+            let distance = cosine_distance(template_frame, &features[j]);
+            let mut candidates = vec![previous[j]];
+            if j > 0 {
+                candidates.push(previous[j - 1]);
+                candidates.push(current[j - 1]);
+            }
+            let (cost, steps) = candidates
+                .into_iter()
+                .min_by(|a, b| (a.0 / a.1 as f64).total_cmp(&(b.0 / b.1 as f64)))
+                .expect("There is at least one predecessor");
+            current.push((cost + distance, steps + 1));
+        }
+        previous = current;
+    }
+
+    previous
+        .iter()
+        .enumerate()
+        .map(|(j, &(cost, steps))| (cost / steps as f64, j))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .expect("The audio has at least one frame")
+}
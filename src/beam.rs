@@ -1,11 +1,14 @@
-use std::cmp::Ordering;
+// the deprecated items use each other
+#![allow(deprecated)]
 
+#[deprecated(note = "decoding uses the beam search of `transcribe`")]
 #[derive(Clone)]
 pub struct BeamNode<T: Clone> {
     pub seq: Vec<T>,
     pub log_prob: f64,
 }
 
+#[deprecated(note = "decoding uses the beam search of `transcribe`")]
 pub fn beam_search<T, F, G>(
     initial_beams: Vec<BeamNode<T>>,
     next: F,
@@ -19,7 +22,7 @@ where
     G: Fn(&[T]) -> bool + Clone,
 {
     let mut beams = initial_beams;
-    for i in 0..max_depth {
+    for _ in 0..max_depth {
         if let Some(beam) = beams
             .iter()
             .max_by(|a, b| a.log_prob.partial_cmp(&b.log_prob).unwrap())
@@ -39,6 +42,7 @@ where
         .unwrap_or_else(Vec::new)
 }
 
+#[deprecated(note = "decoding uses the beam search of `transcribe`")]
 pub fn beam_search_step<T, F, G>(
     beams: Vec<BeamNode<T>>,
     next: F,
@@ -90,10 +94,8 @@ fn get_top_elements<T>(elems: &[T], score: impl Fn(&T) -> f64, num: usize) -> Ve
         let score = score(elem);
 
         // most common scenario
-        if top_elems.len() == num {
-            if score < scores[0] {
-                continue;
-            }
+        if top_elems.len() == num && score < scores[0] {
+            continue;
         }
 
         if let Some((idx, _)) = scores.iter().enumerate().find(|(_, &s)| s >= score) {
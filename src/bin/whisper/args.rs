@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use strum::IntoEnumIterator;
+use whisper_stream::{
+    audio::PcmFormat,
+    model::quantize::Quantization,
+    output::OutputFormat,
+    token::{Language, Task},
+};
+
+/// Finds the variant of an enum named `value` by its `as_str`.
+fn find_variant<T: IntoEnumIterator>(
+    value: &str,
+    as_str: fn(&T) -> &str,
+    kind: &str,
+) -> Result<T, String> {
+    T::iter().find(|variant| as_str(variant) == value).ok_or_else(|| {
+        let names: Vec<String> = T::iter().map(|variant| as_str(&variant).to_string()).collect();
+        format!("Invalid {} {}, expected one of {}", kind, value, names.join(", "))
+    })
+}
+
+pub fn parse_language(value: &str) -> Result<Language, String> {
+    find_variant(value, Language::as_str, "language")
+}
+
+pub fn parse_task(value: &str) -> Result<Task, String> {
+    find_variant(value, Task::as_str, "task")
+}
+
+pub fn parse_output_format(value: &str) -> Result<OutputFormat, String> {
+    find_variant(value, OutputFormat::as_str, "output format")
+}
+
+pub fn parse_pcm_format(value: &str) -> Result<PcmFormat, String> {
+    find_variant(value, PcmFormat::as_str, "raw audio format")
+}
+
+pub fn parse_quantization(value: &str) -> Result<Quantization, String> {
+    find_variant(value, Quantization::as_str, "quantization")
+}
+
+/// Picks the output format by the extension of `path`, falling back to plain text.
+pub fn output_format_of(path: &str) -> OutputFormat {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .unwrap_or_default();
+    OutputFormat::iter()
+        .find(|format| format.as_str() == extension)
+        .unwrap_or(OutputFormat::Txt)
+}
@@ -0,0 +1,201 @@
+use burn::{
+    backend::wgpu::{AutoGraphicsApi, GraphicsApi, Wgpu, WgpuDevice},
+    tensor::backend::Backend,
+};
+use clap::{Args, ValueEnum};
+use half::f16;
+use whisper_stream::{model::quantize::Quantization, token};
+
+use crate::args::parse_quantization;
+
+/// Backend the models run on. Backends other than wgpu are only built with the Cargo feature of
+/// their name.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum BackendKind {
+    /// Vulkan, Metal or DirectX 12 through wgpu, which runs on most GPUs.
+    Wgpu,
+    /// LibTorch, on a CUDA GPU or Apple's MPS when there is one and on the CPU otherwise.
+    Tch,
+    /// Pure Rust on the CPU, for servers without a GPU.
+    Ndarray,
+    /// Hugging Face's candle on the CPU.
+    Candle,
+}
+
+impl BackendKind {
+    fn not_built(&self) -> String {
+        let name = self.to_possible_value().unwrap().get_name().to_string();
+        format!("The {} backend isn't built in, rebuild with `--features {}`", name, name)
+    }
+}
+
+/// Element type of the weights and activations of the models.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    F32,
+    /// Half the memory of f32 and faster on GPUs with native f16 support.
+    F16,
+    /// The range of f32 at the size of f16, where the backend supports it.
+    Bf16,
+}
+
+/// Options of the backend the models run on, shared by the subcommands running a model.
+#[derive(Args, Debug, Clone)]
+pub struct BackendArgs {
+    /// Backend the model runs on.
+    #[arg(long, value_enum, default_value_t = BackendKind::Wgpu)]
+    pub backend: BackendKind,
+    /// Element type the model runs in. The weights are converted from the f32 model files as
+    /// they load.
+    #[arg(long, value_enum, default_value_t = Precision::F32)]
+    pub precision: Precision,
+    /// Quantize the weights of the attention and MLP layers as the model loads, e.g. `int8`
+    /// to hold the larger models in about a quarter of the memory of f32. The weights are
+    /// dequantized layer by layer on every forward pass.
+    #[arg(long, value_parser = parse_quantization)]
+    pub quantize: Option<Quantization>,
+    /// Index of the GPU to run on as listed by `--list-gpus`, instead of the one wgpu deems
+    /// best, which may be the integrated GPU of a laptop. With `--backend tch` the index of the
+    /// CUDA device.
+    #[arg(long, env = "WHISPER_GPU", value_name = "INDEX")]
+    pub gpu: Option<usize>,
+    /// List the GPUs wgpu can run on and exit.
+    #[arg(long, exclusive = true)]
+    pub list_gpus: bool,
+}
+
+/// A subcommand that runs on any backend, which is picked at runtime by `BackendArgs`.
+pub trait BackendCommand: Sized {
+    fn run_on<B: Backend>(args: Self, device: B::Device) -> token::Result<()>;
+}
+
+impl BackendArgs {
+    pub fn run<C: BackendCommand>(&self, args: C) -> token::Result<()> {
+        if self.list_gpus {
+            return list_gpus();
+        }
+        match self.backend {
+            BackendKind::Wgpu => self.run_wgpu(args),
+            #[cfg(feature = "tch")]
+            BackendKind::Tch => self.run_tch(args),
+            #[cfg(feature = "ndarray")]
+            BackendKind::Ndarray => self.run_ndarray(args),
+            #[cfg(feature = "candle")]
+            BackendKind::Candle => self.run_candle(args),
+            #[allow(unreachable_patterns)]
+            backend => Err(backend.not_built().into()),
+        }
+    }
+
+    fn run_wgpu<C: BackendCommand>(&self, args: C) -> token::Result<()> {
+        let device = wgpu_device(self.gpu)?;
+        match self.precision {
+            Precision::F32 => C::run_on::<Wgpu>(args, device),
+            Precision::F16 => C::run_on::<Wgpu<AutoGraphicsApi, f16, i32>>(args, device),
+            Precision::Bf16 => Err("The wgpu backend doesn't support bf16, use f16".into()),
+        }
+    }
+
+    #[cfg(feature = "tch")]
+    fn run_tch<C: BackendCommand>(&self, args: C) -> token::Result<()> {
+        use burn::backend::libtorch::{LibTorch, LibTorchDevice};
+        use half::bf16;
+
+        let device = if let Some(gpu) = self.gpu {
+            let n_cuda = tch::Cuda::device_count() as usize;
+            if gpu >= n_cuda {
+                return Err(format!("There is no CUDA device {}, there are {}", gpu, n_cuda).into());
+            }
+            LibTorchDevice::Cuda(gpu)
+        } else if tch::Cuda::is_available() {
+            LibTorchDevice::Cuda(0)
+        } else if tch::utils::has_mps() {
+            LibTorchDevice::Mps
+        } else {
+            LibTorchDevice::Cpu
+        };
+        match self.precision {
+            Precision::F32 => C::run_on::<LibTorch<f32>>(args, device),
+            Precision::F16 => C::run_on::<LibTorch<f16>>(args, device),
+            Precision::Bf16 => C::run_on::<LibTorch<bf16>>(args, device),
+        }
+    }
+
+    #[cfg(feature = "ndarray")]
+    fn run_ndarray<C: BackendCommand>(&self, args: C) -> token::Result<()> {
+        use burn::backend::ndarray::{NdArray, NdArrayDevice};
+
+        match self.precision {
+            Precision::F32 => C::run_on::<NdArray<f32>>(args, NdArrayDevice::Cpu),
+            _ => Err("The ndarray backend only runs in f32".into()),
+        }
+    }
+
+    #[cfg(feature = "candle")]
+    fn run_candle<C: BackendCommand>(&self, args: C) -> token::Result<()> {
+        use burn::backend::candle::{Candle, CandleDevice};
+        use half::bf16;
+
+        let device = CandleDevice::Cpu;
+        match self.precision {
+            Precision::F32 => C::run_on::<Candle<f32>>(args, device),
+            Precision::F16 => C::run_on::<Candle<f16>>(args, device),
+            Precision::Bf16 => C::run_on::<Candle<bf16>>(args, device),
+        }
+    }
+}
+
+/// The adapters of wgpu for the graphics API of burn-wgpu, in the order burn-wgpu enumerates
+/// them.
+fn wgpu_adapters() -> Vec<wgpu::Adapter> {
+    wgpu::Instance::default().enumerate_adapters(AutoGraphicsApi::backend().into())
+}
+
+/// The device of the adapter `gpu` of `wgpu_adapters`, or the one wgpu deems best if `None`.
+fn wgpu_device(gpu: Option<usize>) -> token::Result<WgpuDevice> {
+    let Some(gpu) = gpu else {
+        return Ok(WgpuDevice::default());
+    };
+    let adapters = wgpu_adapters();
+    let info = adapters
+        .get(gpu)
+        .ok_or_else(|| format!("There is no GPU {}, there are {}", gpu, adapters.len()))?
+        .get_info();
+    // burn-wgpu counts the adapters of every type separately
+    let n_before = adapters[..gpu]
+        .iter()
+        .filter(|adapter| adapter.get_info().device_type == info.device_type)
+        .count();
+    match info.device_type {
+        wgpu::DeviceType::DiscreteGpu => Ok(WgpuDevice::DiscreteGpu(n_before)),
+        wgpu::DeviceType::IntegratedGpu => Ok(WgpuDevice::IntegratedGpu(n_before)),
+        wgpu::DeviceType::VirtualGpu => Ok(WgpuDevice::VirtualGpu(n_before)),
+        wgpu::DeviceType::Cpu => Ok(WgpuDevice::Cpu),
+        wgpu::DeviceType::Other => Err(format!("{} is of unknown type", info.name).into()),
+    }
+}
+
+/// Prints the adapters `--gpu` picks from. wgpu doesn't report how much memory they have, so
+/// the largest buffer they allow is printed as a hint.
+fn list_gpus() -> token::Result<()> {
+    let adapters = wgpu_adapters();
+    if adapters.is_empty() {
+        return Err("wgpu found no GPU".into());
+    }
+
+    println!("GPUs of {:?}:", AutoGraphicsApi::backend());
+    for (i, adapter) in adapters.iter().enumerate() {
+        let info = adapter.get_info();
+        let max_buffer_size = adapter.limits().max_buffer_size;
+        // backends without a limit report u64::MAX
+        let max_buffer = match max_buffer_size < 1 << 48 {
+            true => format!(", buffers up to {:.1} GB", max_buffer_size as f64 / 1e9),
+            false => String::new(),
+        };
+        println!(
+            "{:3}: {} ({:?}, {} {}){}",
+            i, info.name, info.device_type, info.driver, info.driver_info, max_buffer
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::time::Instant;
+
+use burn::tensor::backend::Backend;
+use clap::Args;
+use whisper_stream::{
+    audio::{load_audio, resample, SAMPLE_RATE},
+    token::{self, Language},
+    transcribe::{waveform_to_text, DecodingOptions},
+};
+
+use crate::args::parse_language;
+use crate::backend::{BackendArgs, BackendCommand};
+use crate::models::load_model;
+
+/// Measure how long loading the model and transcribing an audio file take.
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Audio file to transcribe.
+    pub audio: String,
+    /// Model name, loaded from `models/<name>`.
+    #[arg(short, long)]
+    pub model: String,
+    /// Language code of the speech, detected when not given.
+    #[arg(short, long, value_parser = parse_language)]
+    pub language: Option<Language>,
+    /// Number of timed transcriptions, after an untimed one that warms up the GPU.
+    #[arg(long, default_value_t = 3)]
+    pub runs: usize,
+    #[command(flatten)]
+    pub backend: BackendArgs,
+}
+
+pub fn run(args: BenchArgs) -> token::Result<()> {
+    args.backend.clone().run(args)
+}
+
+impl BackendCommand for BenchArgs {
+    fn run_on<B: Backend>(args: Self, device: B::Device) -> token::Result<()> {
+        bench::<B>(args, device)
+    }
+}
+
+fn bench<B: Backend>(args: BenchArgs, device: B::Device) -> token::Result<()> {
+    let (waveform, sample_rate) =
+        load_audio(&args.audio, None).map_err(|e| format!("Failed to load audio file: {}", e))?;
+    let waveform = resample(&waveform, sample_rate)?;
+    let duration = waveform.len() as f64 / SAMPLE_RATE as f64;
+
+    let start = Instant::now();
+    let (bpe, _whisper_config, whisper) =
+        load_model::<B>(&args.model, &device, args.backend.quantize)?;
+    println!("Model loaded in {:.2?}", start.elapsed());
+
+    // seeded so every run decodes the same way
+    let options = DecodingOptions::new()
+        .with_language(args.language)
+        .with_seed(Some(0));
+    waveform_to_text(&whisper, &bpe, waveform.clone(), SAMPLE_RATE, &options)?;
+
+    let mut seconds = Vec::with_capacity(args.runs);
+    for run in 1..=args.runs {
+        let start = Instant::now();
+        waveform_to_text(&whisper, &bpe, waveform.clone(), SAMPLE_RATE, &options)?;
+        let elapsed = start.elapsed().as_secs_f64();
+        println!(
+            "Run {}: {:.2} s, real time factor {:.3}",
+            run,
+            elapsed,
+            elapsed / duration
+        );
+        seconds.push(elapsed);
+    }
+
+    if !seconds.is_empty() {
+        let mean = seconds.iter().sum::<f64>() / seconds.len() as f64;
+        let min = seconds.iter().cloned().fold(f64::INFINITY, f64::min);
+        println!(
+            "{:.1} s of audio: mean {:.2} s, best {:.2} s, mean real time factor {:.3}",
+            duration,
+            mean,
+            min,
+            mean / duration
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::{collections::BTreeMap, fs, process};
+
+use whisper_stream::token;
+
+/// A phrase and the shell command it runs.
+pub struct VoiceCommand {
+    pub phrase: String,
+    pub command: String,
+    /// Words of the phrase as they are compared, see `normalize`.
+    words: Vec<String>,
+}
+
+/// Table of phrases mapped to shell commands, read from a TOML file of `"phrase" = "command"`
+/// lines. Transcripts are matched against the phrases fuzzily since the transcription of a
+/// phrase varies in punctuation, case and the odd misheard word.
+pub struct CommandTable {
+    commands: Vec<VoiceCommand>,
+    /// Similarity from 0 to 1 a transcript needs to a phrase to run its command.
+    threshold: f64,
+}
+
+impl CommandTable {
+    pub fn load(path: &str, threshold: f64) -> token::Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read voice commands {}: {}", path, e))?;
+        let table: BTreeMap<String, String> = toml::from_str(&text)
+            .map_err(|e| format!("Invalid voice commands {}: {}", path, e))?;
+        let commands = table
+            .into_iter()
+            .map(|(phrase, command)| VoiceCommand {
+                words: normalize(&phrase),
+                phrase,
+                command,
+            })
+            .filter(|command| !command.words.is_empty())
+            .collect();
+        Ok(Self {
+            commands,
+            threshold,
+        })
+    }
+
+    /// Returns the command whose phrase is spoken in `text`, the most similar one if there
+    /// are several, and how similar it is.
+    pub fn find(&self, text: &str) -> Option<(&VoiceCommand, f64)> {
+        let words = normalize(text);
+        self.commands
+            .iter()
+            .map(|command| (command, phrase_similarity(&command.words, &words)))
+            .filter(|&(_, similarity)| similarity >= self.threshold)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
+
+/// Runs the command in the shell without waiting for it.
+pub fn run_command(command: &VoiceCommand) -> token::Result<()> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    process::Command::new(shell)
+        .args([flag, &command.command])
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", command.command, e))?;
+    Ok(())
+}
+
+/// Lowercase words without punctuation.
+fn normalize(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric() || *c == '\'')
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Similarity of the phrase to the best matching run of words of the transcript, one minus
+/// their edit distance in characters relative to the length of the phrase. The run may be a
+/// word shorter or longer than the phrase, e.g. for a word split in two.
+fn phrase_similarity(phrase: &[String], words: &[String]) -> f64 {
+    if words.is_empty() {
+        return 0.0;
+    }
+    let phrase = phrase.join(" ");
+    let phrase_chars: Vec<char> = phrase.chars().collect();
+    let n_words = phrase.split(' ').count();
+
+    let mut best = 0.0f64;
+    for length in n_words.saturating_sub(1).max(1)..=n_words + 1 {
+        for run in words.windows(length.min(words.len())) {
+            let run_chars: Vec<char> = run.join(" ").chars().collect();
+            let distance = edit_distance(&phrase_chars, &run_chars);
+            best = best.max(1.0 - distance as f64 / phrase_chars.len() as f64);
+        }
+    }
+    best
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,277 @@
+use std::{env, fs, path::PathBuf};
+
+use clap::{Args, Command};
+use serde::Deserialize;
+use whisper_stream::{token, transcribe::DecodingOptions};
+
+/// Defaults of the command line options, read from `config.toml` in the `whisper-burn`
+/// directory of the user's configuration directory. Options given on the command line take
+/// precedence.
+///
+/// ```toml
+/// model = "tiny_en"
+/// language = "en"
+/// output_format = "srt"
+/// device = "USB Microphone"
+///
+/// [decoding]
+/// beam_size = 5
+/// temperatures = [0.0, 0.4, 0.8]
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub model: Option<String>,
+    pub language: Option<String>,
+    pub output_format: Option<String>,
+    /// Input device of `stream`.
+    pub device: Option<String>,
+    pub decoding: DecodingArgs,
+}
+
+/// Decoding options of `transcribe` and `stream`, given as flags or in the `[decoding]` table
+/// of the config file, which the flags take precedence over.
+#[derive(Args, Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecodingArgs {
+    /// Number of hypotheses kept by beam search, 1 for greedy decoding.
+    #[arg(long, value_name = "N")]
+    pub beam_size: Option<usize>,
+    /// Beam search ends once beam size times patience hypotheses have ended.
+    #[arg(long)]
+    pub patience: Option<f64>,
+    /// Exponent of the length penalty ranking the hypotheses of beam search, by their mean
+    /// log probability when not given.
+    #[arg(long, value_name = "ALPHA")]
+    pub length_penalty: Option<f64>,
+    /// Number of samples drawn per window at temperatures above zero.
+    #[arg(long, value_name = "N")]
+    pub best_of: Option<usize>,
+    /// Temperatures tried in turn until a decode passes the thresholds, e.g. `0,0.4,0.8`.
+    #[arg(long, value_delimiter = ',', value_name = "T,...")]
+    pub temperatures: Option<Vec<f64>>,
+    /// Only sample from the most likely tokens at temperatures above zero.
+    #[arg(long, value_name = "N")]
+    pub top_k: Option<usize>,
+    /// Only sample from the most likely tokens up to this cumulative probability.
+    #[arg(long, value_name = "P")]
+    pub top_p: Option<f64>,
+    /// Maximum number of tokens decoded per window.
+    #[arg(long, value_name = "N")]
+    pub sample_len: Option<usize>,
+    /// Maximum number of tokens of a segment.
+    #[arg(long, value_name = "N")]
+    pub max_tokens_per_segment: Option<usize>,
+    /// Maximum length of a segment.
+    #[arg(long, value_name = "SECONDS")]
+    pub max_segment_duration: Option<f64>,
+    /// Stop decoding a window after this long, keeping what was decoded.
+    #[arg(long, value_name = "SECONDS")]
+    pub max_decode_time: Option<f64>,
+    /// Decodes compressing better than this ratio are retried at the next temperature.
+    #[arg(long, value_name = "RATIO")]
+    pub compression_ratio_threshold: Option<f64>,
+    /// Decodes with a lower mean log probability are retried at the next temperature.
+    #[arg(long, value_name = "LOGPROB")]
+    pub logprob_threshold: Option<f64>,
+    /// Prompt every window with the text decoded before it.
+    #[arg(long, value_name = "BOOL")]
+    pub condition_on_previous_text: Option<bool>,
+    /// Phrases the decoder should prefer, e.g. names, separated by commas.
+    #[arg(long, value_delimiter = ',', value_name = "PHRASE,...")]
+    pub hotwords: Option<Vec<String>>,
+    /// Logit bias of the tokens of `--hotwords`.
+    #[arg(long, value_name = "BIAS")]
+    pub hotword_bias: Option<f64>,
+    /// Regular expression the text of every window has to match, tokens that can't lead to a
+    /// match are suppressed.
+    #[arg(long, value_name = "REGEX")]
+    pub regex_constraint: Option<String>,
+    /// Token ids that are never sampled, separated by commas.
+    #[arg(long, value_delimiter = ',', value_name = "ID,...")]
+    pub suppress_tokens: Option<Vec<usize>>,
+    /// Smaller model with the same tokenizer proposing tokens for the model to verify, e.g.
+    /// distil-large-v3 for large-v3, which speeds up greedy decoding.
+    #[arg(long, value_name = "MODEL")]
+    pub draft: Option<String>,
+    /// Number of tokens `--draft` proposes at a time.
+    #[arg(long, value_name = "N")]
+    pub draft_tokens: Option<usize>,
+    /// Number of windows decoded together when not conditioning on the previous text.
+    #[arg(long, value_name = "N")]
+    pub batch_size: Option<usize>,
+    /// Skip windows quieter than this root mean square amplitude, off unless set.
+    #[arg(long, value_name = "RMS")]
+    pub silence_rms_threshold: Option<f64>,
+    /// Skip windows the decoder predicts `<|nospeech|>` for with a higher probability, 1 to
+    /// never skip.
+    #[arg(long, value_name = "PROBABILITY")]
+    pub no_speech_skip_threshold: Option<f64>,
+}
+
+impl DecodingArgs {
+    /// The options given as flags, the rest taken from `defaults`.
+    pub fn or(self, defaults: DecodingArgs) -> DecodingArgs {
+        DecodingArgs {
+            beam_size: self.beam_size.or(defaults.beam_size),
+            patience: self.patience.or(defaults.patience),
+            length_penalty: self.length_penalty.or(defaults.length_penalty),
+            best_of: self.best_of.or(defaults.best_of),
+            temperatures: self.temperatures.or(defaults.temperatures),
+            top_k: self.top_k.or(defaults.top_k),
+            top_p: self.top_p.or(defaults.top_p),
+            sample_len: self.sample_len.or(defaults.sample_len),
+            max_tokens_per_segment: self
+                .max_tokens_per_segment
+                .or(defaults.max_tokens_per_segment),
+            max_segment_duration: self.max_segment_duration.or(defaults.max_segment_duration),
+            max_decode_time: self.max_decode_time.or(defaults.max_decode_time),
+            compression_ratio_threshold: self
+                .compression_ratio_threshold
+                .or(defaults.compression_ratio_threshold),
+            logprob_threshold: self.logprob_threshold.or(defaults.logprob_threshold),
+            condition_on_previous_text: self
+                .condition_on_previous_text
+                .or(defaults.condition_on_previous_text),
+            hotwords: self.hotwords.or(defaults.hotwords),
+            hotword_bias: self.hotword_bias.or(defaults.hotword_bias),
+            regex_constraint: self.regex_constraint.or(defaults.regex_constraint),
+            suppress_tokens: self.suppress_tokens.or(defaults.suppress_tokens),
+            draft: self.draft.or(defaults.draft),
+            draft_tokens: self.draft_tokens.or(defaults.draft_tokens),
+            batch_size: self.batch_size.or(defaults.batch_size),
+            silence_rms_threshold: self.silence_rms_threshold.or(defaults.silence_rms_threshold),
+            no_speech_skip_threshold: self
+                .no_speech_skip_threshold
+                .or(defaults.no_speech_skip_threshold),
+        }
+    }
+
+    /// Sets the options that are given, the rest are left as they are. The `draft` model is
+    /// loaded by the subcommand.
+    pub fn apply(&self, mut options: DecodingOptions) -> DecodingOptions {
+        if let Some(beam_size) = self.beam_size {
+            options = options.with_beam_size(beam_size);
+        }
+        if let Some(patience) = self.patience {
+            options = options.with_patience(patience);
+        }
+        if let Some(length_penalty) = self.length_penalty {
+            options = options.with_length_penalty(Some(length_penalty));
+        }
+        if let Some(best_of) = self.best_of {
+            options = options.with_best_of(best_of);
+        }
+        if let Some(temperatures) = &self.temperatures {
+            options = options.with_temperatures(temperatures.clone());
+        }
+        if let Some(top_k) = self.top_k {
+            options = options.with_top_k(Some(top_k));
+        }
+        if let Some(top_p) = self.top_p {
+            options = options.with_top_p(Some(top_p));
+        }
+        if let Some(sample_len) = self.sample_len {
+            options = options.with_sample_len(sample_len);
+        }
+        if let Some(max_tokens) = self.max_tokens_per_segment {
+            options = options.with_max_tokens_per_segment(Some(max_tokens));
+        }
+        if let Some(max_duration) = self.max_segment_duration {
+            options = options.with_max_segment_duration(Some(max_duration));
+        }
+        if let Some(max_decode_time) = self.max_decode_time {
+            options = options.with_max_decode_time(Some(max_decode_time));
+        }
+        if let Some(threshold) = self.compression_ratio_threshold {
+            options = options.with_compression_ratio_threshold(Some(threshold));
+        }
+        if let Some(threshold) = self.logprob_threshold {
+            options = options.with_logprob_threshold(Some(threshold));
+        }
+        if let Some(condition) = self.condition_on_previous_text {
+            options = options.with_condition_on_previous_text(condition);
+        }
+        if let Some(hotwords) = &self.hotwords {
+            options = options.with_hotwords(hotwords.clone());
+        }
+        if let Some(hotword_bias) = self.hotword_bias {
+            options = options.with_hotword_bias(hotword_bias);
+        }
+        if let Some(regex) = &self.regex_constraint {
+            options = options.with_regex_constraint(Some(regex.clone()));
+        }
+        if let Some(suppress_tokens) = &self.suppress_tokens {
+            options = options.with_suppress_tokens(suppress_tokens.clone());
+        }
+        if let Some(draft_tokens) = self.draft_tokens {
+            options = options.with_draft_tokens(draft_tokens);
+        }
+        if let Some(batch_size) = self.batch_size {
+            options = options.with_batch_size(batch_size);
+        }
+        if let Some(threshold) = self.silence_rms_threshold {
+            options = options.with_silence_rms_threshold(Some(threshold));
+        }
+        if let Some(threshold) = self.no_speech_skip_threshold {
+            options = options.with_no_speech_skip_threshold(Some(threshold));
+        }
+        options
+    }
+}
+
+impl Config {
+    /// Reads the config file, if there is one.
+    pub fn load() -> token::Result<Self> {
+        let Some(path) = config_path().filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let config =
+            toml::from_str(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        log::info!("Loaded defaults from {}", path.display());
+        Ok(config)
+    }
+
+    /// Makes the values of the config file the defaults of the options of every subcommand
+    /// that has them, so they are used unless given on the command line.
+    pub fn set_defaults(&self, mut command: Command) -> Command {
+        let defaults = [
+            ("model", &self.model),
+            ("language", &self.language),
+            ("output_format", &self.output_format),
+            ("devices", &self.device),
+        ];
+
+        let names = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect::<Vec<_>>();
+        for name in names {
+            command = command.mut_subcommand(name, |mut subcommand| {
+                for (id, value) in defaults {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    if subcommand.get_arguments().any(|arg| arg.get_id() == id) {
+                        subcommand = subcommand
+                            .mut_arg(id, |arg| arg.default_value(value.clone()).required(false));
+                    }
+                }
+                subcommand
+            });
+        }
+        command
+    }
+}
+
+/// `$XDG_CONFIG_HOME/whisper-burn/config.toml`, by default in `~/.config`, or in `%APPDATA%` on
+/// Windows.
+fn config_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_dir.join("whisper-burn").join("config.toml"))
+}
@@ -0,0 +1,188 @@
+use burn::{
+    backend::wgpu::Wgpu,
+    config::Config,
+    module::Module,
+    record::{DefaultRecorder, HalfPrecisionSettings, NamedMpkGzFileRecorder, Recorder},
+};
+use clap::{Args, ValueEnum};
+use std::path::Path;
+use whisper_stream::{
+    audio::{
+        silero::{load_silero_vad, SileroVad, SileroVadConfig},
+        speaker::{load_speaker_encoder, SpeakerEncoder, SpeakerEncoderConfig},
+    },
+    model::{
+        checkpoint::Layout,
+        ggml::load_whisper_ggml,
+        kind::ModelKind,
+        load::load_whisper,
+        safetensors::{load_whisper_safetensors, save_whisper_safetensors},
+        Whisper, WhisperConfig,
+    },
+    token,
+};
+
+/// Convert a model dumped by the Python scripts, or a whisper checkpoint in safetensors or of
+/// whisper.cpp, into Burn's format.
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Folder the model was dumped to, also the name of the converted files, a `.safetensors`
+    /// checkpoint of openai/whisper or Hugging Face transformers, or a `.bin` or `.gguf` model
+    /// of whisper.cpp.
+    pub model: String,
+    /// Name of the files converted from a checkpoint, by default the name of the checkpoint.
+    #[arg(long)]
+    pub name: Option<String>,
+    /// The dump is a Silero VAD model instead of a whisper model.
+    #[arg(long)]
+    pub silero_vad: bool,
+    /// The dump is a speaker embedding model for `--diarize` instead of a whisper model.
+    #[arg(long, conflicts_with = "silero_vad")]
+    pub speaker_encoder: bool,
+    /// File format of the converted whisper model.
+    #[arg(long, value_enum, default_value_t = ModelFormat::Mpk)]
+    pub format: ModelFormat,
+}
+
+/// File formats of converted whisper models.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ModelFormat {
+    /// Burn's named MessagePack record, loaded into a model initialized from the config.
+    Mpk,
+    /// Burn's named MessagePack record in f16, compressed with gzip. Less than half the size of
+    /// `mpk`, and the model still runs in the precision given by `--precision`.
+    MpkGz,
+    /// safetensors in the layout of openai/whisper. The file is mapped into memory as the model
+    /// loads and the tensors are built one by one, so loading is faster and takes half the peak
+    /// memory.
+    Safetensors,
+}
+
+pub fn run(args: ConvertArgs) -> token::Result<()> {
+    if args.silero_vad {
+        convert_silero_vad(&args.model)
+    } else if args.speaker_encoder {
+        convert_speaker_encoder(&args.model)
+    } else {
+        let path = Path::new(&args.model);
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let load: CheckpointLoader = match extension {
+            Some("safetensors") => load_whisper_safetensors::<Wgpu>,
+            Some("bin") | Some("gguf") => load_whisper_ggml::<Wgpu>,
+            _ => return convert_whisper(&args.model, args.format),
+        };
+        let name = match &args.name {
+            Some(name) => name.clone(),
+            None => path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| format!("No file name in {}", args.model))?
+                .to_string(),
+        };
+        convert_whisper_checkpoint(&args.model, &name, load, args.format)
+    }
+}
+
+fn convert_whisper(model_name: &str, format: ModelFormat) -> token::Result<()> {
+    let (whisper, whisper_config): (Whisper<Wgpu>, WhisperConfig) = load_whisper(model_name)
+        .map_err(|e| format!("Error loading model {}: {}", model_name, e))?;
+
+    save_whisper(whisper, &whisper_config, model_name, format)
+}
+
+/// Saves the converted model `model_name` in `format`, with its config for Burn's record.
+fn save_whisper(
+    whisper: Whisper<Wgpu>,
+    whisper_config: &WhisperConfig,
+    model_name: &str,
+    format: ModelFormat,
+) -> token::Result<()> {
+    eprintln!("Saving model...");
+    let result = match format {
+        ModelFormat::Mpk => DefaultRecorder::new()
+            .record(whisper.into_record(), model_name.into())
+            .map_err(|e| e.to_string()),
+        ModelFormat::MpkGz => NamedMpkGzFileRecorder::<HalfPrecisionSettings>::new()
+            .record(whisper.into_record(), model_name.into())
+            .map_err(|e| e.to_string()),
+        ModelFormat::Safetensors => {
+            save_whisper_safetensors(&whisper, &format!("{}.safetensors", model_name))
+                .map_err(|e| e.to_string())
+        }
+    };
+    result.map_err(|e| format!("Error saving model {}: {}", model_name, e))?;
+
+    // the config of a safetensors model is told by the shapes of its tensors
+    if format != ModelFormat::Safetensors {
+        eprintln!("Saving config...");
+        whisper_config
+            .save(format!("{}.cfg", model_name))
+            .map_err(|e| format!("Error saving config for {}: {}", model_name, e))?;
+    }
+
+    eprintln!("Finished.");
+    Ok(())
+}
+
+type CheckpointLoader =
+    fn(&str) -> Result<(Whisper<Wgpu>, WhisperConfig, Layout), Box<dyn std::error::Error>>;
+
+fn convert_whisper_checkpoint(
+    checkpoint: &str,
+    model_name: &str,
+    load: CheckpointLoader,
+    format: ModelFormat,
+) -> token::Result<()> {
+    let (whisper, whisper_config, layout) = load(checkpoint)
+        .map_err(|e| format!("Error loading checkpoint {}: {}", checkpoint, e))?;
+    eprintln!("Loaded {} checkpoint {}.", layout_name(layout), checkpoint);
+    if let Some(kind) = ModelKind::from_config(&whisper_config) {
+        eprintln!("It has the architecture of {}.", kind.name());
+    }
+
+    save_whisper(whisper, &whisper_config, model_name, format)
+}
+
+fn layout_name(layout: Layout) -> &'static str {
+    match layout {
+        Layout::OpenAi => "openai/whisper",
+        Layout::HuggingFace => "Hugging Face",
+    }
+}
+
+fn convert_silero_vad(model_name: &str) -> token::Result<()> {
+    let (vad, vad_config): (SileroVad<Wgpu>, SileroVadConfig) = load_silero_vad(model_name)
+        .map_err(|e| format!("Error loading model {}: {}", model_name, e))?;
+
+    eprintln!("Saving model...");
+    DefaultRecorder::new()
+        .record(vad.into_record(), model_name.into())
+        .map_err(|e| format!("Error saving model {}: {}", model_name, e))?;
+
+    eprintln!("Saving config...");
+    vad_config
+        .save(format!("{}.cfg", model_name))
+        .map_err(|e| format!("Error saving config for {}: {}", model_name, e))?;
+
+    eprintln!("Finished.");
+    Ok(())
+}
+
+fn convert_speaker_encoder(model_name: &str) -> token::Result<()> {
+    let (encoder, encoder_config): (SpeakerEncoder<Wgpu>, SpeakerEncoderConfig) =
+        load_speaker_encoder(model_name)
+            .map_err(|e| format!("Error loading model {}: {}", model_name, e))?;
+
+    eprintln!("Saving model...");
+    DefaultRecorder::new()
+        .record(encoder.into_record(), model_name.into())
+        .map_err(|e| format!("Error saving model {}: {}", model_name, e))?;
+
+    eprintln!("Saving config...");
+    encoder_config
+        .save(format!("{}.cfg", model_name))
+        .map_err(|e| format!("Error saving config for {}: {}", model_name, e))?;
+
+    eprintln!("Finished.");
+    Ok(())
+}
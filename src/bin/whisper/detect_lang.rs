@@ -0,0 +1,53 @@
+use burn::tensor::backend::Backend;
+use clap::Args;
+use whisper_stream::{
+    audio::{is_url, load_audio, load_audio_url, resample, SAMPLE_RATE},
+    token,
+    transcribe::detect_waveform_language,
+};
+
+use crate::backend::{BackendArgs, BackendCommand};
+use crate::models::load_model;
+
+/// Detect the spoken language of an audio file from its first 30 seconds.
+#[derive(Args, Debug)]
+pub struct DetectLangArgs {
+    /// Audio file or http(s) URL.
+    pub audio: String,
+    /// Model name, loaded from `models/<name>`.
+    #[arg(short, long)]
+    pub model: String,
+    /// Number of most likely languages to print.
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+    #[command(flatten)]
+    pub backend: BackendArgs,
+}
+
+pub fn run(args: DetectLangArgs) -> token::Result<()> {
+    args.backend.clone().run(args)
+}
+
+impl BackendCommand for DetectLangArgs {
+    fn run_on<B: Backend>(args: Self, device: B::Device) -> token::Result<()> {
+        detect_lang::<B>(args, device)
+    }
+}
+
+fn detect_lang<B: Backend>(args: DetectLangArgs, device: B::Device) -> token::Result<()> {
+    let (waveform, sample_rate) = if is_url(&args.audio) {
+        load_audio_url(&args.audio, None, 0.0, None)
+            .map_err(|e| format!("Failed to download audio: {}", e))?
+    } else {
+        load_audio(&args.audio, None).map_err(|e| format!("Failed to load audio file: {}", e))?
+    };
+    let waveform = resample(&waveform, sample_rate)?;
+
+    let (bpe, _whisper_config, whisper) =
+        load_model::<B>(&args.model, &device, args.backend.quantize)?;
+    let languages = detect_waveform_language(&whisper, &bpe, &waveform, SAMPLE_RATE)?;
+    for (language, probability) in languages.iter().take(args.top) {
+        println!("{}\t{:.4}", language.as_str(), probability);
+    }
+    Ok(())
+}
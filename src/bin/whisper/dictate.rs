@@ -0,0 +1,66 @@
+use arboard::Clipboard;
+use clap::ValueEnum;
+use enigo::{Enigo, Keyboard, Settings};
+use whisper_stream::token;
+
+/// Where dictated text goes.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DictationTarget {
+    /// Typed into the focused window as keystrokes.
+    Type,
+    /// Copied to the clipboard, replacing the previous utterance.
+    Clipboard,
+}
+
+enum Output {
+    Keyboard(Enigo),
+    Clipboard(Clipboard),
+}
+
+/// Sends finalized text to other applications, turning a stream into dictation.
+pub struct Dictation {
+    output: Output,
+    /// Whether text was typed already, later utterances are separated by a space.
+    typed: bool,
+}
+
+impl Dictation {
+    pub fn new(target: DictationTarget) -> token::Result<Self> {
+        let output = match target {
+            DictationTarget::Type => Output::Keyboard(
+                Enigo::new(&Settings::default())
+                    .map_err(|e| format!("Failed to simulate the keyboard: {}", e))?,
+            ),
+            // the clipboard is kept open since on Linux the text is gone once it is closed
+            DictationTarget::Clipboard => Output::Clipboard(
+                Clipboard::new().map_err(|e| format!("Failed to open the clipboard: {}", e))?,
+            ),
+        };
+        Ok(Self {
+            output,
+            typed: false,
+        })
+    }
+
+    /// Types the text of an utterance or copies it to the clipboard.
+    pub fn insert(&mut self, text: &str) -> token::Result<()> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        match &mut self.output {
+            Output::Keyboard(enigo) => {
+                let separator = if self.typed { " " } else { "" };
+                enigo
+                    .text(&format!("{}{}", separator, text))
+                    .map_err(|e| format!("Failed to type text: {}", e))?;
+                self.typed = true;
+            }
+            Output::Clipboard(clipboard) => clipboard
+                .set_text(text)
+                .map_err(|e| format!("Failed to copy text to the clipboard: {}", e))?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use whisper_stream::{
+    hub::{self, DEFAULT_REPO, MODELS},
+    token,
+};
+
+/// Download a converted model from the Hugging Face Hub.
+#[derive(Args, Debug)]
+pub struct DownloadArgs {
+    /// Model to download: tiny, base, small, medium or large-v3, the English-only tiny_en,
+    /// base_en, small_en or medium_en, or the name of another model folder in `--repo`.
+    pub model: String,
+    /// Hugging Face repository the model is downloaded from.
+    #[arg(long, default_value = DEFAULT_REPO)]
+    pub repo: String,
+    /// Branch, tag or commit of the repository.
+    #[arg(long, default_value = "main")]
+    pub revision: String,
+    /// Directory to download the model to instead of the cache, e.g. `models`.
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<String>,
+}
+
+pub fn run(args: DownloadArgs) -> token::Result<()> {
+    let name = hub::resolve_alias(&args.model);
+    if args.repo == DEFAULT_REPO && !MODELS.contains(&name) {
+        eprintln!("{} is not one of the models of {}: {}", name, DEFAULT_REPO, MODELS.join(", "));
+    }
+    let dir = match &args.dir {
+        Some(dir) => PathBuf::from(dir),
+        None => hub::cache_dir()
+            .ok_or("No cache directory, pass --dir")?
+            .join("models"),
+    };
+
+    let model_dir = hub::download_model(&args.repo, &args.revision, name, &dir, |file| {
+        eprintln!("Downloading {} ({:.1} MB)...", file.path, file.size as f64 / 1e6);
+    })?;
+
+    eprintln!("Model saved to {}, use it with --model {}", model_dir.display(), name);
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use rdev::{listen, EventType, Key};
+
+/// Parses the name of a key for `--push-to-talk` and `--toggle-key`, e.g. `F9`, `ScrollLock`,
+/// `RightControl` or a letter.
+pub fn parse_key(name: &str) -> Result<Key, String> {
+    let key = match name.to_lowercase().as_str() {
+        "f1" => Key::F1,
+        "f2" => Key::F2,
+        "f3" => Key::F3,
+        "f4" => Key::F4,
+        "f5" => Key::F5,
+        "f6" => Key::F6,
+        "f7" => Key::F7,
+        "f8" => Key::F8,
+        "f9" => Key::F9,
+        "f10" => Key::F10,
+        "f11" => Key::F11,
+        "f12" => Key::F12,
+        "scrolllock" => Key::ScrollLock,
+        "pause" => Key::Pause,
+        "capslock" => Key::CapsLock,
+        "insert" => Key::Insert,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "space" => Key::Space,
+        "leftcontrol" | "leftctrl" => Key::ControlLeft,
+        "rightcontrol" | "rightctrl" => Key::ControlRight,
+        "leftshift" => Key::ShiftLeft,
+        "rightshift" => Key::ShiftRight,
+        "leftalt" | "alt" => Key::Alt,
+        "rightalt" | "altgr" => Key::AltGr,
+        "leftmeta" | "leftsuper" => Key::MetaLeft,
+        "rightmeta" | "rightsuper" => Key::MetaRight,
+        "a" => Key::KeyA,
+        "b" => Key::KeyB,
+        "c" => Key::KeyC,
+        "d" => Key::KeyD,
+        "e" => Key::KeyE,
+        "f" => Key::KeyF,
+        "g" => Key::KeyG,
+        "h" => Key::KeyH,
+        "i" => Key::KeyI,
+        "j" => Key::KeyJ,
+        "k" => Key::KeyK,
+        "l" => Key::KeyL,
+        "m" => Key::KeyM,
+        "n" => Key::KeyN,
+        "o" => Key::KeyO,
+        "p" => Key::KeyP,
+        "q" => Key::KeyQ,
+        "r" => Key::KeyR,
+        "s" => Key::KeyS,
+        "t" => Key::KeyT,
+        "u" => Key::KeyU,
+        "v" => Key::KeyV,
+        "w" => Key::KeyW,
+        "x" => Key::KeyX,
+        "y" => Key::KeyY,
+        "z" => Key::KeyZ,
+        _ => return Err(format!("Unknown key {}", name)),
+    };
+    Ok(key)
+}
+
+/// Listens for the hotkeys anywhere on the desktop and returns whether audio should be
+/// captured: while `push_to_talk` is held down, or until `toggle` is pressed again. Capture
+/// starts paused with a push-to-talk key and running otherwise.
+pub fn spawn_hotkey_listener(push_to_talk: Option<Key>, toggle: Option<Key>) -> Arc<AtomicBool> {
+    let capturing = Arc::new(AtomicBool::new(push_to_talk.is_none()));
+
+    let state = capturing.clone();
+    thread::spawn(move || {
+        let result = listen(move |event| {
+            let value = match event.event_type {
+                EventType::KeyPress(key) if Some(key) == push_to_talk => true,
+                EventType::KeyRelease(key) if Some(key) == push_to_talk => false,
+                // held keys repeat their presses, so toggle on the release
+                EventType::KeyRelease(key) if Some(key) == toggle => {
+                    !state.load(Ordering::Relaxed)
+                }
+                _ => return,
+            };
+            if state.swap(value, Ordering::Relaxed) != value {
+                eprintln!("{}", if value { "Listening" } else { "Paused" });
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to listen for hotkeys: {:?}", e);
+        }
+    });
+
+    capturing
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use whisper_stream::{
+    audio::{is_audio_file, is_url},
+    token,
+};
+
+/// An audio file to transcribe.
+#[derive(Debug, Clone)]
+pub struct Input {
+    /// Path or URL of the audio, `-` for stdin.
+    pub source: String,
+    /// Path of the output relative to the output directory, before its extension is set.
+    /// Mirrors the layout of the directory the input was found in.
+    pub relative: PathBuf,
+}
+
+impl Input {
+    /// Where the output of the input is written: under `output_dir` if given, otherwise next to
+    /// a local file or in the current directory.
+    pub fn output_path(&self, output_dir: Option<&Path>, extension: &str) -> PathBuf {
+        match output_dir {
+            Some(dir) => dir.join(&self.relative).with_extension(extension),
+            None if self.is_local() => Path::new(&self.source).with_extension(extension),
+            None => self.relative.with_extension(extension),
+        }
+    }
+
+    fn is_local(&self) -> bool {
+        self.source != "-" && !is_url(&self.source)
+    }
+}
+
+/// Expands the audio arguments into the files to transcribe: files, URLs and `-` as they are,
+/// the audio files of directories, including subdirectories if `recursive`, and the audio
+/// files matching glob patterns.
+pub fn collect_inputs(arguments: &[String], recursive: bool) -> token::Result<Vec<Input>> {
+    let mut inputs = Vec::new();
+
+    for argument in arguments {
+        if argument == "-" {
+            inputs.push(Input {
+                source: argument.clone(),
+                relative: PathBuf::from("stdin"),
+            });
+        } else if is_url(argument) {
+            let file_name = argument
+                .split(['?', '#'])
+                .next()
+                .and_then(|path| path.rsplit('/').next())
+                .filter(|name| !name.is_empty())
+                .unwrap_or("download");
+            inputs.push(Input {
+                source: argument.clone(),
+                relative: PathBuf::from(file_name),
+            });
+        } else if Path::new(argument).is_dir() {
+            let dir = Path::new(argument);
+            for path in audio_files_in(dir, recursive)? {
+                inputs.push(local_input(&path, dir));
+            }
+        } else if Path::new(argument).is_file() {
+            let file_name = Path::new(argument).file_name().map(PathBuf::from).unwrap_or_default();
+            inputs.push(Input {
+                source: argument.clone(),
+                relative: file_name,
+            });
+        } else if argument.contains(['*', '?', '[']) {
+            let base = glob_base(argument);
+            let mut paths = Vec::new();
+            for entry in glob::glob(argument)? {
+                let path = entry?;
+                if path.is_file() && is_audio_file(&path) {
+                    paths.push(path);
+                }
+            }
+            if paths.is_empty() {
+                return Err(format!("No audio files match {}", argument).into());
+            }
+            inputs.extend(paths.iter().map(|path| local_input(path, &base)));
+        } else {
+            return Err(format!("No such file: {}", argument).into());
+        }
+    }
+
+    Ok(inputs)
+}
+
+pub fn local_input(path: &Path, base: &Path) -> Input {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    Input {
+        source: path.to_string_lossy().into_owned(),
+        relative: relative.to_path_buf(),
+    }
+}
+
+/// The audio files in `dir` sorted by path.
+fn audio_files_in(dir: &Path, recursive: bool) -> token::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                files.extend(audio_files_in(&path, recursive)?);
+            }
+        } else if is_audio_file(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The directory a glob pattern matches in, the components in front of the first wildcard.
+fn glob_base(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| match component {
+            Component::Normal(name) => !name.to_string_lossy().contains(['*', '?', '[']),
+            _ => true,
+        })
+        .collect()
+}
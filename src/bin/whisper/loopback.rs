@@ -0,0 +1,84 @@
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host, SupportedStreamConfig};
+use whisper_stream::token;
+
+/// Finds the device recording what the system plays to the output device `name`, where a
+/// part of the name is enough, or to the default output if `None`, and the config to record
+/// it with.
+#[cfg(target_os = "windows")]
+pub fn find_loopback_device(
+    host: &Host,
+    name: Option<&str>,
+) -> token::Result<(Device, SupportedStreamConfig)> {
+    // an input stream built on an output device records it in loopback mode
+    let device = match name {
+        Some(name) => host
+            .output_devices()
+            .map_err(|e| format!("Failed to list output devices: {}", e))?
+            .find(|device| device.name().map_or(false, |device_name| device_name.contains(name))),
+        None => host.default_output_device(),
+    };
+    let device = device.ok_or_else(|| {
+        format!("Failed to find output device {}", name.unwrap_or("default"))
+    })?;
+    let config = device
+        .default_output_config()
+        .map_err(|e| format!("Failed to get default output config: {}", e))?;
+    Ok((device, config))
+}
+
+/// Finds the device recording the monitor of the PulseAudio or PipeWire sink `name`, or of
+/// the default sink if `None`, and the config to record it with.
+#[cfg(target_os = "linux")]
+pub fn find_loopback_device(
+    host: &Host,
+    name: Option<&str>,
+) -> token::Result<(Device, SupportedStreamConfig)> {
+    let sink = match name {
+        Some(name) => name.to_string(),
+        None => default_sink()?,
+    };
+    let monitor = if sink.ends_with(".monitor") {
+        sink
+    } else {
+        format!("{}.monitor", sink)
+    };
+
+    // the `pulse` ALSA device records from the source in `PULSE_SOURCE`, which works the same
+    // with pipewire-pulse
+    std::env::set_var("PULSE_SOURCE", &monitor);
+    let device = host
+        .input_devices()
+        .map_err(|e| format!("Failed to list input devices: {}", e))?
+        .find(|device| device.name().map_or(false, |device_name| device_name == "pulse"))
+        .ok_or("Loopback capture needs PulseAudio or PipeWire with the ALSA pulse plugin")?;
+    let config = device
+        .default_input_config()
+        .map_err(|e| format!("Failed to record {}: {}", monitor, e))?;
+    Ok((device, config))
+}
+
+/// Name of the sink the system plays to.
+#[cfg(target_os = "linux")]
+fn default_sink() -> token::Result<String> {
+    let output = std::process::Command::new("pactl")
+        .arg("get-default-sink")
+        .output()
+        .map_err(|e| format!("Failed to run pactl to find the default sink: {}", e))?;
+    let sink = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || sink.is_empty() {
+        return Err("Failed to find the default sink with pactl".into());
+    }
+    Ok(sink)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+pub fn find_loopback_device(
+    _host: &Host,
+    _name: Option<&str>,
+) -> token::Result<(Device, SupportedStreamConfig)> {
+    Err("Loopback capture isn't supported on this system, route the output to a virtual input \
+         device such as BlackHole and record it with --device"
+        .into())
+}
@@ -0,0 +1,89 @@
+mod args;
+mod backend;
+mod bench;
+mod commands;
+mod config;
+mod convert;
+mod detect_lang;
+mod dictate;
+mod download;
+mod hotkeys;
+mod inputs;
+mod loopback;
+mod manifest;
+mod metrics;
+mod models;
+mod progress;
+mod quantize;
+mod ring;
+mod serve;
+mod stream;
+mod transcribe;
+mod tui;
+mod verify;
+mod watch;
+
+use std::process;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+
+use config::Config;
+use serve::ServeArgs;
+use stream::StreamArgs;
+use transcribe::TranscribeArgs;
+
+/// Speech recognition with OpenAI's Whisper models, running on the GPU with Burn.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Transcribe(transcribe::TranscribeArgs),
+    Stream(stream::StreamArgs),
+    Serve(serve::ServeArgs),
+    Convert(convert::ConvertArgs),
+    Download(download::DownloadArgs),
+    Quantize(quantize::QuantizeArgs),
+    Verify(verify::VerifyArgs),
+    DetectLang(detect_lang::DetectLangArgs),
+    Bench(bench::BenchArgs),
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    let matches = config.set_defaults(Cli::command()).get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let decoding = config.decoding;
+    let result = match cli.command {
+        Command::Transcribe(args) => transcribe::run(TranscribeArgs {
+            decoding: args.decoding.or(decoding),
+            ..args
+        }),
+        Command::Stream(args) => stream::run(StreamArgs {
+            decoding: args.decoding.or(decoding),
+            ..args
+        }),
+        Command::Serve(args) => serve::run(ServeArgs { decoding, ..args }),
+        Command::Convert(args) => convert::run(args),
+        Command::Download(args) => download::run(args),
+        Command::Quantize(args) => quantize::run(args),
+        Command::Verify(args) => verify::run(args),
+        Command::DetectLang(args) => detect_lang::run(args),
+        Command::Bench(args) => bench::run(args),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use whisper_stream::token;
+
+/// A line of the manifest, written once an input is transcribed.
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    source: String,
+    output: String,
+}
+
+/// Record of the inputs of a batch that are transcribed, one JSON object per line, so an
+/// interrupted batch can be resumed without transcribing them again.
+pub struct Manifest {
+    file: Mutex<File>,
+    done: HashSet<String>,
+}
+
+impl Manifest {
+    /// Opens the manifest at `path` for appending, reading the inputs it lists as done.
+    pub fn open<P: AsRef<Path>>(path: P) -> token::Result<Self> {
+        let path = path.as_ref();
+        let mut done = HashSet::new();
+        if path.exists() {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read manifest {}: {}", path.display(), e))?;
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                // the last line is cut off when the process was killed while writing it
+                match serde_json::from_str::<ManifestEntry>(line) {
+                    Ok(entry) => {
+                        done.insert(entry.source);
+                    }
+                    Err(e) => log::warn!("Ignoring invalid manifest line {:?}: {}", line, e),
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open manifest {}: {}", path.display(), e))?;
+        Ok(Self {
+            file: Mutex::new(file),
+            done,
+        })
+    }
+
+    /// Returns whether `source` was transcribed by an earlier run.
+    pub fn is_done(&self, source: &str) -> bool {
+        self.done.contains(source)
+    }
+
+    /// Records that `source` is transcribed into `output` and syncs the manifest.
+    pub fn record(&self, source: &str, output: &Path) -> io::Result<()> {
+        let entry = ManifestEntry {
+            source: source.to_string(),
+            output: output.to_string_lossy().into_owned(),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+}
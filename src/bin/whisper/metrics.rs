@@ -0,0 +1,99 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+use serde::Serialize;
+use whisper_stream::token;
+
+/// How fast a single utterance was transcribed, a line of the `--metrics` file.
+#[derive(Serialize)]
+pub struct UtteranceMetrics {
+    pub id: usize,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+    /// Seconds of speech transcribed.
+    pub audio: f64,
+    /// Seconds the model took.
+    pub decode: f64,
+    /// Seconds of computation per second of speech.
+    pub rtf: f64,
+    /// Seconds from the end of the utterance being captured to its text.
+    pub latency: f64,
+    /// Seconds of speech waiting for transcription.
+    pub backlog: f64,
+}
+
+/// Latency and speed of the transcription of a stream, written per utterance to a file for
+/// monitoring and summed up when the stream ends.
+pub struct Metrics {
+    file: Option<BufWriter<File>>,
+    /// Metrics of the final utterances, partial results vary with the speech they are cut
+    /// off at and would skew the summary.
+    finals: Vec<UtteranceMetrics>,
+    n_partials: usize,
+}
+
+impl Metrics {
+    /// Starts collecting the metrics, writing them as JSON lines to `path` if given.
+    pub fn create(path: Option<&str>) -> token::Result<Self> {
+        let file = match path {
+            Some(path) => Some(BufWriter::new(
+                File::create(path)
+                    .map_err(|e| format!("Failed to create metrics file {}: {}", path, e))?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            file,
+            finals: Vec::new(),
+            n_partials: 0,
+        })
+    }
+
+    pub fn record(&mut self, metrics: UtteranceMetrics) -> token::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", serde_json::to_string(&metrics)?)
+                .and_then(|_| file.flush())
+                .map_err(|e| format!("Error writing metrics: {}", e))?;
+        }
+        if metrics.is_final {
+            self.finals.push(metrics);
+        } else {
+            self.n_partials += 1;
+        }
+        Ok(())
+    }
+
+    /// Prints the latency and speed over all final utterances to stderr.
+    pub fn print_summary(&self) {
+        if self.finals.is_empty() {
+            return;
+        }
+
+        let mut latencies: Vec<f64> = self.finals.iter().map(|metrics| metrics.latency).collect();
+        latencies.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| {
+            let index = ((latencies.len() - 1) as f64 * p).round() as usize;
+            Duration::from_secs_f64(latencies[index])
+        };
+        let audio: f64 = self.finals.iter().map(|metrics| metrics.audio).sum();
+        let decode: f64 = self.finals.iter().map(|metrics| metrics.decode).sum();
+
+        eprintln!(
+            "{} utterances and {} partial results, {:.1} s of speech in {:.1} s, RTF {:.2}",
+            self.finals.len(),
+            self.n_partials,
+            audio,
+            decode,
+            decode / audio.max(f64::EPSILON)
+        );
+        eprintln!(
+            "Latency: median {:.0?}, 95th percentile {:.0?}, max {:.0?}",
+            percentile(0.5),
+            percentile(0.95),
+            percentile(1.0)
+        );
+    }
+}
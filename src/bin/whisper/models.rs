@@ -0,0 +1,207 @@
+use std::path::PathBuf;
+
+use burn::{
+    config::Config,
+    module::Module,
+    record::{
+        FileRecorder, FullPrecisionSettings, HalfPrecisionSettings, NamedMpkFileRecorder,
+        NamedMpkGzFileRecorder, Recorder, RecorderError,
+    },
+    tensor::backend::Backend,
+};
+use whisper_stream::{
+    audio::{
+        silero::{SileroVad, SileroVadConfig},
+        speaker::{SpeakerEncoder, SpeakerEncoderConfig},
+    },
+    hub,
+    model::{
+        kind::ModelKind,
+        quantize::{Quantization, QuantizedPrecisionSettings},
+        safetensors::load_whisper_safetensors,
+        Whisper, WhisperConfig,
+    },
+    token::{self, Gpt2Tokenizer, SpecialToken},
+};
+
+/// Folder of the model `model_name`, `models/<model name>` or else the model downloaded to the
+/// cache by `download`.
+pub fn model_dir(model_name: &str) -> PathBuf {
+    let local = PathBuf::from("models").join(model_name);
+    if local.exists() {
+        return local;
+    }
+    match hub::cache_dir().map(|dir| dir.join("models").join(model_name)) {
+        Some(cached) if cached.exists() => cached,
+        _ => local,
+    }
+}
+
+/// Path of the files of the model `model_name` without their extension.
+fn model_path(model_name: &str) -> String {
+    model_dir(model_name).join(model_name).to_string_lossy().into_owned()
+}
+
+/// Loads the tokenizer, config and weights of the model `model_name`, see `model_dir`. The
+/// config file can be left out for the models of `ModelKind`. A `<model name>.safetensors` file
+/// is preferred over Burn's record, it is mapped into memory and needs no config. The weights
+/// are quantized with `quantization` if given.
+pub fn load_model<B: Backend>(
+    model_name: &str,
+    device: &B::Device,
+    quantization: Option<Quantization>,
+) -> token::Result<(Gpt2Tokenizer, WhisperConfig, Whisper<B>)> {
+    let kind = ModelKind::from_name(model_name);
+    let tokenizer_path = model_dir(model_name).join("tokenizer.json");
+    let bpe = Gpt2Tokenizer::from_file(&tokenizer_path.to_string_lossy()).map_err(|e| {
+        match kind {
+            Some(kind) => format!(
+                "Failed to load tokenizer: {}, it can be downloaded from \
+                 https://huggingface.co/{}/resolve/main/tokenizer.json",
+                e,
+                kind.hugging_face_repo()
+            ),
+            None => format!("Failed to load tokenizer: {}", e),
+        }
+    })?;
+
+    eprintln!("Loading model...");
+    let safetensors_path = format!("{}.safetensors", model_path(model_name));
+    let (whisper_config, whisper) = if PathBuf::from(&safetensors_path).exists() {
+        // built tensor by tensor from the mapped file, the config is told by their shapes
+        let (whisper, whisper_config, _layout) = load_whisper_safetensors::<B>(&safetensors_path)
+            .map_err(|e| format!("Failed to load whisper model file: {}", e))?;
+        (whisper_config, whisper)
+    } else {
+        load_whisper_record(model_name, kind, device)?
+    };
+    let whisper = match quantization {
+        Some(quantization) => whisper.quantize(quantization),
+        None => whisper,
+    };
+
+    // the special tokens of large-v3 and large-v3-turbo are shifted by the added <|yue|>
+    let last_timestamp = bpe.special_token(SpecialToken::Timestamp(30.0));
+    if last_timestamp.map_or(false, |token| token + 1 != whisper.decoder_vocab_size()) {
+        log::warn!(
+            "The tokenizer of {} doesn't match the vocabulary of the model, transcripts will be \
+             garbled",
+            model_name
+        );
+    }
+
+    Ok((bpe, whisper_config, whisper.to_device(device)))
+}
+
+/// Loads the weights of the model `model_name` saved with Burn's recorder into a model
+/// initialized from its config. A compressed `.mpk.gz` record holds the weights in f16.
+fn load_whisper_record<B: Backend>(
+    model_name: &str,
+    kind: Option<ModelKind>,
+    device: &B::Device,
+) -> token::Result<(WhisperConfig, Whisper<B>)> {
+    let config_path = format!("{}.cfg", model_path(model_name));
+    let (whisper_config, config_source) = match kind {
+        Some(kind) if !PathBuf::from(&config_path).exists() => {
+            (kind.config(), format!("the architecture of {}", kind.name()))
+        }
+        _ => (
+            WhisperConfig::load(&config_path)
+                .map_err(|e| format!("Failed to load whisper config: {}", e))?,
+            config_path,
+        ),
+    };
+    if let Some(kind) = kind {
+        let found = ModelKind::from_config(&whisper_config);
+        if found != ModelKind::from_config(&kind.config()) {
+            log::warn!(
+                "{} is named like {} but {} describes {}",
+                model_name,
+                kind.name(),
+                config_source,
+                found.map_or("another model", |found| found.name())
+            );
+        }
+    }
+
+    let path = PathBuf::from(model_path(model_name));
+    let compressed = path.with_extension("mpk.gz").exists();
+    let record_path = format!("{}.{}", path.display(), if compressed { "mpk.gz" } else { "mpk" });
+    let whisper: Whisper<B> = whisper_config.init(device);
+    let expected = whisper.tensor_shapes();
+    // quantized models are saved with their own precision settings by `quantize`
+    let whisper = match (whisper_config.quantization(), compressed) {
+        (Some(_), false) => {
+            let recorder = NamedMpkFileRecorder::<QuantizedPrecisionSettings>::new();
+            load_record(recorder, whisper, path, device)
+        }
+        (Some(_), true) => {
+            let recorder = NamedMpkGzFileRecorder::<QuantizedPrecisionSettings>::new();
+            load_record(recorder, whisper, path, device)
+        }
+        (None, false) => {
+            let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+            load_record(recorder, whisper, path, device)
+        }
+        (None, true) => {
+            let recorder = NamedMpkGzFileRecorder::<HalfPrecisionSettings>::new();
+            load_record(recorder, whisper, path, device)
+        }
+    }
+    .map_err(|e| {
+        format!(
+            "Failed to load whisper model file {}: {}. Check that the file is complete, \
+             downloaded models with the `verify {}` subcommand",
+            record_path, e, model_name
+        )
+    })?;
+    whisper.check_tensors(&expected).map_err(|e| {
+        format!(
+            "The weights in {} don't match {}: {}. Check that both files belong to the same \
+             model",
+            record_path, config_source, e
+        )
+    })?;
+
+    Ok((whisper_config, whisper))
+}
+
+fn load_record<B: Backend, R: FileRecorder<B>>(
+    recorder: R,
+    whisper: Whisper<B>,
+    path: PathBuf,
+    device: &B::Device,
+) -> Result<Whisper<B>, RecorderError> {
+    let record = recorder.load(path, device)?;
+    Ok(whisper.load_record(record))
+}
+
+/// Loads the Silero VAD model `model_name`, see `model_dir`.
+pub fn load_silero_vad_model<B: Backend>(
+    model_name: &str,
+    device: &B::Device,
+) -> token::Result<SileroVad<B>> {
+    let config = SileroVadConfig::load(format!("{}.cfg", model_path(model_name)))
+        .map_err(|e| format!("Failed to load silero vad config: {}", e))?;
+
+    let record = NamedMpkFileRecorder::<FullPrecisionSettings>::new()
+        .load(model_path(model_name).into(), device)
+        .map_err(|e| format!("Failed to load silero vad model file: {}", e))?;
+
+    Ok(config.init(device).load_record(record))
+}
+
+/// Loads the speaker embedding model `model_name`, see `model_dir`.
+pub fn load_speaker_encoder_model<B: Backend>(
+    model_name: &str,
+    device: &B::Device,
+) -> token::Result<SpeakerEncoder<B>> {
+    let config = SpeakerEncoderConfig::load(format!("{}.cfg", model_path(model_name)))
+        .map_err(|e| format!("Failed to load speaker encoder config: {}", e))?;
+
+    let record = NamedMpkFileRecorder::<FullPrecisionSettings>::new()
+        .load(model_path(model_name).into(), device)
+        .map_err(|e| format!("Failed to load speaker encoder model file: {}", e))?;
+
+    Ok(config.init(device).load_record(record))
+}
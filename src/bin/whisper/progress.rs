@@ -0,0 +1,73 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    time::Instant,
+};
+
+use whisper_stream::transcribe::Progress;
+
+const BAR_WIDTH: usize = 30;
+
+/// Progress bar of a transcription drawn on a single line of stderr, with the real-time factor
+/// and the estimated time left. Nothing is drawn when stderr isn't a terminal.
+pub struct ProgressBar {
+    start: Instant,
+    visible: bool,
+    finished: bool,
+}
+
+impl ProgressBar {
+    /// Starts timing the transcription.
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            visible: io::stderr().is_terminal(),
+            finished: false,
+        }
+    }
+
+    pub fn update(&mut self, progress: Progress) {
+        if !self.visible || self.finished || progress.total <= 0.0 {
+            return;
+        }
+
+        let fraction = (progress.processed / progress.total).clamp(0.0, 1.0);
+        let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+        let elapsed = self.start.elapsed().as_secs_f64();
+        // seconds of computation per second of audio
+        let rtf = elapsed / progress.processed.max(f64::EPSILON);
+        let eta = if progress.processed > 0.0 {
+            format_duration((progress.total - progress.processed) * rtf)
+        } else {
+            "?".to_string()
+        };
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:3.0}% {}/{} RTF {:.2} ETA {} ",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            fraction * 100.0,
+            format_duration(progress.processed),
+            format_duration(progress.total),
+            rtf,
+            eta,
+        );
+        if fraction >= 1.0 {
+            self.finished = true;
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+}
+
+/// Formats seconds as `m:ss`, or `h:mm:ss` from an hour on.
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fs, path::Path};
+
+use burn::{
+    backend::wgpu::{Wgpu, WgpuDevice},
+    config::Config,
+    module::Module,
+    record::{NamedMpkFileRecorder, Recorder},
+};
+use clap::Args;
+use whisper_stream::{
+    model::quantize::{Quantization, QuantizedPrecisionSettings},
+    token,
+};
+
+use crate::args::parse_quantization;
+use crate::models::{load_model, model_dir};
+
+/// Quantize the weights of a model and save it as a new model, e.g. a 4-bit large-v3 that fits
+/// on laptops.
+#[derive(Args, Debug)]
+pub struct QuantizeArgs {
+    /// Model name, loaded from `models/<name>`.
+    pub model: String,
+    /// Quantization of the weights of the attention and MLP layers.
+    #[arg(long, value_parser = parse_quantization, default_value = "int4")]
+    pub quantization: Quantization,
+    /// Name of the quantized model, saved to `models/<name>`. By default the model name followed
+    /// by the quantization, e.g. `large-v3-int4`.
+    #[arg(long)]
+    pub name: Option<String>,
+}
+
+pub fn run(args: QuantizeArgs) -> token::Result<()> {
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None => format!("{}-{}", args.model, args.quantization.as_str()),
+    };
+
+    let device = WgpuDevice::default();
+    let (_bpe, whisper_config, whisper) = load_model::<Wgpu>(&args.model, &device, None)?;
+    if let Some(quantization) = whisper_config.quantization() {
+        return Err(format!(
+            "Model {} is already quantized to {}",
+            args.model,
+            quantization.as_str()
+        )
+        .into());
+    }
+
+    eprintln!("Quantizing model...");
+    let whisper = whisper.quantize(args.quantization);
+    let whisper_config = whisper_config.with_quantization(Some(args.quantization));
+
+    let dir = Path::new("models").join(&name);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let path = dir.join(&name);
+
+    eprintln!("Saving model...");
+    NamedMpkFileRecorder::<QuantizedPrecisionSettings>::new()
+        .record(whisper.into_record(), path.clone())
+        .map_err(|e| format!("Error saving model {}: {}", name, e))?;
+
+    eprintln!("Saving config...");
+    whisper_config
+        .save(format!("{}.cfg", path.display()))
+        .map_err(|e| format!("Error saving config for {}: {}", name, e))?;
+
+    let tokenizer = model_dir(&args.model).join("tokenizer.json");
+    fs::copy(&tokenizer, dir.join("tokenizer.json"))
+        .map_err(|e| format!("Failed to copy {}: {}", tokenizer.display(), e))?;
+
+    eprintln!("Saved the quantized model to {}.", dir.display());
+    Ok(())
+}
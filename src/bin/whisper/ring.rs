@@ -0,0 +1,160 @@
+use std::{
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use rtrb::{Consumer, Producer, RingBuffer};
+use whisper_stream::audio::SAMPLE_RATE;
+
+/// Wall clock time the sample with the index `sample` was captured at.
+#[derive(Clone, Copy)]
+struct Mark {
+    sample: u64,
+    time: SystemTime,
+}
+
+/// Creates a bounded lock-free buffer of 16 kHz samples from one thread to another that
+/// carries the wall clock time the samples were captured at, so utterances can be stamped with
+/// the time they were spoken even when the transcription runs behind.
+pub fn timed_ring_buffer(capacity: usize) -> (TimedProducer, TimedConsumer) {
+    let (samples, sample_consumer) = RingBuffer::new(capacity);
+    // a mark per block of samples, blocks are rarely shorter than 10 ms
+    let (marks, mark_consumer) = RingBuffer::new(capacity / 160 + 1);
+    let producer = TimedProducer {
+        samples,
+        marks,
+        n_pushed: 0,
+    };
+    let consumer = TimedConsumer {
+        samples: sample_consumer,
+        marks: mark_consumer,
+        mark: None,
+        n_popped: 0,
+    };
+    (producer, consumer)
+}
+
+pub struct TimedProducer {
+    samples: Producer<i16>,
+    marks: Producer<Mark>,
+    n_pushed: u64,
+}
+
+impl TimedProducer {
+    /// Number of samples that fit into the buffer.
+    pub fn slots(&self) -> usize {
+        self.samples.slots()
+    }
+
+    /// Pushes as many of the samples as fit, the first of them captured at `time`, and returns
+    /// how many did.
+    pub fn push(&mut self, samples: &[i16], time: SystemTime) -> usize {
+        let n_samples = samples.len().min(self.samples.slots());
+        if n_samples == 0 {
+            return 0;
+        }
+
+        // without room for the mark the time of the block follows from an earlier one
+        let _ = self.marks.push(Mark {
+            sample: self.n_pushed,
+            time,
+        });
+        for &sample in &samples[..n_samples] {
+            let _ = self.samples.push(sample);
+        }
+        self.n_pushed += n_samples as u64;
+        n_samples
+    }
+
+    /// Pushes all of the samples, waiting for room in the buffer, for sources that may block.
+    pub fn push_waiting(&mut self, mut samples: &[i16], mut time: SystemTime) {
+        loop {
+            let n_pushed = self.push(samples, time);
+            samples = &samples[n_pushed..];
+            if samples.is_empty() {
+                return;
+            }
+            time += samples_duration(n_pushed);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+pub struct TimedConsumer {
+    samples: Consumer<i16>,
+    marks: Consumer<Mark>,
+    /// Latest mark at or before the next sample.
+    mark: Option<Mark>,
+    n_popped: u64,
+}
+
+impl TimedConsumer {
+    /// Number of samples waiting in the buffer.
+    pub fn slots(&self) -> usize {
+        self.samples.slots()
+    }
+
+    /// Whether the producer is gone, the samples left can still be popped.
+    pub fn is_abandoned(&self) -> bool {
+        self.samples.is_abandoned()
+    }
+
+    pub fn pop(&mut self) -> Option<i16> {
+        let sample = self.samples.pop().ok()?;
+        self.n_popped += 1;
+        Some(sample)
+    }
+
+    /// Wall clock time the next sample was captured at, if any sample was pushed yet.
+    pub fn time(&mut self) -> Option<SystemTime> {
+        while let Ok(next) = self.marks.peek() {
+            if next.sample > self.n_popped {
+                break;
+            }
+            self.mark = Some(*next);
+            let _ = self.marks.pop();
+        }
+        self.mark
+            .map(|mark| mark.time + samples_duration((self.n_popped - mark.sample) as usize))
+    }
+}
+
+/// Duration of `n_samples` at 16 kHz.
+pub fn samples_duration(n_samples: usize) -> Duration {
+    Duration::from_secs_f64(n_samples as f64 / SAMPLE_RATE as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_follows_the_marks() {
+        let (mut producer, mut consumer) = timed_ring_buffer(1600);
+        assert_eq!(consumer.time(), None);
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        // the second block was captured after a gap
+        let later = start + Duration::from_secs(1);
+        assert_eq!(producer.push(&[0; 160], start), 160);
+        assert_eq!(producer.push(&[0; 160], later), 160);
+        assert_eq!(consumer.time(), Some(start));
+
+        for _ in 0..80 {
+            consumer.pop();
+        }
+        assert_eq!(consumer.time(), Some(start + samples_duration(80)));
+        for _ in 0..80 {
+            consumer.pop();
+        }
+        assert_eq!(consumer.time(), Some(later));
+    }
+
+    #[test]
+    fn push_stops_when_full() {
+        let (mut producer, mut consumer) = timed_ring_buffer(100);
+        assert_eq!(producer.push(&[1; 160], SystemTime::UNIX_EPOCH), 100);
+        assert_eq!(producer.push(&[1; 10], SystemTime::UNIX_EPOCH), 0);
+        assert_eq!(consumer.slots(), 100);
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use burn::tensor::backend::Backend;
+use clap::Args;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+use whisper_stream::{
+    audio::{load_audio_bytes, resample, silero::SileroVad, vad::VadConfig, SAMPLE_RATE},
+    model::Whisper,
+    output::{render, OutputFormat, OutputOptions},
+    token::{self, Gpt2Tokenizer, Task},
+    transcribe::{waveform_to_text_with_models, DecodingOptions, ExternalModels},
+};
+
+use crate::args::{parse_language, parse_output_format};
+use crate::backend::{BackendArgs, BackendCommand};
+use crate::config::DecodingArgs;
+use crate::models::{load_model, load_silero_vad_model};
+
+/// Uploads larger than this are rejected, about an hour of compressed audio.
+const MAX_UPLOAD_SIZE: usize = 200 * 1024 * 1024;
+
+/// Serve transcriptions over HTTP with an API compatible with OpenAI's audio endpoints.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Model name, loaded from `models/<name>`.
+    #[arg(short, long)]
+    pub model: String,
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+    /// Silero VAD model used to only transcribe the speech in the uploads.
+    #[arg(long, value_name = "MODEL")]
+    pub silero_vad: Option<String>,
+    #[command(flatten)]
+    pub backend: BackendArgs,
+    /// Defaults of the decoding options from the config file.
+    #[arg(skip)]
+    pub decoding: DecodingArgs,
+}
+
+struct Models<B: Backend> {
+    name: String,
+    whisper: Whisper<B>,
+    bpe: Gpt2Tokenizer,
+    silero_vad: Option<SileroVad<B>>,
+    decoding: DecodingArgs,
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+/// Status code and message of a failed request.
+struct HttpError(u16, String);
+
+/// How the transcription is sent back, the `response_format` of the OpenAI API extended with
+/// the output formats of the `transcribe` command.
+enum ResponseFormat {
+    /// `{"text": ...}`
+    Json,
+    Output(OutputFormat),
+}
+
+pub fn run(args: ServeArgs) -> token::Result<()> {
+    args.backend.clone().run(args)
+}
+
+impl BackendCommand for ServeArgs {
+    fn run_on<B: Backend>(args: Self, device: B::Device) -> token::Result<()> {
+        serve::<B>(args, device)
+    }
+}
+
+fn serve<B: Backend>(args: ServeArgs, device: B::Device) -> token::Result<()> {
+    let (bpe, _whisper_config, whisper) =
+        load_model::<B>(&args.model, &device, args.backend.quantize)?;
+    let silero_vad = match &args.silero_vad {
+        Some(name) => Some(load_silero_vad_model::<B>(name, &device)?),
+        None => None,
+    };
+    let models = Models {
+        name: args.model.clone(),
+        whisper,
+        bpe,
+        silero_vad,
+        decoding: args.decoding.clone(),
+    };
+
+    let address = format!("{}:{}", args.host, args.port);
+    let server = Server::http(&address)
+        .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    eprintln!("Listening on http://{}", address);
+
+    // the model is used by one request at a time, the others wait in the queue of the server
+    for mut request in server.incoming_requests() {
+        let method = request.method().clone();
+        let path = request.url().split('?').next().unwrap_or_default().to_string();
+        let response = match (method, path.as_str()) {
+            (Method::Get, "/health") => Ok(text_response(200, "ok", "text/plain")),
+            (Method::Post, "/v1/audio/transcriptions") => {
+                handle_transcription(&mut request, &models, Task::Transcribe)
+            }
+            (Method::Post, "/v1/audio/translations") => {
+                handle_transcription(&mut request, &models, Task::Translate)
+            }
+            _ => Err(HttpError(404, "Not found".to_string())),
+        };
+        let response = response.unwrap_or_else(|HttpError(status, message)| {
+            let body = json!({ "error": { "message": message } }).to_string();
+            text_response(status, &body, "application/json")
+        });
+
+        if let Err(e) = request.respond(response) {
+            log::warn!("Failed to send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn text_response(status: u16, body: &str, content_type: &str) -> HttpResponse {
+    let header = Header::from_bytes("Content-Type", content_type)
+        .expect("Content type is a valid header");
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header)
+}
+
+/// Transcribes the audio of a request, either a multipart form with a `file` field like the
+/// OpenAI API expects or the audio as the body with the fields in the query string.
+fn handle_transcription<B: Backend>(
+    request: &mut Request,
+    models: &Models<B>,
+    task: Task,
+) -> Result<HttpResponse, HttpError> {
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str().to_string())
+        .unwrap_or_default();
+    let query = request.url().split_once('?').map(|(_, query)| query.to_string());
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_UPLOAD_SIZE as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| HttpError(400, format!("Failed to read request: {}", e)))?;
+    if body.len() > MAX_UPLOAD_SIZE {
+        return Err(HttpError(413, "Upload too large".to_string()));
+    }
+
+    let (fields, audio, file_name) = if content_type.starts_with("multipart/form-data") {
+        let mut parts = parse_multipart(&content_type, &body)
+            .ok_or_else(|| HttpError(400, "Invalid form data".to_string()))?;
+        let file = parts
+            .iter()
+            .position(|part| part.name == "file")
+            .map(|i| parts.remove(i))
+            .ok_or_else(|| HttpError(400, "Missing file".to_string()))?;
+        let fields = parts
+            .into_iter()
+            .map(|part| (part.name, String::from_utf8_lossy(&part.data).into_owned()))
+            .collect::<HashMap<_, _>>();
+        (fields, file.data, file.file_name)
+    } else {
+        (parse_query(query.as_deref().unwrap_or_default()), body, None)
+    };
+
+    let language = match fields.get("language").map(String::as_str) {
+        None | Some("") => None,
+        Some(language) => Some(parse_language(language).map_err(|e| HttpError(400, e))?),
+    };
+    let response_format = match fields.get("response_format").map(String::as_str) {
+        None | Some("json") => ResponseFormat::Json,
+        Some("text") => ResponseFormat::Output(OutputFormat::Txt),
+        Some("verbose_json") => ResponseFormat::Output(OutputFormat::Json),
+        Some(format) => {
+            ResponseFormat::Output(parse_output_format(format).map_err(|e| HttpError(400, e))?)
+        }
+    };
+
+    let extension = file_name
+        .as_deref()
+        .and_then(|name| Path::new(name).extension())
+        .and_then(|extension| extension.to_str());
+    let (waveform, sample_rate) = load_audio_bytes(audio, extension, None)
+        .map_err(|e| HttpError(400, format!("Failed to decode audio: {}", e)))?;
+    let waveform =
+        resample(&waveform, sample_rate).map_err(|e| HttpError(500, e.to_string()))?;
+
+    let word_timestamps = fields
+        .get("timestamp_granularities[]")
+        .map_or(false, |granularity| granularity == "word");
+    let options = models
+        .decoding
+        .apply(DecodingOptions::new())
+        .with_task(task)
+        .with_language(language)
+        .with_vad(models.silero_vad.as_ref().map(|_| VadConfig::new()))
+        .with_word_timestamps(word_timestamps);
+    let external_models = ExternalModels {
+        vad: models.silero_vad.as_ref(),
+        ..Default::default()
+    };
+    let result = waveform_to_text_with_models(
+        &models.whisper,
+        &models.bpe,
+        waveform,
+        SAMPLE_RATE,
+        &options,
+        &external_models,
+    )
+    .map_err(|e| HttpError(500, format!("Error during transcription: {}", e)))?;
+
+    Ok(match response_format {
+        ResponseFormat::Json => {
+            let body = json!({ "text": result.text.trim() }).to_string();
+            text_response(200, &body, "application/json")
+        }
+        ResponseFormat::Output(format) => {
+            let options = OutputOptions::new().with_model(Some(models.name.clone()));
+            let content_type = match format {
+                OutputFormat::Json | OutputFormat::Jsonl => "application/json",
+                _ => "text/plain; charset=utf-8",
+            };
+            text_response(200, &render(&result, format, &options), content_type)
+        }
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (percent_decode(name), percent_decode(value)))
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+struct FormPart {
+    name: String,
+    file_name: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Splits a `multipart/form-data` body into its parts.
+fn parse_multipart(content_type: &str, body: &[u8]) -> Option<Vec<FormPart>> {
+    let boundary = content_type
+        .split(';')
+        .filter_map(|parameter| parameter.trim().strip_prefix("boundary="))
+        .next()?
+        .trim_matches('"');
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut parts = Vec::new();
+    let mut position = find(body, &delimiter, 0)? + delimiter.len();
+    // the last delimiter is followed by `--`
+    while !body[position..].starts_with(b"--") {
+        let next = find(body, &delimiter, position)?;
+        // every part starts after the line break of the delimiter and ends with a line break
+        let part = body[position..next]
+            .strip_prefix(b"\r\n")?
+            .strip_suffix(b"\r\n")?;
+        let header_end = find(part, b"\r\n\r\n", 0)?;
+        let headers = String::from_utf8_lossy(&part[..header_end]);
+
+        let disposition = headers
+            .lines()
+            .find(|line| line.to_lowercase().starts_with("content-disposition:"))?;
+        let parameter = |name: &str| {
+            disposition
+                .split(';')
+                .filter_map(|parameter| parameter.trim().strip_prefix(name))
+                .filter_map(|value| value.strip_prefix('='))
+                .map(|value| value.trim_matches('"').to_string())
+                .next()
+        };
+
+        parts.push(FormPart {
+            name: parameter("name")?,
+            file_name: parameter("filename"),
+            data: part[header_end + 4..].to_vec(),
+        });
+        position = next + delimiter.len();
+    }
+
+    Some(parts)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
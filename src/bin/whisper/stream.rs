@@ -0,0 +1,1333 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    iter, process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use burn::tensor::backend::Backend;
+use clap::{Args, ValueEnum};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use hound::{self, SampleFormat};
+use rdev::Key;
+use serde::Serialize;
+use webrtc_vad::{Vad, VadMode};
+use whisper_stream::{
+    audio::{
+        agc::{AgcConfig, AutomaticGainControl},
+        diagnostics::check_levels,
+        downmix,
+        filter::HighPassFilter,
+        loudness::{normalize_loudness, TARGET_LOUDNESS},
+        network::NetworkSource,
+        load_audio, resample,
+        silero::{SileroVad, CHUNK_SIZE},
+        wakeword::{WakeWordConfig, WakeWordDetector},
+        Resampler, SAMPLE_RATE,
+    },
+    diarization::{DiarizationConfig, SpeakerChangeDetector, SpeakerTracker},
+    model::Whisper,
+    output::{format_wall_clock, writer::SegmentWriter, OutputOptions},
+    streaming::{self, LocalAgreement, RollingContext, Segmenter, StreamConfig},
+    timestamps::WordTiming,
+    token::{self, Gpt2Tokenizer, Language, Task},
+    transcribe::{waveform_to_text_with_models, DecodingOptions, ExternalModels, Segment},
+};
+
+use crate::args::{output_format_of, parse_language, parse_task};
+use crate::backend::{BackendArgs, BackendCommand};
+use crate::commands::{run_command, CommandTable};
+use crate::config::DecodingArgs;
+use crate::dictate::{Dictation, DictationTarget};
+use crate::hotkeys::{parse_key, spawn_hotkey_listener};
+use crate::loopback::find_loopback_device;
+use crate::metrics::{Metrics, UtteranceMetrics};
+use crate::models::{load_model, load_silero_vad_model, load_speaker_encoder_model};
+use crate::ring::{samples_duration, timed_ring_buffer, TimedConsumer, TimedProducer};
+use crate::tui::{level_db, new_level_meter, spawn_caption_view, Captions, LevelMeter};
+
+const SILERO_THRESHOLD: f32 = 0.5;
+/// Samples the ring buffers between the capture and the voice activity detection hold, 4 s,
+/// enough to ride out the detection stalling for a moment, e.g. while Silero waits for the
+/// GPU.
+const RING_BUFFER_SIZE: usize = SAMPLE_RATE * 4;
+/// Line marking a change of speaker with `--speaker-changes`.
+const SPEAKER_CHANGE: &str = "--- speaker change ---";
+/// Longest silence between utterances that `--backlog-policy widen` joins, in samples, 1 s.
+const MAX_WIDEN_GAP: usize = SAMPLE_RATE;
+/// Length of the audio window of the model, `widen` joins utterances up to it.
+const MAX_WIDENED_SAMPLES: usize = SAMPLE_RATE * 30;
+
+/// What happens to the speech waiting for transcription once there is more of it than
+/// `--max-backlog`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum BacklogPolicy {
+    /// Transcribe every utterance however late.
+    Queue,
+    /// Drop the oldest utterances and mark the gap in the transcript.
+    DropOldest,
+    /// Join the waiting utterances so the model runs less often on more audio at once.
+    Widen,
+}
+
+/// Live transcription or translation of the microphone or a network stream.
+#[derive(Args, Debug)]
+pub struct StreamArgs {
+    /// Model name, loaded from `models/<name>`.
+    #[arg(short, long, required_unless_present = "list_devices")]
+    pub model: Option<String>,
+    /// Language code of the speech, detected for every utterance when not given.
+    #[arg(short, long, value_parser = parse_language)]
+    pub language: Option<Language>,
+    #[arg(long, default_value = "transcribe", value_parser = parse_task)]
+    pub task: Task,
+    /// With `--task translate`, also transcribe every finished utterance in the language it
+    /// was spoken in and print it next to the translation.
+    #[arg(long)]
+    pub with_original: bool,
+    /// Capture a single channel instead of the mix of all channels.
+    #[arg(long)]
+    pub channel: Option<usize>,
+    /// Silero VAD model used instead of WebRTC VAD to detect speech.
+    #[arg(long, value_name = "MODEL")]
+    pub silero_vad: Option<String>,
+    /// Speaker embedding model used to label every finished utterance with its speaker.
+    #[arg(long, value_name = "MODEL")]
+    pub diarize: Option<String>,
+    /// Speaker embedding model used to mark where the speaker changes from one finished
+    /// utterance to the next, without telling the speakers apart.
+    #[arg(long, value_name = "MODEL", conflicts_with = "diarize")]
+    pub speaker_changes: Option<String>,
+    /// Most speakers `--diarize` tells apart, later voices are taken for the closest of them.
+    #[arg(long, value_name = "N", requires = "diarize")]
+    pub speakers: Option<usize>,
+    /// Cosine distance between two voices up to which `--diarize` and `--speaker-changes` take
+    /// them for the same speaker.
+    #[arg(long, default_value_t = 0.6, value_name = "DISTANCE")]
+    pub speaker_threshold: f64,
+    /// Normalize the loudness of every utterance.
+    #[arg(long)]
+    pub normalize: bool,
+    /// Cutoff in Hz of a high pass filter applied to the captured audio.
+    #[arg(long, value_name = "HZ")]
+    pub high_pass: Option<f64>,
+    /// Apply automatic gain control to the captured audio.
+    #[arg(long)]
+    pub agc: bool,
+    /// RMS level the automatic gain control adjusts to, implies `--agc`.
+    #[arg(long, value_name = "RMS")]
+    pub agc_target: Option<f64>,
+    /// Time constant of the gain dropping, implies `--agc`.
+    #[arg(long, value_name = "SECONDS")]
+    pub agc_attack: Option<f64>,
+    /// Time constant of the gain recovering, implies `--agc`.
+    #[arg(long, value_name = "SECONDS")]
+    pub agc_release: Option<f64>,
+    /// Transcribe a network stream, http(s), rtsp or rtp, instead of the microphone.
+    #[arg(long, value_name = "URL")]
+    pub source: Option<String>,
+    /// Record the captured audio to a wav file.
+    #[arg(long, value_name = "WAV FILE")]
+    pub save_audio: Option<String>,
+    /// Input device to record, its index in `--list-devices` or name, where a part of the name
+    /// is enough. Repeat to mix several devices.
+    #[arg(long = "device", value_name = "NAME|INDEX")]
+    pub devices: Vec<String>,
+    /// Record what the system plays instead of a microphone, `--device` then picks the output
+    /// device on Windows or the sink on Linux.
+    #[arg(long, conflicts_with = "source")]
+    pub loopback: bool,
+    /// Only capture while this key is held down, anywhere on the desktop, e.g. `F9` or
+    /// `RightControl`.
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    pub push_to_talk: Option<Key>,
+    /// Pause and resume capturing with this key, anywhere on the desktop.
+    #[arg(long, value_name = "KEY", value_parser = parse_key)]
+    pub toggle_key: Option<Key>,
+    /// Dictate into other applications: type the finished utterances into the focused window
+    /// or copy them to the clipboard.
+    #[arg(long, value_name = "TARGET")]
+    pub dictate: Option<DictationTarget>,
+    /// Recording of a wake word, only speech following it is transcribed. Repeat with more
+    /// recordings of the phrase to detect it more reliably.
+    #[arg(long, value_name = "WAV FILE")]
+    pub wake_word: Vec<String>,
+    /// Distance to the recordings up to which speech counts as the wake word, 0.3 by default.
+    /// Lower is stricter.
+    #[arg(long, value_name = "DISTANCE")]
+    pub wake_threshold: Option<f64>,
+    /// Seconds after the last transcribed speech until the wake word is needed again.
+    #[arg(long, default_value_t = 10.0, value_name = "SECONDS")]
+    pub wake_time: f64,
+    /// TOML file of `"phrase" = "shell command"` lines, the command of a phrase is run when it
+    /// is spoken.
+    #[arg(long, value_name = "TOML FILE")]
+    pub commands: Option<String>,
+    /// Similarity from 0 to 1 speech needs to a phrase of `--commands` to run its command.
+    #[arg(long, default_value_t = 0.8, value_name = "SIMILARITY")]
+    pub command_threshold: f64,
+    /// List the input devices and exit.
+    #[arg(long)]
+    pub list_devices: bool,
+    /// Transcribe every input device on its own instead of mixing them.
+    #[arg(long, conflicts_with = "source")]
+    pub separate_devices: bool,
+    /// Print partial and final results as JSON lines instead of text.
+    #[arg(long)]
+    pub jsonl: bool,
+    /// Seconds of speech after which an utterance is ended even though someone is still
+    /// speaking. Longer utterances give the model more context, shorter ones come out sooner.
+    #[arg(long, default_value_t = 5.0, value_name = "SECONDS")]
+    pub max_utterance: f64,
+    /// Shorter speech is taken for noise and not transcribed.
+    #[arg(long, default_value_t = 0.4, value_name = "SECONDS")]
+    pub min_utterance: f64,
+    /// Silence that ends an utterance.
+    #[arg(long, default_value_t = 0.35, value_name = "SECONDS")]
+    pub end_silence: f64,
+    /// Seconds of new speech after which the partial result of an utterance is transcribed
+    /// again, with `--jsonl` or `--tui`.
+    #[arg(long, default_value_t = 1.0, value_name = "SECONDS")]
+    pub partial_interval: f64,
+    /// What to do once the transcription falls behind by more than `--max-backlog`.
+    #[arg(long, value_enum, default_value_t = BacklogPolicy::Queue)]
+    pub backlog_policy: BacklogPolicy,
+    /// Seconds of speech waiting for transcription after which it counts as falling behind.
+    #[arg(long, default_value_t = 30.0, value_name = "SECONDS")]
+    pub max_backlog: f64,
+    /// Transcribe every utterance on its own instead of prompting it with the text of the
+    /// previous ones.
+    #[arg(long)]
+    pub no_context: bool,
+    /// Number of successive partial results that have to agree on words before they are
+    /// taken as stable, the rest of a partial result may still change.
+    #[arg(long, default_value_t = 2, value_name = "N")]
+    pub agreement: usize,
+    /// Show the captions in a terminal UI with the audio level and the transcription speed.
+    #[arg(long, conflicts_with = "jsonl")]
+    pub tui: bool,
+    /// Write the latency and speed of the transcription of every utterance to this file as
+    /// JSON lines. A summary is printed when the stream ends either way.
+    #[arg(long, value_name = "JSONL FILE")]
+    pub metrics: Option<String>,
+    /// Transcription file finalized segments are appended to.
+    #[arg(short, long, value_name = "TRANSCRIPTION FILE")]
+    pub output: Option<String>,
+    #[command(flatten)]
+    pub backend: BackendArgs,
+    #[command(flatten)]
+    pub decoding: DecodingArgs,
+}
+
+impl StreamArgs {
+    fn agc_config(&self) -> Option<AgcConfig> {
+        let enabled = self.agc
+            || self.agc_target.is_some()
+            || self.agc_attack.is_some()
+            || self.agc_release.is_some();
+        if !enabled {
+            return None;
+        }
+
+        let mut config = AgcConfig::new();
+        if let Some(target_rms) = self.agc_target {
+            config = config.with_target_rms(target_rms);
+        }
+        if let Some(attack) = self.agc_attack {
+            config = config.with_attack(attack);
+        }
+        if let Some(release) = self.agc_release {
+            config = config.with_release(release);
+        }
+        Some(config)
+    }
+
+    fn capture_options(&self) -> CaptureOptions {
+        CaptureOptions {
+            devices: self.devices.clone(),
+            loopback: self.loopback,
+            channel: self.channel,
+            high_pass: self.high_pass,
+            agc: self.agc_config(),
+            source: self.source.clone(),
+            save_audio: self.save_audio.clone(),
+            segmenter: StreamConfig::new()
+                .with_max_utterance(self.max_utterance)
+                .with_min_utterance(self.min_utterance)
+                .with_end_silence(self.end_silence)
+                .with_partial_interval((self.jsonl || self.tui).then_some(self.partial_interval))
+                .with_agreement(self.agreement),
+            capturing: None,
+            level: None,
+            stopping: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// How audio is captured, applied to every device when several are recorded.
+#[derive(Clone)]
+struct CaptureOptions {
+    /// Names of the input devices to record, the default input device if empty.
+    devices: Vec<String>,
+    /// Record the output devices instead, see `find_loopback_device`.
+    loopback: bool,
+    channel: Option<usize>,
+    /// Cutoff in Hz of the high pass applied to the captured audio.
+    high_pass: Option<f64>,
+    /// Automatic gain control applied to the captured audio.
+    agc: Option<AgcConfig>,
+    /// URL of a network stream transcribed instead of the microphone.
+    source: Option<String>,
+    /// Wav file the captured audio is recorded to.
+    save_audio: Option<String>,
+    /// How the speech is cut into utterances. The speech captured so far is sent again every
+    /// `partial_interval` while someone is still speaking.
+    segmenter: StreamConfig,
+    /// Whether to capture at the moment, switched by the hotkeys. Always if `None`.
+    capturing: Option<Arc<AtomicBool>>,
+    /// Meter the level of the captured audio is shown on.
+    level: Option<LevelMeter>,
+    /// Set to end the capture, e.g. by Ctrl-C.
+    stopping: Arc<AtomicBool>,
+}
+
+/// Speech cut from the captured audio, labelled with the device it was recorded by when
+/// devices are transcribed separately. Partial utterances hold the speech captured so far of
+/// an utterance that is still going on, the final one with the same id all of it.
+struct Utterance {
+    id: usize,
+    label: Option<String>,
+    /// Time in seconds from the start of the capture.
+    start: f64,
+    /// Wall clock time the utterance was spoken at.
+    time: SystemTime,
+    samples: Vec<i16>,
+    is_final: bool,
+    /// When the utterance was sent for transcription.
+    captured: Instant,
+}
+
+/// A line of the `--jsonl` output.
+#[derive(Serialize)]
+struct JsonlResult<'a> {
+    id: usize,
+    #[serde(rename = "final")]
+    is_final: bool,
+    start: f64,
+    end: f64,
+    /// Wall clock time of `start` in seconds since the Unix epoch.
+    time: f64,
+    text: &'a str,
+    /// Start of `text` that won't change anymore, only of partial results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stable: Option<&'a str>,
+    /// Transcript in the spoken language next to the translation in `text`, with
+    /// `--with-original`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+    /// Name of the speaker of a final result, with `--diarize`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speaker: Option<&'a str>,
+    /// Set on the final results spoken by another speaker than the one before, with
+    /// `--speaker-changes`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    speaker_change: bool,
+    /// Set on the lines marking speech dropped by `--backlog-policy drop-oldest`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    gap: bool,
+}
+
+pub fn run(args: StreamArgs) -> token::Result<()> {
+    if args.list_devices {
+        return list_input_devices();
+    }
+    args.backend.clone().run(args)
+}
+
+impl BackendCommand for StreamArgs {
+    fn run_on<B: Backend>(args: Self, device: B::Device) -> token::Result<()> {
+        stream::<B>(args, device)
+    }
+}
+
+fn stream<B: Backend>(args: StreamArgs, device: B::Device) -> token::Result<()> {
+    let model = args.model.as_deref().ok_or("--model is required")?;
+    if args.with_original && args.task != Task::Translate {
+        return Err("--with-original needs --task translate".into());
+    }
+    if args.max_utterance <= args.min_utterance {
+        return Err("--max-utterance has to be longer than --min-utterance".into());
+    }
+    let (bpe, _whisper_config, whisper) = load_model::<B>(model, &device, args.backend.quantize)?;
+    eprintln!("Model {} loaded successfully", model);
+    let silero_vad = match &args.silero_vad {
+        Some(name) => Some(load_silero_vad_model::<B>(name, &device)?),
+        None => None,
+    };
+    let speaker_encoder = match args.diarize.as_ref().or(args.speaker_changes.as_ref()) {
+        Some(name) => Some(load_speaker_encoder_model::<B>(name, &device)?),
+        None => None,
+    };
+    let draft = match &args.decoding.draft {
+        Some(name) => Some(load_model::<B>(name, &device, args.backend.quantize)?.2),
+        None => None,
+    };
+
+    let segment_writer = match &args.output {
+        Some(path) => {
+            let format = output_format_of(path);
+            if !format.is_appendable() {
+                return Err(format!(
+                    "The {} format can't be written while streaming, use jsonl instead",
+                    format.as_str()
+                )
+                .into());
+            }
+            let writer = SegmentWriter::create(path, format, OutputOptions::new())
+                .map_err(|e| format!("Error creating transcription file: {}", e))?;
+            Some(writer)
+        }
+        None => None,
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let mut capture = args.capture_options();
+    let stopping = capture.stopping.clone();
+    ctrlc::set_handler(move || {
+        // a second Ctrl-C doesn't wait for the transcription to finish
+        if stopping.swap(true, Ordering::Relaxed) {
+            process::exit(130);
+        }
+        eprintln!("Finishing the transcription, press Ctrl-C again to quit right away");
+    })
+    .map_err(|e| format!("Failed to set the Ctrl-C handler: {}", e))?;
+    if args.push_to_talk.is_some() || args.toggle_key.is_some() {
+        capture.capturing = Some(spawn_hotkey_listener(args.push_to_talk, args.toggle_key));
+    }
+    let captions = if args.tui {
+        let captions = Arc::new(Mutex::new(Captions::default()));
+        let level = new_level_meter();
+        capture.level = Some(level.clone());
+        spawn_caption_view(captions.clone(), level, capture.stopping.clone())?;
+        Some(captions)
+    } else {
+        None
+    };
+    if args.separate_devices {
+        // every device is transcribed on its own and labelled with its name
+        for (i, device) in capture.devices.iter().enumerate() {
+            let capture = CaptureOptions {
+                devices: vec![device.clone()],
+                save_audio: capture.save_audio.as_ref().map(|path| numbered_path(path, i)),
+                ..capture.clone()
+            };
+            let label = Some(device.clone());
+            spawn_capture(sender.clone(), label, capture, silero_vad.clone());
+        }
+    } else {
+        spawn_capture(sender.clone(), None, capture, silero_vad);
+    }
+    // the transcription ends once every capture has
+    drop(sender);
+
+    let models = ExternalModels {
+        draft: draft.as_ref(),
+        speaker: speaker_encoder.as_ref(),
+        ..Default::default()
+    };
+    process_audio_data(receiver, segment_writer, captions, whisper, bpe, models, &args)
+}
+
+/// Inserts `_<i>` in front of the extension of `path`.
+fn numbered_path(path: &str, i: usize) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}_{}.{}", stem, i, extension),
+        None => format!("{}_{}", path, i),
+    }
+}
+
+/// Records on a thread of its own, an error ends the transcription.
+fn spawn_capture<B: Backend>(
+    sender: mpsc::Sender<token::Result<Utterance>>,
+    label: Option<String>,
+    capture: CaptureOptions,
+    silero_vad: Option<SileroVad<B>>,
+) {
+    thread::spawn(move || {
+        if let Err(e) = record_audio(sender.clone(), label, capture, silero_vad) {
+            let _ = sender.send(Err(e));
+        }
+    });
+}
+
+fn process_audio_data<B: Backend>(
+    receiver: mpsc::Receiver<token::Result<Utterance>>,
+    mut segment_writer: Option<SegmentWriter>,
+    captions: Option<Arc<Mutex<Captions>>>,
+    whisper: Whisper<B>,
+    bpe: Gpt2Tokenizer,
+    models: ExternalModels<B>,
+    args: &StreamArgs,
+) -> token::Result<()> {
+    let mut pending = VecDeque::new();
+    let mut metrics = Metrics::create(args.metrics.as_deref())?;
+    // whether the speech waiting for transcription is longer than `--max-backlog`
+    let mut behind = false;
+    let mut dictation = args.dictate.map(Dictation::new).transpose()?;
+    let wake_word = load_wake_word(args)?;
+    let commands = match &args.commands {
+        Some(path) => Some(CommandTable::load(path, args.command_threshold)?),
+        None => None,
+    };
+    // partial results of the utterance being spoken per device
+    let mut agreements: HashMap<Option<String>, LocalAgreement> = HashMap::new();
+    // text of the last finished utterances per device the next ones are prompted with
+    let mut contexts: HashMap<Option<String>, RollingContext> = HashMap::new();
+    // speakers heard so far per device
+    let mut speakers: HashMap<Option<String>, SpeakerTracker> = HashMap::new();
+    let mut speaker_changes: HashMap<Option<String>, SpeakerChangeDetector> = HashMap::new();
+    let diarization = DiarizationConfig::new()
+        .with_n_speakers(args.speakers)
+        .with_threshold(args.speaker_threshold);
+    // end of the time the wake word keeps transcription going, in seconds from the start
+    let mut awake_until = f64::NEG_INFINITY;
+    // skip utterances the voice activity detection let through that the decoder takes for
+    // silence, unless the config says otherwise
+    let decoding_options = args
+        .decoding
+        .apply(DecodingOptions::new().with_no_speech_skip_threshold(Some(0.6)))
+        .with_task(args.task)
+        .with_language(args.language);
+    for i in 0.. {
+        // Wait for data from the receiver
+        if pending.is_empty() {
+            match receiver.recv() {
+                Ok(utterance) => pending.push_back(utterance?),
+                Err(_) => break,
+            }
+        }
+        for utterance in receiver.try_iter() {
+            pending.push_back(utterance?);
+        }
+
+        let backlog = backlog_seconds(&pending);
+        if backlog > args.max_backlog {
+            if !behind {
+                eprintln!("Falling behind, {:.1} s of speech wait for transcription", backlog);
+                behind = true;
+            }
+            match args.backlog_policy {
+                BacklogPolicy::Queue => {}
+                BacklogPolicy::DropOldest => {
+                    for dropped in drop_oldest(&mut pending, args.max_backlog) {
+                        print_gap(&dropped, captions.as_deref(), args.jsonl)?;
+                    }
+                }
+                BacklogPolicy::Widen => widen(&mut pending),
+            }
+        } else if behind && backlog == 0.0 {
+            eprintln!("Caught up");
+            behind = false;
+        }
+        if let Some(captions) = &captions {
+            captions.lock().unwrap_or_else(|e| e.into_inner()).backlog = backlog;
+        }
+
+        // a partial utterance is outdated once more audio has arrived, which skipping keeps
+        // the transcription from falling behind
+        let utterance = pending.pop_front().expect("An utterance was just received");
+        if !utterance.is_final && !pending.is_empty() {
+            continue;
+        }
+        let Utterance {
+            id,
+            label,
+            mut start,
+            mut time,
+            samples: mut audio_data_vectors,
+            is_final,
+            captured,
+        } = utterance;
+
+        // nothing is transcribed until the wake word is heard, then only the speech after it
+        if let Some(wake_word) = &wake_word {
+            let end = start + audio_data_vectors.len() as f64 / SAMPLE_RATE as f64;
+            if start > awake_until {
+                if !is_final {
+                    continue;
+                }
+                // the conversation before the wake word is over
+                contexts.clear();
+                let waveform: Vec<f32> =
+                    audio_data_vectors.iter().map(|&x| x as f32 / 32767.0).collect();
+                let Some(found) = wake_word.detect(&waveform) else {
+                    continue;
+                };
+                eprintln!("Wake word detected");
+                let n_samples = (found.end * SAMPLE_RATE as f64) as usize;
+                audio_data_vectors.drain(..n_samples.min(audio_data_vectors.len()));
+                start += found.end;
+                time += Duration::from_secs_f64(found.end);
+            }
+            awake_until = end + args.wake_time;
+            if audio_data_vectors.len() < seconds_to_samples(args.min_utterance) {
+                continue;
+            }
+        }
+        let processed_len = audio_data_vectors.len();
+
+        //RUN INFERENCE
+        let mut speech_segment_f32: Vec<f32> =
+            audio_data_vectors.into_iter().map(|x| x as f32 / 32767.0).collect();
+        check_levels(&speech_segment_f32);
+        if args.normalize {
+            normalize_loudness(&mut speech_segment_f32, SAMPLE_RATE, TARGET_LOUDNESS);
+        }
+        let start_time = Instant::now(); // Capture the start time
+        // the speaker of the partial results is only known once the utterance is finished
+        let embedding = models
+            .speaker
+            .filter(|_| is_final)
+            .map(|encoder| encoder.embed(&speech_segment_f32));
+        let speaker = match embedding.as_ref().filter(|_| args.diarize.is_some()) {
+            Some(embedding) => Some(
+                speakers
+                    .entry(label.clone())
+                    .or_insert_with(|| SpeakerTracker::new(diarization.clone()))
+                    .assign(embedding),
+            ),
+            None => None,
+        };
+        let speaker_change = match embedding.filter(|_| args.speaker_changes.is_some()) {
+            Some(embedding) => speaker_changes
+                .entry(label.clone())
+                .or_insert_with(|| SpeakerChangeDetector::new(args.speaker_threshold))
+                .push(embedding),
+            None => false,
+        };
+        let speaker_name = speaker.map(|speaker| format!("SPEAKER {}", speaker + 1));
+        let original_waveform =
+            Some(&speech_segment_f32).filter(|_| is_final && args.with_original).cloned();
+        let context = contexts
+            .entry(label.clone())
+            .or_insert_with(|| RollingContext::new(whisper.decoder_ctx_size()));
+        let prompt = if args.no_context || !decoding_options.condition_on_previous_text {
+            Vec::new()
+        } else {
+            context.tokens().to_vec()
+        };
+        let options = decoding_options.clone().with_initial_prompt(prompt);
+        let result = waveform_to_text_with_models(
+            &whisper,
+            &bpe,
+            speech_segment_f32,
+            SAMPLE_RATE,
+            &options,
+            &models,
+        )
+        .map_err(|e| format!("Error during transcription: {}", e))?;
+        if is_final {
+            context.push(&result.segments);
+        }
+        // the speech is transcribed in the language the translation detected
+        let original = match original_waveform {
+            Some(waveform) => {
+                let options = decoding_options
+                    .clone()
+                    .with_task(Task::Transcribe)
+                    .with_language(Some(result.language));
+                let original = waveform_to_text_with_models(
+                    &whisper,
+                    &bpe,
+                    waveform,
+                    SAMPLE_RATE,
+                    &options,
+                    &models,
+                )
+                .map_err(|e| format!("Error during transcription: {}", e))?;
+                Some(original.text.trim().to_string())
+            }
+            None => None,
+        };
+        if let Some(writer) = segment_writer.as_mut().filter(|_| is_final) {
+            // segment times are relative to the utterance
+            let segments: Vec<Segment> = result
+                .segments
+                .iter()
+                .map(|segment| Segment {
+                    start: segment.start + start,
+                    end: segment.end + start,
+                    words: segment
+                        .words
+                        .iter()
+                        .map(|word| WordTiming {
+                            start: word.start + start,
+                            end: word.end + start,
+                            ..word.clone()
+                        })
+                        .collect(),
+                    speaker,
+                    ..segment.clone()
+                })
+                .collect();
+            writer.set_wall_clock_start(unix_time(time) - start);
+            writer
+                .append(&segments)
+                .map_err(|e| format!("Error writing transcription file: {}", e))?;
+        }
+        if let Some(dictation) = dictation.as_mut().filter(|_| is_final) {
+            dictation.insert(&result.text)?;
+        }
+        if let Some((command, similarity)) = commands
+            .as_ref()
+            .filter(|_| is_final)
+            .and_then(|commands| commands.find(&result.text))
+        {
+            eprintln!("Running \"{}\" ({:.2}): {}", command.phrase, similarity, command.command);
+            if let Err(e) = run_command(command) {
+                eprintln!("Error: {}", e);
+            }
+        }
+
+        let agreement = agreements
+            .entry(label.clone())
+            .or_insert_with(|| LocalAgreement::new(args.agreement));
+        let partial = if is_final {
+            agreement.reset();
+            None
+        } else {
+            Some(agreement.insert(&result.text))
+        };
+
+        let decode_time = start_time.elapsed();
+        let latency = captured.elapsed();
+        let duration = processed_len as f64 / SAMPLE_RATE as f64;
+        let rtf = decode_time.as_secs_f64() / duration;
+        metrics.record(UtteranceMetrics {
+            id,
+            is_final,
+            audio: duration,
+            decode: decode_time.as_secs_f64(),
+            rtf,
+            latency: latency.as_secs_f64(),
+            backlog,
+        })?;
+
+        let speaker_prefix =
+            speaker_name.as_ref().map(|name| format!("{}: ", name)).unwrap_or_default();
+        if let Some(captions) = &captions {
+            let mut captions = captions.lock().unwrap_or_else(|e| e.into_inner());
+            let label = label.map(|label| format!("[{}] ", label)).unwrap_or_default();
+            match partial {
+                Some(partial) => {
+                    captions.partial = partial;
+                    captions.partial_label = label;
+                }
+                None => {
+                    let stamp = format!("[{}] {}", format_wall_clock(unix_time(time)), label);
+                    if speaker_change {
+                        captions.lines.push(format!("{}{}", label, SPEAKER_CHANGE));
+                    }
+                    if let Some(original) = &original {
+                        captions.lines.push(format!("{}{}", stamp, original));
+                    }
+                    let text = result.text.trim();
+                    captions.lines.push(format!("{}{}{}", stamp, speaker_prefix, text));
+                    captions.partial = Default::default();
+                }
+            }
+            captions.rtf = Some(rtf);
+            captions.latency = Some(latency);
+        } else if args.jsonl {
+            // the stable words stay as they were first transcribed
+            let text = match &partial {
+                Some(partial) => format!("{} {}", partial.stable, partial.unstable),
+                None => result.text.clone(),
+            };
+            let line = JsonlResult {
+                id,
+                is_final,
+                start,
+                end: start + duration,
+                time: unix_time(time),
+                text: text.trim(),
+                stable: partial.as_ref().map(|partial| partial.stable.as_str()),
+                original: original.as_deref(),
+                label: label.as_deref(),
+                speaker: speaker_name.as_deref(),
+                speaker_change,
+                gap: false,
+            };
+            println!("{}", serde_json::to_string(&line)?);
+        } else {
+            let mut label = label.map(|label| format!("[{}] ", label)).unwrap_or_default();
+            if speaker_change {
+                println!("\n{}{}", label, SPEAKER_CHANGE);
+            }
+            if is_final {
+                label = format!("[{}] {}", format_wall_clock(unix_time(time)), label);
+            }
+            if let Some(original) = &original {
+                println!("\n{}Original: {}", label, original);
+            }
+            println!(
+                "\n{}Text: {}{}, Iteration: {}, Time:{:?}",
+                label,
+                speaker_prefix,
+                result.text,
+                i,
+                decode_time
+            );
+        }
+    }
+    metrics.print_summary();
+    Ok(())
+}
+
+/// Seconds of speech of the final utterances waiting for transcription, partial ones are
+/// skipped anyway when the transcription is behind.
+fn backlog_seconds(pending: &VecDeque<Utterance>) -> f64 {
+    let n_samples: usize = pending
+        .iter()
+        .filter(|utterance| utterance.is_final)
+        .map(|utterance| utterance.samples.len())
+        .sum();
+    n_samples as f64 / SAMPLE_RATE as f64
+}
+
+/// Drops the oldest final utterances until at most `max_backlog` seconds of speech are left
+/// and returns them. The newest utterance is always kept, even if it alone is longer.
+fn drop_oldest(pending: &mut VecDeque<Utterance>, max_backlog: f64) -> Vec<Utterance> {
+    let mut dropped = Vec::new();
+    while backlog_seconds(pending) > max_backlog {
+        let older = pending.len().saturating_sub(1);
+        let Some(oldest) = pending.iter().take(older).position(|utterance| utterance.is_final)
+        else {
+            break;
+        };
+        dropped.extend(pending.remove(oldest));
+    }
+    dropped
+}
+
+/// Joins consecutive final utterances of the same device with the silence between them, up
+/// to the length of the audio window of the model.
+fn widen(pending: &mut VecDeque<Utterance>) {
+    let mut widened: VecDeque<Utterance> = VecDeque::with_capacity(pending.len());
+    for utterance in pending.drain(..) {
+        if let Some(previous) = widened.back_mut().filter(|previous| previous.is_final) {
+            let previous_end = previous.start + previous.samples.len() as f64 / SAMPLE_RATE as f64;
+            let gap = seconds_to_samples(utterance.start - previous_end);
+            let length = previous.samples.len() + gap + utterance.samples.len();
+            let joinable = utterance.is_final && previous.label == utterance.label;
+            if joinable && gap <= MAX_WIDEN_GAP && length <= MAX_WIDENED_SAMPLES {
+                previous.samples.resize(previous.samples.len() + gap, 0);
+                previous.samples.extend(utterance.samples);
+                previous.captured = utterance.captured;
+                continue;
+            }
+        }
+        widened.push_back(utterance);
+    }
+    *pending = widened;
+}
+
+/// Marks speech dropped by `--backlog-policy drop-oldest` in the output.
+fn print_gap(
+    dropped: &Utterance,
+    captions: Option<&Mutex<Captions>>,
+    jsonl: bool,
+) -> token::Result<()> {
+    let duration = dropped.samples.len() as f64 / SAMPLE_RATE as f64;
+    let label = dropped.label.as_ref().map(|label| format!("[{}] ", label)).unwrap_or_default();
+    let marker = format!("{}[{:.1} s of speech skipped]", label, duration);
+    if let Some(captions) = captions {
+        captions.lock().unwrap_or_else(|e| e.into_inner()).lines.push(marker);
+    } else if jsonl {
+        let line = JsonlResult {
+            id: dropped.id,
+            is_final: true,
+            start: dropped.start,
+            end: dropped.start + duration,
+            time: unix_time(dropped.time),
+            text: "",
+            stable: None,
+            original: None,
+            label: dropped.label.as_deref(),
+            speaker: None,
+            speaker_change: false,
+            gap: true,
+        };
+        println!("{}", serde_json::to_string(&line)?);
+    } else {
+        println!("\n{}", marker);
+    }
+    Ok(())
+}
+
+fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.as_secs_f64())
+}
+
+fn seconds_to_samples(seconds: f64) -> usize {
+    (seconds.max(0.0) * SAMPLE_RATE as f64) as usize
+}
+
+/// Loads the recordings of the wake word into a detector, if there are any.
+fn load_wake_word(args: &StreamArgs) -> token::Result<Option<WakeWordDetector>> {
+    if args.wake_word.is_empty() {
+        return Ok(None);
+    }
+
+    let mut templates = Vec::new();
+    for path in &args.wake_word {
+        let (waveform, sample_rate) = load_audio(path, None)
+            .map_err(|e| format!("Failed to load wake word {}: {}", path, e))?;
+        templates.push(resample(&waveform, sample_rate)?);
+    }
+    let mut config = WakeWordConfig::new();
+    if let Some(threshold) = args.wake_threshold {
+        config = config.with_threshold(threshold);
+    }
+    Ok(Some(WakeWordDetector::new(&templates, config)))
+}
+
+/// Turns captured mono audio into the 16 kHz samples the voice activity detection runs on.
+struct Preprocessor {
+    resampler: Resampler,
+    high_pass: Option<HighPassFilter>,
+    agc: Option<AutomaticGainControl>,
+}
+
+impl Preprocessor {
+    fn new(
+        sample_rate: usize,
+        high_pass: Option<f64>,
+        agc: Option<&AgcConfig>,
+    ) -> token::Result<Self> {
+        Ok(Self {
+            resampler: Resampler::new(sample_rate)?,
+            high_pass: high_pass.map(|cutoff| HighPassFilter::new(cutoff, SAMPLE_RATE)),
+            agc: agc.map(|config| AutomaticGainControl::new(config, SAMPLE_RATE)),
+        })
+    }
+
+    fn process(&mut self, mono: &[f32]) -> Vec<i16> {
+        let mut data_16k = self.resampler.process(mono).expect("Failed to resample audio");
+        if let Some(high_pass) = self.high_pass.as_mut() {
+            high_pass.process(&mut data_16k);
+        }
+        if let Some(agc) = self.agc.as_mut() {
+            agc.process(&mut data_16k);
+        }
+        data_16k.iter().map(|x| (*x * 32767.0) as i16).collect()
+    }
+}
+
+/// Prints the input devices of the audio host with the index `--device` takes.
+fn list_input_devices() -> token::Result<()> {
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().and_then(|device| device.name().ok());
+    let devices = host
+        .input_devices()
+        .map_err(|e| format!("Failed to list input devices: {}", e))?;
+
+    println!("Input devices of {}:", host.id().name());
+    for (i, device) in devices.enumerate() {
+        let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+        let default = if default_name.as_ref() == Some(&name) {
+            " (default)"
+        } else {
+            ""
+        };
+        let config = device.default_input_config().map_or_else(
+            |_| "no supported input config".to_string(),
+            |config| format!("{} channels, {} Hz", config.channels(), config.sample_rate().0),
+        );
+        println!("{:3}: {}{}, {}", i, name, default, config);
+    }
+    Ok(())
+}
+
+/// Finds the input device `name`, an index in the list of input devices, the exact name or a
+/// part of it, or the default input device if `None`.
+fn find_input_device(host: &cpal::Host, name: Option<&str>) -> token::Result<cpal::Device> {
+    let Some(name) = name else {
+        return host
+            .default_input_device()
+            .ok_or_else(|| "No default input device, pick one with --device".into());
+    };
+
+    let mut devices = host
+        .input_devices()
+        .map_err(|e| format!("Failed to list input devices: {}", e))?
+        .collect::<Vec<_>>();
+    let names = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect::<Vec<_>>();
+    // an exact match wins over devices that only contain the name
+    let position = match name.parse::<usize>() {
+        Ok(index) => Some(index).filter(|&index| index < devices.len()),
+        Err(_) => names
+            .iter()
+            .position(|device_name| device_name == name)
+            .or_else(|| names.iter().position(|device_name| device_name.contains(name))),
+    };
+    let device = position.map(|i| devices.swap_remove(i));
+
+    device.ok_or_else(|| {
+        format!(
+            "Failed to find input device {}, see --list-devices for the available ones",
+            name
+        )
+        .into()
+    })
+}
+
+fn start_microphone(
+    device_name: Option<&str>,
+    loopback: bool,
+    channel: Option<usize>,
+    high_pass: Option<f64>,
+    agc: Option<&AgcConfig>,
+    mut producer: TimedProducer,
+) -> token::Result<cpal::Stream> {
+    let host = cpal::default_host();
+    let (device, config) = if loopback {
+        find_loopback_device(&host, device_name)?
+    } else {
+        let device = find_input_device(&host, device_name)?;
+        let config = device
+            .default_input_config()
+            .map_err(|e| format!("Failed to get default input config: {}", e))?;
+        (device, config)
+    };
+    eprintln!("Recording from {}", device.name().unwrap_or_default());
+    let n_channels = config.channels() as usize;
+    if let Some(channel) = channel.filter(|&channel| channel >= n_channels) {
+        return Err(format!(
+            "Channel {} does not exist, the input device has {} channels",
+            channel, n_channels
+        )
+        .into());
+    }
+    let mut preprocessor = Preprocessor::new(config.sample_rate().0 as usize, high_pass, agc)?;
+
+    // the stream lives as long as the capture and only hands the audio over, the callback
+    // must never block, so samples that don't fit into a full buffer are dropped
+    let mut overflowing = false;
+    let stream = device.build_input_stream(
+        &config.config(),
+        move |data: &[f32], _: &cpal::InputCallbackInfo| {
+            let mono = downmix(data, n_channels, channel);
+            let samples = preprocessor.process(&mono);
+            // the block was captured over its duration up to now
+            let time = SystemTime::now() - samples_duration(samples.len());
+            let n_dropped = samples.len() - producer.push(&samples, time);
+            if n_dropped > 0 && !overflowing {
+                eprintln!("Audio is captured faster than speech is detected, dropping samples");
+            }
+            overflowing = n_dropped > 0;
+        },
+        move |err| eprintln!("Error: {}", err),
+        None,
+    )?;
+    // Play the stream
+    stream.play()?;
+    Ok(stream)
+}
+
+/// Starts recording every input device of `capture` and mixes them into `producer`.
+fn start_microphones(
+    capture: &CaptureOptions,
+    mut producer: TimedProducer,
+) -> token::Result<Vec<cpal::Stream>> {
+    let start = |name: Option<&str>, producer| {
+        let agc = capture.agc.as_ref();
+        start_microphone(name, capture.loopback, capture.channel, capture.high_pass, agc, producer)
+    };
+
+    if capture.devices.len() <= 1 {
+        return Ok(vec![start(capture.devices.first().map(String::as_str), producer)?]);
+    }
+
+    // every device fills a buffer of its own and the mixer adds up what all of them captured
+    let mut streams = Vec::new();
+    let mut consumers = Vec::new();
+    for name in &capture.devices {
+        let (device_producer, consumer) = timed_ring_buffer(RING_BUFFER_SIZE);
+        streams.push(start(Some(name), device_producer)?);
+        consumers.push(consumer);
+    }
+
+    thread::spawn(move || loop {
+        let n_samples = consumers.iter().map(|consumer| consumer.slots()).min().unwrap_or(0);
+        if n_samples == 0 {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        // the clocks of the devices drift apart, a device running ahead drops what it is
+        // ahead by once that exceeds a second
+        for consumer in &mut consumers {
+            if consumer.slots() > n_samples + SAMPLE_RATE {
+                for _ in 0..consumer.slots() - n_samples {
+                    let _ = consumer.pop();
+                }
+            }
+        }
+
+        // the mix is as old as what the first device captured
+        let time = consumers[0].time().unwrap_or_else(SystemTime::now);
+        let mix: Vec<i16> = (0..n_samples)
+            .map(|_| {
+                consumers.iter_mut().fold(0i16, |sum, consumer| {
+                    sum.saturating_add(consumer.pop().unwrap_or(0))
+                })
+            })
+            .collect();
+        producer.push_waiting(&mix, time);
+    });
+
+    Ok(streams)
+}
+
+/// Receives the stream at `url` on a thread of its own, which ends with the stream.
+fn start_network_source(
+    url: &str,
+    channel: Option<usize>,
+    high_pass: Option<f64>,
+    agc: Option<&AgcConfig>,
+    mut producer: TimedProducer,
+) -> token::Result<JoinHandle<token::Result<()>>> {
+    let mut source = NetworkSource::open(url, channel)
+        .map_err(|e| format!("Failed to connect to {}: {}", url, e))?;
+    let mut preprocessor = Preprocessor::new(source.sample_rate(), high_pass, agc)?;
+
+    Ok(thread::spawn(move || {
+        while let Some(chunk) = source
+            .next_chunk()
+            .map_err(|e| format!("Error receiving stream: {}", e))?
+        {
+            // the stream arrives in real time, so this only waits while the voice activity
+            // detection catches up
+            let samples = preprocessor.process(&chunk);
+            let time = SystemTime::now() - samples_duration(samples.len());
+            producer.push_waiting(&samples, time);
+        }
+        Ok(())
+    }))
+}
+
+fn record_audio<B: Backend>(
+    sender: mpsc::Sender<token::Result<Utterance>>,
+    label: Option<String>,
+    capture: CaptureOptions,
+    silero_vad: Option<SileroVad<B>>,
+) -> token::Result<()> {
+    let mut audio_writer = match &capture.save_audio {
+        Some(path) => {
+            let spec = hound::WavSpec {
+                channels: 1,
+                sample_rate: SAMPLE_RATE as u32,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            };
+            let writer = hound::WavWriter::create(path, spec)
+                .map_err(|e| format!("Failed to create {}: {}", path, e))?;
+            Some(writer)
+        }
+        None => None,
+    };
+    let mut vad = Vad::new_with_rate(webrtc_vad::SampleRate::Rate16kHz);
+    vad.set_mode(VadMode::Aggressive);
+    let mut silero_vad = silero_vad.map(|model| {
+        let state = model.initial_state();
+        (model, state)
+    });
+    // webrtc vad frames are 10 ms long
+    let frame_size = if silero_vad.is_some() { CHUNK_SIZE } else { 160 };
+
+    // microphone and network audio go through the same buffer and speech detection
+    let (producer, mut consumer) = timed_ring_buffer(RING_BUFFER_SIZE);
+    let (_streams, mut network_thread) = match &capture.source {
+        Some(url) => {
+            let agc = capture.agc.as_ref();
+            let thread =
+                start_network_source(url, capture.channel, capture.high_pass, agc, producer)?;
+            (Vec::new(), Some(thread))
+        }
+        None => (start_microphones(&capture, producer)?, None),
+    };
+
+    let mut segmenter = Segmenter::new(&capture.segmenter);
+    // number of samples captured before the current frame
+    let mut n_captured = 0;
+    // start in seconds and wall clock time of the speech being spoken, utterances split from
+    // it are timed from there
+    let mut speech_start = (0.0, SystemTime::now());
+    let send = |utterance: streaming::Utterance, (start, time): (f64, SystemTime), is_final| {
+        let utterance = Utterance {
+            id: utterance.id,
+            label: label.clone(),
+            start: utterance.start,
+            time: time + Duration::from_secs_f64((utterance.start - start).max(0.0)),
+            samples: utterance
+                .samples
+                .iter()
+                .map(|&x| (x * 32767.0).round() as i16)
+                .collect(),
+            is_final,
+            captured: Instant::now(),
+        };
+        // the transcription only stops early because of an error, which it reports
+        let _ = sender.send(Ok(utterance));
+    };
+    loop {
+        // Ctrl-C ends the capture after sending the utterance being spoken
+        if capture.stopping.load(Ordering::Relaxed) {
+            if segmenter.is_speaking() {
+                let rest: Vec<i16> = iter::from_fn(|| consumer.pop()).collect();
+                let split = segmenter.step(&rest, true);
+                for utterance in split.into_iter().chain(segmenter.finish()) {
+                    send(utterance, speech_start, true);
+                }
+            }
+            if let Some(writer) = audio_writer.take() {
+                writer.finalize()?;
+            }
+            return Ok(());
+        }
+
+        if consumer.slots() <= frame_size {
+            // a network stream has ended once its thread is done
+            if consumer.is_abandoned() {
+                if let Some(utterance) = segmenter.finish() {
+                    send(utterance, speech_start, true);
+                }
+                eprintln!("Stream ended");
+                return match network_thread.take() {
+                    Some(thread) => thread.join().map_err(|_| "Network thread panicked")?,
+                    None => Ok(()),
+                };
+            }
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        let frame_time = consumer.time().unwrap_or_else(SystemTime::now);
+        let mut audio_frame = Vec::<i16>::new();
+        for _ in 0..frame_size {
+            match consumer.pop() {
+                Some(value) => audio_frame.push(value),
+                None => break,
+            }
+        }
+
+        let frame_start = n_captured;
+        n_captured += audio_frame.len();
+        if let Some(level) = &capture.level {
+            level.store(level_db(&audio_frame).to_bits(), Ordering::Relaxed);
+        }
+
+        // while paused by the hotkeys the audio is dropped, pausing ends the utterance
+        let capturing = capture.capturing.as_ref();
+        if !capturing.map_or(true, |capturing| capturing.load(Ordering::Relaxed)) {
+            if let Some(utterance) = segmenter.skip(audio_frame.len()) {
+                send(utterance, speech_start, true);
+            }
+            continue;
+        }
+
+        if let Some(writer) = audio_writer.as_mut() {
+            for &sample in &audio_frame {
+                writer.write_sample(sample)?;
+            }
+            // keeps the header up to date so the file stays readable if the process is
+            // killed
+            if writer.duration() as usize % SAMPLE_RATE < frame_size {
+                writer.flush()?;
+            }
+        }
+
+        let speech_active = match silero_vad.as_mut() {
+            Some((model, state)) => {
+                let samples: Vec<f32> = audio_frame.iter().map(|&x| x as f32 / 32767.0).collect();
+                model.step(&samples, state) >= SILERO_THRESHOLD
+            }
+            None => vad
+                .is_voice_segment(&audio_frame)
+                .map_err(|_| "Failed to check voice segment")?,
+        };
+        let speaking = segmenter.is_speaking();
+        let utterance = segmenter.step(&audio_frame, speech_active);
+        if !speaking && segmenter.is_speaking() {
+            speech_start = (frame_start as f64 / SAMPLE_RATE as f64, frame_time);
+        }
+        match utterance {
+            Some(utterance) => send(utterance, speech_start, true),
+            None => {
+                if let Some(utterance) = segmenter.partial() {
+                    send(utterance, speech_start, false);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utterance(id: usize, seconds: usize, is_final: bool) -> Utterance {
+        Utterance {
+            id,
+            label: None,
+            start: 0.0,
+            time: SystemTime::UNIX_EPOCH,
+            samples: vec![0; seconds * SAMPLE_RATE],
+            is_final,
+            captured: Instant::now(),
+        }
+    }
+
+    fn ids(utterances: &VecDeque<Utterance>) -> Vec<usize> {
+        utterances.iter().map(|utterance| utterance.id).collect()
+    }
+
+    #[test]
+    fn drop_oldest_drops_final_utterances_first() {
+        let mut pending: VecDeque<_> = [
+            utterance(0, 2, true),
+            utterance(1, 1, false),
+            utterance(2, 2, true),
+            utterance(3, 2, true),
+        ]
+        .into_iter()
+        .collect();
+        let dropped = drop_oldest(&mut pending, 3.0);
+        assert_eq!(dropped.iter().map(|utterance| utterance.id).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(ids(&pending), [1, 3]);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest_utterance() {
+        let mut pending: VecDeque<_> = [utterance(0, 1, true), utterance(1, 5, true)]
+            .into_iter()
+            .collect();
+        assert_eq!(drop_oldest(&mut pending, 0.0).len(), 1);
+        assert_eq!(ids(&pending), [1]);
+
+        // a single utterance longer than the backlog stays
+        assert!(drop_oldest(&mut pending, 0.0).is_empty());
+        assert_eq!(ids(&pending), [1]);
+    }
+}
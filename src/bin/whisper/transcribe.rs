@@ -0,0 +1,487 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use burn::tensor::backend::Backend;
+use clap::Args;
+use whisper_stream::{
+    audio::{
+        diagnostics::check_levels,
+        dump::{save_mels_npy, save_mels_png},
+        filter::high_pass,
+        is_url, load_audio_range, load_audio_url, load_pcm, log_mel_spectrogram,
+        loudness::{normalize_loudness, TARGET_LOUDNESS},
+        resample,
+        vad::VadConfig,
+        PcmFormat, SAMPLE_RATE,
+    },
+    diarization::DiarizationConfig,
+    model::Whisper,
+    output::{write_output, writer::SegmentWriter, OutputFormat, OutputOptions},
+    token::{self, Gpt2Tokenizer, Language, Task},
+    transcribe::{waveform_to_text_incremental, DecodingOptions, ExternalModels},
+};
+
+use crate::args::{
+    output_format_of, parse_language, parse_output_format, parse_pcm_format, parse_task,
+};
+use crate::backend::{BackendArgs, BackendCommand};
+use crate::config::DecodingArgs;
+use crate::inputs::{collect_inputs, Input};
+use crate::manifest::Manifest;
+use crate::models::{load_model, load_silero_vad_model, load_speaker_encoder_model};
+use crate::progress::ProgressBar;
+use crate::watch::watch_directories;
+
+/// Transcribe or translate audio files.
+#[derive(Args, Debug)]
+pub struct TranscribeArgs {
+    /// Audio files, directories, glob patterns, http(s) URLs, or `-` for raw PCM on stdin.
+    #[arg(required = true, num_args = 1..)]
+    pub audio: Vec<String>,
+    /// Model name, loaded from `models/<name>`.
+    #[arg(short, long)]
+    pub model: String,
+    /// Language code of the speech, detected when not given.
+    #[arg(short, long, value_parser = parse_language)]
+    pub language: Option<Language>,
+    /// Transcription file to write, only for a single input. By default the transcription is
+    /// written next to the audio file.
+    #[arg(short, long, conflicts_with = "output_dir")]
+    pub output: Option<String>,
+    /// Directory to write the transcriptions to, mirroring the layout of the input directories.
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<String>,
+    /// Also transcribe the audio files in the subdirectories of input directories.
+    #[arg(short, long)]
+    pub recursive: bool,
+    /// Number of files transcribed at the same time, sharing the loaded model.
+    #[arg(short, long, default_value_t = 1, value_name = "N")]
+    pub jobs: usize,
+    /// Skip the inputs whose transcription file exists already.
+    #[arg(long)]
+    pub skip_existing: bool,
+    /// File recording the transcribed inputs. Inputs it lists are skipped, so an interrupted
+    /// batch continues where it stopped when run again.
+    #[arg(long, value_name = "FILE")]
+    pub manifest: Option<String>,
+    /// Keep watching the input directories and transcribe the audio files that appear in them.
+    #[arg(long)]
+    pub watch: bool,
+    #[arg(long, default_value = "transcribe", value_parser = parse_task)]
+    pub task: Task,
+    /// Seed of the sampling at higher temperatures, for reproducible transcriptions.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Transcribe a single channel instead of the mix of all channels.
+    #[arg(long)]
+    pub channel: Option<usize>,
+    /// Compute the mel spectrogram exactly like openai/whisper.
+    #[arg(long)]
+    pub reference_frontend: bool,
+    /// Only transcribe the speech found by voice activity detection.
+    #[arg(long)]
+    pub vad: bool,
+    /// Silero VAD model used for the voice activity detection, implies `--vad`.
+    #[arg(long, value_name = "MODEL")]
+    pub silero_vad: Option<String>,
+    /// Speaker embedding model used to label the segments with their speakers.
+    #[arg(long, value_name = "MODEL")]
+    pub diarize: Option<String>,
+    /// Number of speakers `--diarize` tells apart, found by `--speaker-threshold` when not
+    /// given.
+    #[arg(long, value_name = "N", requires = "diarize")]
+    pub speakers: Option<usize>,
+    /// Cosine distance between two voices up to which `--diarize` takes them for the same
+    /// speaker.
+    #[arg(long, default_value_t = 0.6, value_name = "DISTANCE")]
+    pub speaker_threshold: f64,
+    /// Normalize the loudness of the audio.
+    #[arg(long)]
+    pub normalize: bool,
+    /// Cutoff in Hz of a high pass filter applied to the audio.
+    #[arg(long, value_name = "HZ")]
+    pub high_pass: Option<f64>,
+    /// Second of the audio to start at.
+    #[arg(long, default_value_t = 0.0, value_name = "SECONDS")]
+    pub start: f64,
+    /// Number of seconds of audio to transcribe.
+    #[arg(long, value_name = "SECONDS")]
+    pub duration: Option<f64>,
+    /// Sample rate of raw PCM read from stdin.
+    #[arg(long, default_value_t = SAMPLE_RATE, value_name = "HZ")]
+    pub sample_rate: usize,
+    /// Sample format of raw PCM read from stdin.
+    #[arg(long, default_value = "s16le", value_parser = parse_pcm_format)]
+    pub format: PcmFormat,
+    /// Write the log mel spectrogram the model sees to a `.npy` file.
+    #[arg(long, value_name = "NPY FILE")]
+    pub dump_mel: Option<String>,
+    /// Render the log mel spectrogram the model sees to a PNG image.
+    #[arg(long, value_name = "PNG FILE")]
+    pub dump_mel_png: Option<String>,
+    /// Format of the transcription file, by default picked by its extension.
+    #[arg(long, value_parser = parse_output_format)]
+    pub output_format: Option<OutputFormat>,
+    #[command(flatten)]
+    pub output_options: OutputArgs,
+    #[command(flatten)]
+    pub backend: BackendArgs,
+    #[command(flatten)]
+    pub decoding: DecodingArgs,
+}
+
+/// Options of how the transcription is rendered.
+#[derive(Args, Debug, Clone)]
+pub struct OutputArgs {
+    /// Mark up the timing of every word in the subtitles.
+    #[arg(long)]
+    pub word_highlight: bool,
+    /// Font of ASS subtitles.
+    #[arg(long, value_name = "NAME")]
+    pub ass_font: Option<String>,
+    /// Font size of ASS subtitles.
+    #[arg(long, value_name = "SIZE")]
+    pub ass_font_size: Option<usize>,
+    /// Position of ASS subtitles as on a numeric keypad.
+    #[arg(long, value_name = "1-9", value_parser = clap::value_parser!(u8).range(1..=9))]
+    pub ass_alignment: Option<u8>,
+    /// Maximum number of characters per subtitle line.
+    #[arg(long, value_name = "CHARACTERS")]
+    pub max_line_width: Option<usize>,
+    /// Maximum number of lines per subtitle cue.
+    #[arg(long, value_name = "LINES")]
+    pub max_line_count: Option<usize>,
+    /// Maximum number of words per subtitle cue.
+    #[arg(long, value_name = "WORDS")]
+    pub max_words_per_cue: Option<usize>,
+}
+
+impl OutputArgs {
+    /// Whether the options need the timing of the words, cues are split at the word timestamps.
+    pub fn word_timestamps(&self) -> bool {
+        self.word_highlight
+            || self.max_line_width.is_some()
+            || self.max_line_count.is_some()
+            || self.max_words_per_cue.is_some()
+    }
+
+    pub fn to_options(&self, model_name: &str) -> OutputOptions {
+        let mut options = OutputOptions::new()
+            .with_word_highlight(self.word_highlight)
+            .with_model(Some(model_name.to_string()))
+            .with_max_line_width(self.max_line_width)
+            .with_max_line_count(self.max_line_count)
+            .with_max_words_per_cue(self.max_words_per_cue);
+        if let Some(font) = &self.ass_font {
+            options = options.with_ass_font(font.clone());
+        }
+        if let Some(font_size) = self.ass_font_size {
+            options = options.with_ass_font_size(font_size);
+        }
+        if let Some(alignment) = self.ass_alignment {
+            options = options.with_ass_alignment(alignment as usize);
+        }
+        options
+    }
+}
+
+pub fn run(args: TranscribeArgs) -> token::Result<()> {
+    args.backend.clone().run(args)
+}
+
+impl BackendCommand for TranscribeArgs {
+    fn run_on<B: Backend>(args: Self, device: B::Device) -> token::Result<()> {
+        transcribe::<B>(args, device)
+    }
+}
+
+fn transcribe<B: Backend>(args: TranscribeArgs, device: B::Device) -> token::Result<()> {
+    if args.jobs == 0 {
+        return Err("--jobs must be at least 1".into());
+    }
+    if args.watch && (args.output.is_some() || args.audio.iter().any(|audio| audio == "-")) {
+        return Err("--watch writes a transcription per file and can't read stdin".into());
+    }
+    let inputs = if args.watch {
+        Vec::new()
+    } else {
+        collect_inputs(&args.audio, args.recursive)?
+    };
+    if inputs.is_empty() && !args.watch {
+        return Err("No audio files found".into());
+    }
+    if inputs.len() > 1 && args.output.is_some() {
+        return Err("--output takes a single input, use --output-dir for several".into());
+    }
+    if inputs.len() > 1 && (args.dump_mel.is_some() || args.dump_mel_png.is_some()) {
+        return Err("--dump-mel and --dump-mel-png take a single input".into());
+    }
+
+    let output_format = args.output_format.unwrap_or_else(|| match &args.output {
+        Some(output) => output_format_of(output),
+        None => OutputFormat::Txt,
+    });
+    let output_dir = args.output_dir.as_deref().map(Path::new);
+    let output_of = |input: &Input| match &args.output {
+        Some(output) => PathBuf::from(output),
+        None => input.output_path(output_dir, output_format.as_str()),
+    };
+
+    let manifest = args.manifest.as_deref().map(Manifest::open).transpose()?;
+    let n_inputs = inputs.len();
+    let inputs = inputs
+        .into_iter()
+        .filter(|input| {
+            let done = manifest.as_ref().map_or(false, |manifest| manifest.is_done(&input.source));
+            !done && !(args.skip_existing && output_of(input).exists())
+        })
+        .collect::<Vec<_>>();
+    if inputs.len() < n_inputs {
+        eprintln!("Skipping {} files transcribed already", n_inputs - inputs.len());
+    }
+    if inputs.is_empty() && !args.watch {
+        return Ok(());
+    }
+    // the manifest is updated only once the transcription file is complete
+    let record = |input: &Input, output: &Path| match &manifest {
+        Some(manifest) => manifest
+            .record(&input.source, output)
+            .map_err(|e| format!("Failed to update manifest: {}", e)),
+        None => Ok(()),
+    };
+
+    let (bpe, _whisper_config, whisper) =
+        load_model::<B>(&args.model, &device, args.backend.quantize)?;
+    let silero_vad = match &args.silero_vad {
+        Some(name) => Some(load_silero_vad_model::<B>(name, &device)?),
+        None => None,
+    };
+    let speaker_encoder = match &args.diarize {
+        Some(name) => Some(load_speaker_encoder_model::<B>(name, &device)?),
+        None => None,
+    };
+    let draft = match &args.decoding.draft {
+        Some(name) => Some(load_model::<B>(name, &device, args.backend.quantize)?.2),
+        None => None,
+    };
+
+    if args.watch {
+        let models = ExternalModels {
+            vad: silero_vad.as_ref(),
+            speaker: speaker_encoder.as_ref(),
+            draft: draft.as_ref(),
+            ..Default::default()
+        };
+        return watch_directories(&args.audio, args.recursive, |input| {
+            let output = output_of(input);
+            transcribe_input(
+                &args,
+                input,
+                &output,
+                output_format,
+                &whisper,
+                &bpe,
+                &models,
+                &device,
+            )?;
+            Ok(record(input, &output)?)
+        });
+    }
+
+    if let [input] = inputs.as_slice() {
+        let models = ExternalModels {
+            vad: silero_vad.as_ref(),
+            speaker: speaker_encoder.as_ref(),
+            draft: draft.as_ref(),
+            ..Default::default()
+        };
+        let output = output_of(input);
+        transcribe_input(&args, input, &output, output_format, &whisper, &bpe, &models, &device)?;
+        return Ok(record(input, &output)?);
+    }
+
+    // a broken file doesn't stop the batch, the failures are counted and reported at the end
+    let jobs = args.jobs.min(inputs.len());
+    let next_input = AtomicUsize::new(0);
+    let failures = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            // the workers take turns on the device, each with a clone of the models sharing
+            // their weights
+            let whisper = whisper.clone();
+            let silero_vad = silero_vad.clone();
+            let speaker_encoder = speaker_encoder.clone();
+            let draft = draft.clone();
+            let (args, inputs, bpe, device) = (&args, &inputs, &bpe, &device);
+            let (next_input, failures) = (&next_input, &failures);
+            let (output_of, record) = (&output_of, &record);
+            scope.spawn(move || {
+                let models = ExternalModels {
+                    vad: silero_vad.as_ref(),
+                    speaker: speaker_encoder.as_ref(),
+                    draft: draft.as_ref(),
+                    ..Default::default()
+                };
+                loop {
+                    let i = next_input.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(i) else {
+                        break;
+                    };
+                    eprintln!("[{}/{}] {}", i + 1, inputs.len(), input.source);
+                    let output = output_of(input);
+                    let result = transcribe_input(
+                        args,
+                        input,
+                        &output,
+                        output_format,
+                        &whisper,
+                        bpe,
+                        &models,
+                        device,
+                    )
+                    .and_then(|()| Ok(record(input, &output)?));
+                    if let Err(e) = result {
+                        eprintln!("Error transcribing {}: {}", input.source, e);
+                        failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+
+    let failures = failures.into_inner();
+    if failures > 0 {
+        return Err(format!("{} of {} files failed", failures, inputs.len()).into());
+    }
+    Ok(())
+}
+
+/// Transcribes one input into the transcription file `output`.
+#[allow(clippy::too_many_arguments)]
+fn transcribe_input<B: Backend>(
+    args: &TranscribeArgs,
+    input: &Input,
+    output: &Path,
+    output_format: OutputFormat,
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    models: &ExternalModels<B>,
+    device: &B::Device,
+) -> token::Result<()> {
+    let waveform = load_waveform(args, &input.source)?;
+
+    if args.dump_mel.is_some() || args.dump_mel_png.is_some() {
+        let n_mels = whisper.encoder_mel_size();
+        let mels = log_mel_spectrogram::<B>(&waveform, n_mels, device);
+        if let Some(path) = &args.dump_mel {
+            save_mels_npy(mels.clone(), path)
+                .map_err(|e| format!("Failed to write mel spectrogram: {}", e))?;
+        }
+        if let Some(path) = &args.dump_mel_png {
+            save_mels_png(mels, path)
+                .map_err(|e| format!("Failed to write mel spectrogram image: {}", e))?;
+        }
+    }
+
+    let vad = args.vad || models.vad.is_some();
+    let decoding_options = args
+        .decoding
+        .apply(DecodingOptions::new())
+        .with_task(args.task)
+        .with_language(args.language)
+        .with_seed(args.seed)
+        .with_reference_frontend(args.reference_frontend)
+        .with_vad(vad.then(VadConfig::new))
+        .with_diarization(models.speaker.map(|_| {
+            DiarizationConfig::new()
+                .with_n_speakers(args.speakers)
+                .with_threshold(args.speaker_threshold)
+        }))
+        .with_word_timestamps(args.output_options.word_timestamps());
+    let output_options = args.output_options.to_options(&args.model);
+
+    if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+
+    // segments are written as their window is decoded so a crash doesn't lose the transcription
+    // of a long recording, the complete file replaces them at the end
+    let mut segment_writer = if output_format.is_appendable() {
+        Some(
+            SegmentWriter::create(output, output_format, output_options.clone())
+                .map_err(|e| format!("Error writing transcription file: {}", e))?,
+        )
+    } else {
+        None
+    };
+    // the bars of files transcribed at the same time would overwrite each other
+    let mut progress_bar = (args.jobs == 1).then(ProgressBar::start);
+    let result = waveform_to_text_incremental(
+        whisper,
+        bpe,
+        waveform,
+        SAMPLE_RATE,
+        &decoding_options,
+        models,
+        |segments| match segment_writer.as_mut() {
+            Some(writer) => Ok(writer.append(segments)?),
+            None => Ok(()),
+        },
+        |progress| {
+            if let Some(progress_bar) = progress_bar.as_mut() {
+                progress_bar.update(progress);
+            }
+        },
+    )
+    .map_err(|e| format!("Error during transcription: {}", e))?;
+    if args.language.is_none() {
+        eprintln!("Detected language of {}: {}", input.source, result.language.as_str());
+    }
+
+    write_output(output, &result, output_format, &output_options)
+        .map_err(|e| format!("Error writing transcription file: {}", e))?;
+
+    eprintln!("Transcription written to {}", output.display());
+    Ok(())
+}
+
+/// Loads the audio `source` as 16 kHz mono, preprocessed as asked by `args`.
+pub fn load_waveform(args: &TranscribeArgs, source: &str) -> token::Result<Vec<f32>> {
+    let (waveform, sample_rate) = if source == "-" {
+        if args.start < 0.0 || args.duration.map_or(false, |duration| duration < 0.0) {
+            return Err("Start and duration must not be negative".into());
+        }
+        // raw PCM piped in, e.g. `ffmpeg -i audio.mp3 -f s16le -ac 1 -ar 16000 -`
+        let waveform = load_pcm(io::stdin().lock(), args.format, 1, args.channel)
+            .map_err(|e| format!("Failed to read audio from stdin: {}", e))?;
+        let seconds_to_samples =
+            |seconds: f64| ((seconds * args.sample_rate as f64) as usize).min(waveform.len());
+        let start_sample = seconds_to_samples(args.start);
+        let end_sample = args
+            .duration
+            .map_or(waveform.len(), |duration| seconds_to_samples(args.start + duration));
+        (waveform[start_sample..end_sample].to_vec(), args.sample_rate)
+    } else if is_url(source) {
+        load_audio_url(source, args.channel, args.start, args.duration)
+            .map_err(|e| format!("Failed to download audio: {}", e))?
+    } else {
+        load_audio_range(source, args.channel, args.start, args.duration)
+            .map_err(|e| format!("Failed to load audio file: {}", e))?
+    };
+    check_levels(&waveform);
+
+    let mut waveform = resample(&waveform, sample_rate)
+        .map_err(|e| format!("Failed to resample audio: {}", e))?;
+    if let Some(cutoff) = args.high_pass {
+        high_pass(&mut waveform, SAMPLE_RATE, cutoff);
+    }
+    if args.normalize {
+        normalize_loudness(&mut waveform, SAMPLE_RATE, TARGET_LOUDNESS);
+    }
+    Ok(waveform)
+}
@@ -0,0 +1,186 @@
+use std::{
+    io::{self, Stdout},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Gauge, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use whisper_stream::{streaming::PartialText, token};
+
+/// Time between redraws, which is also how often keys are checked.
+const FRAME_TIME: Duration = Duration::from_millis(100);
+/// Level in dBFS shown as an empty meter.
+const MIN_LEVEL_DB: f32 = -60.0;
+
+/// What the caption view shows, updated by the transcription.
+#[derive(Default)]
+pub struct Captions {
+    /// Text of the finished utterances.
+    pub lines: Vec<String>,
+    /// Text of the utterance still being spoken.
+    pub partial: PartialText,
+    /// Label of the utterance still being spoken, e.g. `[device] `.
+    pub partial_label: String,
+    /// Seconds of computation per second of audio of the last utterance.
+    pub rtf: Option<f64>,
+    /// Time from the end of the last utterance being captured to its text.
+    pub latency: Option<Duration>,
+    /// Seconds of speech waiting for transcription.
+    pub backlog: f64,
+}
+
+/// Level of the captured audio in dBFS, shared with the capture thread as the bits of an
+/// `f32`.
+pub type LevelMeter = Arc<AtomicU32>;
+
+pub fn new_level_meter() -> LevelMeter {
+    Arc::new(AtomicU32::new(MIN_LEVEL_DB.to_bits()))
+}
+
+/// Root mean square level of 16 bit samples in dBFS.
+pub fn level_db(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return MIN_LEVEL_DB;
+    }
+    let mean_square =
+        samples.iter().map(|&x| (x as f32 / 32767.0).powi(2)).sum::<f32>() / samples.len() as f32;
+    (10.0 * mean_square.max(1e-10).log10()).max(MIN_LEVEL_DB)
+}
+
+/// Takes over the terminal with a live caption view drawn on a thread of its own until `q`,
+/// `Esc` or `Ctrl-C` is pressed, which sets `stopping` to end the stream.
+pub fn spawn_caption_view(
+    captions: Arc<Mutex<Captions>>,
+    level: LevelMeter,
+    stopping: Arc<AtomicBool>,
+) -> token::Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    thread::spawn(move || {
+        let result = run_caption_view(&mut terminal, &captions, &level);
+        let _ = restore_terminal(&mut terminal);
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        // the terminal is in raw mode while the view is shown, so Ctrl-C arrives here instead
+        // of as a signal
+        stopping.store(true, Ordering::Relaxed);
+    });
+    Ok(())
+}
+
+fn run_caption_view(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    captions: &Mutex<Captions>,
+    level: &LevelMeter,
+) -> io::Result<()> {
+    loop {
+        {
+            let captions = captions.lock().unwrap_or_else(|e| e.into_inner());
+            let level = f32::from_bits(level.load(Ordering::Relaxed));
+            terminal.draw(|frame| draw(frame, &captions, level))?;
+        }
+
+        if event::poll(FRAME_TIME)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                let quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c;
+                if key.kind == KeyEventKind::Press && quit {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()
+}
+
+fn draw(frame: &mut Frame, captions: &Captions, level: f32) {
+    let areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3), Constraint::Length(1)])
+        .split(frame.size());
+    let (caption_area, level_area, stats_area) = (areas[0], areas[1], areas[2]);
+
+    // only the newest lines that fit are shown, wrapped to the width of the view
+    let block = Block::default().borders(Borders::ALL).title(" Captions ");
+    let inner = block.inner(caption_area);
+    let width = inner.width.max(1) as usize;
+    let mut lines: Vec<Line> = captions.lines.iter().map(|line| Line::raw(line.as_str())).collect();
+    // the words that may still change are dimmed
+    let partial = &captions.partial;
+    if !partial.stable.is_empty() || !partial.unstable.is_empty() {
+        let dim = Style::default().add_modifier(Modifier::DIM);
+        let separator = if partial.stable.is_empty() { "" } else { " " };
+        lines.push(Line::from(vec![
+            Span::raw(format!("{}{}", captions.partial_label, partial.stable)),
+            Span::styled(format!("{}{}", separator, partial.unstable), dim),
+        ]));
+    }
+    let mut height = 0;
+    let n_visible = lines
+        .iter()
+        .rev()
+        .take_while(|line| {
+            height += line.width().max(1).div_ceil(width);
+            height <= inner.height as usize
+        })
+        .count();
+    let lines = lines.split_off(lines.len() - n_visible);
+    frame.render_widget(
+        Paragraph::new(lines).block(block).wrap(Wrap { trim: true }),
+        caption_area,
+    );
+
+    let ratio = ((level - MIN_LEVEL_DB) / -MIN_LEVEL_DB).clamp(0.0, 1.0) as f64;
+    let gauge = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title(" Level "))
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(ratio)
+        .label(format!("{:.0} dB", level));
+    frame.render_widget(gauge, level_area);
+
+    let rtf = captions.rtf.map_or("-".to_string(), |rtf| format!("{:.2}", rtf));
+    let latency = captions
+        .latency
+        .map_or("-".to_string(), |latency| format!("{} ms", latency.as_millis()));
+    let stats = format!(
+        " RTF {}  Latency {}  Behind {:.1} s  Utterances {}  (q to quit)",
+        rtf,
+        latency,
+        captions.backlog,
+        captions.lines.len()
+    );
+    // the transcription is falling behind while speech is waiting
+    let style = if captions.backlog > 0.0 {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+    frame.render_widget(Paragraph::new(stats).style(style), stats_area);
+}
@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use clap::Args;
+use whisper_stream::{
+    hub::{self, CHECKSUMS_FILE},
+    token,
+};
+
+use crate::models::model_dir;
+
+/// Check the files of a model against the SHA-256 recorded when it was downloaded.
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Model name, looked up in `models` and the download cache.
+    pub model: String,
+    /// Record the checksums of the files as they are instead, e.g. for a converted model.
+    #[arg(long)]
+    pub update: bool,
+}
+
+pub fn run(args: VerifyArgs) -> token::Result<()> {
+    let dir = model_dir(&args.model);
+    if !dir.exists() {
+        return Err(format!("There is no model folder {}", dir.display()).into());
+    }
+
+    if args.update {
+        let mut checksums = BTreeMap::new();
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if !path.is_file() || file_name == CHECKSUMS_FILE || file_name.ends_with(".part") {
+                continue;
+            }
+            eprintln!("Hashing {}...", file_name);
+            checksums.insert(file_name, hub::sha256_file(&path)?);
+        }
+        hub::write_checksums(&dir, &checksums)?;
+        eprintln!("Checksums saved to {}", dir.join(CHECKSUMS_FILE).display());
+        return Ok(());
+    }
+
+    let checks = hub::verify_model(&dir, |file_name| eprintln!("Checking {}...", file_name))
+        .map_err(|e| format!("{}, `verify --update` records the current files", e))?;
+    for check in &checks {
+        match &check.actual {
+            _ if check.is_ok() => println!("{}: OK", check.file_name),
+            Some(actual) => println!(
+                "{}: FAILED, SHA-256 {} instead of {}",
+                check.file_name, actual, check.expected
+            ),
+            None => println!("{}: FAILED, missing", check.file_name),
+        }
+    }
+
+    let n_failed = checks.iter().filter(|check| !check.is_ok()).count();
+    if n_failed > 0 {
+        return Err(format!(
+            "{} of {} files don't match, download or convert the model again",
+            n_failed,
+            checks.len()
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use notify::{event::EventKind, RecursiveMode, Watcher};
+use whisper_stream::{audio::is_audio_file, token};
+
+use crate::inputs::{local_input, Input};
+
+/// Time without changes after which a new file is taken as complete. Recorders and copies
+/// write a file over a while and it can only be transcribed once they're done.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Watches the directories, and their subdirectories if `recursive`, and calls `transcribe`
+/// for every audio file that is created or changed in them, once it stopped changing. Runs
+/// until the watcher fails, failed transcriptions are reported and the watch goes on.
+pub fn watch_directories<F>(
+    dirs: &[String],
+    recursive: bool,
+    mut transcribe: F,
+) -> token::Result<()>
+where
+    F: FnMut(&Input) -> token::Result<()>,
+{
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|e| format!("Failed to watch for files: {}", e))?;
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    // the watcher reports absolute paths
+    let mut watched = Vec::new();
+    for dir in dirs {
+        if !Path::new(dir).is_dir() {
+            return Err(format!("Only directories can be watched, {} isn't one", dir).into());
+        }
+        let path = fs::canonicalize(dir).map_err(|e| format!("Failed to watch {}: {}", dir, e))?;
+        watcher
+            .watch(&path, mode)
+            .map_err(|e| format!("Failed to watch {}: {}", dir, e))?;
+        eprintln!("Watching {} for new audio files", dir);
+        watched.push(path);
+    }
+
+    // files that changed recently, with the time of their last change
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    loop {
+        match receiver.recv_timeout(SETTLE_TIME / 4) {
+            Ok(Ok(event)) => match event.kind {
+                EventKind::Create(_) | EventKind::Modify(_) => {
+                    for path in event.paths {
+                        if is_audio_file(&path) && path.is_file() {
+                            pending.insert(path, Instant::now());
+                        }
+                    }
+                }
+                EventKind::Remove(_) => {
+                    for path in &event.paths {
+                        pending.remove(path);
+                    }
+                }
+                _ => (),
+            },
+            Ok(Err(e)) => log::warn!("Error watching files: {}", e),
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err("The file watcher stopped".into())
+            }
+        }
+
+        let mut settled = pending
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        settled.sort();
+        for path in settled {
+            pending.remove(&path);
+            // the layout below the watched directory is mirrored in the output directory
+            let dir = watched
+                .iter()
+                .filter(|dir| path.starts_with(dir))
+                .max_by_key(|dir| dir.components().count());
+            let input = local_input(&path, dir.map_or(Path::new(""), PathBuf::as_path));
+            eprintln!("New file {}", input.source);
+            if let Err(e) = transcribe(&input) {
+                eprintln!("Error transcribing {}: {}", input.source, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use burn::{config::Config, tensor::backend::Backend};
+
+use crate::audio::{speaker::SpeakerEncoder, SAMPLE_RATE};
+use crate::transcribe::Segment;
+
+/// Options of telling the speakers apart by the embeddings of their speech, see
+/// `audio::speaker`.
+#[derive(Config, Debug)]
+pub struct DiarizationConfig {
+    /// Number of speakers when known, otherwise found by `threshold`. Live streams take it as
+    /// the most speakers there are.
+    pub n_speakers: Option<usize>,
+    /// Cosine distance between the embeddings of two groups of speech up to which they are of
+    /// the same speaker.
+    #[config(default = 0.6)]
+    pub threshold: f64,
+    /// Seconds of audio around shorter segments their embedding is computed of, shorter
+    /// speech says little about its speaker.
+    #[config(default = 1.5)]
+    pub min_duration: f64,
+}
+
+/// Sets the speaker of the segments transcribed from a 16 kHz waveform, numbered in the order
+/// they first speak. The segments are grouped by average linkage agglomerative clustering of
+/// their embeddings.
+pub fn diarize<B: Backend>(
+    encoder: &SpeakerEncoder<B>,
+    waveform: &[f32],
+    segments: &mut [Segment],
+    config: &DiarizationConfig,
+) {
+    let embeddings: Vec<Vec<f32>> = segments
+        .iter()
+        .map(|segment| encoder.embed(segment_audio(waveform, segment, config.min_duration)))
+        .collect();
+
+    let speakers = cluster(&embeddings, config.n_speakers, config.threshold);
+    for (segment, speaker) in segments.iter_mut().zip(speakers) {
+        segment.speaker = Some(speaker);
+    }
+}
+
+/// Audio of the segment, widened around its middle to at least `min_duration` seconds.
+fn segment_audio<'a>(waveform: &'a [f32], segment: &Segment, min_duration: f64) -> &'a [f32] {
+    let middle = (segment.start + segment.end) / 2.0;
+    let half = (segment.end - segment.start).max(min_duration) / 2.0;
+    let to_sample =
+        |seconds: f64| ((seconds.max(0.0) * SAMPLE_RATE as f64) as usize).min(waveform.len());
+
+    let start = to_sample(middle - half);
+    let end = to_sample(middle + half).max(start);
+    &waveform[start..end]
+}
+
+/// Groups the embeddings by average linkage agglomerative clustering on their cosine distance,
+/// into `n_clusters` clusters when given and otherwise until the closest clusters are further
+/// apart than `threshold`. Returns the cluster of every embedding, numbered in the order of
+/// their first embedding.
+pub fn cluster(embeddings: &[Vec<f32>], n_clusters: Option<usize>, threshold: f64) -> Vec<usize> {
+    let n = embeddings.len();
+    let mut distances: Vec<Vec<f64>> = embeddings
+        .iter()
+        .map(|a| embeddings.iter().map(|b| cosine_distance(a, b)).collect())
+        .collect();
+    // embeddings of every cluster, a merged cluster is emptied
+    let mut clusters: Vec<Vec<usize>> = (0..n).map(|i| vec![i]).collect();
+
+    for _ in 0..n.saturating_sub(n_clusters.unwrap_or(1).max(1)) {
+        let mut closest: Option<(usize, usize)> = None;
+        for i in (0..n).filter(|&i| !clusters[i].is_empty()) {
+            for j in (i + 1..n).filter(|&j| !clusters[j].is_empty()) {
+                if closest.map_or(true, |(a, b)| distances[i][j] < distances[a][b]) {
+                    closest = Some((i, j));
+                }
+            }
+        }
+        let Some((i, j)) = closest else {
+            break;
+        };
+        if n_clusters.is_none() && distances[i][j] > threshold {
+            break;
+        }
+
+        // the distance to the merged cluster is the mean distance to its embeddings
+        let n_i = clusters[i].len() as f64;
+        let n_j = clusters[j].len() as f64;
+        for k in 0..n {
+            let distance = (n_i * distances[i][k] + n_j * distances[j][k]) / (n_i + n_j);
+            distances[i][k] = distance;
+            distances[k][i] = distance;
+        }
+        let merged = std::mem::take(&mut clusters[j]);
+        clusters[i].extend(merged);
+    }
+
+    // clusters keep the index of their first embedding, so they are in order already
+    let mut labels = vec![0; n];
+    for (label, members) in clusters.iter().filter(|members| !members.is_empty()).enumerate() {
+        for &i in members {
+            labels[i] = label;
+        }
+    }
+    labels
+}
+
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(&a, &b)| a as f64 * b as f64).sum();
+    let norm = |x: &[f32]| x.iter().map(|&x| (x as f64).powi(2)).sum::<f64>().sqrt();
+    1.0 - dot / (norm(a) * norm(b)).max(f64::EPSILON)
+}
+
+/// Numbers the speakers of a live stream one utterance at a time: an utterance is of the
+/// speaker whose mean embedding is closest, or of a new speaker when none is within the
+/// threshold.
+pub struct SpeakerTracker {
+    config: DiarizationConfig,
+    /// Sum of the normalized embeddings of every speaker, in the direction of their mean.
+    speakers: Vec<Vec<f32>>,
+}
+
+impl SpeakerTracker {
+    pub fn new(config: DiarizationConfig) -> Self {
+        Self {
+            config,
+            speakers: Vec::new(),
+        }
+    }
+
+    /// Returns the speaker of the utterance with the embedding and adds it to the speaker.
+    pub fn assign(&mut self, embedding: &[f32]) -> usize {
+        let closest = self
+            .speakers
+            .iter()
+            .map(|speaker| cosine_distance(speaker, embedding))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        let is_full = self
+            .config
+            .n_speakers
+            .map_or(false, |n_speakers| self.speakers.len() >= n_speakers);
+
+        let norm = embedding.iter().map(|&x| x * x).sum::<f32>().sqrt().max(f32::EPSILON);
+        match closest {
+            Some((speaker, distance)) if distance <= self.config.threshold || is_full => {
+                for (sum, &x) in self.speakers[speaker].iter_mut().zip(embedding) {
+                    *sum += x / norm;
+                }
+                speaker
+            }
+            _ => {
+                self.speakers.push(embedding.iter().map(|&x| x / norm).collect());
+                self.speakers.len() - 1
+            }
+        }
+    }
+}
+
+/// Tells when the speaker of a live stream changes, short of telling the speakers apart, by
+/// the distance between the embeddings of consecutive utterances.
+pub struct SpeakerChangeDetector {
+    threshold: f64,
+    previous: Option<Vec<f32>>,
+}
+
+impl SpeakerChangeDetector {
+    /// Takes utterances further apart than the cosine distance `threshold` for different
+    /// speakers.
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            previous: None,
+        }
+    }
+
+    /// Whether the utterance with the embedding is of another speaker than the one before.
+    pub fn push(&mut self, embedding: Vec<f32>) -> bool {
+        let changed = self
+            .previous
+            .as_ref()
+            .map_or(false, |previous| cosine_distance(previous, &embedding) > self.threshold);
+        self.previous = Some(embedding);
+        changed
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::token;
+
+/// Hugging Face repository the converted models are downloaded from by default.
+pub const DEFAULT_REPO: &str = "Gadersd/whisper-burn";
+
+/// Models converted to Burn's format in `DEFAULT_REPO`, every one in a folder of its name
+/// holding `<name>.mpk.gz`, `<name>.cfg` and `tokenizer.json`.
+pub const MODELS: [&str; 9] = [
+    "tiny", "tiny_en", "base", "base_en", "small", "small_en", "medium", "medium_en", "large-v3",
+];
+
+/// File of a model folder listing the SHA-256 of the other files as `sha256sum` does, written
+/// by `download_model` after unpacking and checked by `verify_model`.
+pub const CHECKSUMS_FILE: &str = "SHA256SUMS";
+
+/// Name of the model an alias such as `large` or `tiny.en` stands for, other names are taken as
+/// they are.
+pub fn resolve_alias(alias: &str) -> &str {
+    match alias {
+        "large" => "large-v3",
+        "tiny.en" => "tiny_en",
+        "base.en" => "base_en",
+        "small.en" => "small_en",
+        "medium.en" => "medium_en",
+        alias => alias,
+    }
+}
+
+/// `$XDG_CACHE_HOME/whisper-burn`, by default in `~/.cache`, or in `%LOCALAPPDATA%` on
+/// Windows, where downloaded models are kept under `models/<name>`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache_dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(cache_dir.join("whisper-burn"))
+}
+
+/// A file of a model folder on the Hub.
+#[derive(Deserialize, Debug)]
+pub struct HubFile {
+    /// `file` or `directory`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Path of the file in the repository.
+    pub path: String,
+    pub size: u64,
+    /// Set for the files stored with Git LFS, the weights.
+    pub lfs: Option<LfsInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LfsInfo {
+    /// SHA-256 of the file as a hex string.
+    pub oid: String,
+}
+
+/// Authenticates with the token in `HF_TOKEN` if set, needed for private repositories.
+fn get(url: &str) -> token::Result<ureq::Response> {
+    let mut request = ureq::get(url);
+    if let Ok(hf_token) = env::var("HF_TOKEN") {
+        request = request.set("Authorization", &format!("Bearer {}", hf_token));
+    }
+    Ok(request.call()?)
+}
+
+/// Lists the files of the folder of the model `name` in the Hub repository `repo`.
+pub fn list_model_files(repo: &str, revision: &str, name: &str) -> token::Result<Vec<HubFile>> {
+    let url = format!("https://huggingface.co/api/models/{}/tree/{}/{}", repo, revision, name);
+    let response = get(&url)
+        .map_err(|e| format!("Failed to list the files of {} in {}: {}", name, repo, e))?;
+    let files: Vec<HubFile> = serde_json::from_reader(response.into_reader())?;
+    let files: Vec<HubFile> = files.into_iter().filter(|file| file.kind == "file").collect();
+    if files.is_empty() {
+        return Err(format!("There is no model {} in {}", name, repo).into());
+    }
+    Ok(files)
+}
+
+/// Downloads the model `name` of the Hub repository `repo` to `dir/<name>`, where it is loaded
+/// from like a model under `models`. Files that are there already are kept if their checksum
+/// was recorded or matches the Hub, downloads are checked against the size and, for the
+/// weights, the SHA-256 the Hub lists and only moved in place when they match. Gzipped files are unpacked. `on_file` is called with every file
+/// before it is downloaded.
+pub fn download_model<F>(
+    repo: &str,
+    revision: &str,
+    name: &str,
+    dir: &Path,
+    mut on_file: F,
+) -> token::Result<PathBuf>
+where
+    F: FnMut(&HubFile),
+{
+    let model_dir = dir.join(name);
+    fs::create_dir_all(&model_dir)
+        .map_err(|e| format!("Failed to create {}: {}", model_dir.display(), e))?;
+    let mut checksums = read_checksums(&model_dir).unwrap_or_default();
+
+    for file in list_model_files(repo, revision, name)? {
+        let file_name = file.path.rsplit('/').next().unwrap_or(&file.path);
+        let unpacked_name = file_name.strip_suffix(".gz");
+        let path = model_dir.join(unpacked_name.unwrap_or(file_name));
+        // files are only moved in place once complete
+        let mut exists = match unpacked_name {
+            Some(_) => path.exists(),
+            None => fs::metadata(&path).map_or(false, |metadata| metadata.len() == file.size),
+        };
+        let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if exists && !checksums.contains_key(&file_name) {
+            // files of an earlier run without a recorded checksum may be corrupted, the weights
+            // are checked against the Hub and the rest, which can't be, downloaded again
+            match file.lfs.as_ref().filter(|_| unpacked_name.is_none()) {
+                Some(lfs) => {
+                    let sha256 = sha256_file(&path)?;
+                    if sha256 != lfs.oid {
+                        return Err(format!(
+                            "{} doesn't match its checksum on the Hub, delete it and download \
+                             again",
+                            path.display()
+                        )
+                        .into());
+                    }
+                    checksums.insert(file_name.clone(), sha256);
+                }
+                None => exists = false,
+            }
+        }
+        if exists {
+            continue;
+        }
+
+        on_file(&file);
+        let url = format!("https://huggingface.co/{}/resolve/{}/{}", repo, revision, file.path);
+        let download_path = model_dir.join(format!("{}.part", file_name));
+        download_file(&url, &download_path, &file)
+            .map_err(|e| format!("Failed to download {}: {}", file.path, e))?;
+        match unpacked_name {
+            Some(unpacked_name) => {
+                let unpacked_path = model_dir.join(format!("{}.part", unpacked_name));
+                unpack(&download_path, &unpacked_path)
+                    .map_err(|e| format!("Failed to unpack {}: {}", file.path, e))?;
+                fs::remove_file(&download_path)?;
+                fs::rename(&unpacked_path, &path)?;
+                checksums.insert(file_name, sha256_file(&path)?);
+            }
+            None => {
+                fs::rename(&download_path, &path)?;
+                let sha256 = match &file.lfs {
+                    Some(lfs) => lfs.oid.clone(),
+                    None => sha256_file(&path)?,
+                };
+                checksums.insert(file_name, sha256);
+            }
+        }
+    }
+    write_checksums(&model_dir, &checksums)
+        .map_err(|e| format!("Failed to write {}: {}", CHECKSUMS_FILE, e))?;
+
+    Ok(model_dir)
+}
+
+/// SHA-256 of the file `path` as a hex string.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut reader = io::BufReader::new(fs::File::open(path)?);
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The checksums listed in the `CHECKSUMS_FILE` of the model folder `dir` by file name.
+pub fn read_checksums(dir: &Path) -> token::Result<BTreeMap<String, String>> {
+    let path = dir.join(CHECKSUMS_FILE);
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.split_once("  ") {
+            Some((sha256, file_name)) => Ok((file_name.to_string(), sha256.to_string())),
+            None => Err(format!("Invalid line in {}: {}", path.display(), line).into()),
+        })
+        .collect()
+}
+
+/// Writes `checksums` by file name to the `CHECKSUMS_FILE` of the model folder `dir`.
+pub fn write_checksums(dir: &Path, checksums: &BTreeMap<String, String>) -> io::Result<()> {
+    let text: String = checksums
+        .iter()
+        .map(|(file_name, sha256)| format!("{}  {}\n", sha256, file_name))
+        .collect();
+    fs::write(dir.join(CHECKSUMS_FILE), text)
+}
+
+/// A file of a model folder checked against its listed checksum.
+#[derive(Debug)]
+pub struct FileCheck {
+    pub file_name: String,
+    pub expected: String,
+    /// SHA-256 of the file, `None` if it is missing.
+    pub actual: Option<String>,
+}
+
+impl FileCheck {
+    pub fn is_ok(&self) -> bool {
+        self.actual.as_ref() == Some(&self.expected)
+    }
+}
+
+/// Checks the files of the model folder `dir` against the SHA-256 listed in its
+/// `CHECKSUMS_FILE`. `on_file` is called with the name of every file before it is hashed.
+pub fn verify_model<F>(dir: &Path, mut on_file: F) -> token::Result<Vec<FileCheck>>
+where
+    F: FnMut(&str),
+{
+    read_checksums(dir)?
+        .into_iter()
+        .map(|(file_name, expected)| {
+            on_file(&file_name);
+            let path = dir.join(&file_name);
+            let actual = match path.exists() {
+                true => Some(
+                    sha256_file(&path)
+                        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+                ),
+                false => None,
+            };
+            Ok(FileCheck {
+                file_name,
+                expected,
+                actual,
+            })
+        })
+        .collect()
+}
+
+/// Downloads `url` to `path`, checking it against `file` as it is written.
+fn download_file(url: &str, path: &Path, file: &HubFile) -> token::Result<()> {
+    let mut reader = get(url)?.into_reader();
+    let mut writer = io::BufWriter::new(fs::File::create(path)?);
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    writer.flush()?;
+
+    if size != file.size {
+        return Err(format!("got {} bytes instead of {}", size, file.size).into());
+    }
+    if let Some(lfs) = &file.lfs {
+        let sha256: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        if sha256 != lfs.oid {
+            return Err(format!("checksum {} doesn't match {}", sha256, lfs.oid).into());
+        }
+    }
+    Ok(())
+}
+
+fn unpack(gz_path: &Path, path: &Path) -> io::Result<()> {
+    let mut reader = GzDecoder::new(io::BufReader::new(fs::File::open(gz_path)?));
+    let mut writer = io::BufWriter::new(fs::File::create(path)?);
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()
+}
@@ -1,6 +1,15 @@
 pub mod audio;
+/// The generic beam search the decoder used before it moved into `transcribe`, kept for code
+/// calling it directly. Decoding is configured with `DecodingOptions::beam_size` instead.
 pub mod beam;
+pub mod diarization;
 pub mod helper;
+pub mod hub;
+pub mod logit_filter;
 pub mod model;
+pub mod output;
+pub mod rescorer;
+pub mod streaming;
+pub mod timestamps;
 pub mod token;
 pub mod transcribe;
@@ -0,0 +1,448 @@
+use crate::token;
+use regex_automata::{
+    dfa::{dense, Automaton},
+    util::{primitives::StateID, start},
+    Anchored,
+};
+use std::f32::NEG_INFINITY;
+
+/// A transformation of the next token logits applied at every decoding step.
+pub trait LogitFilter {
+    /// `tokens` is the sequence decoded so far, including the prompt, and `logits` holds the
+    /// logits of the next token over the whole vocabulary.
+    fn apply(&self, logits: &mut [f32], tokens: &[usize]);
+}
+
+/// Masks out special tokens for the first `n_steps` sampled tokens so the decoder starts with
+/// actual text.
+pub struct SuppressInitialSpecialTokens {
+    pub maskout: Vec<f32>,
+    pub sample_begin: usize,
+    pub n_steps: usize,
+}
+
+impl LogitFilter for SuppressInitialSpecialTokens {
+    fn apply(&self, logits: &mut [f32], tokens: &[usize]) {
+        if tokens.len() < self.sample_begin + self.n_steps {
+            logits
+                .iter_mut()
+                .zip(&self.maskout)
+                .for_each(|(logit, mask)| *logit += mask);
+        }
+    }
+}
+
+/// Masks out a fixed set of tokens at every step, e.g. non-speech symbols or a user provided
+/// list.
+pub struct SuppressTokens {
+    pub tokens: Vec<usize>,
+}
+
+impl LogitFilter for SuppressTokens {
+    fn apply(&self, logits: &mut [f32], _tokens: &[usize]) {
+        for &token in &self.tokens {
+            if let Some(logit) = logits.get_mut(token) {
+                *logit = NEG_INFINITY;
+            }
+        }
+    }
+}
+
+/// Keeps the first sampled token from being a blank, i.e. a lone space or end of text, which
+/// would produce an empty transcription.
+pub struct SuppressBlank {
+    pub blank_tokens: Vec<usize>,
+    pub sample_begin: usize,
+}
+
+impl LogitFilter for SuppressBlank {
+    fn apply(&self, logits: &mut [f32], tokens: &[usize]) {
+        if tokens.len() == self.sample_begin {
+            for &token in &self.blank_tokens {
+                if let Some(logit) = logits.get_mut(token) {
+                    *logit = NEG_INFINITY;
+                }
+            }
+        }
+    }
+}
+
+/// Adds `bias` to the logits of tokens that start or continue one of `phrases`, so that known
+/// names and terms are preferred over similar sounding words.
+pub struct BoostPhrases {
+    pub phrases: Vec<Vec<usize>>,
+    pub bias: f32,
+    pub sample_begin: usize,
+}
+
+impl LogitFilter for BoostPhrases {
+    fn apply(&self, logits: &mut [f32], tokens: &[usize]) {
+        let sampled = &tokens[self.sample_begin.min(tokens.len())..];
+
+        for phrase in &self.phrases {
+            // longest phrase prefix the sampled tokens end with, the next phrase token is boosted
+            let matched = (1..phrase.len())
+                .rev()
+                .find(|&n| sampled.ends_with(&phrase[..n]))
+                .unwrap_or(0);
+
+            if let Some(logit) = logits.get_mut(phrase[matched]) {
+                *logit += self.bias;
+            }
+        }
+    }
+}
+
+/// Restricts the decoded text to a regular expression, e.g. digits only or a fixed set of
+/// commands, by masking every text token that can no longer lead to a match. End of text is
+/// only allowed once the text matches. Timestamp tokens are left untouched.
+pub struct ConstrainToRegex {
+    dfa: dense::DFA<Vec<u32>>,
+    start_state: StateID,
+    token_bytes: Vec<Vec<u8>>,
+    sample_begin: usize,
+    end_token: usize,
+}
+
+impl ConstrainToRegex {
+    /// `token_bytes` holds the bytes of every text token, i.e. of all tokens below `end_token`.
+    /// Whitespace around the pattern is allowed since decoded text starts with a space.
+    pub fn new(
+        pattern: &str,
+        token_bytes: Vec<Vec<u8>>,
+        sample_begin: usize,
+        end_token: usize,
+    ) -> token::Result<Self> {
+        let dfa = dense::DFA::new(&format!(r"\s*(?:{})\s*", pattern))?;
+        let start_state = dfa.start_state(&start::Config::new().anchored(Anchored::Yes))?;
+
+        Ok(Self {
+            dfa,
+            start_state,
+            token_bytes,
+            sample_begin,
+            end_token,
+        })
+    }
+
+    /// Advances `state` over `bytes`, returning `None` once no match is possible anymore.
+    fn walk(&self, mut state: StateID, bytes: &[u8]) -> Option<StateID> {
+        for &byte in bytes {
+            state = self.dfa.next_state(state, byte);
+            if self.dfa.is_dead_state(state) || self.dfa.is_quit_state(state) {
+                return None;
+            }
+        }
+
+        Some(state)
+    }
+}
+
+impl LogitFilter for ConstrainToRegex {
+    fn apply(&self, logits: &mut [f32], tokens: &[usize]) {
+        let sampled = &tokens[self.sample_begin.min(tokens.len())..];
+        let text_bytes: Vec<u8> = sampled
+            .iter()
+            .filter(|&&token| token < self.end_token)
+            .flat_map(|&token| self.token_bytes[token].iter().cloned())
+            .collect();
+
+        let state = match self.walk(self.start_state, &text_bytes) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let n_text = self.end_token.min(logits.len()).min(self.token_bytes.len());
+        for token in 0..n_text {
+            if logits[token] > NEG_INFINITY
+                && self.walk(state, &self.token_bytes[token]).is_none()
+            {
+                logits[token] = NEG_INFINITY;
+            }
+        }
+
+        if !self.dfa.is_match_state(self.dfa.next_eoi_state(state)) {
+            if let Some(logit) = logits.get_mut(self.end_token) {
+                *logit = NEG_INFINITY;
+            }
+        }
+    }
+}
+
+/// Caps the length of a segment. Once a segment holds `max_tokens` text tokens it has to be
+/// closed by a timestamp, or the window ended when decoding without timestamps, and a segment
+/// can't be closed by a timestamp more than `max_timestamp_span` timestamps after its start.
+pub struct LimitSegmentLength {
+    pub max_tokens: Option<usize>,
+    pub max_timestamp_span: Option<usize>,
+    pub sample_begin: usize,
+    pub timestamp_begin: usize,
+    pub end_token: usize,
+    pub without_timestamps: bool,
+}
+
+impl LogitFilter for LimitSegmentLength {
+    fn apply(&self, logits: &mut [f32], tokens: &[usize]) {
+        let n_vocab = logits.len();
+        let sampled = &tokens[self.sample_begin.min(tokens.len())..];
+        let is_timestamp =
+            |token: &usize| !self.without_timestamps && *token >= self.timestamp_begin;
+
+        let last_timestamp_index = sampled.iter().rposition(is_timestamp);
+        let since_timestamp = &sampled[last_timestamp_index.map_or(0, |i| i + 1)..];
+        let n_text = since_timestamp
+            .iter()
+            .filter(|&&token| token < self.end_token)
+            .count();
+
+        if let Some(max_tokens) = self.max_tokens {
+            if n_text >= max_tokens {
+                mask(&mut logits[..self.end_token.min(n_vocab)]);
+                if self.without_timestamps && self.end_token + 1 < n_vocab {
+                    mask(&mut logits[self.end_token + 1..]);
+                }
+            }
+        }
+
+        // only an open segment, a timestamp followed by text, can be closed
+        if let (Some(max_span), Some(index)) = (self.max_timestamp_span, last_timestamp_index) {
+            if n_text > 0 {
+                let last_allowed = sampled[index] + max_span;
+                if last_allowed + 1 < n_vocab {
+                    mask(&mut logits[last_allowed + 1..]);
+                }
+            }
+        }
+    }
+}
+
+/// Enforces the timestamp token grammar of Whisper: timestamps come in pairs around text,
+/// never decrease, and the first one may not be later than `max_initial_timestamp_index`.
+pub struct ApplyTimestampRules {
+    pub sample_begin: usize,
+    pub timestamp_begin: usize,
+    pub end_token: usize,
+    pub no_timestamps_token: usize,
+    pub max_initial_timestamp_index: Option<usize>,
+}
+
+impl LogitFilter for ApplyTimestampRules {
+    fn apply(&self, logits: &mut [f32], tokens: &[usize]) {
+        let n_vocab = logits.len();
+        let timestamp_begin = self.timestamp_begin.min(n_vocab);
+
+        logits[self.no_timestamps_token] = NEG_INFINITY;
+
+        let sampled = &tokens[self.sample_begin.min(tokens.len())..];
+        let is_timestamp = |token: &usize| *token >= timestamp_begin;
+
+        let last_was_timestamp = sampled.last().map_or(false, is_timestamp);
+        let penultimate_was_timestamp =
+            sampled.len() < 2 || is_timestamp(&sampled[sampled.len() - 2]);
+
+        if last_was_timestamp {
+            if penultimate_was_timestamp {
+                // a timestamp pair was just closed, the next token has to be text
+                mask(&mut logits[timestamp_begin..]);
+            } else {
+                // a segment was just opened, it cannot be followed by text ending the window
+                mask(&mut logits[..self.end_token]);
+            }
+        }
+
+        // timestamps have to be monotonically increasing
+        if let Some(&last_timestamp) = sampled.iter().filter(|t| is_timestamp(t)).last() {
+            let timestamp_last = if last_was_timestamp && !penultimate_was_timestamp {
+                last_timestamp
+            } else {
+                last_timestamp + 1
+            };
+            mask(&mut logits[timestamp_begin..timestamp_last.min(n_vocab)]);
+        }
+
+        if sampled.is_empty() {
+            // the first sampled token has to be a timestamp
+            mask(&mut logits[..timestamp_begin]);
+
+            if let Some(max_initial) = self.max_initial_timestamp_index {
+                let last_allowed = timestamp_begin + max_initial;
+                if last_allowed + 1 < n_vocab {
+                    mask(&mut logits[last_allowed + 1..]);
+                }
+            }
+        }
+
+        // sample a timestamp if its total probability exceeds that of any single text token
+        let max_logit = logits.iter().cloned().fold(NEG_INFINITY, f32::max);
+        if max_logit == NEG_INFINITY {
+            return;
+        }
+        let log_sum_exp = |logits: &[f32]| {
+            logits
+                .iter()
+                .map(|&l| ((l - max_logit) as f64).exp())
+                .sum::<f64>()
+                .ln()
+        };
+
+        let timestamp_logprob = log_sum_exp(&logits[timestamp_begin..]);
+        let max_text_logprob = logits[..timestamp_begin]
+            .iter()
+            .map(|&l| (l - max_logit) as f64)
+            .fold(f64::NEG_INFINITY, f64::max);
+        if timestamp_logprob > max_text_logprob {
+            mask(&mut logits[..timestamp_begin]);
+        }
+    }
+}
+
+fn mask(logits: &mut [f32]) {
+    logits.iter_mut().for_each(|logit| *logit = NEG_INFINITY);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // text tokens 0 to 4, end of text 5, no timestamps 6 and timestamps 7 to 11, with the prompt
+    // being the single token 0
+    const END_TOKEN: usize = 5;
+    const NO_TIMESTAMPS_TOKEN: usize = 6;
+    const TIMESTAMP_BEGIN: usize = 7;
+    const N_VOCAB: usize = 12;
+    const SAMPLE_BEGIN: usize = 1;
+
+    /// Tokens the filter masks out given the logits it starts from.
+    fn masked(filter: &dyn LogitFilter, mut logits: Vec<f32>, tokens: &[usize]) -> Vec<usize> {
+        filter.apply(&mut logits, tokens);
+        (0..logits.len())
+            .filter(|&token| logits[token] == NEG_INFINITY)
+            .collect()
+    }
+
+    /// Text more likely than any timestamp, so the timestamp probability rule stays out of
+    /// the way.
+    fn text_logits() -> Vec<f32> {
+        (0..N_VOCAB)
+            .map(|token| if token < TIMESTAMP_BEGIN { 10.0 } else { 0.0 })
+            .collect()
+    }
+
+    #[test]
+    fn timestamp_rules() {
+        let filter = ApplyTimestampRules {
+            sample_begin: SAMPLE_BEGIN,
+            timestamp_begin: TIMESTAMP_BEGIN,
+            end_token: END_TOKEN,
+            no_timestamps_token: NO_TIMESTAMPS_TOKEN,
+            max_initial_timestamp_index: Some(1),
+        };
+        let cases: [(&[usize], &[usize]); 4] = [
+            // the first token is one of the first two timestamps
+            (&[0], &[0, 1, 2, 3, 4, 5, 6, 9, 10, 11]),
+            // the initial timestamp is followed by text
+            (&[0, 7], &[6, 7, 8, 9, 10, 11]),
+            // text can be followed by text or a later timestamp
+            (&[0, 7, 1], &[6, 7]),
+            // a closing timestamp is followed by the next opening one or the end of text
+            (&[0, 7, 1, 9], &[0, 1, 2, 3, 4, 6, 7, 8]),
+        ];
+        for (tokens, expected) in cases {
+            assert_eq!(masked(&filter, text_logits(), tokens), expected, "{:?}", tokens);
+        }
+
+        // timestamps together more likely than any text token
+        let logits = (0..N_VOCAB)
+            .map(|token| if token < TIMESTAMP_BEGIN { 0.0 } else { 1.0 })
+            .collect();
+        assert_eq!(masked(&filter, logits, &[0, 7, 1]), [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn suppress_blank_at_the_start() {
+        let filter = SuppressBlank {
+            blank_tokens: vec![1, END_TOKEN],
+            sample_begin: SAMPLE_BEGIN,
+        };
+        let cases: [(&[usize], &[usize]); 2] = [(&[0], &[1, END_TOKEN]), (&[0, 2], &[])];
+        for (tokens, expected) in cases {
+            assert_eq!(masked(&filter, vec![0.0; N_VOCAB], tokens), expected, "{:?}", tokens);
+        }
+    }
+
+    #[test]
+    fn boost_phrases_next_token() {
+        let filter = BoostPhrases {
+            phrases: vec![vec![2, 3, 4]],
+            bias: 1.0,
+            sample_begin: SAMPLE_BEGIN,
+        };
+        let cases: [(&[usize], usize); 4] = [
+            (&[0], 2),
+            (&[0, 2], 3),
+            (&[0, 1, 2, 3], 4),
+            // a phrase that broke off starts over
+            (&[0, 2, 1], 2),
+        ];
+        for (tokens, boosted) in cases {
+            let mut logits = vec![0.0; N_VOCAB];
+            filter.apply(&mut logits, tokens);
+            let expected: Vec<f32> = (0..N_VOCAB)
+                .map(|token| if token == boosted { 1.0 } else { 0.0 })
+                .collect();
+            assert_eq!(logits, expected, "{:?}", tokens);
+        }
+    }
+
+    #[test]
+    fn constrain_to_digits() {
+        let token_bytes = [" 1", "2", "a", " ", "12"]
+            .iter()
+            .map(|text| text.as_bytes().to_vec())
+            .collect();
+        let filter = ConstrainToRegex::new("[0-9]+", token_bytes, SAMPLE_BEGIN, END_TOKEN).unwrap();
+        let cases: [(&[usize], &[usize]); 3] = [
+            // no text yet, which doesn't match
+            (&[0], &[2, END_TOKEN]),
+            // the number may go on, or end
+            (&[0, 0], &[0, 2]),
+            // trailing whitespace ends the number, timestamps are left alone
+            (&[0, 0, 3, 7], &[0, 1, 2, 4]),
+        ];
+        for (tokens, expected) in cases {
+            assert_eq!(masked(&filter, vec![0.0; N_VOCAB], tokens), expected, "{:?}", tokens);
+        }
+    }
+
+    #[test]
+    fn limit_segment_length() {
+        let filter = LimitSegmentLength {
+            max_tokens: Some(2),
+            max_timestamp_span: Some(2),
+            sample_begin: SAMPLE_BEGIN,
+            timestamp_begin: TIMESTAMP_BEGIN,
+            end_token: END_TOKEN,
+            without_timestamps: false,
+        };
+        let cases: [(&[usize], &[usize]); 3] = [
+            // no segment is open
+            (&[0, 7], &[]),
+            // the segment has to be closed within two timestamps of its start
+            (&[0, 7, 1], &[10, 11]),
+            // and now, as it is full
+            (&[0, 7, 1, 2], &[0, 1, 2, 3, 4, 10, 11]),
+        ];
+        for (tokens, expected) in cases {
+            assert_eq!(masked(&filter, vec![0.0; N_VOCAB], tokens), expected, "{:?}", tokens);
+        }
+
+        // without timestamps a full segment ends the window
+        let filter = LimitSegmentLength {
+            max_timestamp_span: None,
+            without_timestamps: true,
+            ..filter
+        };
+        let expected: Vec<usize> = (0..N_VOCAB).filter(|&token| token != END_TOKEN).collect();
+        assert_eq!(masked(&filter, vec![0.0; N_VOCAB], &[0, 1, 2]), expected);
+    }
+}
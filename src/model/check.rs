@@ -0,0 +1,99 @@
+use burn::tensor::{backend::Backend, Tensor};
+use std::{error::Error, fmt};
+
+use super::{
+    checkpoint::{visit_tensors, TensorVisitor},
+    quantize::QuantizableLinear,
+    Whisper,
+};
+
+/// Name and size of a tensor of a model as it is stored.
+pub type TensorShape = (String, Vec<usize>);
+
+/// A tensor of a loaded model that doesn't match the model its config describes, e.g. because
+/// the config and the weights belong to different models.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TensorMismatch {
+    /// The weights have no such tensor, or one Burn dropped on loading, such as the plain
+    /// weight of a layer the config says is quantized.
+    Missing { name: String, expected: Vec<usize> },
+    /// The weights hold the tensor with another size.
+    Shape {
+        name: String,
+        expected: Vec<usize>,
+        found: Vec<usize>,
+    },
+}
+
+impl fmt::Display for TensorMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TensorMismatch::Missing { name, expected } => {
+                write!(f, "tensor {} of size {:?} is missing", name, expected)
+            }
+            TensorMismatch::Shape {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "tensor {} has size {:?} instead of {:?}",
+                name, found, expected
+            ),
+        }
+    }
+}
+
+impl Error for TensorMismatch {}
+
+/// Lists the shapes of the tensors, quantized weights as they are packed.
+struct ShapeVisitor(Vec<TensorShape>);
+
+impl<B: Backend> TensorVisitor<B> for ShapeVisitor {
+    fn visit<const D: usize>(&mut self, name: &str, tensor: Tensor<B, D>) {
+        self.0.push((name.to_string(), tensor.dims().to_vec()));
+    }
+
+    fn visit_linear(&mut self, name: &str, linear: &QuantizableLinear<B>) {
+        for (tensor_name, dims) in linear.shapes() {
+            self.0.push((format!("{}.{}", name, tensor_name), dims));
+        }
+    }
+}
+
+impl<B: Backend> Whisper<B> {
+    /// The tensors of the model under their openai/whisper names with their sizes as stored.
+    /// Taken from a model initialized from its config before its record is loaded, they tell
+    /// what `check_tensors` expects.
+    pub fn tensor_shapes(&self) -> Vec<TensorShape> {
+        let mut visitor = ShapeVisitor(Vec::new());
+        visit_tensors(self, &mut visitor);
+        visitor.0
+    }
+
+    /// Checks that the model has the tensors of `expected` with the same sizes. Burn loads a
+    /// record into a model of another size without complaint, so a mismatch would otherwise
+    /// only surface as a panic in the middle of a forward pass.
+    pub fn check_tensors(&self, expected: &[TensorShape]) -> Result<(), TensorMismatch> {
+        let found = self.tensor_shapes();
+        for (name, expected) in expected {
+            match found.iter().find(|(found_name, _)| found_name == name) {
+                None => {
+                    return Err(TensorMismatch::Missing {
+                        name: name.clone(),
+                        expected: expected.clone(),
+                    })
+                }
+                Some((_, found)) if found != expected => {
+                    return Err(TensorMismatch::Shape {
+                        name: name.clone(),
+                        expected: expected.clone(),
+                        found: found.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
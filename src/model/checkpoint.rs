@@ -0,0 +1,498 @@
+use burn::{
+    module::{Module, Param},
+    nn::{
+        self,
+        conv::{Conv1d, Conv1dConfig, Conv1dRecord},
+        PaddingConfig1d,
+    },
+    tensor::{backend::Backend, Shape, Tensor},
+};
+use memmap2::Mmap;
+use std::{error::Error, fs::File};
+
+use super::*;
+
+/// Width of the attention heads of every Whisper model, which gives the number of heads.
+const HEAD_DIM: usize = 64;
+
+const LAYER_NORM_EPS: f64 = 1e-5;
+
+/// How the tensors of a checkpoint are named.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// The state dict of openai/whisper, e.g. `encoder.blocks.0.attn.query.weight`.
+    OpenAi,
+    /// `WhisperForConditionalGeneration` of Hugging Face transformers, e.g.
+    /// `model.encoder.layers.0.self_attn.q_proj.weight`.
+    HuggingFace,
+}
+
+/// Name of the tensor of a Hugging Face checkpoint that is named `name` in openai/whisper.
+fn hugging_face_name(name: &str) -> String {
+    let renames = [
+        (".attn.query.", ".self_attn.q_proj."),
+        (".attn.key.", ".self_attn.k_proj."),
+        (".attn.value.", ".self_attn.v_proj."),
+        (".attn.out.", ".self_attn.out_proj."),
+        (".cross_attn.query.", ".encoder_attn.q_proj."),
+        (".cross_attn.key.", ".encoder_attn.k_proj."),
+        (".cross_attn.value.", ".encoder_attn.v_proj."),
+        (".cross_attn.out.", ".encoder_attn.out_proj."),
+        (".attn_ln.", ".self_attn_layer_norm."),
+        (".cross_attn_ln.", ".encoder_attn_layer_norm."),
+        (".mlp.0.", ".fc1."),
+        (".mlp.2.", ".fc2."),
+        (".mlp_ln.", ".final_layer_norm."),
+        (".blocks.", ".layers."),
+        ("encoder.ln_post.", "encoder.layer_norm."),
+        ("decoder.ln.", "decoder.layer_norm."),
+        ("decoder.token_embedding.", "decoder.embed_tokens."),
+    ];
+    let name = match name {
+        "encoder.positional_embedding" => "encoder.embed_positions.weight".to_string(),
+        "decoder.positional_embedding" => "decoder.embed_positions.weight".to_string(),
+        name => renames
+            .iter()
+            .fold(name.to_string(), |name, (from, to)| name.replace(from, to)),
+    };
+    format!("model.{}", name)
+}
+
+/// Maps the checkpoint file `path` into memory. The tensors are read from disk as the modules
+/// are built from them, so the file is never held in memory as a whole next to the model.
+pub(crate) fn map_file(path: &str) -> Result<Mmap, Box<dyn Error>> {
+    let file = File::open(path)?;
+    // the file must not be truncated while the model loads
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(map)
+}
+
+/// The tensors of a checkpoint file, in `f32` whatever type they are stored in.
+pub(crate) trait TensorSource {
+    fn names(&self) -> Vec<String>;
+
+    /// Shape of the tensor `name` in PyTorch's order, `None` if there is no such tensor.
+    fn dims(&self, name: &str) -> Option<Vec<usize>>;
+
+    fn values(&self, name: &str) -> Result<Vec<f32>, Box<dyn Error>>;
+}
+
+/// Builds a Whisper model from the tensors of a checkpoint, read by their openai/whisper names,
+/// with the config inferred from their shapes.
+pub(crate) struct Checkpoint<S> {
+    source: S,
+    layout: Layout,
+}
+
+impl<S: TensorSource> Checkpoint<S> {
+    /// Tells the layout of the checkpoint by the names of its tensors.
+    pub(crate) fn new(source: S) -> Result<Self, Box<dyn Error>> {
+        let names = source.names();
+        let layout = if names.iter().any(|name| name.starts_with("model.encoder.")) {
+            Layout::HuggingFace
+        } else if names.iter().any(|name| name.starts_with("encoder.blocks.")) {
+            Layout::OpenAi
+        } else {
+            return Err("Not a Whisper checkpoint of openai/whisper or Hugging Face".into());
+        };
+        Ok(Self { source, layout })
+    }
+
+    pub(crate) fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub(crate) fn whisper<B: Backend>(
+        &self,
+    ) -> Result<(Whisper<B>, WhisperConfig), Box<dyn Error>> {
+        let (encoder, encoder_config) = self.audio_encoder()?;
+        let (decoder, decoder_config) = self.text_decoder()?;
+        let whisper = Whisper { encoder, decoder };
+
+        let config = WhisperConfig {
+            audio_encoder_config: encoder_config,
+            text_decoder_config: decoder_config,
+            quantization: None,
+        };
+
+        Ok((whisper, config))
+    }
+
+    fn key(&self, name: &str) -> String {
+        match self.layout {
+            Layout::OpenAi => name.to_string(),
+            Layout::HuggingFace => hugging_face_name(name),
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.source.dims(&self.key(name)).is_some()
+    }
+
+    fn tensor<B: Backend, const D: usize>(
+        &self,
+        name: &str,
+    ) -> Result<Tensor<B, D>, Box<dyn Error>> {
+        let key = self.key(name);
+        let mut dims = self
+            .source
+            .dims(&key)
+            .ok_or_else(|| format!("Missing tensor {}", key))?;
+        // whisper.cpp stores the convolution biases as (n_state, 1)
+        while dims.len() > D && dims.last() == Some(&1) {
+            dims.pop();
+        }
+        if dims.len() != D {
+            let message = format!("{} has {} dimensions instead of {}", key, dims.len(), D);
+            return Err(message.into());
+        }
+        let shape: Shape<D> = dims.into();
+
+        let tensor_device_ref = Default::default();
+        let values = self.source.values(&key)?;
+        Ok(Tensor::from_floats(&values[..], &tensor_device_ref).reshape(shape))
+    }
+
+    /// Number of blocks of the `encoder` or the `decoder`.
+    fn n_layer(&self, prefix: &str) -> usize {
+        (0..)
+            .take_while(|i| self.contains(&format!("{}.blocks.{}.attn_ln.weight", prefix, i)))
+            .count()
+    }
+
+    fn linear<B: Backend>(&self, name: &str) -> Result<nn::Linear<B>, Box<dyn Error>> {
+        // Burn linear weights are (in, out)
+        let weight = self
+            .tensor::<B, 2>(&format!("{}.weight", name))?
+            .transpose();
+        // the key projections have no bias
+        let bias_name = format!("{}.bias", name);
+        let bias = match self.contains(&bias_name) {
+            true => Some(self.tensor::<B, 1>(&bias_name)?),
+            false => None,
+        };
+        let tensor_device_ref = weight.device();
+
+        let record = nn::LinearRecord {
+            weight: Param::from_tensor(weight),
+            bias: bias.map(|t| Param::from_tensor(t)),
+        };
+
+        let linear: nn::Linear<B> = nn::LinearConfig::new(3, 3)
+            .init(&tensor_device_ref)
+            .load_record(record);
+        Ok(linear)
+    }
+
+    fn layer_norm<B: Backend>(&self, name: &str) -> Result<nn::LayerNorm<B>, Box<dyn Error>> {
+        let weight = Param::from_tensor(self.tensor::<B, 1>(&format!("{}.weight", name))?);
+        let bias = Param::from_tensor(self.tensor::<B, 1>(&format!("{}.bias", name))?);
+        let tensor_device_ref = weight.device();
+
+        let [n_state] = weight.dims();
+
+        let record = nn::LayerNormRecord {
+            gamma: weight,
+            beta: bias,
+            epsilon: <f64 as Module<B>>::into_record(LAYER_NORM_EPS),
+        };
+
+        let layer_norm: nn::LayerNorm<B> = nn::LayerNormConfig::new(n_state)
+            .init(&tensor_device_ref)
+            .load_record(record);
+        Ok(layer_norm)
+    }
+
+    fn conv1d<B: Backend>(
+        &self,
+        name: &str,
+        config: Conv1dConfig,
+    ) -> Result<Conv1d<B>, Box<dyn Error>> {
+        let weight = Param::from_tensor(self.tensor::<B, 3>(&format!("{}.weight", name))?);
+        let bias = Param::from_tensor(self.tensor::<B, 1>(&format!("{}.bias", name))?);
+        let tensor_device_ref = weight.device();
+
+        let record = Conv1dRecord {
+            weight,
+            bias: Some(bias),
+            stride: <usize as Module<B>>::into_record(1),
+            kernel_size: <usize as Module<B>>::into_record(1),
+            dilation: <usize as Module<B>>::into_record(1),
+            groups: <usize as Module<B>>::into_record(1),
+            padding: <usize as Module<B>>::into_record(10),
+        };
+
+        Ok(config.init(&tensor_device_ref).load_record(record))
+    }
+
+    fn mlp<B: Backend>(&self, name: &str) -> Result<MLP<B>, Box<dyn Error>> {
+        Ok(MLP {
+            lin1: self.linear(&format!("{}.0", name))?.into(),
+            gelu: nn::Gelu::new(),
+            lin2: self.linear(&format!("{}.2", name))?.into(),
+        })
+    }
+
+    fn self_attention<B: Backend>(
+        &self,
+        name: &str,
+    ) -> Result<MultiHeadSelfAttention<B>, Box<dyn Error>> {
+        let query: nn::Linear<B> = self.linear(&format!("{}.query", name))?;
+        let [_, n_state] = query.weight.dims();
+        Ok(MultiHeadSelfAttention {
+            n_head: n_state / HEAD_DIM,
+            query: query.into(),
+            key: self.linear(&format!("{}.key", name))?.into(),
+            value: self.linear(&format!("{}.value", name))?.into(),
+            out: self.linear(&format!("{}.out", name))?.into(),
+        })
+    }
+
+    fn cross_attention<B: Backend>(
+        &self,
+        name: &str,
+    ) -> Result<MultiHeadCrossAttention<B>, Box<dyn Error>> {
+        let query: nn::Linear<B> = self.linear(&format!("{}.query", name))?;
+        let [_, n_state] = query.weight.dims();
+        Ok(MultiHeadCrossAttention {
+            n_head: n_state / HEAD_DIM,
+            query: query.into(),
+            key: self.linear(&format!("{}.key", name))?.into(),
+            value: self.linear(&format!("{}.value", name))?.into(),
+            out: self.linear(&format!("{}.out", name))?.into(),
+        })
+    }
+
+    fn audio_encoder<B: Backend>(
+        &self,
+    ) -> Result<(AudioEncoder<B>, AudioEncoderConfig), Box<dyn Error>> {
+        let conv1_weight = self.tensor::<B, 3>("encoder.conv1.weight")?;
+        let [n_audio_state, n_mels, _] = conv1_weight.dims();
+
+        let conv1_config =
+            Conv1dConfig::new(n_mels, n_audio_state, 3).with_padding(PaddingConfig1d::Explicit(1));
+        let conv2_config = Conv1dConfig::new(n_audio_state, n_audio_state, 3)
+            .with_padding(PaddingConfig1d::Explicit(1))
+            .with_stride(2);
+
+        let conv1 = self.conv1d("encoder.conv1", conv1_config)?;
+        let conv2 = self.conv1d("encoder.conv2", conv2_config)?;
+
+        let n_layer = self.n_layer("encoder");
+        let blocks = (0..n_layer)
+            .map(|i| {
+                let name = format!("encoder.blocks.{}", i);
+                Ok(ResidualEncoderAttentionBlock {
+                    attn: self.self_attention(&format!("{}.attn", name))?,
+                    attn_ln: self.layer_norm(&format!("{}.attn_ln", name))?,
+                    mlp: self.mlp(&format!("{}.mlp", name))?,
+                    mlp_ln: self.layer_norm(&format!("{}.mlp_ln", name))?,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let positional_embedding = self.tensor::<B, 2>("encoder.positional_embedding")?;
+        let [n_audio_ctx, _] = positional_embedding.dims();
+
+        let encoder = AudioEncoder {
+            conv1,
+            gelu1: nn::Gelu::new(),
+            conv2,
+            gelu2: nn::Gelu::new(),
+            blocks,
+            ln_post: self.layer_norm("encoder.ln_post")?,
+            positional_embedding: Param::from_tensor(positional_embedding),
+            n_mels,
+            n_audio_ctx,
+        };
+
+        let config = AudioEncoderConfig {
+            n_mels,
+            n_audio_ctx,
+            n_audio_state,
+            n_audio_head: n_audio_state / HEAD_DIM,
+            n_audio_layer: n_layer,
+        };
+
+        Ok((encoder, config))
+    }
+
+    fn text_decoder<B: Backend>(
+        &self,
+    ) -> Result<(TextDecoder<B>, TextDecoderConfig), Box<dyn Error>> {
+        let token_embedding = self.tensor::<B, 2>("decoder.token_embedding.weight")?;
+        let positional_embedding = self.tensor::<B, 2>("decoder.positional_embedding")?;
+        let tensor_device_ref = token_embedding.device();
+
+        let n_layer = self.n_layer("decoder");
+        let blocks = (0..n_layer)
+            .map(|i| {
+                let name = format!("decoder.blocks.{}", i);
+                Ok(ResidualDecoderAttentionBlock {
+                    attn: self.self_attention(&format!("{}.attn", name))?,
+                    attn_ln: self.layer_norm(&format!("{}.attn_ln", name))?,
+                    cross_attn: self.cross_attention(&format!("{}.cross_attn", name))?,
+                    cross_attn_ln: self.layer_norm(&format!("{}.cross_attn_ln", name))?,
+                    mlp: self.mlp(&format!("{}.mlp", name))?,
+                    mlp_ln: self.layer_norm(&format!("{}.mlp_ln", name))?,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let [n_text_ctx, n_text_state] = positional_embedding.dims();
+        let [n_vocab, _] = token_embedding.dims();
+
+        let decoder = TextDecoder {
+            token_embedding: Param::from_tensor(token_embedding),
+            positional_embedding: Param::from_tensor(positional_embedding),
+            blocks,
+            ln: self.layer_norm("decoder.ln")?,
+            mask: Param::from_tensor(attn_decoder_mask(n_text_ctx, &tensor_device_ref)),
+            n_vocab,
+            n_text_ctx,
+        };
+
+        let config = TextDecoderConfig {
+            n_vocab,
+            n_text_ctx,
+            n_text_state,
+            n_text_head: n_text_state / HEAD_DIM,
+            n_text_layer: n_layer,
+        };
+
+        Ok((decoder, config))
+    }
+}
+
+/// A tensor of a Whisper model named as in openai/whisper, with its shape in PyTorch's order.
+pub(crate) struct NamedTensor {
+    pub(crate) name: String,
+    pub(crate) dims: Vec<usize>,
+    pub(crate) values: Vec<f32>,
+}
+
+/// Walks the tensors of a model under their names in the layout of openai/whisper, see
+/// `visit_tensors`.
+pub(crate) trait TensorVisitor<B: Backend> {
+    fn visit<const D: usize>(&mut self, name: &str, tensor: Tensor<B, D>);
+
+    /// Visits the weight of a linear layer in PyTorch's (out, in) layout, dequantized, and the
+    /// bias.
+    fn visit_linear(&mut self, name: &str, linear: &QuantizableLinear<B>) {
+        self.visit(&format!("{}.weight", name), linear.weight().transpose());
+        if let Some(bias) = linear.bias() {
+            self.visit(&format!("{}.bias", name), bias);
+        }
+    }
+}
+
+impl<B: Backend> TensorVisitor<B> for Vec<NamedTensor> {
+    fn visit<const D: usize>(&mut self, name: &str, tensor: Tensor<B, D>) {
+        let dims = tensor.dims().to_vec();
+        let values = tensor.into_data().convert::<f32>().value;
+        self.push(NamedTensor {
+            name: name.to_string(),
+            dims,
+            values,
+        });
+    }
+}
+
+/// The tensors of `whisper` in the layout of openai/whisper, the inverse of
+/// `Checkpoint::whisper`. Quantized weights are dequantized.
+pub(crate) fn named_tensors<B: Backend>(whisper: &Whisper<B>) -> Vec<NamedTensor> {
+    let mut tensors = Vec::new();
+    visit_tensors(whisper, &mut tensors);
+    tensors
+}
+
+/// Calls `visitor` with every tensor of `whisper`, in the order of openai/whisper.
+pub(crate) fn visit_tensors<B: Backend, V: TensorVisitor<B>>(
+    whisper: &Whisper<B>,
+    visitor: &mut V,
+) {
+    let encoder = &whisper.encoder;
+    visit_conv1d(visitor, "encoder.conv1", &encoder.conv1);
+    visit_conv1d(visitor, "encoder.conv2", &encoder.conv2);
+    visitor.visit(
+        "encoder.positional_embedding",
+        encoder.positional_embedding.val(),
+    );
+    for (i, block) in encoder.blocks.iter().enumerate() {
+        let name = format!("encoder.blocks.{}", i);
+        visit_self_attention(visitor, &format!("{}.attn", name), &block.attn);
+        visit_layer_norm(visitor, &format!("{}.attn_ln", name), &block.attn_ln);
+        visit_mlp(visitor, &format!("{}.mlp", name), &block.mlp);
+        visit_layer_norm(visitor, &format!("{}.mlp_ln", name), &block.mlp_ln);
+    }
+    visit_layer_norm(visitor, "encoder.ln_post", &encoder.ln_post);
+
+    let decoder = &whisper.decoder;
+    visitor.visit(
+        "decoder.token_embedding.weight",
+        decoder.token_embedding.val(),
+    );
+    visitor.visit(
+        "decoder.positional_embedding",
+        decoder.positional_embedding.val(),
+    );
+    for (i, block) in decoder.blocks.iter().enumerate() {
+        let name = format!("decoder.blocks.{}", i);
+        visit_self_attention(visitor, &format!("{}.attn", name), &block.attn);
+        visit_layer_norm(visitor, &format!("{}.attn_ln", name), &block.attn_ln);
+        visit_cross_attention(visitor, &format!("{}.cross_attn", name), &block.cross_attn);
+        visit_layer_norm(
+            visitor,
+            &format!("{}.cross_attn_ln", name),
+            &block.cross_attn_ln,
+        );
+        visit_mlp(visitor, &format!("{}.mlp", name), &block.mlp);
+        visit_layer_norm(visitor, &format!("{}.mlp_ln", name), &block.mlp_ln);
+    }
+    visit_layer_norm(visitor, "decoder.ln", &decoder.ln);
+}
+
+fn visit_layer_norm<B: Backend, V: TensorVisitor<B>>(
+    visitor: &mut V,
+    name: &str,
+    layer_norm: &nn::LayerNorm<B>,
+) {
+    let record = layer_norm.clone().into_record();
+    visitor.visit(&format!("{}.weight", name), record.gamma.val());
+    visitor.visit(&format!("{}.bias", name), record.beta.val());
+}
+
+fn visit_conv1d<B: Backend, V: TensorVisitor<B>>(visitor: &mut V, name: &str, conv: &Conv1d<B>) {
+    let record = conv.clone().into_record();
+    visitor.visit(&format!("{}.weight", name), record.weight.val());
+    if let Some(bias) = record.bias {
+        visitor.visit(&format!("{}.bias", name), bias.val());
+    }
+}
+
+fn visit_mlp<B: Backend, V: TensorVisitor<B>>(visitor: &mut V, name: &str, mlp: &MLP<B>) {
+    visitor.visit_linear(&format!("{}.0", name), &mlp.lin1);
+    visitor.visit_linear(&format!("{}.2", name), &mlp.lin2);
+}
+
+fn visit_self_attention<B: Backend, V: TensorVisitor<B>>(
+    visitor: &mut V,
+    name: &str,
+    attention: &MultiHeadSelfAttention<B>,
+) {
+    visitor.visit_linear(&format!("{}.query", name), &attention.query);
+    visitor.visit_linear(&format!("{}.key", name), &attention.key);
+    visitor.visit_linear(&format!("{}.value", name), &attention.value);
+    visitor.visit_linear(&format!("{}.out", name), &attention.out);
+}
+
+fn visit_cross_attention<B: Backend, V: TensorVisitor<B>>(
+    visitor: &mut V,
+    name: &str,
+    attention: &MultiHeadCrossAttention<B>,
+) {
+    visitor.visit_linear(&format!("{}.query", name), &attention.query);
+    visitor.visit_linear(&format!("{}.key", name), &attention.key);
+    visitor.visit_linear(&format!("{}.value", name), &attention.value);
+    visitor.visit_linear(&format!("{}.out", name), &attention.out);
+}
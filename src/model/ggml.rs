@@ -0,0 +1,361 @@
+use burn::tensor::backend::Backend;
+use half::{bf16, f16};
+use std::{collections::HashMap, error::Error};
+
+use super::{
+    checkpoint::{map_file, Checkpoint, Layout, TensorSource},
+    Whisper, WhisperConfig,
+};
+
+/// "ggml" read as a little endian u32, the magic of the model files of whisper.cpp.
+const GGML_MAGIC: u32 = 0x67676d6c;
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+const GGUF_DEFAULT_ALIGNMENT: usize = 32;
+
+/// Number of values in a block of the quantized types.
+const QK: usize = 32;
+
+/// Element types of ggml, numbered as in `ggml_type`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GgmlType {
+    F32,
+    F16,
+    Q4_0,
+    Q4_1,
+    Q5_0,
+    Q5_1,
+    Q8_0,
+    BF16,
+}
+
+impl GgmlType {
+    fn from_id(id: u32) -> Result<Self, Box<dyn Error>> {
+        let ggml_type = match id {
+            0 => GgmlType::F32,
+            1 => GgmlType::F16,
+            2 => GgmlType::Q4_0,
+            3 => GgmlType::Q4_1,
+            6 => GgmlType::Q5_0,
+            7 => GgmlType::Q5_1,
+            8 => GgmlType::Q8_0,
+            30 => GgmlType::BF16,
+            id => return Err(format!("Unsupported ggml tensor type {}", id).into()),
+        };
+        Ok(ggml_type)
+    }
+
+    /// Bytes taken by `n` values.
+    fn size(&self, n: usize) -> usize {
+        match self {
+            GgmlType::F32 => 4 * n,
+            GgmlType::F16 | GgmlType::BF16 => 2 * n,
+            GgmlType::Q4_0 => n / QK * 18,
+            GgmlType::Q4_1 => n / QK * 20,
+            GgmlType::Q5_0 => n / QK * 22,
+            GgmlType::Q5_1 => n / QK * 24,
+            GgmlType::Q8_0 => n / QK * 34,
+        }
+    }
+}
+
+fn read_f16(bytes: &[u8]) -> f32 {
+    f16::from_le_bytes([bytes[0], bytes[1]]).to_f32()
+}
+
+/// Dequantizes `data` of type `ggml_type` to `f32`.
+fn dequantize(ggml_type: GgmlType, data: &[u8]) -> Vec<f32> {
+    match ggml_type {
+        GgmlType::F32 => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        GgmlType::F16 => data.chunks_exact(2).map(read_f16).collect(),
+        GgmlType::BF16 => data
+            .chunks_exact(2)
+            .map(|b| bf16::from_le_bytes([b[0], b[1]]).to_f32())
+            .collect(),
+        GgmlType::Q4_0 => data
+            .chunks_exact(18)
+            .flat_map(|block| {
+                let d = read_f16(&block[0..2]);
+                let qs = &block[2..18];
+                let low = qs.iter().map(move |q| ((q & 0xF) as i32 - 8) as f32 * d);
+                let high = qs.iter().map(move |q| ((q >> 4) as i32 - 8) as f32 * d);
+                low.chain(high)
+            })
+            .collect(),
+        GgmlType::Q4_1 => data
+            .chunks_exact(20)
+            .flat_map(|block| {
+                let d = read_f16(&block[0..2]);
+                let m = read_f16(&block[2..4]);
+                let qs = &block[4..20];
+                let low = qs.iter().map(move |q| (q & 0xF) as f32 * d + m);
+                let high = qs.iter().map(move |q| (q >> 4) as f32 * d + m);
+                low.chain(high)
+            })
+            .collect(),
+        GgmlType::Q5_0 | GgmlType::Q5_1 => {
+            let (block_size, has_min) = match ggml_type {
+                GgmlType::Q5_0 => (22, false),
+                _ => (24, true),
+            };
+            data.chunks_exact(block_size)
+                .flat_map(|block| {
+                    let d = read_f16(&block[0..2]);
+                    let (m, rest) = match has_min {
+                        true => (read_f16(&block[2..4]), &block[4..]),
+                        false => (0.0, &block[2..]),
+                    };
+                    // the fifth bits of the 32 values
+                    let qh = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+                    let qs = &rest[4..20];
+                    let offset = if has_min { 0 } else { 16 };
+                    let value = move |q: u8, bit: usize| {
+                        let q = q as i32 | ((((qh >> bit) & 1) as i32) << 4);
+                        (q - offset) as f32 * d + m
+                    };
+                    let low = qs.iter().enumerate().map(move |(j, q)| value(q & 0xF, j));
+                    let high = qs.iter().enumerate().map(move |(j, q)| value(q >> 4, j + 16));
+                    low.chain(high).collect::<Vec<_>>()
+                })
+                .collect()
+        }
+        GgmlType::Q8_0 => data
+            .chunks_exact(34)
+            .flat_map(|block| {
+                let d = read_f16(&block[0..2]);
+                block[2..34].iter().map(move |&q| q as i8 as f32 * d)
+            })
+            .collect(),
+    }
+}
+
+struct GgmlTensor {
+    /// Shape in PyTorch's order, ggml lists the dimensions innermost first.
+    dims: Vec<usize>,
+    ggml_type: GgmlType,
+    /// Position of the data in the file.
+    offset: usize,
+}
+
+/// A model file of whisper.cpp, in the legacy ggml format or in GGUF, borrowing its bytes.
+struct GgmlFile<'a> {
+    bytes: &'a [u8],
+    tensors: HashMap<String, GgmlTensor>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or("Unexpected end of the model file")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    /// A size stored as an i32 by the legacy format.
+    fn size(&mut self) -> Result<usize, Box<dyn Error>> {
+        let value = self.u32()? as i32;
+        let size = usize::try_from(value)
+            .map_err(|_| format!("Invalid size {} in the model file", value))?;
+        Ok(size)
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, Box<dyn Error>> {
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+}
+
+impl<'a> GgmlFile<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let tensors = if bytes.starts_with(GGUF_MAGIC) {
+            reader.take(GGUF_MAGIC.len())?;
+            parse_gguf(&mut reader)?
+        } else if reader.u32()? == GGML_MAGIC {
+            parse_ggml(&mut reader)?
+        } else {
+            return Err("Not a ggml or GGUF model file".into());
+        };
+        Ok(Self { bytes, tensors })
+    }
+}
+
+/// Reads the tensors of a model file of whisper.cpp after the magic. The hyperparameters,
+/// mel filters and vocabulary before them are skipped, the config is inferred from the shapes
+/// of the tensors and the tokenizer comes from `tokenizer.json`.
+fn parse_ggml(reader: &mut Reader) -> Result<HashMap<String, GgmlTensor>, Box<dyn Error>> {
+    // n_vocab, n_audio_ctx, n_audio_state, n_audio_head, n_audio_layer, n_text_ctx,
+    // n_text_state, n_text_head, n_text_layer, n_mels, ftype
+    reader.take(11 * 4)?;
+
+    let n_mel = reader.size()?;
+    let n_fft = reader.size()?;
+    reader.take(n_mel * n_fft * 4)?;
+
+    let n_vocab = reader.size()?;
+    for _ in 0..n_vocab {
+        let len = reader.size()?;
+        reader.take(len)?;
+    }
+
+    let mut tensors = HashMap::new();
+    while !reader.is_at_end() {
+        let n_dims = reader.size()?;
+        let name_len = reader.size()?;
+        let ggml_type = GgmlType::from_id(reader.u32()?)?;
+        let mut dims = (0..n_dims)
+            .map(|_| reader.size())
+            .collect::<Result<Vec<_>, _>>()?;
+        dims.reverse();
+        let name = reader.string(name_len)?;
+
+        let offset = reader.pos;
+        reader.take(ggml_type.size(dims.iter().product()))?;
+        tensors.insert(name, GgmlTensor { dims, ggml_type, offset });
+    }
+    Ok(tensors)
+}
+
+/// Skips a metadata value of GGUF of the type `value_type`.
+fn skip_gguf_value(reader: &mut Reader, value_type: u32) -> Result<(), Box<dyn Error>> {
+    match value_type {
+        // u8, i8, bool
+        0 | 1 | 7 => {
+            reader.take(1)?;
+        }
+        // u16, i16
+        2 | 3 => {
+            reader.take(2)?;
+        }
+        // u32, i32, f32
+        4 | 5 | 6 => {
+            reader.take(4)?;
+        }
+        // u64, i64, f64
+        10 | 11 | 12 => {
+            reader.take(8)?;
+        }
+        // string
+        8 => {
+            let len = reader.u64()? as usize;
+            reader.take(len)?;
+        }
+        // array
+        9 => {
+            let item_type = reader.u32()?;
+            let len = reader.u64()?;
+            for _ in 0..len {
+                skip_gguf_value(reader, item_type)?;
+            }
+        }
+        value_type => return Err(format!("Unknown GGUF value type {}", value_type).into()),
+    }
+    Ok(())
+}
+
+fn parse_gguf(reader: &mut Reader) -> Result<HashMap<String, GgmlTensor>, Box<dyn Error>> {
+    let version = reader.u32()?;
+    if version < 2 {
+        return Err(format!("Unsupported GGUF version {}", version).into());
+    }
+    let n_tensors = reader.u64()?;
+    let n_metadata = reader.u64()?;
+
+    let mut alignment = GGUF_DEFAULT_ALIGNMENT;
+    for _ in 0..n_metadata {
+        let key_len = reader.u64()? as usize;
+        let key = reader.string(key_len)?;
+        let value_type = reader.u32()?;
+        if key == "general.alignment" && value_type == 4 {
+            alignment = reader.u32()? as usize;
+            if !alignment.is_power_of_two() {
+                return Err(format!("Invalid GGUF alignment {}", alignment).into());
+            }
+        } else {
+            skip_gguf_value(reader, value_type)?;
+        }
+    }
+
+    let mut infos = Vec::new();
+    for _ in 0..n_tensors {
+        let name_len = reader.u64()? as usize;
+        let name = reader.string(name_len)?;
+        let n_dims = reader.u32()?;
+        let mut dims = (0..n_dims)
+            .map(|_| reader.u64().map(|dim| dim as usize))
+            .collect::<Result<Vec<_>, _>>()?;
+        dims.reverse();
+        let ggml_type = GgmlType::from_id(reader.u32()?)?;
+        let offset = reader.u64()? as usize;
+        infos.push((name, dims, ggml_type, offset));
+    }
+
+    // the offsets count from the aligned start of the tensor data
+    let data_start = (reader.pos + alignment - 1) / alignment * alignment;
+    let tensors = infos
+        .into_iter()
+        .map(|(name, dims, ggml_type, offset)| {
+            let tensor = GgmlTensor { dims, ggml_type, offset: data_start + offset };
+            (name, tensor)
+        })
+        .collect();
+    Ok(tensors)
+}
+
+impl TensorSource for GgmlFile<'_> {
+    fn names(&self) -> Vec<String> {
+        self.tensors.keys().cloned().collect()
+    }
+
+    fn dims(&self, name: &str) -> Option<Vec<usize>> {
+        self.tensors.get(name).map(|tensor| tensor.dims.clone())
+    }
+
+    fn values(&self, name: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        let tensor = self
+            .tensors
+            .get(name)
+            .ok_or_else(|| format!("Missing tensor {}", name))?;
+        let size = tensor.ggml_type.size(tensor.dims.iter().product());
+        let data = self
+            .bytes
+            .get(tensor.offset..tensor.offset + size)
+            .ok_or_else(|| format!("The data of {} is past the end of the file", name))?;
+        Ok(dequantize(tensor.ggml_type, data))
+    }
+}
+
+/// Loads a model file of whisper.cpp such as `ggml-base.en.bin`, in the legacy ggml format or
+/// in GGUF. Quantized and half precision weights are dequantized to `f32`.
+pub fn load_whisper_ggml<B: Backend>(
+    path: &str,
+) -> Result<(Whisper<B>, WhisperConfig, Layout), Box<dyn Error>> {
+    let bytes = map_file(path)?;
+    let checkpoint = Checkpoint::new(GgmlFile::parse(&bytes)?)?;
+    let (whisper, config) = checkpoint.whisper()?;
+    Ok((whisper, config, checkpoint.layout()))
+}
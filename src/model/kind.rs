@@ -0,0 +1,173 @@
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use super::{AudioEncoderConfig, TextDecoderConfig, WhisperConfig};
+
+/// The released Whisper models, which know their architecture so they load without a `.cfg`
+/// file.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum ModelKind {
+    Tiny,
+    TinyEn,
+    Base,
+    BaseEn,
+    Small,
+    SmallEn,
+    Medium,
+    MediumEn,
+    LargeV1,
+    LargeV2,
+    LargeV3,
+    /// large-v3 with its decoder pruned to 4 layers and fine-tuned.
+    LargeV3Turbo,
+    /// Distil-Whisper's distillation of large-v3 with 2 decoder layers.
+    DistilLargeV3,
+    /// Distil-Whisper's distillation of small.en with 4 decoder layers.
+    DistilSmallEn,
+}
+
+/// The tokenizers of the models, which differ in their special tokens.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Vocabulary {
+    /// GPT-2's vocabulary of the English-only models.
+    English,
+    /// The multilingual vocabulary with the language tokens.
+    Multilingual,
+    /// The multilingual vocabulary with the `<|yue|>` Cantonese token added by large-v3.
+    MultilingualV3,
+}
+
+impl Vocabulary {
+    pub fn n_vocab(&self) -> usize {
+        match self {
+            Vocabulary::English => 51864,
+            Vocabulary::Multilingual => 51865,
+            Vocabulary::MultilingualV3 => 51866,
+        }
+    }
+}
+
+const N_AUDIO_CTX: usize = 1500;
+const N_TEXT_CTX: usize = 448;
+
+impl ModelKind {
+    /// Name of the model as openai/whisper calls it, e.g. `tiny.en` or `large-v3`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelKind::Tiny => "tiny",
+            ModelKind::TinyEn => "tiny.en",
+            ModelKind::Base => "base",
+            ModelKind::BaseEn => "base.en",
+            ModelKind::Small => "small",
+            ModelKind::SmallEn => "small.en",
+            ModelKind::Medium => "medium",
+            ModelKind::MediumEn => "medium.en",
+            ModelKind::LargeV1 => "large-v1",
+            ModelKind::LargeV2 => "large-v2",
+            ModelKind::LargeV3 => "large-v3",
+            ModelKind::LargeV3Turbo => "large-v3-turbo",
+            ModelKind::DistilLargeV3 => "distil-large-v3",
+            ModelKind::DistilSmallEn => "distil-small.en",
+        }
+    }
+
+    /// The model called `name`, ignoring case and whether `.`, `-` or `_` separate its parts
+    /// so model folders like `tiny_en` are recognized. `large` is the latest large model and
+    /// `turbo` large-v3-turbo, as in openai/whisper.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let normalize = |name: &str| name.to_lowercase().replace(['.', '_'], "-");
+        let name = match normalize(name).as_str() {
+            "large" => return Some(ModelKind::LargeV3),
+            "turbo" => return Some(ModelKind::LargeV3Turbo),
+            name => name.to_string(),
+        };
+        ModelKind::iter().find(|kind| normalize(kind.name()) == name)
+    }
+
+    /// The model with the architecture of `config`. The English-only and multilingual models
+    /// differ in their vocabulary, large-v1 and large-v2 are only told apart by their weights
+    /// and are taken to be large-v2.
+    pub fn from_config(config: &WhisperConfig) -> Option<Self> {
+        let (encoder, decoder) = (&config.audio_encoder_config, &config.text_decoder_config);
+        ModelKind::iter().filter(|kind| *kind != ModelKind::LargeV1).find(|kind| {
+            let kind_config = kind.config();
+            let (kind_encoder, kind_decoder) = (
+                &kind_config.audio_encoder_config,
+                &kind_config.text_decoder_config,
+            );
+            encoder.n_mels == kind_encoder.n_mels
+                && encoder.n_audio_state == kind_encoder.n_audio_state
+                && encoder.n_audio_layer == kind_encoder.n_audio_layer
+                && decoder.n_text_layer == kind_decoder.n_text_layer
+                && decoder.n_vocab == kind_decoder.n_vocab
+        })
+    }
+
+    pub fn is_multilingual(&self) -> bool {
+        self.vocabulary() != Vocabulary::English
+    }
+
+    pub fn vocabulary(&self) -> Vocabulary {
+        match self {
+            ModelKind::TinyEn
+            | ModelKind::BaseEn
+            | ModelKind::SmallEn
+            | ModelKind::MediumEn
+            | ModelKind::DistilSmallEn => Vocabulary::English,
+            ModelKind::LargeV3 | ModelKind::LargeV3Turbo | ModelKind::DistilLargeV3 => {
+                Vocabulary::MultilingualV3
+            }
+            _ => Vocabulary::Multilingual,
+        }
+    }
+
+    /// Number of mel bins of the spectrogram the model takes.
+    pub fn n_mels(&self) -> usize {
+        match self {
+            ModelKind::LargeV3 | ModelKind::LargeV3Turbo | ModelKind::DistilLargeV3 => 128,
+            _ => 80,
+        }
+    }
+
+    /// Width and number of heads of the encoder and the decoder, and their numbers of layers.
+    /// The distilled models keep the encoder of their teacher and shrink the decoder.
+    fn dims(&self) -> (usize, usize, usize, usize) {
+        match self {
+            ModelKind::Tiny | ModelKind::TinyEn => (384, 6, 4, 4),
+            ModelKind::Base | ModelKind::BaseEn => (512, 8, 6, 6),
+            ModelKind::Small | ModelKind::SmallEn => (768, 12, 12, 12),
+            ModelKind::Medium | ModelKind::MediumEn => (1024, 16, 24, 24),
+            ModelKind::LargeV1 | ModelKind::LargeV2 | ModelKind::LargeV3 => (1280, 20, 32, 32),
+            ModelKind::LargeV3Turbo => (1280, 20, 32, 4),
+            ModelKind::DistilLargeV3 => (1280, 20, 32, 2),
+            ModelKind::DistilSmallEn => (768, 12, 12, 4),
+        }
+    }
+
+    /// Whether the model is distilled by Distil-Whisper, which decodes several times faster.
+    pub fn is_distilled(&self) -> bool {
+        matches!(self, ModelKind::DistilLargeV3 | ModelKind::DistilSmallEn)
+    }
+
+    pub fn config(&self) -> WhisperConfig {
+        let (n_state, n_head, n_audio_layer, n_text_layer) = self.dims();
+        let audio_encoder_config =
+            AudioEncoderConfig::new(self.n_mels(), N_AUDIO_CTX, n_state, n_head, n_audio_layer);
+        let text_decoder_config = TextDecoderConfig::new(
+            self.vocabulary().n_vocab(),
+            N_TEXT_CTX,
+            n_state,
+            n_head,
+            n_text_layer,
+        );
+        WhisperConfig::new(audio_encoder_config, text_decoder_config)
+    }
+
+    /// Hugging Face repository of the model, where its `tokenizer.json` can be found.
+    pub fn hugging_face_repo(&self) -> String {
+        match self.is_distilled() {
+            true => format!("distil-whisper/{}", self.name()),
+            false => format!("openai/whisper-{}", self.name()),
+        }
+    }
+}
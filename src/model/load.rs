@@ -29,7 +29,7 @@ fn numpy_to_tensor<B: Backend, const D: usize>(numpy_data: NpyData<f32>) -> Tens
     Tensor::from_floats(&v[D..], &tensor_device_ref).reshape(shape)
 }
 
-fn load_tensor<B: Backend, const D: usize>(
+pub(crate) fn load_tensor<B: Backend, const D: usize>(
     name: &str,
     path: &str,
 ) -> Result<Tensor<B, D>, Box<dyn Error>> {
@@ -49,11 +49,11 @@ fn load_f32<B: Backend>(name: &str, path: &str) -> Result<f32, Box<dyn Error>> {
     load_tensor::<B, 1>(name, path).map(|t| t.into_scalar().to_f32().unwrap())
 }
 
-fn load_usize<B: Backend>(name: &str, path: &str) -> Result<usize, Box<dyn Error>> {
+pub(crate) fn load_usize<B: Backend>(name: &str, path: &str) -> Result<usize, Box<dyn Error>> {
     load_tensor::<B, 1>(name, path).map(|t| t.into_scalar().to_usize().unwrap())
 }
 
-fn load_linear<B: Backend>(path: &str) -> Result<nn::Linear<B>, Box<dyn Error>> {
+pub(crate) fn load_linear<B: Backend>(path: &str) -> Result<nn::Linear<B>, Box<dyn Error>> {
     let weight = load_tensor::<B, 2>("weight", path)?;
     let bias = load_tensor::<B, 1>("bias", path).ok();
     let tensor_device_ref = weight.device();
@@ -102,10 +102,10 @@ fn load_multihead_self_attention<B: Backend>(
     // Initializing attention block
     let attention_block = MultiHeadSelfAttention {
         n_head: n_head,
-        query: query,
-        key: key,
-        value: value,
-        out: out,
+        query: query.into(),
+        key: key.into(),
+        value: value.into(),
+        out: out.into(),
     };
 
     Ok(attention_block)
@@ -124,10 +124,10 @@ fn load_multihead_cross_attention<B: Backend>(
     // Initializing attention block
     let attention_block = MultiHeadCrossAttention {
         n_head: n_head,
-        query: query,
-        key: key,
-        value: value,
-        out: out,
+        query: query.into(),
+        key: key.into(),
+        value: value.into(),
+        out: out.into(),
     };
 
     Ok(attention_block)
@@ -140,15 +140,15 @@ fn load_mlp<B: Backend>(path: &str) -> Result<MLP<B>, Box<dyn Error>> {
     let gelu = nn::Gelu::new();
 
     let mlp = MLP {
-        lin1: lin1,
-        lin2: lin2,
+        lin1: lin1.into(),
+        lin2: lin2.into(),
         gelu: gelu,
     };
 
     Ok(mlp)
 }
 
-fn load_conv1d<B: Backend>(path: &str, config: Conv1dConfig) -> Result<Conv1d<B>, Box<dyn Error>> {
+pub(crate) fn load_conv1d<B: Backend>(path: &str, config: Conv1dConfig) -> Result<Conv1d<B>, Box<dyn Error>> {
     let weight = Param::from_tensor(load_tensor::<B, 3>("weight", path)?);
     let bias = Param::from_tensor(load_tensor::<B, 1>("bias", path)?);
     let tensor_device_ref = weight.device();
@@ -313,6 +313,7 @@ pub fn load_whisper<B: Backend>(path: &str) -> Result<(Whisper<B>, WhisperConfig
     let config = WhisperConfig {
         audio_encoder_config: encoder_config,
         text_decoder_config: decoder_config,
+        quantization: None,
     };
 
     Ok((whisper, config))
@@ -1,4 +1,10 @@
+pub mod check;
+pub mod checkpoint;
+pub mod ggml;
+pub mod kind;
 pub mod load;
+pub mod quantize;
+pub mod safetensors;
 
 use std::f32::NEG_INFINITY;
 
@@ -10,13 +16,19 @@ use burn::{
         conv::{Conv1d, Conv1dConfig, Conv1dRecord},
         PaddingConfig1d,
     },
-    tensor::{activation::softmax, backend::Backend, module::embedding, Distribution, Int, Tensor},
+    tensor::{
+        activation::softmax, backend::Backend, module::embedding, Data, Distribution, Int, Tensor,
+    },
 };
 
+use quantize::{QuantizableLinear, QuantizableLinearConfig, Quantization};
+
 #[derive(Config, Debug)]
 pub struct WhisperConfig {
     audio_encoder_config: AudioEncoderConfig,
     text_decoder_config: TextDecoderConfig,
+    /// Quantization of the weights of the model files, see `quantize::Quantization`.
+    quantization: Option<Quantization>,
 }
 
 impl WhisperConfig {
@@ -31,11 +43,19 @@ impl WhisperConfig {
             n_text_state
         );
 
-        let encoder = self.audio_encoder_config.init(tensor_device_ref);
-        let decoder = self.text_decoder_config.init(tensor_device_ref);
+        let encoder = self
+            .audio_encoder_config
+            .init_quantized(self.quantization, tensor_device_ref);
+        let decoder = self
+            .text_decoder_config
+            .init_quantized(self.quantization, tensor_device_ref);
 
         Whisper { encoder, decoder }
     }
+
+    pub fn quantization(&self) -> Option<Quantization> {
+        self.quantization
+    }
 }
 
 #[derive(Module, Debug)]
@@ -61,6 +81,34 @@ impl<B: Backend> Whisper<B> {
         self.decoder.forward(tokens, encoder_output)
     }
 
+    /// Projects the encoder output into the cross-attention keys and values of every decoder
+    /// layer. The encoder output is fixed for a window so this only has to be done once.
+    pub fn cross_attention_cache(&self, encoder_output: Tensor<B, 3>) -> CrossAttentionCache<B> {
+        self.decoder.cross_attention_cache(encoder_output)
+    }
+
+    /// Decodes only the tokens that are not yet covered by `cache`, i.e. `tokens` holds the
+    /// tokens following the `cache.len()` tokens decoded so far. The cache is extended with the
+    /// self-attention keys and values of the new tokens.
+    pub fn forward_decoder_cached(
+        &self,
+        tokens: Tensor<B, 2, Int>,
+        cross_attention: &CrossAttentionCache<B>,
+        cache: &mut DecoderCache<B>,
+    ) -> Tensor<B, 3> {
+        self.decoder.forward_cached(tokens, cross_attention, cache)
+    }
+
+    /// Same as `forward_decoder` but also returns the cross-attention weights of every decoder
+    /// layer, each of size (n_batch, n_head, n_token, n_audio_ctx).
+    pub fn forward_decoder_with_cross_attention(
+        &self,
+        tokens: Tensor<B, 2, Int>,
+        encoder_output: Tensor<B, 3>,
+    ) -> (Tensor<B, 3>, Vec<Tensor<B, 4>>) {
+        self.decoder.forward_with_cross_attention(tokens, encoder_output)
+    }
+
     pub fn encoder_ctx_size(&self) -> usize {
         self.encoder.ctx_size()
     }
@@ -71,6 +119,124 @@ impl<B: Backend> Whisper<B> {
     pub fn decoder_ctx_size(&self) -> usize {
         self.decoder.ctx_size()
     }
+
+    pub fn decoder_vocab_size(&self) -> usize {
+        self.decoder.n_vocab
+    }
+}
+
+/// Self-attention keys and values of the tokens decoded so far, one entry per decoder layer,
+/// each of size (n_batch, n_token, n_state).
+#[derive(Clone, Debug)]
+pub struct DecoderCache<B: Backend> {
+    self_attn: Vec<(Tensor<B, 3>, Tensor<B, 3>)>,
+}
+
+impl<B: Backend> DecoderCache<B> {
+    pub fn new() -> Self {
+        Self {
+            self_attn: Vec::new(),
+        }
+    }
+
+    /// Number of tokens whose keys and values are cached.
+    pub fn len(&self) -> usize {
+        self.self_attn
+            .first()
+            .map(|(key, _)| key.dims()[1])
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Rearranges the batch so that row `i` of the cache becomes row `indices[i]` of the old
+    /// cache. Used to follow the surviving hypotheses during beam search.
+    pub fn reorder(&mut self, indices: &[usize]) {
+        let device = match self.self_attn.first() {
+            Some((key, _)) => key.device(),
+            None => return,
+        };
+
+        let indices = Tensor::<B, 1, Int>::from_ints(
+            Data::from_usize(Data::new(indices.to_vec(), [indices.len()].into())),
+            &device,
+        );
+
+        self.self_attn = self
+            .self_attn
+            .drain(..)
+            .map(|(key, value)| {
+                (
+                    key.select(0, indices.clone()),
+                    value.select(0, indices.clone()),
+                )
+            })
+            .collect();
+    }
+
+    /// Drops the keys and values of every token after the first `len` ones.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len() {
+            return;
+        }
+
+        self.self_attn = self
+            .self_attn
+            .drain(..)
+            .map(|(key, value)| {
+                let [n_batch, _, n_state] = key.dims();
+                (
+                    key.slice([0..n_batch, 0..len, 0..n_state]),
+                    value.slice([0..n_batch, 0..len, 0..n_state]),
+                )
+            })
+            .collect();
+    }
+}
+
+impl<B: Backend> Default for DecoderCache<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cross-attention keys and values of every decoder layer computed from a single encoder
+/// output, each of size (n_batch, n_audio_ctx, n_state). A batch size of one is broadcast to
+/// the size of the token batch.
+#[derive(Clone, Debug)]
+pub struct CrossAttentionCache<B: Backend> {
+    key_values: Vec<(Tensor<B, 3>, Tensor<B, 3>)>,
+}
+
+impl<B: Backend> CrossAttentionCache<B> {
+    /// Gathers the rows `indices` of the batch, e.g. to pair every hypothesis of a batched
+    /// search with the encoder output of its window.
+    pub fn select(&self, indices: &[usize]) -> Self {
+        let device = match self.key_values.first() {
+            Some((key, _)) => key.device(),
+            None => return self.clone(),
+        };
+
+        let indices = Tensor::<B, 1, Int>::from_ints(
+            Data::from_usize(Data::new(indices.to_vec(), [indices.len()].into())),
+            &device,
+        );
+
+        let key_values = self
+            .key_values
+            .iter()
+            .map(|(key, value)| {
+                (
+                    key.clone().select(0, indices.clone()),
+                    value.clone().select(0, indices.clone()),
+                )
+            })
+            .collect();
+
+        Self { key_values }
+    }
 }
 
 #[derive(Config, Debug)]
@@ -84,6 +250,15 @@ pub struct TextDecoderConfig {
 
 impl TextDecoderConfig {
     pub fn init<B: Backend>(&self, tensor_device_ref: &B::Device) -> TextDecoder<B> {
+        self.init_quantized(None, tensor_device_ref)
+    }
+
+    /// Initializes the decoder with its linear layers quantized with `quantization`.
+    pub fn init_quantized<B: Backend>(
+        &self,
+        quantization: Option<Quantization>,
+        tensor_device_ref: &B::Device,
+    ) -> TextDecoder<B> {
         let token_embedding = Param::from_tensor(Tensor::random(
             [self.n_vocab, self.n_text_state],
             Distribution::Normal(0.0, 1.0),
@@ -98,6 +273,7 @@ impl TextDecoderConfig {
             .into_iter()
             .map(|_| {
                 ResidualDecoderAttentionBlockConfig::new(self.n_text_state, self.n_text_head)
+                    .with_quantization(quantization)
                     .init(tensor_device_ref)
             })
             .collect();
@@ -133,6 +309,25 @@ pub struct TextDecoder<B: Backend> {
 
 impl<B: Backend> TextDecoder<B> {
     fn forward(&self, x: Tensor<B, 2, Int>, xa: Tensor<B, 3>) -> Tensor<B, 3> {
+        let cross_attention = self.cross_attention_cache(xa);
+        self.forward_cached(x, &cross_attention, &mut DecoderCache::new())
+    }
+
+    fn cross_attention_cache(&self, xa: Tensor<B, 3>) -> CrossAttentionCache<B> {
+        let key_values = self
+            .blocks
+            .iter()
+            .map(|block| block.cross_attn.key_value(xa.clone()))
+            .collect();
+
+        CrossAttentionCache { key_values }
+    }
+
+    fn forward_with_cross_attention(
+        &self,
+        x: Tensor<B, 2, Int>,
+        xa: Tensor<B, 3>,
+    ) -> (Tensor<B, 3>, Vec<Tensor<B, 4>>) {
         let [n_batch, seq_len] = x.dims();
 
         assert!(
@@ -152,9 +347,67 @@ impl<B: Backend> TextDecoder<B> {
         //let mask = attn_decoder_mask(seq_len);
 
         let mut x = x;
+        let mut cross_attention_weights = Vec::with_capacity(self.blocks.len());
         for block in &self.blocks {
-            x = block.forward(x, xa.clone(), self.mask.val());
+            let (out, weights) = block.forward(x, xa.clone(), self.mask.val());
+            x = out;
+            cross_attention_weights.push(weights);
+        }
+
+        let x = self.ln.forward(x);
+        let logits = x.matmul(self.token_embedding.val().transpose().unsqueeze::<3>());
+
+        return (logits, cross_attention_weights);
+    }
+
+    fn forward_cached(
+        &self,
+        x: Tensor<B, 2, Int>,
+        cross_attention: &CrossAttentionCache<B>,
+        cache: &mut DecoderCache<B>,
+    ) -> Tensor<B, 3> {
+        let [n_batch, n_new] = x.dims();
+        let offset = cache.len();
+        let seq_len = offset + n_new;
+
+        assert!(
+            seq_len <= self.n_text_ctx,
+            "Token sequence length {} must not exceed {}.",
+            seq_len,
+            self.n_text_ctx
+        );
+
+        let x = embedding(self.token_embedding.val(), x)
+            + self
+                .positional_embedding
+                .val()
+                .slice([offset..seq_len])
+                .unsqueeze::<3>();
+
+        // the new tokens attend to every cached token and causally among themselves
+        let mask = self.mask.val().slice([offset..seq_len, 0..seq_len]);
+
+        let mut x = x;
+        let mut self_attn_cache = Vec::with_capacity(self.blocks.len());
+        for (i, block) in self.blocks.iter().enumerate() {
+            let (cross_key, cross_value) = cross_attention.key_values[i].clone();
+            let (cross_key, cross_value) = if cross_key.dims()[0] != n_batch {
+                (cross_key.repeat(0, n_batch), cross_value.repeat(0, n_batch))
+            } else {
+                (cross_key, cross_value)
+            };
+
+            let (out, key_value) = block.forward_cached(
+                x,
+                cross_key,
+                cross_value,
+                mask.clone(),
+                cache.self_attn.get(i).cloned(),
+            );
+            x = out;
+            self_attn_cache.push(key_value);
         }
+        cache.self_attn = self_attn_cache;
 
         let x = self.ln.forward(x);
         return x.matmul(self.token_embedding.val().transpose().unsqueeze::<3>());
@@ -176,6 +429,15 @@ pub struct AudioEncoderConfig {
 
 impl AudioEncoderConfig {
     pub fn init<B: Backend>(&self, tensor_device_ref: &B::Device) -> AudioEncoder<B> {
+        self.init_quantized(None, tensor_device_ref)
+    }
+
+    /// Initializes the encoder with its linear layers quantized with `quantization`.
+    pub fn init_quantized<B: Backend>(
+        &self,
+        quantization: Option<Quantization>,
+        tensor_device_ref: &B::Device,
+    ) -> AudioEncoder<B> {
         let conv1 = Conv1dConfig::new(self.n_mels, self.n_audio_state, 3)
             .with_padding(PaddingConfig1d::Explicit(1))
             .init(tensor_device_ref);
@@ -189,6 +451,7 @@ impl AudioEncoderConfig {
             .into_iter()
             .map(|_| {
                 ResidualEncoderAttentionBlockConfig::new(self.n_audio_state, self.n_audio_head)
+                    .with_quantization(quantization)
                     .init(tensor_device_ref)
             })
             .collect();
@@ -237,11 +500,12 @@ impl<B: Backend> AudioEncoder<B> {
             "Audio mel spectrum size must be {}.",
             self.n_mels
         );
+        // the second convolution halves the number of frames
         assert!(
-            n_ctx <= self.n_audio_ctx,
+            n_ctx <= 2 * self.n_audio_ctx,
             "Audio length {} cannot exceed {}.",
             n_ctx,
-            self.n_audio_ctx
+            2 * self.n_audio_ctx
         );
 
         let x = self.gelu1.forward(self.conv1.forward(x));
@@ -276,6 +540,7 @@ impl<B: Backend> AudioEncoder<B> {
 pub struct ResidualEncoderAttentionBlockConfig {
     n_state: usize,
     n_head: usize,
+    quantization: Option<Quantization>,
 }
 
 impl ResidualEncoderAttentionBlockConfig {
@@ -283,10 +548,13 @@ impl ResidualEncoderAttentionBlockConfig {
         &self,
         tensor_device_ref: &B::Device,
     ) -> ResidualEncoderAttentionBlock<B> {
-        let attn =
-            MultiHeadSelfAttentionConfig::new(self.n_state, self.n_head).init(tensor_device_ref);
+        let attn = MultiHeadSelfAttentionConfig::new(self.n_state, self.n_head)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
         let attn_ln = nn::LayerNormConfig::new(self.n_state).init(tensor_device_ref);
-        let mlp = MLPConfig::new(self.n_state).init(tensor_device_ref);
+        let mlp = MLPConfig::new(self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
         let mlp_ln = nn::LayerNormConfig::new(self.n_state).init(tensor_device_ref);
 
         ResidualEncoderAttentionBlock {
@@ -318,6 +586,7 @@ impl<B: Backend> ResidualEncoderAttentionBlock<B> {
 pub struct ResidualDecoderAttentionBlockConfig {
     n_state: usize,
     n_head: usize,
+    quantization: Option<Quantization>,
 }
 
 impl ResidualDecoderAttentionBlockConfig {
@@ -325,15 +594,19 @@ impl ResidualDecoderAttentionBlockConfig {
         &self,
         tensor_device_ref: &B::Device,
     ) -> ResidualDecoderAttentionBlock<B> {
-        let attn =
-            MultiHeadSelfAttentionConfig::new(self.n_state, self.n_head).init(tensor_device_ref);
+        let attn = MultiHeadSelfAttentionConfig::new(self.n_state, self.n_head)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
         let attn_ln = nn::LayerNormConfig::new(self.n_state).init(tensor_device_ref);
 
-        let cross_attn =
-            MultiHeadCrossAttentionConfig::new(self.n_state, self.n_head).init(tensor_device_ref);
+        let cross_attn = MultiHeadCrossAttentionConfig::new(self.n_state, self.n_head)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
         let cross_attn_ln = nn::LayerNormConfig::new(self.n_state).init(tensor_device_ref);
 
-        let mlp = MLPConfig::new(self.n_state).init(tensor_device_ref);
+        let mlp = MLPConfig::new(self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
         let mlp_ln = nn::LayerNormConfig::new(self.n_state).init(tensor_device_ref);
 
         ResidualDecoderAttentionBlock {
@@ -358,24 +631,57 @@ pub struct ResidualDecoderAttentionBlock<B: Backend> {
 }
 
 impl<B: Backend> ResidualDecoderAttentionBlock<B> {
-    fn forward(&self, x: Tensor<B, 3>, xa: Tensor<B, 3>, mask: Tensor<B, 2>) -> Tensor<B, 3> {
+    fn forward(
+        &self,
+        x: Tensor<B, 3>,
+        xa: Tensor<B, 3>,
+        mask: Tensor<B, 2>,
+    ) -> (Tensor<B, 3>, Tensor<B, 4>) {
         let x = x.clone() + self.attn.forward(self.attn_ln.forward(x), Some(mask));
-        let x = x.clone() + self.cross_attn.forward(self.cross_attn_ln.forward(x), xa);
+        let (cross_attn_out, cross_attn_weights) = self
+            .cross_attn
+            .forward_with_weights(self.cross_attn_ln.forward(x.clone()), xa);
+        let x = x + cross_attn_out;
         let x = x.clone() + self.mlp.forward(self.mlp_ln.forward(x));
-        return x;
+        return (x, cross_attn_weights);
+    }
+
+    fn forward_cached(
+        &self,
+        x: Tensor<B, 3>,
+        cross_key: Tensor<B, 3>,
+        cross_value: Tensor<B, 3>,
+        mask: Tensor<B, 2>,
+        cache: Option<(Tensor<B, 3>, Tensor<B, 3>)>,
+    ) -> (Tensor<B, 3>, (Tensor<B, 3>, Tensor<B, 3>)) {
+        let (attn_out, key_value) = self
+            .attn
+            .forward_cached(self.attn_ln.forward(x.clone()), Some(mask), cache);
+        let x = x + attn_out;
+        let x = x.clone()
+            + self
+                .cross_attn
+                .forward_cached(self.cross_attn_ln.forward(x), cross_key, cross_value);
+        let x = x.clone() + self.mlp.forward(self.mlp_ln.forward(x));
+        return (x, key_value);
     }
 }
 
 #[derive(Config)]
 pub struct MLPConfig {
     n_state: usize,
+    quantization: Option<Quantization>,
 }
 
 impl MLPConfig {
     pub fn init<B: Backend>(&self, tensor_device_ref: &B::Device) -> MLP<B> {
-        let lin1 = nn::LinearConfig::new(self.n_state, 4 * self.n_state).init(tensor_device_ref);
+        let lin1 = QuantizableLinearConfig::new(self.n_state, 4 * self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
         let gelu = nn::Gelu::new();
-        let lin2 = nn::LinearConfig::new(4 * self.n_state, self.n_state).init(tensor_device_ref);
+        let lin2 = QuantizableLinearConfig::new(4 * self.n_state, self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
 
         MLP { lin1, gelu, lin2 }
     }
@@ -383,9 +689,9 @@ impl MLPConfig {
 
 #[derive(Module, Debug)]
 pub struct MLP<B: Backend> {
-    lin1: nn::Linear<B>,
+    lin1: QuantizableLinear<B>,
     gelu: nn::Gelu,
-    lin2: nn::Linear<B>,
+    lin2: QuantizableLinear<B>,
 }
 
 impl<B: Backend> MLP<B> {
@@ -402,6 +708,7 @@ impl<B: Backend> MLP<B> {
 pub struct MultiHeadSelfAttentionConfig {
     n_state: usize,
     n_head: usize,
+    quantization: Option<Quantization>,
 }
 
 impl MultiHeadSelfAttentionConfig {
@@ -414,12 +721,19 @@ impl MultiHeadSelfAttentionConfig {
         );
 
         let n_head = self.n_head;
-        let query = nn::LinearConfig::new(self.n_state, self.n_state).init(tensor_device_ref);
-        let key = nn::LinearConfig::new(self.n_state, self.n_state)
+        let query = QuantizableLinearConfig::new(self.n_state, self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
+        let key = QuantizableLinearConfig::new(self.n_state, self.n_state)
             .with_bias(false)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
+        let value = QuantizableLinearConfig::new(self.n_state, self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
+        let out = QuantizableLinearConfig::new(self.n_state, self.n_state)
+            .with_quantization(self.quantization)
             .init(tensor_device_ref);
-        let value = nn::LinearConfig::new(self.n_state, self.n_state).init(tensor_device_ref);
-        let out = nn::LinearConfig::new(self.n_state, self.n_state).init(tensor_device_ref);
 
         MultiHeadSelfAttention {
             n_head,
@@ -434,10 +748,10 @@ impl MultiHeadSelfAttentionConfig {
 #[derive(Module, Debug)]
 pub struct MultiHeadSelfAttention<B: Backend> {
     n_head: usize,
-    query: nn::Linear<B>,
-    key: nn::Linear<B>,
-    value: nn::Linear<B>,
-    out: nn::Linear<B>,
+    query: QuantizableLinear<B>,
+    key: QuantizableLinear<B>,
+    value: QuantizableLinear<B>,
+    out: QuantizableLinear<B>,
 }
 
 impl<B: Backend> MultiHeadSelfAttention<B> {
@@ -450,12 +764,38 @@ impl<B: Backend> MultiHeadSelfAttention<B> {
 
         return self.out.forward(wv);
     }
+
+    /// Attends over the cached keys and values followed by those of `x`. Returns the output and
+    /// the keys and values of all tokens so far.
+    pub fn forward_cached(
+        &self,
+        x: Tensor<B, 3>,
+        mask: Option<Tensor<B, 2>>,
+        cache: Option<(Tensor<B, 3>, Tensor<B, 3>)>,
+    ) -> (Tensor<B, 3>, (Tensor<B, 3>, Tensor<B, 3>)) {
+        let q = self.query.forward(x.clone());
+        let k = self.key.forward(x.clone());
+        let v = self.value.forward(x);
+
+        let (k, v) = match cache {
+            Some((k_cache, v_cache)) => (
+                Tensor::cat(vec![k_cache, k], 1),
+                Tensor::cat(vec![v_cache, v], 1),
+            ),
+            None => (k, v),
+        };
+
+        let wv = qkv_attention(q, k.clone(), v.clone(), mask, self.n_head);
+
+        return (self.out.forward(wv), (k, v));
+    }
 }
 
 #[derive(Config)]
 pub struct MultiHeadCrossAttentionConfig {
     n_state: usize,
     n_head: usize,
+    quantization: Option<Quantization>,
 }
 
 impl MultiHeadCrossAttentionConfig {
@@ -468,12 +808,19 @@ impl MultiHeadCrossAttentionConfig {
         );
 
         let n_head = self.n_head;
-        let query = nn::LinearConfig::new(self.n_state, self.n_state).init(tensor_device_ref);
-        let key = nn::LinearConfig::new(self.n_state, self.n_state)
+        let query = QuantizableLinearConfig::new(self.n_state, self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
+        let key = QuantizableLinearConfig::new(self.n_state, self.n_state)
             .with_bias(false)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
+        let value = QuantizableLinearConfig::new(self.n_state, self.n_state)
+            .with_quantization(self.quantization)
+            .init(tensor_device_ref);
+        let out = QuantizableLinearConfig::new(self.n_state, self.n_state)
+            .with_quantization(self.quantization)
             .init(tensor_device_ref);
-        let value = nn::LinearConfig::new(self.n_state, self.n_state).init(tensor_device_ref);
-        let out = nn::LinearConfig::new(self.n_state, self.n_state).init(tensor_device_ref);
 
         MultiHeadCrossAttention {
             n_head,
@@ -488,21 +835,49 @@ impl MultiHeadCrossAttentionConfig {
 #[derive(Module, Debug)]
 pub struct MultiHeadCrossAttention<B: Backend> {
     n_head: usize,
-    query: nn::Linear<B>,
-    key: nn::Linear<B>,
-    value: nn::Linear<B>,
-    out: nn::Linear<B>,
+    query: QuantizableLinear<B>,
+    key: QuantizableLinear<B>,
+    value: QuantizableLinear<B>,
+    out: QuantizableLinear<B>,
 }
 
 impl<B: Backend> MultiHeadCrossAttention<B> {
     pub fn forward(&self, x: Tensor<B, 3>, xa: Tensor<B, 3>) -> Tensor<B, 3> {
+        self.forward_with_weights(x, xa).0
+    }
+
+    /// Keys and values of the encoder output `xa`.
+    pub fn key_value(&self, xa: Tensor<B, 3>) -> (Tensor<B, 3>, Tensor<B, 3>) {
+        (self.key.forward(xa.clone()), self.value.forward(xa))
+    }
+
+    /// Attends over precomputed keys and values, see `key_value`.
+    pub fn forward_cached(
+        &self,
+        x: Tensor<B, 3>,
+        k: Tensor<B, 3>,
+        v: Tensor<B, 3>,
+    ) -> Tensor<B, 3> {
+        let q = self.query.forward(x);
+        let wv = qkv_attention(q, k, v, None, self.n_head);
+
+        return self.out.forward(wv);
+    }
+
+    /// Returns the attention output together with the attention weights of size
+    /// (n_batch, n_head, n_qctx, n_ctx).
+    pub fn forward_with_weights(
+        &self,
+        x: Tensor<B, 3>,
+        xa: Tensor<B, 3>,
+    ) -> (Tensor<B, 3>, Tensor<B, 4>) {
         let q = self.query.forward(x);
         let k = self.key.forward(xa.clone());
         let v = self.value.forward(xa);
 
-        let wv = qkv_attention(q, k, v, None, self.n_head);
+        let (wv, weights) = qkv_attention_with_weights(q, k, v, None, self.n_head);
 
-        return self.out.forward(wv);
+        return (self.out.forward(wv), weights);
     }
 }
 
@@ -513,6 +888,16 @@ pub fn qkv_attention<B: Backend>(
     mask: Option<Tensor<B, 2>>,
     n_head: usize,
 ) -> Tensor<B, 3> {
+    qkv_attention_with_weights(q, k, v, mask, n_head).0
+}
+
+pub fn qkv_attention_with_weights<B: Backend>(
+    q: Tensor<B, 3>,
+    k: Tensor<B, 3>,
+    v: Tensor<B, 3>,
+    mask: Option<Tensor<B, 2>>,
+    n_head: usize,
+) -> (Tensor<B, 3>, Tensor<B, 4>) {
     let [n_batch, n_qctx, n_state] = q.dims();
     let [_, n_ctx, _] = k.dims();
 
@@ -543,9 +928,9 @@ pub fn qkv_attention<B: Backend>(
 
     // normalize value weightings
     let w = softmax(qk, 3);
-    let o = w.matmul(v).swap_dims(1, 2).flatten(2, 3);
+    let o = w.clone().matmul(v).swap_dims(1, 2).flatten(2, 3);
 
-    return o;
+    return (o, w);
 }
 
 pub fn attn_decoder_mask<B: Backend>(
@@ -0,0 +1,387 @@
+use burn::{
+    config::Config,
+    module::{Module, Param},
+    nn,
+    record::PrecisionSettings,
+    tensor::{backend::Backend, Int, Tensor},
+};
+use half::f16;
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
+
+use super::*;
+
+/// Post-training quantization of the weights of the attention and MLP linear layers, which hold
+/// nearly all weights of a model.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum Quantization {
+    /// Symmetric 8-bit integers with a scale per output feature.
+    Int8,
+    /// Symmetric 4-bit integers with a scale per output feature for every group of 32 input
+    /// features, like the Q4 models of whisper.cpp.
+    Int4,
+}
+
+impl Quantization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quantization::Int8 => "int8",
+            Quantization::Int4 => "int4",
+        }
+    }
+
+    fn bits(&self) -> usize {
+        match self {
+            Quantization::Int8 => 8,
+            Quantization::Int4 => 4,
+        }
+    }
+
+    /// Number of consecutive input features sharing a scale, all of them if `None`.
+    fn group_size(&self) -> Option<usize> {
+        match self {
+            Quantization::Int8 => None,
+            Quantization::Int4 => Some(32),
+        }
+    }
+
+    /// Number of values packed into an element of the int tensors of the backends.
+    fn values_per_elem(&self) -> usize {
+        32 / self.bits()
+    }
+
+    /// Largest magnitude of a value, the most negative one is left out to keep the range
+    /// symmetric.
+    fn max_value(&self) -> i32 {
+        (1 << (self.bits() - 1)) - 1
+    }
+
+    /// Whether a weight with `d_input` input features can be quantized.
+    fn fits(&self, d_input: usize) -> bool {
+        d_input % self.values_per_elem() == 0
+            && self
+                .group_size()
+                .map_or(true, |group_size| d_input % group_size == 0)
+    }
+
+    fn n_group(&self, d_input: usize) -> usize {
+        self.group_size()
+            .map_or(1, |group_size| d_input / group_size)
+    }
+}
+
+/// Record settings of quantized models. The packed weights need all 32 bits of an i32 and the
+/// weights that are not quantized are stored in half precision, as whisper.cpp does.
+#[derive(Debug, Default, Clone)]
+pub struct QuantizedPrecisionSettings;
+
+impl PrecisionSettings for QuantizedPrecisionSettings {
+    type FloatElem = f16;
+    type IntElem = i32;
+}
+
+#[derive(Config, Debug)]
+pub struct QuantizableLinearConfig {
+    d_input: usize,
+    d_output: usize,
+    #[config(default = true)]
+    bias: bool,
+    /// Initializes the layer quantized, to load the record of a quantized model into.
+    quantization: Option<Quantization>,
+}
+
+impl QuantizableLinearConfig {
+    pub fn init<B: Backend>(&self, tensor_device_ref: &B::Device) -> QuantizableLinear<B> {
+        let quantization = match self.quantization {
+            Some(quantization) if quantization.fits(self.d_input) => quantization,
+            _ => {
+                return nn::LinearConfig::new(self.d_input, self.d_output)
+                    .with_bias(self.bias)
+                    .init(tensor_device_ref)
+                    .into()
+            }
+        };
+
+        let packed = Param::from_tensor(Tensor::zeros(
+            [self.d_input / quantization.values_per_elem(), self.d_output],
+            tensor_device_ref,
+        ));
+        let scale = Param::from_tensor(Tensor::zeros(
+            [quantization.n_group(self.d_input), self.d_output],
+            tensor_device_ref,
+        ));
+        let bias = match self.bias {
+            true => Some(Param::from_tensor(Tensor::zeros(
+                [self.d_output],
+                tensor_device_ref,
+            ))),
+            false => None,
+        };
+
+        let (int8, int4) = match quantization {
+            Quantization::Int8 => (Some(packed), None),
+            Quantization::Int4 => (None, Some(packed)),
+        };
+
+        QuantizableLinear {
+            weight: None,
+            int8,
+            int4,
+            scale: Some(scale),
+            bias,
+        }
+    }
+}
+
+/// A linear layer whose weight is either kept as is or quantized. The quantized weight is
+/// dequantized on every forward pass as Burn has no integer matmul, so only memory is saved.
+#[derive(Module, Debug)]
+pub struct QuantizableLinear<B: Backend> {
+    /// Weight of size (d_input, d_output) as in `nn::Linear`.
+    weight: Option<Param<Tensor<B, 2>>>,
+    /// Int8 weight of size (d_input / 4, d_output), see `pack`.
+    int8: Option<Param<Tensor<B, 2, Int>>>,
+    /// Int4 weight of size (d_input / 8, d_output), see `pack`.
+    int4: Option<Param<Tensor<B, 2, Int>>>,
+    /// Scale of the quantized weight of size (n_group, d_output).
+    scale: Option<Param<Tensor<B, 2>>>,
+    bias: Option<Param<Tensor<B, 1>>>,
+}
+
+impl<B: Backend> From<nn::Linear<B>> for QuantizableLinear<B> {
+    fn from(linear: nn::Linear<B>) -> Self {
+        Self {
+            weight: Some(linear.weight),
+            int8: None,
+            int4: None,
+            scale: None,
+            bias: linear.bias,
+        }
+    }
+}
+
+impl<B: Backend> QuantizableLinear<B> {
+    pub fn forward<const D: usize>(&self, input: Tensor<B, D>) -> Tensor<B, D> {
+        let output = input.matmul(self.weight().unsqueeze());
+
+        match &self.bias {
+            Some(bias) => output + bias.val().unsqueeze(),
+            None => output,
+        }
+    }
+
+    /// Weight of size (d_input, d_output), dequantized if the layer is quantized.
+    pub fn weight(&self) -> Tensor<B, 2> {
+        if let Some(weight) = &self.weight {
+            return weight.val();
+        }
+        let (quantization, packed) = match (&self.int8, &self.int4) {
+            (Some(packed), _) => (Quantization::Int8, packed),
+            (None, Some(packed)) => (Quantization::Int4, packed),
+            (None, None) => panic!("Linear layer has neither a weight nor a quantized weight"),
+        };
+        let scale = self
+            .scale
+            .as_ref()
+            .expect("Quantized linear layer without a scale")
+            .val();
+
+        let values = unpack(packed.val(), quantization.bits());
+        let [d_input, d_output] = values.dims();
+        let [n_group, _] = scale.dims();
+        let weight = values
+            .float()
+            .reshape([n_group, d_input / n_group, d_output])
+            * scale.unsqueeze_dim(1);
+        weight.reshape([d_input, d_output])
+    }
+
+    pub fn bias(&self) -> Option<Tensor<B, 1>> {
+        self.bias.as_ref().map(|bias| bias.val())
+    }
+
+    /// Names and sizes of the tensors the layer is stored as, the weight isn't dequantized.
+    pub fn shapes(&self) -> Vec<(&'static str, Vec<usize>)> {
+        let mut shapes = Vec::new();
+        if let Some(weight) = &self.weight {
+            shapes.push(("weight", weight.val().dims().to_vec()));
+        }
+        if let Some(int8) = &self.int8 {
+            shapes.push(("int8", int8.val().dims().to_vec()));
+        }
+        if let Some(int4) = &self.int4 {
+            shapes.push(("int4", int4.val().dims().to_vec()));
+        }
+        if let Some(scale) = &self.scale {
+            shapes.push(("scale", scale.val().dims().to_vec()));
+        }
+        if let Some(bias) = &self.bias {
+            shapes.push(("bias", bias.val().dims().to_vec()));
+        }
+        shapes
+    }
+
+    /// Quantizes the weight, leaving layers whose input size doesn't fit the packing or the
+    /// groups as they are.
+    pub fn quantize(self, quantization: Quantization) -> Self {
+        let weight = match &self.weight {
+            Some(weight) => weight.val(),
+            None => return self,
+        };
+        let [d_input, d_output] = weight.dims();
+        if !quantization.fits(d_input) {
+            return self;
+        }
+
+        let max_value = quantization.max_value();
+        let n_group = quantization.n_group(d_input);
+        let weight = weight.reshape([n_group, d_input / n_group, d_output]);
+        let scale = weight
+            .clone()
+            .abs()
+            .max_dim(1)
+            .div_scalar(max_value as f32)
+            .clamp_min(1e-6);
+        // rounds half up, the offset keeps the values positive so truncating floors
+        let values = (weight / scale.clone())
+            .add_scalar(max_value as f32 + 1.5)
+            .int()
+            .sub_scalar(max_value + 1)
+            .clamp(-max_value, max_value)
+            .reshape([d_input, d_output]);
+
+        let packed = Some(Param::from_tensor(pack(values, quantization.bits())));
+        let (int8, int4) = match quantization {
+            Quantization::Int8 => (packed, None),
+            Quantization::Int4 => (None, packed),
+        };
+
+        Self {
+            weight: None,
+            int8,
+            int4,
+            scale: Some(Param::from_tensor(scale.reshape([n_group, d_output]))),
+            bias: self.bias,
+        }
+    }
+}
+
+/// Packs the values of `bits` bits of size (d_input, d_output) into elements of size
+/// (d_input / n, d_output) with n = 32 / bits, taking one value from each of n consecutive
+/// parts of the rows. The values of all parts but the last are stored offset by half their
+/// range in the lower bits and those of the last part signed in the upper bits, so the packed
+/// value fits an i32.
+fn pack<B: Backend>(values: Tensor<B, 2, Int>, bits: usize) -> Tensor<B, 2, Int> {
+    let [d_input, d_output] = values.dims();
+    let n_part = 32 / bits;
+    let n_row = d_input / n_part;
+    let offset = 1 << (bits - 1);
+
+    (0..n_part)
+        .map(|i| {
+            let part = values
+                .clone()
+                .slice([i * n_row..(i + 1) * n_row, 0..d_output]);
+            let part = match i + 1 < n_part {
+                true => part.add_scalar(offset),
+                false => part,
+            };
+            part.mul_scalar(1 << (i * bits))
+        })
+        .reduce(|packed, part| packed + part)
+        .unwrap()
+}
+
+/// Inverse of `pack`.
+fn unpack<B: Backend>(packed: Tensor<B, 2, Int>, bits: usize) -> Tensor<B, 2, Int> {
+    let n_part = 32 / bits;
+    let offset = 1 << (bits - 1);
+    let upper_shift = 1 << ((n_part - 1) * bits);
+
+    let upper = packed.clone().div_scalar(upper_shift);
+    let lower = packed - upper.clone().mul_scalar(upper_shift);
+    // backends either truncate or floor the division, the lower bits must be positive
+    let borrow = lower.clone().lower_elem(0).int();
+    let upper = upper - borrow.clone();
+    let mut lower = lower + borrow.mul_scalar(upper_shift);
+
+    let mut parts = Vec::with_capacity(n_part);
+    for _ in 0..n_part - 1 {
+        let rest = lower.clone().div_scalar(1 << bits);
+        parts.push((lower - rest.clone().mul_scalar(1 << bits)).sub_scalar(offset));
+        lower = rest;
+    }
+    parts.push(upper);
+
+    Tensor::cat(parts, 0)
+}
+
+impl<B: Backend> Whisper<B> {
+    /// Quantizes the weights of the linear layers of the attention blocks and MLPs. The
+    /// embeddings, convolutions and layer norms are kept as they are.
+    pub fn quantize(self, quantization: Quantization) -> Self {
+        Whisper {
+            encoder: AudioEncoder {
+                blocks: self
+                    .encoder
+                    .blocks
+                    .into_iter()
+                    .map(|block| ResidualEncoderAttentionBlock {
+                        attn: block.attn.quantize(quantization),
+                        mlp: block.mlp.quantize(quantization),
+                        ..block
+                    })
+                    .collect(),
+                ..self.encoder
+            },
+            decoder: TextDecoder {
+                blocks: self
+                    .decoder
+                    .blocks
+                    .into_iter()
+                    .map(|block| ResidualDecoderAttentionBlock {
+                        attn: block.attn.quantize(quantization),
+                        cross_attn: block.cross_attn.quantize(quantization),
+                        mlp: block.mlp.quantize(quantization),
+                        ..block
+                    })
+                    .collect(),
+                ..self.decoder
+            },
+        }
+    }
+}
+
+impl<B: Backend> MultiHeadSelfAttention<B> {
+    fn quantize(self, quantization: Quantization) -> Self {
+        MultiHeadSelfAttention {
+            n_head: self.n_head,
+            query: self.query.quantize(quantization),
+            key: self.key.quantize(quantization),
+            value: self.value.quantize(quantization),
+            out: self.out.quantize(quantization),
+        }
+    }
+}
+
+impl<B: Backend> MultiHeadCrossAttention<B> {
+    fn quantize(self, quantization: Quantization) -> Self {
+        MultiHeadCrossAttention {
+            n_head: self.n_head,
+            query: self.query.quantize(quantization),
+            key: self.key.quantize(quantization),
+            value: self.value.quantize(quantization),
+            out: self.out.quantize(quantization),
+        }
+    }
+}
+
+impl<B: Backend> MLP<B> {
+    fn quantize(self, quantization: Quantization) -> Self {
+        MLP {
+            lin1: self.lin1.quantize(quantization),
+            gelu: self.gelu,
+            lin2: self.lin2.quantize(quantization),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use burn::tensor::backend::Backend;
+use half::{bf16, f16};
+use safetensors::{tensor::TensorView, Dtype, SafeTensors};
+use std::{error::Error, path::Path};
+
+use super::{
+    checkpoint::{map_file, named_tensors, Checkpoint, Layout, TensorSource},
+    Whisper, WhisperConfig,
+};
+
+fn to_f32(view: &TensorView) -> Result<Vec<f32>, Box<dyn Error>> {
+    let data = view.data();
+    let values = match view.dtype() {
+        Dtype::F32 => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        Dtype::F16 => data
+            .chunks_exact(2)
+            .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32())
+            .collect(),
+        Dtype::BF16 => data
+            .chunks_exact(2)
+            .map(|b| bf16::from_le_bytes([b[0], b[1]]).to_f32())
+            .collect(),
+        dtype => return Err(format!("Unsupported tensor type {:?}", dtype).into()),
+    };
+    Ok(values)
+}
+
+impl TensorSource for SafeTensors<'_> {
+    fn names(&self) -> Vec<String> {
+        SafeTensors::names(self).into_iter().cloned().collect()
+    }
+
+    fn dims(&self, name: &str) -> Option<Vec<usize>> {
+        self.tensor(name).ok().map(|view| view.shape().to_vec())
+    }
+
+    fn values(&self, name: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+        to_f32(&self.tensor(name)?)
+    }
+}
+
+/// Loads a Whisper checkpoint saved with safetensors in the layout of openai/whisper or of
+/// Hugging Face transformers, which is told by the names of the tensors. Half precision
+/// weights are widened to `f32`.
+pub fn load_whisper_safetensors<B: Backend>(
+    path: &str,
+) -> Result<(Whisper<B>, WhisperConfig, Layout), Box<dyn Error>> {
+    let bytes = map_file(path)?;
+    let checkpoint = Checkpoint::new(SafeTensors::deserialize(&bytes)?)?;
+    let (whisper, config) = checkpoint.whisper()?;
+    Ok((whisper, config, checkpoint.layout()))
+}
+
+/// Saves `whisper` with safetensors in the layout of openai/whisper, in `f32`. The file loads
+/// with `load_whisper_safetensors`, which maps it into memory instead of reading it whole.
+pub fn save_whisper_safetensors<B: Backend>(
+    whisper: &Whisper<B>,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let tensors: Vec<_> = named_tensors(whisper)
+        .into_iter()
+        .map(|tensor| {
+            let bytes: Vec<u8> = tensor
+                .values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+            (tensor.name, tensor.dims, bytes)
+        })
+        .collect();
+    let views = tensors
+        .iter()
+        .map(|(name, dims, bytes)| Ok((name, TensorView::new(Dtype::F32, dims.clone(), bytes)?)))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    safetensors::serialize_to_file(views, &None, Path::new(path))?;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use super::OutputOptions;
+use crate::transcribe::Segment;
+
+/// Renders the segments as Advanced SubStation Alpha subtitles, one dialogue line per segment,
+/// styled by the `ass_*` options. With `word_highlight` the words are timed with karaoke tags
+/// so players fill them in with the primary color as they are spoken. That requires word
+/// timestamps, segments without words are rendered as plain lines.
+pub fn to_ass(segments: &[Segment], options: &OutputOptions) -> String {
+    let mut ass = format!(
+        "[Script Info]\n\
+         ScriptType: v4.00+\n\
+         PlayResX: 1920\n\
+         PlayResY: 1080\n\
+         WrapStyle: 0\n\
+         ScaledBorderAndShadow: yes\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+         BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+         BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Default,{},{},&H00FFFFFF,&H00808080,&H00000000,&H80000000,0,0,0,0,100,100,0,0,\
+         1,3,1,{},40,40,{},1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        options.ass_font, options.ass_font_size, options.ass_alignment, options.ass_margin,
+    );
+
+    for segment in segments.iter().filter(|segment| !segment.text.trim().is_empty()) {
+        let text = if options.word_highlight && !segment.words.is_empty() {
+            karaoke_text(segment)
+        } else {
+            escape(segment.text.trim())
+        };
+
+        ass.push_str(&format!(
+            "Dialogue: 0,{},{},Default,,0,0,0,,{}\n",
+            format_time(segment.start),
+            format_time(segment.end),
+            text
+        ));
+    }
+
+    ass
+}
+
+/// Text of the segment with a `\k` tag in front of every word holding its duration, preceded
+/// by an empty one for any pause before it.
+fn karaoke_text(segment: &Segment) -> String {
+    let mut text = String::new();
+    let mut time = segment.start;
+
+    for (i, word) in segment.words.iter().enumerate() {
+        let pause = centiseconds(word.start - time);
+        if pause > 0 {
+            text.push_str(&format!("{{\\k{}}}", pause));
+        }
+
+        let word_text = escape(&word.word);
+        let word_text = if i == 0 { word_text.trim_start() } else { &word_text };
+        text.push_str(&format!("{{\\k{}}}{}", centiseconds(word.end - word.start), word_text));
+        time = word.end.max(time);
+    }
+
+    text
+}
+
+fn centiseconds(seconds: f64) -> u64 {
+    (seconds.max(0.0) * 100.0).round() as u64
+}
+
+/// Formats a time as `H:MM:SS.cc`, the format of ASS event times.
+fn format_time(seconds: f64) -> String {
+    let centiseconds = centiseconds(seconds);
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centiseconds / 360_000,
+        centiseconds / 6000 % 60,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
+
+/// Braces start override tags in ASS and line breaks have to be written as `\N`.
+fn escape(text: &str) -> String {
+    text.replace('{', "(")
+        .replace('}', ")")
+        .replace("\r\n", "\\N")
+        .replace('\n', "\\N")
+}
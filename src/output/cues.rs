@@ -0,0 +1,113 @@
+use super::OutputOptions;
+use crate::timestamps::WordTiming;
+use crate::transcribe::Segment;
+
+/// Splits the segments into subtitle cues of at most `max_words_per_cue` words and
+/// `max_line_count` lines of at most `max_line_width` characters, see `OutputOptions`. Long
+/// segments are split at the word timestamps, each cue being timed by its own words. Segments
+/// without word timestamps can't be split in time and only have their text wrapped.
+pub fn split_cues(segments: &[Segment], options: &OutputOptions) -> Vec<Segment> {
+    let limited = options.max_line_width.is_some()
+        || options.max_line_count.is_some()
+        || options.max_words_per_cue.is_some();
+    if !limited {
+        return segments.to_vec();
+    }
+
+    segments
+        .iter()
+        .flat_map(|segment| {
+            if segment.words.is_empty() {
+                let text = match options.max_line_width {
+                    Some(width) => wrap_text(&segment.text, width),
+                    None => segment.text.clone(),
+                };
+                vec![Segment {
+                    text,
+                    ..segment.clone()
+                }]
+            } else {
+                split_segment(segment, options)
+            }
+        })
+        .collect()
+}
+
+fn split_segment(segment: &Segment, options: &OutputOptions) -> Vec<Segment> {
+    let mut cues = Vec::new();
+    let mut words: Vec<WordTiming> = Vec::new();
+    let mut lines: Vec<String> = Vec::new();
+
+    for word in &segment.words {
+        let line_width = lines.last().map_or(0, |line| line.chars().count());
+        let cue_full = options
+            .max_words_per_cue
+            .map_or(false, |max_words| words.len() >= max_words);
+        // words of languages written with spaces carry the space in front of them
+        let line_full = options.max_line_width.map_or(false, |width| {
+            line_width > 0 && line_width + word.word.chars().count() > width
+        });
+        let lines_full = options
+            .max_line_count
+            .map_or(false, |max_lines| lines.len() >= max_lines);
+
+        if cue_full || (line_full && lines_full) {
+            push_cue(&mut cues, segment, &mut words, &mut lines);
+        }
+        if lines.is_empty() || line_full {
+            lines.push(String::new());
+        }
+
+        let line = lines.last_mut().expect("A line was just added");
+        if line.is_empty() {
+            line.push_str(word.word.trim_start());
+        } else {
+            line.push_str(&word.word);
+        }
+        words.push(word.clone());
+    }
+    push_cue(&mut cues, segment, &mut words, &mut lines);
+
+    cues
+}
+
+/// Turns the collected words and lines into a cue timed by the words.
+fn push_cue(
+    cues: &mut Vec<Segment>,
+    segment: &Segment,
+    words: &mut Vec<WordTiming>,
+    lines: &mut Vec<String>,
+) {
+    if words.is_empty() {
+        return;
+    }
+
+    cues.push(Segment {
+        start: words[0].start,
+        end: words[words.len() - 1].end,
+        text: lines.join("\n"),
+        words: std::mem::take(words),
+        ..segment.clone()
+    });
+    lines.clear();
+}
+
+/// Breaks the text into lines of at most `width` characters at spaces. Words longer than a
+/// line are left whole.
+fn wrap_text(text: &str, width: usize) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+
+    lines.join("\n")
+}
@@ -0,0 +1,142 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::speakers::speaker_labels;
+use crate::timestamps::WordTiming;
+use crate::token::Language;
+use crate::transcribe::{Segment, TranscriptionResult};
+
+/// The transcription in the shape of the `verbose_json` response of the OpenAI transcription
+/// API, with the confidence and speaker of every segment and the model name added.
+#[derive(Serialize)]
+struct Transcription<'a> {
+    task: &'a str,
+    language: String,
+    duration: f64,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+    segments: Vec<JsonSegment<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    words: Vec<JsonWord<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonSegment<'a> {
+    id: usize,
+    start: f64,
+    end: f64,
+    text: &'a str,
+    tokens: &'a [usize],
+    temperature: f64,
+    avg_logprob: f64,
+    compression_ratio: f64,
+    no_speech_prob: f64,
+    confidence: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speaker: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    words: Vec<JsonWord<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonWord<'a> {
+    word: &'a str,
+    start: f64,
+    end: f64,
+    probability: f64,
+}
+
+fn json_segment<'a>(
+    id: usize,
+    segment: &'a Segment,
+    speakers: &'a HashMap<usize, String>,
+) -> JsonSegment<'a> {
+    JsonSegment {
+        id,
+        start: segment.start,
+        end: segment.end,
+        text: &segment.text,
+        tokens: &segment.tokens,
+        temperature: segment.temperature,
+        avg_logprob: segment.avg_logprob,
+        compression_ratio: segment.compression_ratio,
+        no_speech_prob: segment.no_speech_prob,
+        confidence: segment.confidence,
+        speaker: segment
+            .speaker
+            .and_then(|speaker| speakers.get(&speaker))
+            .map(String::as_str),
+        words: segment.words.iter().map(json_word).collect(),
+    }
+}
+
+fn json_word(word: &WordTiming) -> JsonWord {
+    JsonWord {
+        word: &word.word,
+        start: word.start,
+        end: word.end,
+        probability: word.probability,
+    }
+}
+
+/// Lower case English name of the language, e.g. `haitian creole`, as used by the OpenAI API.
+fn language_name(language: Language) -> String {
+    let mut name = String::new();
+    for (i, c) in format!("{:?}", language).chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            name.push(' ');
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
+
+/// Renders the transcription as a single JSON object, see `Transcription`. `model` is the name
+/// of the model used, left out when unset.
+pub fn to_json(result: &TranscriptionResult, model: Option<&str>) -> String {
+    let speakers = speaker_labels(&result.segments);
+    let transcription = Transcription {
+        task: result.task.as_str(),
+        language: language_name(result.language),
+        duration: result.duration,
+        text: &result.text,
+        model,
+        segments: result
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(id, segment)| json_segment(id, segment, &speakers))
+            .collect(),
+        words: result
+            .segments
+            .iter()
+            .flat_map(|segment| segment.words.iter().map(json_word))
+            .collect(),
+    };
+
+    serde_json::to_string_pretty(&transcription).expect("Transcription can be serialized") + "\n"
+}
+
+/// Renders every segment as a JSON object on a line of its own, in the shape of the segments
+/// of `to_json`.
+pub fn to_jsonl(segments: &[Segment]) -> String {
+    to_jsonl_numbered(segments, 0, &speaker_labels(segments))
+}
+
+/// Same as `to_jsonl` with the segment ids counted from `first_id` and the speakers named by
+/// `speakers`, for appending to a file that already has segments.
+pub fn to_jsonl_numbered(
+    segments: &[Segment],
+    first_id: usize,
+    speakers: &HashMap<usize, String>,
+) -> String {
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let segment = json_segment(first_id + i, segment, &speakers);
+            serde_json::to_string(&segment).expect("Segment can be serialized") + "\n"
+        })
+        .collect()
+}
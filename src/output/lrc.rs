@@ -0,0 +1,39 @@
+use crate::transcribe::Segment;
+
+/// Renders the segments as LRC lyrics, a line per segment stamped with its start time. With
+/// `word_highlight` the lines are in the enhanced LRC format, with the start of every word and
+/// the end of the line stamped too, so karaoke players can highlight the words as they are
+/// sung.
+pub fn to_lrc(segments: &[Segment], word_highlight: bool) -> String {
+    let mut lrc = String::new();
+
+    for segment in segments.iter().filter(|segment| !segment.text.trim().is_empty()) {
+        let text = if word_highlight && !segment.words.is_empty() {
+            let mut text: String = segment
+                .words
+                .iter()
+                .map(|word| format!("<{}>{} ", format_time(word.start), word.word.trim()))
+                .collect();
+            let end = segment.words.last().map_or(segment.end, |word| word.end);
+            text.push_str(&format!("<{}>", format_time(end)));
+            text
+        } else {
+            segment.text.trim().to_string()
+        };
+
+        lrc.push_str(&format!("[{}]{}\n", format_time(segment.start), text));
+    }
+
+    lrc
+}
+
+/// Formats a time in seconds as `mm:ss.xx`, with the minutes going past 59.
+fn format_time(seconds: f64) -> String {
+    let centiseconds = (seconds.max(0.0) * 100.0).round() as u64;
+    format!(
+        "{:02}:{:02}.{:02}",
+        centiseconds / 6000,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
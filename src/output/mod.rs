@@ -0,0 +1,181 @@
+pub mod ass;
+pub mod cues;
+pub mod json;
+pub mod lrc;
+pub mod speakers;
+pub mod srt;
+pub mod table;
+pub mod vtt;
+pub mod writer;
+
+use burn::config::Config;
+use chrono::{DateTime, Local};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+use strum_macros::EnumIter;
+
+use crate::transcribe::{Segment, TranscriptionResult};
+
+/// Formats a transcription can be written in.
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter)]
+pub enum OutputFormat {
+    /// Plain text.
+    Txt,
+    /// SubRip subtitles, see `srt::to_srt`.
+    Srt,
+    /// WebVTT subtitles, see `vtt::to_vtt`.
+    Vtt,
+    /// JSON in the shape of OpenAI's `verbose_json`, see `json::to_json`.
+    Json,
+    /// A JSON object per segment and line, see `json::to_jsonl`.
+    Jsonl,
+    /// Tab separated start, end and text rows, see `table::to_tsv`.
+    Tsv,
+    /// Comma separated start, end and text rows, see `table::to_csv`.
+    Csv,
+    /// Advanced SubStation Alpha subtitles, see `ass::to_ass`.
+    Ass,
+    /// LRC lyrics for karaoke players, see `lrc::to_lrc`.
+    Lrc,
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &str {
+        match self {
+            OutputFormat::Txt => "txt",
+            OutputFormat::Srt => "srt",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Ass => "ass",
+            OutputFormat::Lrc => "lrc",
+        }
+    }
+
+    /// Whether segments can be appended to a file of this format, see `writer::SegmentWriter`.
+    pub fn is_appendable(&self) -> bool {
+        !matches!(self, OutputFormat::Json | OutputFormat::Ass)
+    }
+}
+
+/// Options of how a transcription is rendered, see `render`.
+#[derive(Config, Debug)]
+pub struct OutputOptions {
+    /// Mark up the timing of every word in formats that support it, VTT, ASS and LRC, or give
+    /// every word a cue of its own in SRT. Requires the transcription to be decoded with
+    /// `DecodingOptions::word_timestamps`.
+    #[config(default = false)]
+    pub word_highlight: bool,
+    /// Name of the model the transcription was made with, included in the JSON output.
+    pub model: Option<String>,
+    /// Maximum number of characters per line of subtitles, see `cues::split_cues`.
+    pub max_line_width: Option<usize>,
+    /// Maximum number of lines per subtitle cue.
+    pub max_line_count: Option<usize>,
+    /// Maximum number of words per subtitle cue.
+    pub max_words_per_cue: Option<usize>,
+    /// Font of ASS subtitles.
+    #[config(default = "String::from(\"Arial\")")]
+    pub ass_font: String,
+    /// Font size of ASS subtitles, relative to a 1920x1080 frame.
+    #[config(default = 64)]
+    pub ass_font_size: usize,
+    /// Position of ASS subtitles on the screen as on a numeric keypad, e.g. 2 for bottom
+    /// center and 8 for top center.
+    #[config(default = 2)]
+    pub ass_alignment: usize,
+    /// Distance of ASS subtitles from the top or bottom edge of a 1920x1080 frame.
+    #[config(default = 60)]
+    pub ass_margin: usize,
+    /// Wall clock time the audio started at, in seconds since the Unix epoch. Plain text
+    /// written by `writer::SegmentWriter` then stamps every segment with the local time it was
+    /// spoken at, see `format_wall_clock`.
+    pub wall_clock_start: Option<f64>,
+}
+
+/// Renders the transcription in `format`.
+pub fn render(
+    result: &TranscriptionResult,
+    format: OutputFormat,
+    options: &OutputOptions,
+) -> String {
+    match format {
+        OutputFormat::Txt if speakers::has_speakers(&result.segments) => {
+            speakers::to_labelled_txt(&result.segments)
+        }
+        OutputFormat::Txt => result.text.clone(),
+        OutputFormat::Srt => srt::to_srt(&srt_cues(&result.segments, options)),
+        OutputFormat::Vtt => vtt::to_vtt(
+            &cues::split_cues(&result.segments, options),
+            options.word_highlight,
+        ),
+        OutputFormat::Json => json::to_json(result, options.model.as_deref()),
+        OutputFormat::Jsonl => json::to_jsonl(&result.segments),
+        OutputFormat::Tsv => table::to_tsv(&result.segments),
+        OutputFormat::Csv => table::to_csv(&result.segments),
+        OutputFormat::Ass => ass::to_ass(&cues::split_cues(&result.segments, options), options),
+        OutputFormat::Lrc => lrc::to_lrc(
+            &cues::split_cues(&result.segments, options),
+            options.word_highlight,
+        ),
+    }
+}
+
+/// Cues of SRT subtitles labelled with their speakers, a cue per word with `word_highlight`.
+fn srt_cues(segments: &[Segment], options: &OutputOptions) -> Vec<Segment> {
+    let cues = cues::split_cues(segments, options);
+    let cues = if options.word_highlight {
+        srt::highlight_words(&cues)
+    } else {
+        cues
+    };
+    speakers::label_segments(&cues)
+}
+
+/// Writes the transcription to `path` in `format`. The file is replaced atomically, so a crash
+/// while writing leaves either the previous file, e.g. one written by `writer::SegmentWriter`,
+/// or the complete new one.
+pub fn write_output<P: AsRef<Path>>(
+    path: P,
+    result: &TranscriptionResult,
+    format: OutputFormat,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(render(result, format, options).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+/// Formats a time in seconds as `HH:MM:SS` followed by `decimal_marker` and milliseconds. The
+/// hours are left out when zero unless `always_include_hours` is set.
+pub fn format_timestamp(seconds: f64, always_include_hours: bool, decimal_marker: char) -> String {
+    let milliseconds = (seconds.max(0.0) * 1000.0).round() as u64;
+    let hours = milliseconds / 3_600_000;
+    let minutes = milliseconds / 60_000 % 60;
+    let secs = milliseconds / 1000 % 60;
+    let millis = milliseconds % 1000;
+
+    if always_include_hours || hours > 0 {
+        format!("{:02}:{:02}:{:02}{}{:03}", hours, minutes, secs, decimal_marker, millis)
+    } else {
+        format!("{:02}:{:02}{}{:03}", minutes, secs, decimal_marker, millis)
+    }
+}
+
+/// Formats a time in seconds since the Unix epoch as the local time of day, `HH:MM:SS`.
+pub fn format_wall_clock(unix_time: f64) -> String {
+    let millis = (unix_time.max(0.0) * 1000.0).round() as i64;
+    DateTime::from_timestamp_millis(millis)
+        .map(|time| time.with_timezone(&Local).format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
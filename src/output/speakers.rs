@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use crate::transcribe::Segment;
+
+/// Names the speakers of the segments `SPEAKER 1`, `SPEAKER 2` and so on in the order they
+/// first speak, so the names don't depend on how speaker diarization happened to number them.
+/// Returns the name of every speaker number.
+pub fn speaker_labels(segments: &[Segment]) -> HashMap<usize, String> {
+    let mut labels = HashMap::new();
+    add_speaker_labels(&mut labels, segments);
+    labels
+}
+
+/// Names the speakers of the segments that don't have a name in `labels` yet, for segments
+/// that are written out a few at a time.
+pub fn add_speaker_labels(labels: &mut HashMap<usize, String>, segments: &[Segment]) {
+    for speaker in segments.iter().filter_map(|segment| segment.speaker) {
+        let n_labels = labels.len();
+        labels
+            .entry(speaker)
+            .or_insert_with(|| format!("SPEAKER {}", n_labels + 1));
+    }
+}
+
+/// Whether any segment has a speaker, see `Segment::speaker`.
+pub fn has_speakers(segments: &[Segment]) -> bool {
+    segments.iter().any(|segment| segment.speaker.is_some())
+}
+
+/// Prefixes the text of every segment with the name of its speaker, e.g. `SPEAKER 1: Hello`.
+pub fn label_segments(segments: &[Segment]) -> Vec<Segment> {
+    let labels = speaker_labels(segments);
+
+    segments
+        .iter()
+        .map(|segment| match segment.speaker.and_then(|speaker| labels.get(&speaker)) {
+            Some(label) => Segment {
+                text: format!("{}: {}", label, segment.text.trim()),
+                ..segment.clone()
+            },
+            None => segment.clone(),
+        })
+        .collect()
+}
+
+/// Plain text with a paragraph per turn, starting with the name of the speaker.
+pub fn to_labelled_txt(segments: &[Segment]) -> String {
+    let labels = speaker_labels(segments);
+    let mut text = String::new();
+    let mut previous_speaker = None;
+
+    for segment in segments.iter().filter(|segment| !segment.text.trim().is_empty()) {
+        if segment.speaker != previous_speaker || text.is_empty() {
+            if !text.is_empty() {
+                text.push_str("\n\n");
+            }
+            if let Some(label) = segment.speaker.and_then(|speaker| labels.get(&speaker)) {
+                text.push_str(&format!("{}:", label));
+            }
+            previous_speaker = segment.speaker;
+        }
+
+        if !text.is_empty() && !text.ends_with("\n\n") {
+            text.push(' ');
+        }
+        text.push_str(segment.text.trim());
+    }
+    text.push('\n');
+
+    text
+}
@@ -0,0 +1,74 @@
+use super::format_timestamp;
+use crate::transcribe::Segment;
+
+/// Renders the segments as SubRip subtitles, one numbered cue per segment. Segments without
+/// text are left out.
+pub fn to_srt(segments: &[Segment]) -> String {
+    to_srt_numbered(segments, 1)
+}
+
+/// Same as `to_srt` with the cues numbered from `first_number`, for appending to a file that
+/// already has cues.
+pub fn to_srt_numbered(segments: &[Segment], first_number: usize) -> String {
+    segments
+        .iter()
+        .filter(|segment| !segment.text.trim().is_empty())
+        .enumerate()
+        .map(|(i, segment)| {
+            format!(
+                "{}\n{} --> {}\n{}\n\n",
+                first_number + i,
+                format_timestamp(segment.start, true, ','),
+                format_timestamp(segment.end, true, ','),
+                segment.text.trim().replace("-->", "->"),
+            )
+        })
+        .collect()
+}
+
+/// Turns every cue with word timestamps into a cue per word showing the whole text with that
+/// word underlined, lasting until the next word starts, like the `highlight_words` option of
+/// openai/whisper. Subtitle players without karaoke support show the words being spoken this
+/// way.
+pub fn highlight_words(cues: &[Segment]) -> Vec<Segment> {
+    let mut highlighted = Vec::new();
+
+    for cue in cues {
+        // the byte range of every word in the text, which may have been wrapped into lines
+        let mut ranges = Vec::with_capacity(cue.words.len());
+        let mut position = 0;
+        for word in &cue.words {
+            let word_text = word.word.trim();
+            match cue.text[position..].find(word_text) {
+                Some(offset) if !word_text.is_empty() => {
+                    let start = position + offset;
+                    position = start + word_text.len();
+                    ranges.push(start..position);
+                }
+                _ => break,
+            }
+        }
+        if cue.words.is_empty() || ranges.len() < cue.words.len() {
+            highlighted.push(cue.clone());
+            continue;
+        }
+
+        for (i, (word, range)) in cue.words.iter().zip(&ranges).enumerate() {
+            let end = cue.words.get(i + 1).map_or(cue.end, |next| next.start);
+            let text = format!(
+                "{}<u>{}</u>{}",
+                &cue.text[..range.start],
+                &cue.text[range.clone()],
+                &cue.text[range.end..]
+            );
+            highlighted.push(Segment {
+                start: word.start,
+                end: end.max(word.end),
+                text,
+                ..cue.clone()
+            });
+        }
+    }
+
+    highlighted
+}
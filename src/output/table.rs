@@ -0,0 +1,38 @@
+use crate::transcribe::Segment;
+
+/// Renders the segments as tab separated rows of start and end time in milliseconds and text,
+/// after a header row, like openai/whisper. Tabs and line breaks in the text are replaced by
+/// spaces.
+pub fn to_tsv(segments: &[Segment]) -> String {
+    let mut tsv = String::from("start\tend\ttext\n");
+    for segment in segments.iter().filter(|segment| !segment.text.trim().is_empty()) {
+        let text = segment.text.trim().replace(['\t', '\n', '\r'], " ");
+        tsv.push_str(&format!(
+            "{}\t{}\t{}\n",
+            milliseconds(segment.start),
+            milliseconds(segment.end),
+            text
+        ));
+    }
+    tsv
+}
+
+/// Renders the segments as comma separated rows like `to_tsv`, quoting the text as RFC 4180
+/// requires so spreadsheets keep commas and quotes in it.
+pub fn to_csv(segments: &[Segment]) -> String {
+    let mut csv = String::from("start,end,text\r\n");
+    for segment in segments.iter().filter(|segment| !segment.text.trim().is_empty()) {
+        let text = segment.text.trim().replace('"', "\"\"");
+        csv.push_str(&format!(
+            "{},{},\"{}\"\r\n",
+            milliseconds(segment.start),
+            milliseconds(segment.end),
+            text
+        ));
+    }
+    csv
+}
+
+fn milliseconds(seconds: f64) -> u64 {
+    (seconds.max(0.0) * 1000.0).round() as u64
+}
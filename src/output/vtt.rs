@@ -0,0 +1,48 @@
+use super::format_timestamp;
+use crate::transcribe::Segment;
+
+/// Renders the segments as WebVTT subtitles, one cue per segment. With `word_highlight` every
+/// word of a cue is preceded by a timestamp tag and wrapped in a `<c>` span, so players can
+/// highlight the words as they are spoken. That requires word timestamps, segments without
+/// words are rendered as plain cues.
+pub fn to_vtt(segments: &[Segment], word_highlight: bool) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+
+    for segment in segments.iter().filter(|segment| !segment.text.trim().is_empty()) {
+        let text = if word_highlight && !segment.words.is_empty() {
+            segment
+                .words
+                .iter()
+                .enumerate()
+                .map(|(i, word)| {
+                    let word_text = escape(&word.word);
+                    // the first word starts with the cue, which needs no timestamp tag
+                    if i == 0 {
+                        format!("<c>{}</c>", word_text.trim_start())
+                    } else {
+                        let start = format_timestamp(word.start, true, '.');
+                        format!("<{}><c>{}</c>", start, word_text)
+                    }
+                })
+                .collect()
+        } else {
+            escape(segment.text.trim())
+        };
+
+        vtt.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(segment.start, true, '.'),
+            format_timestamp(segment.end, true, '.'),
+            text,
+        ));
+    }
+
+    vtt
+}
+
+/// Escapes the characters WebVTT cue text reserves for markup.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use super::{
+    cues, format_wall_clock, json, lrc, speakers::add_speaker_labels, srt, srt_cues, table, vtt,
+    OutputFormat, OutputOptions,
+};
+use crate::token;
+use crate::transcribe::Segment;
+
+/// Appends finalized segments to a transcription file while transcription is still going on,
+/// syncing the file to disk after every write so everything transcribed so far survives a
+/// crash or power loss. Formats that are a single document, JSON and ASS, can't be appended
+/// to and are rejected.
+pub struct SegmentWriter {
+    file: File,
+    format: OutputFormat,
+    options: OutputOptions,
+    n_segments: usize,
+    n_cues: usize,
+    /// Names of the speakers written so far, see `output::speakers`.
+    speakers: HashMap<usize, String>,
+}
+
+impl SegmentWriter {
+    /// Creates the file at `path`, replacing any existing one, and writes the header of the
+    /// format.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: OutputFormat,
+        options: OutputOptions,
+    ) -> token::Result<Self> {
+        if !format.is_appendable() {
+            return Err(format!(
+                "The {} format can't be written incrementally, use jsonl instead",
+                format.as_str()
+            )
+            .into());
+        }
+
+        let mut writer = Self {
+            file: File::create(path)?,
+            format,
+            options,
+            n_segments: 0,
+            n_cues: 0,
+            speakers: HashMap::new(),
+        };
+        let header = match format {
+            OutputFormat::Vtt => "WEBVTT\n\n",
+            OutputFormat::Tsv => "start\tend\ttext\n",
+            OutputFormat::Csv => "start,end,text\r\n",
+            _ => "",
+        };
+        writer.write(header)?;
+        Ok(writer)
+    }
+
+    /// Appends the segments and syncs the file.
+    pub fn append(&mut self, segments: &[Segment]) -> io::Result<()> {
+        add_speaker_labels(&mut self.speakers, segments);
+        let text = match self.format {
+            OutputFormat::Txt => segments
+                .iter()
+                .filter(|segment| !segment.text.trim().is_empty())
+                .map(|segment| {
+                    let label = segment.speaker.and_then(|speaker| self.speakers.get(&speaker));
+                    let text = match label {
+                        Some(label) => format!("{}: {}", label, segment.text.trim()),
+                        None => segment.text.trim().to_string(),
+                    };
+                    match self.options.wall_clock_start {
+                        Some(start) => {
+                            format!("[{}] {}\n", format_wall_clock(start + segment.start), text)
+                        }
+                        None => format!("{}\n", text),
+                    }
+                })
+                .collect(),
+            OutputFormat::Srt => {
+                let cues = srt_cues(segments, &self.options);
+                let srt = srt::to_srt_numbered(&cues, self.n_cues + 1);
+                self.n_cues += cues.iter().filter(|cue| !cue.text.trim().is_empty()).count();
+                srt
+            }
+            OutputFormat::Vtt => {
+                let cues = cues::split_cues(segments, &self.options);
+                without_header(vtt::to_vtt(&cues, self.options.word_highlight), 2)
+            }
+            OutputFormat::Lrc => {
+                lrc::to_lrc(&cues::split_cues(segments, &self.options), self.options.word_highlight)
+            }
+            OutputFormat::Jsonl => {
+                json::to_jsonl_numbered(segments, self.n_segments, &self.speakers)
+            }
+            OutputFormat::Tsv => without_header(table::to_tsv(segments), 1),
+            OutputFormat::Csv => without_header(table::to_csv(segments), 1),
+            OutputFormat::Json | OutputFormat::Ass => unreachable!("Rejected by create"),
+        };
+        self.n_segments += segments.len();
+
+        self.write(&text)
+    }
+
+    /// Sets the wall clock time the audio started at, see `OutputOptions::wall_clock_start`.
+    /// A live stream sets it before every append, which keeps the stamps from drifting away
+    /// from the clock of the audio device.
+    pub fn set_wall_clock_start(&mut self, unix_time: f64) {
+        self.options.wall_clock_start = Some(unix_time);
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all(text.as_bytes())?;
+        self.file.sync_data()
+    }
+}
+
+/// Drops the first `n_lines` lines of a rendered document.
+fn without_header(text: String, n_lines: usize) -> String {
+    text.splitn(n_lines + 1, '\n').nth(n_lines).unwrap_or_default().to_string()
+}
@@ -0,0 +1,88 @@
+use crate::token::Gpt2Tokenizer;
+use std::collections::HashMap;
+
+/// An external language model whose scores are interpolated with the decoder log probabilities
+/// during beam search (shallow fusion).
+pub trait Rescorer {
+    /// Log probability of `token` following the text tokens in `context`.
+    fn score(&self, context: &[usize], token: usize) -> f64;
+}
+
+/// Backoff factor applied each time a shorter context has to be used.
+const BACKOFF: f64 = 0.4;
+
+/// Token level n-gram model scored with stupid backoff (Brants et al., 2007). It is cheap to
+/// build from a few megabytes of domain text and is enough to push the decoder towards
+/// domain-specific vocabulary.
+pub struct NgramModel {
+    order: usize,
+    vocab_size: usize,
+    n_tokens: usize,
+    counts: HashMap<Vec<usize>, usize>,
+}
+
+impl NgramModel {
+    /// Counts every n-gram up to length `order` in the token sequences of `corpus`.
+    pub fn new(corpus: &[Vec<usize>], order: usize, vocab_size: usize) -> Self {
+        let order = order.max(1);
+        let mut counts = HashMap::new();
+        let mut n_tokens = 0;
+
+        for sequence in corpus {
+            n_tokens += sequence.len();
+            for n in 1..=order {
+                for ngram in sequence.windows(n) {
+                    *counts.entry(ngram.to_vec()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        Self {
+            order,
+            vocab_size,
+            n_tokens,
+            counts,
+        }
+    }
+
+    /// Builds a model from text, each non-empty line being an independent sequence.
+    pub fn from_text(bpe: &Gpt2Tokenizer, text: &str, order: usize) -> Self {
+        let corpus: Vec<Vec<usize>> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| bpe.encode_ordinary(&format!(" {}", line)))
+            .collect();
+
+        Self::new(&corpus, order, bpe.vocab_size())
+    }
+
+    fn count(&self, ngram: &[usize]) -> usize {
+        self.counts.get(ngram).cloned().unwrap_or(0)
+    }
+}
+
+impl Rescorer for NgramModel {
+    fn score(&self, context: &[usize], token: usize) -> f64 {
+        let context = &context[context.len().saturating_sub(self.order - 1)..];
+
+        let mut penalty = 0.0;
+        for start in 0..context.len() {
+            let history = &context[start..];
+            let history_count = self.count(history);
+            if history_count > 0 {
+                let mut ngram = history.to_vec();
+                ngram.push(token);
+                let count = self.count(&ngram);
+                if count > 0 {
+                    return penalty + (count as f64 / history_count as f64).ln();
+                }
+            }
+            penalty += BACKOFF.ln();
+        }
+
+        // add-one smoothed unigram so unseen tokens are not ruled out entirely
+        let unigram = (self.count(&[token]) + 1) as f64 / (self.n_tokens + self.vocab_size) as f64;
+        penalty + unigram.ln()
+    }
+}
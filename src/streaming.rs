@@ -0,0 +1,631 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::mpsc,
+    task::{Context, Poll},
+    thread,
+};
+
+use burn::{config::Config, module::Module, tensor::backend::Backend};
+use futures_core::Stream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use webrtc_vad::{SampleRate, Vad, VadMode};
+
+use crate::audio::{MelStream, HOP_LENGTH, SAMPLE_RATE};
+use crate::model::Whisper;
+use crate::token::{self, Gpt2Tokenizer};
+use crate::transcribe::{
+    spectrogram_to_text, waveform_to_text, DecodingOptions, Segment, TranscriptionResult,
+};
+
+/// Number of samples of the frames voice activity is detected on, 10 ms.
+const FRAME_SIZE: usize = 160;
+/// Utterances running too long are split within this many samples of their end, 1.5 s.
+const SPLIT_SEARCH_SAMPLES: usize = SAMPLE_RATE * 3 / 2;
+
+/// Options of how a `TranscriptionStream` or a `Segmenter` cuts the audio into utterances.
+#[derive(Config, Debug)]
+pub struct StreamConfig {
+    /// Seconds of speech after which an utterance is split at its quietest pause even though
+    /// someone is still speaking.
+    #[config(default = 5.0)]
+    pub max_utterance: f64,
+    /// Shorter speech is taken for noise and not transcribed.
+    #[config(default = 0.4)]
+    pub min_utterance: f64,
+    /// Seconds of silence that end an utterance.
+    #[config(default = 0.35)]
+    pub end_silence: f64,
+    /// Seconds of new speech after which the utterance being spoken is transcribed again as a
+    /// partial result. No partial results are transcribed when unset.
+    #[config(default = "Some(1.0)")]
+    pub partial_interval: Option<f64>,
+    /// Number of successive partial results that have to agree on words for them to be stable,
+    /// see `LocalAgreement`.
+    #[config(default = 2)]
+    pub agreement: usize,
+}
+
+/// What a `TranscriptionStream` yields. Times are in seconds from the start of the audio.
+#[derive(Debug, Clone)]
+pub enum SegmentEvent {
+    /// Transcription of the utterance being spoken so far, followed by more partial events and
+    /// a final one with the same id.
+    Partial {
+        id: usize,
+        start: f64,
+        end: f64,
+        text: PartialText,
+    },
+    /// Transcription of a whole utterance.
+    Final {
+        id: usize,
+        start: f64,
+        end: f64,
+        text: String,
+        segments: Vec<Segment>,
+    },
+}
+
+/// Sends audio to a `TranscriptionStream`. Dropping it ends the stream once the audio sent is
+/// transcribed.
+pub struct AudioSender {
+    sender: mpsc::Sender<Vec<f32>>,
+}
+
+impl AudioSender {
+    /// Sends 16 kHz mono samples in the order they were captured.
+    pub fn send(&self, samples: Vec<f32>) -> token::Result<()> {
+        self.sender
+            .send(samples)
+            .map_err(|_| "The transcription stream has ended".into())
+    }
+}
+
+/// Live transcription as an async stream of `SegmentEvent`s, for applications embedding it
+/// in an async runtime. Audio sent through the `AudioSender` is cut into utterances by voice
+/// activity detection and transcribed on a thread of its own, the model doesn't block the
+/// runtime. The stream ends after the sender is dropped or with the first error.
+pub struct TranscriptionStream {
+    events: UnboundedReceiver<token::Result<SegmentEvent>>,
+}
+
+impl TranscriptionStream {
+    pub fn new<B: Backend>(
+        whisper: Whisper<B>,
+        bpe: Gpt2Tokenizer,
+        options: DecodingOptions,
+        config: StreamConfig,
+    ) -> (AudioSender, Self) {
+        let (sender, audio) = mpsc::channel();
+        let (events, receiver) = unbounded_channel();
+        thread::spawn(move || {
+            if let Err(e) = run_stream(&whisper, &bpe, &options, &config, audio, &events) {
+                let _ = events.send(Err(e));
+            }
+        });
+        (AudioSender { sender }, Self { events: receiver })
+    }
+}
+
+impl Stream for TranscriptionStream {
+    type Item = token::Result<SegmentEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+fn run_stream<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    options: &DecodingOptions,
+    config: &StreamConfig,
+    audio: mpsc::Receiver<Vec<f32>>,
+    events: &UnboundedSender<token::Result<SegmentEvent>>,
+) -> token::Result<()> {
+    let mut segmenter = Segmenter::new(config);
+    // the spectrogram is computed as the audio arrives, so the partial results don't compute
+    // it again for the whole utterance every time
+    let mut mel_stream = MelStream::new(
+        SAMPLE_RATE as f64,
+        whisper.encoder_mel_size(),
+        2 * whisper.encoder_ctx_size(),
+        &whisper.devices()[0],
+    );
+    let mut agreement = LocalAgreement::new(config.agreement);
+    let mut context = RollingContext::new(whisper.decoder_ctx_size());
+    // utterances carry the context on as windows of a long recording do
+    let prompted = |context: &RollingContext| {
+        let prompt = if options.condition_on_previous_text {
+            context.tokens().to_vec()
+        } else {
+            Vec::new()
+        };
+        options.clone().with_initial_prompt(prompt)
+    };
+    let duration = |utterance: &Utterance| utterance.samples.len() as f64 / SAMPLE_RATE as f64;
+    loop {
+        // all the audio that arrived is cut up first, so partial results are only transcribed
+        // while the transcription keeps up
+        let (utterances, ended) = match audio.recv() {
+            Ok(mut samples) => {
+                for more in audio.try_iter() {
+                    samples.extend(more);
+                }
+                mel_stream.push(&samples);
+                (segmenter.push(&samples), false)
+            }
+            Err(_) => (segmenter.finish().into_iter().collect(), true),
+        };
+
+        for utterance in utterances {
+            let end = utterance.start + duration(&utterance);
+            let options = prompted(&context);
+            let result = utterance_to_text(whisper, bpe, &mel_stream, &utterance, &options)?;
+            context.push(&result.segments);
+            let segments = result
+                .segments
+                .into_iter()
+                .map(|mut segment| {
+                    segment.start += utterance.start;
+                    segment.end += utterance.start;
+                    for word in &mut segment.words {
+                        word.start += utterance.start;
+                        word.end += utterance.start;
+                    }
+                    segment
+                })
+                .collect();
+            agreement.reset();
+            let event = SegmentEvent::Final {
+                id: utterance.id,
+                start: utterance.start,
+                end,
+                text: result.text.trim().to_string(),
+                segments,
+            };
+            // the stream was dropped, nobody listens anymore
+            if events.send(Ok(event)).is_err() {
+                return Ok(());
+            }
+        }
+        if ended {
+            return Ok(());
+        }
+
+        if let Some(utterance) = segmenter.partial() {
+            let end = utterance.start + duration(&utterance);
+            let options = prompted(&context);
+            let result = utterance_to_text(whisper, bpe, &mel_stream, &utterance, &options)?;
+            let event = SegmentEvent::Partial {
+                id: utterance.id,
+                start: utterance.start,
+                end,
+                text: agreement.insert(&result.text),
+            };
+            if events.send(Ok(event)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Transcribes an utterance from the frames of the spectrogram of the stream it spans, or from
+/// its samples if its start isn't buffered anymore.
+fn utterance_to_text<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    mel_stream: &MelStream<B>,
+    utterance: &Utterance,
+    options: &DecodingOptions,
+) -> token::Result<TranscriptionResult> {
+    let start = (utterance.start * SAMPLE_RATE as f64).round() as usize / HOP_LENGTH;
+    let end = start + utterance.samples.len() / HOP_LENGTH;
+    match mel_stream.frames(start..end) {
+        Some(mels) => spectrogram_to_text(whisper, bpe, mels, options),
+        None => waveform_to_text(whisper, bpe, utterance.samples.clone(), SAMPLE_RATE, options),
+    }
+}
+
+/// Text tokens of the last finished utterances of a stream. Prompting the next utterance with
+/// them keeps casing, punctuation and sentences going across utterances, which otherwise are
+/// transcribed as if every one started a new conversation.
+#[derive(Debug, Clone)]
+pub struct RollingContext {
+    tokens: Vec<usize>,
+    max_tokens: usize,
+}
+
+impl RollingContext {
+    /// Creates the context of a decoder with `n_ctx_max_decoder` tokens of text context. The
+    /// previous text takes up to half of it, as it does when decoding a long recording.
+    pub fn new(n_ctx_max_decoder: usize) -> Self {
+        Self {
+            tokens: Vec::new(),
+            max_tokens: (n_ctx_max_decoder / 2).saturating_sub(1),
+        }
+    }
+
+    pub fn tokens(&self) -> &[usize] {
+        &self.tokens
+    }
+
+    /// Adds the segments of a finished utterance, dropping the oldest tokens that don't fit.
+    pub fn push(&mut self, segments: &[Segment]) {
+        for segment in segments {
+            self.tokens.extend(&segment.tokens);
+        }
+        let n_dropped = self.tokens.len().saturating_sub(self.max_tokens);
+        self.tokens.drain(..n_dropped);
+    }
+
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+    }
+}
+
+/// Speech cut from the audio by `Segmenter`.
+#[derive(Debug, Clone)]
+pub struct Utterance {
+    /// Number of the utterance, which partial results share with the final one.
+    pub id: usize,
+    /// Time in seconds from the start of the audio.
+    pub start: f64,
+    pub samples: Vec<f32>,
+}
+
+/// Cuts 16 kHz audio into utterances by voice activity, detected with WebRTC by `push` or by
+/// the caller with `step`.
+pub struct Segmenter {
+    vad: Vad,
+    max_samples: usize,
+    min_samples: usize,
+    end_silence: usize,
+    partial_interval: Option<usize>,
+    /// Samples of the frame being filled.
+    frame: Vec<i16>,
+    speaking: bool,
+    speech: Vec<f32>,
+    /// Number of samples before the current frame and before the speech.
+    n_captured: usize,
+    speech_start: usize,
+    /// Samples of silence at the end of the speech.
+    n_silent: usize,
+    /// Length of the speech when a partial result was last taken.
+    n_partial: usize,
+    next_id: usize,
+}
+
+impl Segmenter {
+    pub fn new(config: &StreamConfig) -> Self {
+        let mut vad = Vad::new_with_rate(SampleRate::Rate16kHz);
+        vad.set_mode(VadMode::Aggressive);
+        let seconds_to_samples = |seconds: f64| (seconds.max(0.0) * SAMPLE_RATE as f64) as usize;
+        Self {
+            vad,
+            max_samples: seconds_to_samples(config.max_utterance),
+            min_samples: seconds_to_samples(config.min_utterance),
+            end_silence: seconds_to_samples(config.end_silence),
+            partial_interval: config
+                .partial_interval
+                .map(|seconds| seconds_to_samples(seconds).max(1)),
+            frame: Vec::with_capacity(FRAME_SIZE),
+            speaking: false,
+            speech: Vec::new(),
+            n_captured: 0,
+            speech_start: 0,
+            n_silent: 0,
+            n_partial: 0,
+            next_id: 0,
+        }
+    }
+
+    /// Adds samples and returns the utterances they end.
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Utterance> {
+        let mut utterances = Vec::new();
+        for &sample in samples {
+            self.frame.push((sample.clamp(-1.0, 1.0) * 32767.0) as i16);
+            if self.frame.len() == FRAME_SIZE {
+                let frame = std::mem::take(&mut self.frame);
+                utterances.extend(self.push_frame(&frame));
+            }
+        }
+        utterances
+    }
+
+    fn push_frame(&mut self, frame: &[i16]) -> Option<Utterance> {
+        let active = self.vad.is_voice_segment(frame).unwrap_or(false);
+        self.step(frame, active)
+    }
+
+    /// Adds a frame the voice activity detection found `active` or not.
+    pub fn step(&mut self, frame: &[i16], active: bool) -> Option<Utterance> {
+        let frame_start = self.n_captured;
+        self.n_captured += frame.len();
+        let samples = frame.iter().map(|&x| x as f32 / 32767.0);
+
+        if !self.speaking {
+            if active {
+                self.speaking = true;
+                self.speech.extend(samples);
+                self.speech_start = frame_start;
+                self.n_silent = 0;
+                self.n_partial = 0;
+            }
+            return None;
+        }
+
+        self.speech.extend(samples);
+        self.n_silent = if active { 0 } else { self.n_silent + frame.len() };
+        if self.n_silent >= self.end_silence {
+            self.speech.truncate(self.speech.len() - self.n_silent);
+            self.finish()
+        } else if self.speech.len() > self.max_samples {
+            // the speech after the split starts the next utterance
+            let split = quietest_split(&self.speech, SPLIT_SEARCH_SAMPLES);
+            let rest = self.speech.split_off(split);
+            let utterance = self.utterance(std::mem::replace(&mut self.speech, rest));
+            self.speech_start += split;
+            // the split is usually in the trailing silence, which then partly went out
+            self.n_silent = self.n_silent.min(self.speech.len());
+            self.n_partial = 0;
+            Some(utterance)
+        } else {
+            None
+        }
+    }
+
+    /// Ends the utterance being spoken, if it is long enough to be transcribed.
+    pub fn finish(&mut self) -> Option<Utterance> {
+        let speaking = std::mem::replace(&mut self.speaking, false);
+        let speech = std::mem::take(&mut self.speech);
+        (speaking && speech.len() >= self.min_samples).then(|| self.utterance(speech))
+    }
+
+    /// The utterance being spoken so far, once `partial_interval` of new speech arrived.
+    pub fn partial(&mut self) -> Option<Utterance> {
+        let interval = self.partial_interval?;
+        let due = (self.n_partial + interval).max(self.min_samples);
+        if !self.speaking || self.speech.len() < due {
+            return None;
+        }
+        self.n_partial = self.speech.len();
+        Some(Utterance {
+            id: self.next_id,
+            start: self.speech_start as f64 / SAMPLE_RATE as f64,
+            samples: self.speech.clone(),
+        })
+    }
+
+    /// Passes over `n_samples` of audio that isn't there, e.g. while the capture is paused,
+    /// which ends the utterance being spoken.
+    pub fn skip(&mut self, n_samples: usize) -> Option<Utterance> {
+        let utterance = self.finish();
+        self.n_captured += n_samples;
+        utterance
+    }
+
+    /// Whether an utterance is being spoken.
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    fn utterance(&mut self, samples: Vec<f32>) -> Utterance {
+        self.next_id += 1;
+        Utterance {
+            id: self.next_id - 1,
+            start: self.speech_start as f64 / SAMPLE_RATE as f64,
+            samples,
+        }
+    }
+}
+
+/// Returns where to split speech that has run too long: the start of the quietest 10 ms
+/// frame among the last `search` samples, which is usually a pause between words.
+pub fn quietest_split<T: Copy + Into<f64>>(samples: &[T], search: usize) -> usize {
+    let first = samples.len().saturating_sub(search) / FRAME_SIZE;
+    let n_frames = samples.len() / FRAME_SIZE;
+    let energy = |i: usize| -> f64 {
+        samples[i * FRAME_SIZE..(i + 1) * FRAME_SIZE]
+            .iter()
+            .map(|&x| x.into().powi(2))
+            .sum()
+    };
+    // splitting at the very start would leave nothing to send
+    (first.max(1)..n_frames)
+        .min_by(|&a, &b| energy(a).total_cmp(&energy(b)))
+        .map_or(samples.len(), |i| i * FRAME_SIZE)
+}
+
+/// Text of an utterance still being spoken, split into the words that won't change anymore
+/// and those that still may.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartialText {
+    /// Words the last hypotheses agree on.
+    pub stable: String,
+    /// The rest of the latest hypothesis.
+    pub unstable: String,
+}
+
+/// The LocalAgreement-n policy of whisper_streaming. An utterance is transcribed again as it
+/// grows, and the words at its end change from one hypothesis to the next while the speech
+/// they belong to is cut off. The prefix the last `n` hypotheses agree on is taken as stable
+/// and never retracted, the rest is shown as it is and may still change.
+pub struct LocalAgreement {
+    n: usize,
+    /// The last hypotheses as normalized words, the newest at the back.
+    hypotheses: VecDeque<Vec<String>>,
+    /// Stable words as they were transcribed.
+    stable: Vec<String>,
+}
+
+impl LocalAgreement {
+    /// Creates the policy with the number of hypotheses that have to agree, at least 1, where
+    /// 1 takes every hypothesis as stable.
+    pub fn new(n: usize) -> Self {
+        Self {
+            n: n.max(1),
+            hypotheses: VecDeque::new(),
+            stable: Vec::new(),
+        }
+    }
+
+    /// Adds the transcription of the utterance so far and returns its stable and unstable
+    /// text.
+    pub fn insert(&mut self, text: &str) -> PartialText {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        self.hypotheses.push_back(words.iter().map(|word| normalize(word)).collect());
+        if self.hypotheses.len() > self.n {
+            self.hypotheses.pop_front();
+        }
+
+        if self.hypotheses.len() == self.n {
+            let newest = self.hypotheses.back().expect("A hypothesis was just added");
+            let agreed = self
+                .hypotheses
+                .iter()
+                .map(|hypothesis| common_prefix(hypothesis, newest))
+                .min()
+                .unwrap_or(0);
+            // stable words stay, even if a later hypothesis transcribes them differently
+            if agreed > self.stable.len() {
+                let n_stable = self.stable.len();
+                self.stable.extend(words[n_stable..agreed].iter().map(|word| word.to_string()));
+            }
+        }
+
+        let unstable = words.get(self.stable.len()..).unwrap_or_default();
+        PartialText {
+            stable: self.stable.join(" "),
+            unstable: unstable.join(" "),
+        }
+    }
+
+    /// Forgets the hypotheses, e.g. once the utterance has ended.
+    pub fn reset(&mut self) {
+        self.hypotheses.clear();
+        self.stable.clear();
+    }
+}
+
+/// Lowercase word without punctuation, which hypotheses often disagree on at their end.
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn common_prefix(a: &[String], b: &[String]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(amplitude: i16) -> Vec<i16> {
+        vec![amplitude; FRAME_SIZE]
+    }
+
+    fn segmenter(max_utterance: f64) -> Segmenter {
+        let config = StreamConfig::new()
+            .with_max_utterance(max_utterance)
+            .with_min_utterance(0.05)
+            .with_partial_interval(None);
+        Segmenter::new(&config)
+    }
+
+    #[test]
+    fn finish_drops_trailing_silence() {
+        let mut segmenter = segmenter(5.0);
+        for _ in 0..8 {
+            assert!(segmenter.step(&frame(10000), true).is_none());
+        }
+        // 0.35 s of silence end the utterance
+        let utterances: Vec<_> = (0..35)
+            .filter_map(|_| segmenter.step(&frame(0), false))
+            .collect();
+        assert_eq!(utterances.len(), 1);
+        assert_eq!(utterances[0].id, 0);
+        assert_eq!(utterances[0].samples.len(), 8 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn split_in_trailing_silence() {
+        let mut segmenter = segmenter(0.1);
+        for _ in 0..8 {
+            assert!(segmenter.step(&frame(10000), true).is_none());
+        }
+        // fading silence, so the quietest frame is the last and the split leaves a single
+        // frame of the silence in the speech
+        let utterances: Vec<_> = [300, 200, 100]
+            .into_iter()
+            .filter_map(|amplitude| segmenter.step(&frame(amplitude), false))
+            .collect();
+        assert_eq!(utterances.len(), 1);
+        assert_eq!(utterances[0].samples.len(), 10 * FRAME_SIZE);
+
+        // the rest is too short to be an utterance once the silence ends it
+        for _ in 0..40 {
+            assert!(segmenter.step(&frame(0), false).is_none());
+        }
+        assert!(!segmenter.speaking);
+    }
+
+    #[test]
+    fn skip_ends_the_utterance() {
+        let mut segmenter = segmenter(5.0);
+        for _ in 0..8 {
+            segmenter.step(&frame(10000), true);
+        }
+        let utterance = segmenter.skip(SAMPLE_RATE).expect("Speech was going on");
+        assert_eq!(utterance.samples.len(), 8 * FRAME_SIZE);
+        assert!(!segmenter.is_speaking());
+
+        // the audio skipped still counts towards the start of the next utterance
+        for _ in 0..8 {
+            segmenter.step(&frame(10000), true);
+        }
+        let utterance = segmenter.finish().expect("Speech was going on");
+        assert_eq!(utterance.id, 1);
+        let start = 8 * FRAME_SIZE + SAMPLE_RATE;
+        assert_eq!(utterance.start, start as f64 / SAMPLE_RATE as f64);
+    }
+
+    #[test]
+    fn quietest_split_finds_the_pause() {
+        let mut samples = vec![0.5f32; 6 * FRAME_SIZE];
+        samples[3 * FRAME_SIZE..4 * FRAME_SIZE].fill(0.01);
+        assert_eq!(quietest_split(&samples, samples.len()), 3 * FRAME_SIZE);
+        // the pause is outside the frames searched
+        assert_eq!(quietest_split(&samples, 2 * FRAME_SIZE), 4 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn quietest_split_of_short_speech() {
+        let samples = vec![0.5f32; FRAME_SIZE / 2];
+        assert_eq!(quietest_split(&samples, FRAME_SIZE), samples.len());
+    }
+
+    #[test]
+    fn local_agreement_keeps_the_agreed_prefix() {
+        let mut agreement = LocalAgreement::new(2);
+        let text = agreement.insert("hello world");
+        assert_eq!(text.stable, "");
+        assert_eq!(text.unstable, "hello world");
+
+        let text = agreement.insert("Hello world, how");
+        assert_eq!(text.stable, "Hello world,");
+        assert_eq!(text.unstable, "how");
+
+        // stable words are never retracted
+        let text = agreement.insert("yellow world, how are");
+        assert_eq!(text.stable, "Hello world,");
+        assert_eq!(text.unstable, "how are");
+
+        agreement.reset();
+        assert_eq!(agreement.insert("bye").stable, "");
+    }
+}
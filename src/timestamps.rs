@@ -0,0 +1,424 @@
+use crate::model::*;
+use crate::token::{self, Gpt2Tokenizer, Language};
+use burn::tensor::{activation::log_softmax, backend::Backend, Data, Tensor};
+use serde::{Deserialize, Serialize};
+
+/// Number of encoder output frames per second of audio.
+pub const TOKENS_PER_SECOND: f64 = 50.0;
+
+/// Width of the median filter smoothing the cross-attention weights along the time axis.
+const MEDIAN_FILTER_WIDTH: usize = 7;
+
+const PREPEND_PUNCTUATIONS: &str = "\"'“¿([{-";
+const APPEND_PUNCTUATIONS: &str = "\"'.。,，!！?？:：”)]}、";
+
+/// How text tokens are grouped into words for word-level timestamps.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WordSplitting {
+    /// A word starts at every leading space, for languages that separate words by spaces.
+    Spaces,
+    /// Every complete unicode character sequence is a word, for languages written without
+    /// spaces such as Chinese or Japanese.
+    Unicode,
+}
+
+impl WordSplitting {
+    /// Splitting used by default for `lang`.
+    pub fn for_language(lang: Language) -> Self {
+        match lang {
+            Language::Chinese
+            | Language::Japanese
+            | Language::Thai
+            | Language::Lao
+            | Language::Burmese
+            | Language::Cantonese => WordSplitting::Unicode,
+            _ => WordSplitting::Spaces,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WordTiming {
+    pub word: String,
+    pub tokens: Vec<usize>,
+    pub start: f64,
+    pub end: f64,
+    /// Mean probability the tokens of the word were decoded with.
+    pub probability: f64,
+}
+
+/// Aligns `text_tokens` to the audio by teacher forcing them through the decoder and running
+/// dynamic time warping over the cross-attention weights, as done by openai/whisper.
+///
+/// `prompt` is the start of transcript sequence the tokens were decoded with (without
+/// `<|notimestamps|>`), `no_timestamps_token` is placed between the prompt and the text and
+/// `num_frames` is the number of mel frames of actual audio in the window. Returned times are
+/// relative to the window start plus `time_offset`. `text_logprobs` holds the log probability
+/// each text token was decoded with and is aggregated into the word probabilities. Without it
+/// the probabilities of the teacher forced tokens are used. `splitting` decides how the tokens
+/// are grouped into words.
+pub fn find_alignment<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    encoder_output: Tensor<B, 3>,
+    prompt: &[usize],
+    no_timestamps_token: usize,
+    end_token: usize,
+    text_tokens: &[usize],
+    text_logprobs: Option<&[f64]>,
+    num_frames: usize,
+    time_offset: f64,
+    splitting: WordSplitting,
+) -> token::Result<Vec<WordTiming>> {
+    if text_tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let device = encoder_output.device();
+
+    let tokens: Vec<usize> = prompt
+        .iter()
+        .cloned()
+        .chain(std::iter::once(no_timestamps_token))
+        .chain(text_tokens.iter().cloned())
+        .chain(std::iter::once(end_token))
+        .collect();
+    let n_tokens = tokens.len();
+
+    let token_tensor = Tensor::from_ints(
+        Data::from_usize(Data::new(tokens, [1, n_tokens].into())),
+        &device,
+    );
+    let (logits, cross_attention) =
+        whisper.forward_decoder_with_cross_attention(token_tensor.clone(), encoder_output);
+
+    // position `i` predicts token `i + 1` so the text tokens follow the prompt positions
+    let text_logprobs = match text_logprobs {
+        Some(text_logprobs) => text_logprobs.to_vec(),
+        None => {
+            let [_, _, n_vocab] = logits.dims();
+            let first_text = prompt.len() + 1;
+            let last_text = n_tokens - 1;
+            let text_token_tensor = token_tensor
+                .slice([0..1, first_text..last_text])
+                .unsqueeze_dim::<3>(2);
+
+            log_softmax(logits.slice([0..1, first_text - 1..last_text - 1, 0..n_vocab]), 2)
+                .gather(2, text_token_tensor)
+                .into_data()
+                .convert::<f64>()
+                .value
+        }
+    };
+
+    // without model specific alignment heads use every head of the upper half of the decoder
+    let n_layers = cross_attention.len();
+    let alignment_layers: Vec<Tensor<B, 4>> = cross_attention
+        .into_iter()
+        .skip(n_layers / 2)
+        .collect();
+    let weights = Tensor::cat(alignment_layers, 1);
+
+    let [_, n_heads, _, n_audio_ctx] = weights.dims();
+    let n_frames = (num_frames / 2).min(n_audio_ctx).max(1);
+    let weights: Vec<f32> = weights
+        .slice([0..1, 0..n_heads, 0..n_tokens, 0..n_frames])
+        .into_data()
+        .convert::<f32>()
+        .value;
+
+    let matrix = attention_matrix(&weights, n_heads, n_tokens, n_frames);
+
+    // the rows between the prompt and the end of text token belong to the text
+    let first_row = prompt.len();
+    let last_row = n_tokens - 1;
+    let matrix: Vec<Vec<f32>> = matrix[first_row..last_row]
+        .iter()
+        .map(|row| row.iter().map(|w| -w).collect())
+        .collect();
+
+    let (text_indices, time_indices) = dtw(&matrix);
+
+    let mut words = match splitting {
+        WordSplitting::Spaces => split_tokens_on_spaces(bpe, text_tokens)?,
+        WordSplitting::Unicode => split_tokens_on_unicode(bpe, text_tokens)?,
+    };
+    merge_punctuations(&mut words);
+
+    // timestamps at which the alignment moves on to the next token
+    let jump_times: Vec<f64> = text_indices
+        .iter()
+        .enumerate()
+        .filter(|&(i, &index)| i == 0 || index != text_indices[i - 1])
+        .map(|(i, _)| time_indices[i] as f64 / TOKENS_PER_SECOND)
+        .collect();
+
+    let time_at = |index: usize| {
+        jump_times
+            .get(index)
+            .or(jump_times.last())
+            .cloned()
+            .unwrap_or(0.0)
+    };
+
+    let mut timings = Vec::with_capacity(words.len());
+    let mut boundary = 0;
+    for (word, word_tokens) in words {
+        let start_boundary = boundary;
+        boundary += word_tokens.len();
+
+        let word_logprobs = &text_logprobs[start_boundary.min(text_logprobs.len())
+            ..boundary.min(text_logprobs.len())];
+        let probability = if word_logprobs.is_empty() {
+            0.0
+        } else {
+            word_logprobs.iter().map(|logprob| logprob.exp()).sum::<f64>()
+                / word_logprobs.len() as f64
+        };
+
+        timings.push(WordTiming {
+            word,
+            tokens: word_tokens,
+            start: time_offset + time_at(start_boundary),
+            end: time_offset + time_at(boundary),
+            probability,
+        });
+    }
+
+    Ok(timings)
+}
+
+/// Normalizes the attention weights of each head over the token axis, smooths them along time
+/// with a median filter and averages the heads into a (n_tokens, n_frames) matrix.
+fn attention_matrix(
+    weights: &[f32],
+    n_heads: usize,
+    n_tokens: usize,
+    n_frames: usize,
+) -> Vec<Vec<f32>> {
+    let mut matrix = vec![vec![0.0f32; n_frames]; n_tokens];
+
+    for head in weights.chunks(n_tokens * n_frames).take(n_heads) {
+        let mut head: Vec<Vec<f32>> = head.chunks(n_frames).map(|row| row.to_vec()).collect();
+
+        for frame in 0..n_frames {
+            let mean = head.iter().map(|row| row[frame]).sum::<f32>() / n_tokens as f32;
+            let variance = head
+                .iter()
+                .map(|row| (row[frame] - mean).powi(2))
+                .sum::<f32>()
+                / n_tokens as f32;
+            let std = variance.sqrt().max(1e-10);
+
+            for row in head.iter_mut() {
+                row[frame] = (row[frame] - mean) / std;
+            }
+        }
+
+        for (token, row) in head.iter().enumerate() {
+            for (frame, w) in median_filter(row, MEDIAN_FILTER_WIDTH).into_iter().enumerate() {
+                matrix[token][frame] += w / n_heads as f32;
+            }
+        }
+    }
+
+    matrix
+}
+
+/// Median filter with reflect padding.
+fn median_filter(values: &[f32], width: usize) -> Vec<f32> {
+    let pad = width / 2;
+    let len = values.len();
+    if len <= pad {
+        return values.to_vec();
+    }
+
+    let reflect = |i: isize| -> f32 {
+        let i = if i < 0 {
+            -i
+        } else if i >= len as isize {
+            2 * (len as isize - 1) - i
+        } else {
+            i
+        };
+        values[i.clamp(0, len as isize - 1) as usize]
+    };
+
+    (0..len as isize)
+        .map(|i| {
+            let mut window: Vec<f32> = (i - pad as isize..=i + pad as isize)
+                .map(reflect)
+                .collect();
+            window.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            window[pad]
+        })
+        .collect()
+}
+
+/// Dynamic time warping over a (n_rows, n_cols) cost matrix. Returns the row and column index
+/// of every step of the cheapest monotonic path from the top left to the bottom right corner.
+pub fn dtw(cost_matrix: &[Vec<f32>]) -> (Vec<usize>, Vec<usize>) {
+    let n = cost_matrix.len();
+    let m = cost_matrix.first().map(|row| row.len()).unwrap_or(0);
+    if n == 0 || m == 0 {
+        return (Vec::new(), Vec::new());
+    }
+
+    let mut cost = vec![vec![f32::INFINITY; m + 1]; n + 1];
+    let mut trace = vec![vec![-1i8; m + 1]; n + 1];
+    cost[0][0] = 0.0;
+
+    for j in 1..=m {
+        for i in 1..=n {
+            let c0 = cost[i - 1][j - 1];
+            let c1 = cost[i - 1][j];
+            let c2 = cost[i][j - 1];
+
+            let (c, t) = if c0 < c1 && c0 < c2 {
+                (c0, 0)
+            } else if c1 < c0 && c1 < c2 {
+                (c1, 1)
+            } else {
+                (c2, 2)
+            };
+
+            cost[i][j] = cost_matrix[i - 1][j - 1] + c;
+            trace[i][j] = t;
+        }
+    }
+
+    for j in 0..=m {
+        trace[0][j] = 2;
+    }
+    for i in 0..=n {
+        trace[i][0] = 1;
+    }
+
+    let mut i = n;
+    let mut j = m;
+    let mut path = Vec::with_capacity(n + m);
+    while i > 0 || j > 0 {
+        path.push((i.saturating_sub(1), j.saturating_sub(1)));
+        match trace[i][j] {
+            0 => {
+                i -= 1;
+                j -= 1;
+            }
+            1 => i -= 1,
+            _ => j -= 1,
+        }
+    }
+    path.reverse();
+
+    path.into_iter().unzip()
+}
+
+/// Groups tokens into chunks that decode to complete unicode strings.
+fn split_tokens_on_unicode(
+    bpe: &Gpt2Tokenizer,
+    tokens: &[usize],
+) -> token::Result<Vec<(String, Vec<usize>)>> {
+    let mut words = Vec::new();
+    let mut current_tokens = Vec::new();
+
+    for &token in tokens {
+        current_tokens.push(token);
+        let decoded = bpe.decode(&current_tokens, false)?;
+        if !decoded.contains('\u{fffd}') {
+            words.push((decoded, std::mem::take(&mut current_tokens)));
+        }
+    }
+
+    if !current_tokens.is_empty() {
+        words.push((bpe.decode(&current_tokens, false)?, current_tokens));
+    }
+
+    Ok(words)
+}
+
+/// Groups tokens into words, starting a new word at every leading space or punctuation mark.
+fn split_tokens_on_spaces(
+    bpe: &Gpt2Tokenizer,
+    tokens: &[usize],
+) -> token::Result<Vec<(String, Vec<usize>)>> {
+    let mut words: Vec<(String, Vec<usize>)> = Vec::new();
+
+    for (subword, subword_tokens) in split_tokens_on_unicode(bpe, tokens)? {
+        let with_space = subword.starts_with(' ');
+        let trimmed = subword.trim();
+        let punctuation = !trimmed.is_empty()
+            && trimmed
+                .chars()
+                .all(|c| PREPEND_PUNCTUATIONS.contains(c) || APPEND_PUNCTUATIONS.contains(c));
+
+        match words.last_mut() {
+            Some((word, word_tokens)) if !with_space && !punctuation => {
+                word.push_str(&subword);
+                word_tokens.extend(subword_tokens);
+            }
+            _ => words.push((subword, subword_tokens)),
+        }
+    }
+
+    Ok(words)
+}
+
+/// Attaches opening punctuation to the following word and closing punctuation to the preceding
+/// word so that punctuation never becomes a word of its own.
+fn merge_punctuations(words: &mut Vec<(String, Vec<usize>)>) {
+    let is_only = |word: &str, set: &str| {
+        let trimmed = word.trim();
+        !trimmed.is_empty() && trimmed.chars().all(|c| set.contains(c))
+    };
+
+    // prepend punctuations
+    let mut i = words.len();
+    while i > 1 {
+        i -= 1;
+        if is_only(&words[i - 1].0, PREPEND_PUNCTUATIONS) && !words[i - 1].0.ends_with(' ') {
+            let (word, tokens) = words.remove(i - 1);
+            let next = &mut words[i - 1];
+            next.0 = format!("{}{}", word, next.0.trim_start());
+            next.1 = tokens.into_iter().chain(next.1.drain(..)).collect();
+        }
+    }
+
+    // append punctuations
+    let mut i = 1;
+    while i < words.len() {
+        if is_only(&words[i].0, APPEND_PUNCTUATIONS) {
+            let (word, tokens) = words.remove(i);
+            let previous = &mut words[i - 1];
+            previous.0.push_str(word.trim());
+            previous.1.extend(tokens);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dtw_follows_the_cheap_diagonal() {
+        let cost = vec![
+            vec![0.0, 1.0, 1.0],
+            vec![1.0, 0.0, 1.0],
+            vec![1.0, 1.0, 0.0],
+        ];
+        assert_eq!(dtw(&cost), (vec![0, 1, 2], vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn dtw_spans_several_columns_per_row() {
+        let cost = vec![vec![0.0, 0.0, 1.0, 1.0], vec![1.0, 1.0, 0.0, 0.0]];
+        assert_eq!(dtw(&cost), (vec![0, 0, 1, 1], vec![0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn dtw_of_empty_matrix() {
+        assert_eq!(dtw(&[]), (Vec::new(), Vec::new()));
+    }
+}
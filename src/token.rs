@@ -1,4 +1,5 @@
 use serde::ser::StdError;
+use serde::{Deserialize, Serialize};
 use std::result;
 
 use tokenizers::{AddedToken, Tokenizer};
@@ -11,12 +12,21 @@ pub struct Gpt2Tokenizer {
 
 impl Gpt2Tokenizer {
     pub fn new(model_name: &str) -> Result<Self> {
-        let mut tokenizer =
-            tokenizers::Tokenizer::from_file(&format!("models/{}/tokenizer.json", &model_name))?;
+        Self::from_file(&format!("models/{}/tokenizer.json", &model_name))
+    }
+
+    /// Loads the `tokenizer.json` at `path`, e.g. of a model downloaded by `hub`.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let tokenizer = tokenizers::Tokenizer::from_file(path)?;
 
         Ok(Self { tokenizer })
     }
 
+    #[cfg(test)]
+    pub(crate) fn from_tokenizer(tokenizer: Tokenizer) -> Self {
+        Self { tokenizer }
+    }
+
     pub fn encode(&self, text: &str) -> Vec<usize> {
         let tokens = self.tokenizer.encode(text, true).unwrap();
         tokens.get_ids().iter().map(|t| *t as usize).collect()
@@ -46,21 +56,93 @@ impl Gpt2Tokenizer {
     pub fn vocab_size(&self) -> usize {
         self.tokenizer.get_vocab_size(true)
     }
+
+    /// Raw utf-8 bytes of a single regular token. A token can hold part of a multi byte
+    /// character so its bytes don't have to be valid utf-8 on their own.
+    pub fn token_bytes(&self, token: usize) -> Vec<u8> {
+        self.tokenizer
+            .id_to_token(token as u32)
+            .map(|token| token.chars().filter_map(byte_level_char_to_byte).collect())
+            .unwrap_or_default()
+    }
+
+    /// Encodes `text` without adding any special tokens around it.
+    pub fn encode_ordinary(&self, text: &str) -> Vec<usize> {
+        let tokens = self.tokenizer.encode(text, false).unwrap();
+        tokens.get_ids().iter().map(|t| *t as usize).collect()
+    }
+
+    /// Tokens of symbols that are not spoken, like brackets, music notes or speaker tags, so
+    /// they can be suppressed while decoding. Symbols are only included if they are a single
+    /// token, either on their own or with a leading space, except for music notes which also
+    /// suppress the first token of longer encodings.
+    pub fn non_speech_tokens(&self) -> Vec<usize> {
+        const SYMBOLS: &str = "\"#()*+/:;<=>@[\\]^_`{|}~「」『』";
+        const MULTI_SYMBOLS: [&str; 20] = [
+            "<<", ">>", "<<<", ">>>", "--", "---", "-(", "-[", "('", "(\"", "((", "))", "(((",
+            ")))", "[[", "]]", "{{", "}}", "♪♪", "♪♪♪",
+        ];
+        const MISCELLANEOUS: &str = "♩♪♫♬♭♮♯";
+
+        let mut result: Vec<usize> = [" -", " '"]
+            .iter()
+            .filter_map(|text| self.encode_ordinary(text).first().cloned())
+            .collect();
+
+        let symbols = SYMBOLS
+            .chars()
+            .map(|c| (c.to_string(), false))
+            .chain(MULTI_SYMBOLS.iter().map(|s| (s.to_string(), false)))
+            .chain(MISCELLANEOUS.chars().map(|c| (c.to_string(), true)));
+
+        for (symbol, miscellaneous) in symbols {
+            for text in [symbol.clone(), format!(" {}", symbol)] {
+                let tokens = self.encode_ordinary(&text);
+                if tokens.len() == 1 || (miscellaneous && !tokens.is_empty()) {
+                    result.push(tokens[0]);
+                }
+            }
+        }
+
+        result.sort();
+        result.dedup();
+        result
+    }
+}
+
+/// Inverse of the GPT-2 byte level mapping, which represents every byte by a printable
+/// character: printable bytes map to themselves and the remaining ones to 256 and above in order.
+fn byte_level_char_to_byte(c: char) -> Option<u8> {
+    let is_printable = |b: u32| {
+        ('!' as u32..='~' as u32).contains(&b)
+            || ('¡' as u32..='¬' as u32).contains(&b)
+            || ('®' as u32..='ÿ' as u32).contains(&b)
+    };
+
+    let c = c as u32;
+    if c < 256 {
+        return is_printable(c).then_some(c as u8);
+    }
+
+    (0..256u32)
+        .filter(|&b| !is_printable(b))
+        .nth((c - 256) as usize)
+        .map(|b| b as u8)
 }
 
-pub const LANGUAGES: [&str; 98] = [
+pub const LANGUAGES: [&str; 99] = [
     "en", "zh", "de", "es", "ru", "ko", "fr", "ja", "pt", "tr", "pl", "ca", "nl", "ar", "sv", "it",
     "id", "hi", "fi", "vi", "he", "uk", "el", "ms", "cs", "ro", "da", "hu", "ta", "no", "th", "ur",
     "hr", "bg", "lt", "la", "mi", "ml", "cy", "sk", "te", "fa", "lv", "bn", "sr", "az", "sl", "kn",
     "et", "mk", "br", "eu", "is", "hy", "ne", "mn", "bs", "kk", "sq", "sw", "gl", "mr", "pa", "si",
     "km", "sn", "yo", "so", "af", "oc", "ka", "be", "tg", "sd", "gu", "am", "yi", "lo", "uz", "fo",
     "ht", "ps", "tk", "nn", "mt", "sa", "lb", "my", "bo", "tl", "mg", "as", "tt", "ln", "ha", "ba",
-    "jw", "su",
+    "jw", "su", "yue",
 ];
 
 use strum_macros::EnumIter;
 
-#[derive(Debug, Copy, Clone, EnumIter)]
+#[derive(Debug, Copy, Clone, EnumIter, Serialize, Deserialize)]
 pub enum Language {
     English,
     Chinese,
@@ -159,6 +241,7 @@ pub enum Language {
     Bashkir,
     Javanese,
     Sundanese,
+    Cantonese,
 }
 
 impl Language {
@@ -261,6 +344,29 @@ impl Language {
             Language::Bashkir => "ba",
             Language::Javanese => "jw",
             Language::Sundanese => "su",
+            Language::Cantonese => "yue",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum Task {
+    Transcribe,
+    Translate,
+}
+
+impl Task {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Task::Transcribe => "transcribe",
+            Task::Translate => "translate",
+        }
+    }
+
+    pub fn special_token(&self) -> SpecialToken {
+        match self {
+            Task::Transcribe => SpecialToken::Transcribe,
+            Task::Translate => SpecialToken::Translate,
         }
     }
 }
@@ -1,276 +1,2314 @@
-use crate::audio::{max_waveform_samples, prep_audio};
-use crate::beam;
+use crate::audio::{
+    max_waveform_samples, prep_audio,
+    silero::SileroVad,
+    speaker::SpeakerEncoder,
+    vad::{self, VadConfig},
+    HOP_LENGTH, SAMPLE_RATE,
+};
+use crate::diarization::{diarize, DiarizationConfig};
 use crate::helper::*;
+use crate::logit_filter::{
+    ApplyTimestampRules, BoostPhrases, ConstrainToRegex, LimitSegmentLength, LogitFilter,
+    SuppressBlank, SuppressInitialSpecialTokens, SuppressTokens,
+};
 use crate::model::*;
+use crate::rescorer::Rescorer;
+use crate::timestamps::{self, WordSplitting, WordTiming};
 use crate::token::{self, *};
 use burn::{
     config::Config,
     module::Module,
     tensor::{
         self,
-        activation::log_softmax,
         backend::{self, Backend},
         Data, ElementConversion, Float, Int, Tensor,
     },
 };
+use flate2::{write::GzEncoder, Compression};
 use num_traits::ToPrimitive;
-use std::{f32, iter, ops::Div};
-use std::time::Instant;
+use rand::{
+    distributions::WeightedIndex, prelude::Distribution, rngs::StdRng, Rng, SeedableRng,
+};
+use std::cmp::Ordering;
+use std::io::Write;
+use std::{f32, iter, ops::Div, ops::Range};
+use std::time::{Duration, Instant};
+use strum::IntoEnumIterator;
+
+/// Windows decoded above this temperature are not used as prompt for the following windows.
+const PROMPT_RESET_TEMPERATURE: f64 = 0.5;
+
+/// Words ending within this many seconds of the end of a window are aligned again as part of
+/// the next window since their audio may continue past the window.
+const ALIGNMENT_MARGIN: f64 = 2.0;
+
+/// Time in seconds between two consecutive timestamp tokens.
+const TIME_PRECISION: f64 = 0.02;
+
+/// Duration in seconds of a single mel frame.
+const HOP_DURATION: f64 = 0.01;
+
+/// Seconds of audio consecutive windows of a long waveform share, so a word cut off at the end
+/// of one window is whole in the next.
+const WINDOW_OVERLAP: f64 = 3.0;
+
+/// Number of tokens the end of a window and the start of the next have to share for their
+/// segments to be joined there, see `merge_window_segments`.
+const MIN_ALIGNED_TOKENS: usize = 3;
+
+/// Range of n-gram sizes checked for repetition loops while decoding.
+const REPETITION_NGRAM_SIZES: std::ops::RangeInclusive<usize> = 3..=5;
+
+/// Options controlling how tokens are selected while decoding each audio window.
+#[derive(Config, Debug)]
+pub struct DecodingOptions {
+    /// Number of hypotheses kept alive by beam search. A beam size of 1 is greedy decoding.
+    #[config(default = 5)]
+    pub beam_size: usize,
+    /// Beam search finishes once `beam_size * patience` hypotheses have emitted end of text.
+    #[config(default = 1.0)]
+    pub patience: f64,
+    /// Exponent alpha of the Google NMT length penalty `((5 + length) / 6) ^ alpha` used to
+    /// rank finished hypotheses. Higher values favor longer transcripts. When unset hypotheses
+    /// are ranked by their mean token log probability.
+    pub length_penalty: Option<f64>,
+    /// Added to the score of a hypothesis when it emits end of text during beam search.
+    /// Negative values keep beam search from ending hypotheses early on long utterances.
+    #[config(default = 0.0)]
+    pub eot_bonus: f64,
+    /// Maximum number of tokens sampled for a single window.
+    #[config(default = 224)]
+    pub sample_len: usize,
+    /// Temperatures tried in order until a decode passes the fallback thresholds. A temperature
+    /// of zero uses beam search, higher temperatures sample from the token distribution.
+    #[config(default = "vec![0.0, 0.2, 0.4, 0.6, 0.8, 1.0]")]
+    pub temperatures: Vec<f64>,
+    /// Number of independent samples drawn per window when decoding with a non-zero temperature.
+    #[config(default = 5)]
+    pub best_of: usize,
+    /// Only sample from the `top_k` most likely tokens when decoding with a non-zero
+    /// temperature.
+    pub top_k: Option<usize>,
+    /// Only sample from the smallest set of most likely tokens whose cumulative probability
+    /// reaches `top_p` when decoding with a non-zero temperature.
+    pub top_p: Option<f64>,
+    /// Seed of the random number generators used for sampling. Two runs over the same audio
+    /// with the same seed and options produce identical transcriptions. A random seed is used
+    /// when unset.
+    pub seed: Option<u64>,
+    /// Decodes whose gzip compression ratio exceeds this value are retried at the next
+    /// temperature since they are most likely repetition loops.
+    #[config(default = "Some(2.4)")]
+    pub compression_ratio_threshold: Option<f64>,
+    /// Decodes whose average token log probability falls below this value are retried at the
+    /// next temperature.
+    #[config(default = "Some(-1.0)")]
+    pub logprob_threshold: Option<f64>,
+    /// Align every decoded word to the audio using the decoder cross-attention weights.
+    #[config(default = false)]
+    pub word_timestamps: bool,
+    /// How tokens are grouped into words for word timestamps. When unset the splitting is
+    /// chosen by language, see `WordSplitting::for_language`.
+    pub word_splitting: Option<WordSplitting>,
+    /// Decode with `<|notimestamps|>` instead of predicting segment timestamp tokens.
+    #[config(default = false)]
+    pub without_timestamps: bool,
+    /// Latest time in seconds the first timestamp token of a window may point to.
+    #[config(default = "Some(1.0)")]
+    pub max_initial_timestamp: Option<f64>,
+    /// Whether to transcribe the speech or translate it to English.
+    #[config(default = "Task::Transcribe")]
+    pub task: Task,
+    /// Compute the mel spectrogram exactly like openai/whisper: of the whole recording at once
+    /// and in full 30 second windows padded with silence. Checkpoints converted from
+    /// openai/whisper are most accurate with it, at the cost of computing the spectrogram of the
+    /// whole audio up front.
+    #[config(default = false)]
+    pub reference_frontend: bool,
+    /// Only transcribe the parts of the audio the voice activity detection finds speech in,
+    /// energy based unless `ExternalModels::vad` is set. Timestamps still refer to the original
+    /// audio.
+    pub vad: Option<VadConfig>,
+    /// Label the segments with their speakers using `ExternalModels::speaker`, once the whole
+    /// audio is transcribed.
+    pub diarization: Option<DiarizationConfig>,
+    /// Language spoken in the audio. When unset it is detected from the first window, see
+    /// `detect_waveform_language`.
+    pub language: Option<Language>,
+    /// Prompt every window with the tokens decoded so far, which keeps casing, punctuation and
+    /// vocabulary consistent across windows. Disabling it keeps the decoder from repeating a
+    /// hallucination of one window in the following ones.
+    #[config(default = true)]
+    pub condition_on_previous_text: bool,
+    /// Text tokens the first window is prompted with as if they were spoken before the audio,
+    /// e.g. the end of the previous utterance of a stream. Windows decoded in a batch aren't
+    /// prompted.
+    #[config(default = "Vec::new()")]
+    pub initial_prompt: Vec<usize>,
+    /// Keep the first sampled token of a window from being a space or end of text.
+    #[config(default = true)]
+    pub suppress_blank: bool,
+    /// Never sample symbols that are not spoken, see `Gpt2Tokenizer::non_speech_tokens`.
+    #[config(default = true)]
+    pub suppress_non_speech: bool,
+    /// Additional token ids that are never sampled.
+    #[config(default = "Vec::new()")]
+    pub suppress_tokens: Vec<usize>,
+    /// Abort a decode and fall back to the next temperature once a sequence of 3 to 5 tokens
+    /// repeats more than this many times in a row.
+    #[config(default = "Some(4)")]
+    pub max_ngram_repeats: Option<usize>,
+    /// Weight of the external language model scores added to the decoder log probabilities
+    /// during beam search, see `ExternalModels::rescorer`.
+    #[config(default = 0.3)]
+    pub lm_weight: f64,
+    /// Phrases such as product names or proper nouns the decoder should prefer.
+    #[config(default = "Vec::new()")]
+    pub hotwords: Vec<String>,
+    /// Logit bias added to the tokens that start or continue one of the `hotwords`.
+    #[config(default = 2.0)]
+    pub hotword_bias: f64,
+    /// Regular expression the text of every window has to match, e.g. `[0-9 ]+` or
+    /// `(lights on|lights off)`. Tokens that cannot lead to a match are never sampled.
+    pub regex_constraint: Option<String>,
+    /// Maximum number of text tokens in a single segment.
+    pub max_tokens_per_segment: Option<usize>,
+    /// Maximum duration in seconds of a single segment.
+    pub max_segment_duration: Option<f64>,
+    /// Wall clock time in seconds after which decoding a window is stopped, keeping whatever
+    /// was decoded so far, so a single pathological window can't stall a stream.
+    pub max_decode_time: Option<f64>,
+    /// Number of tokens the draft model proposes per verification step when decoding
+    /// speculatively, see `ExternalModels::draft`.
+    #[config(default = 5)]
+    pub draft_tokens: usize,
+    /// Number of windows of a long waveform run through the encoder and the first beam search
+    /// together. Only used without `condition_on_previous_text` since otherwise every window
+    /// depends on the text of the previous one.
+    #[config(default = 1)]
+    pub batch_size: usize,
+    /// Windows whose root mean square amplitude is below this value are treated as silence and
+    /// skipped without running the model.
+    pub silence_rms_threshold: Option<f64>,
+    /// Windows whose `<|nospeech|>` probability, predicted by a single decoder step, exceeds
+    /// this value are skipped without running the full decode.
+    pub no_speech_skip_threshold: Option<f64>,
+}
+
+impl Default for DecodingOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Models taking part in transcription besides the Whisper model itself.
+pub struct ExternalModels<'a, B: Backend> {
+    /// Language model fused into beam search, weighted by `DecodingOptions::lm_weight`.
+    pub rescorer: Option<&'a dyn Rescorer>,
+    /// Smaller model sharing the tokenizer and mel size of the main model, e.g. distil-whisper.
+    /// When set, decoding at temperature zero is greedy and speculative: the draft proposes
+    /// tokens that the main model verifies in a single forward pass.
+    pub draft: Option<&'a Whisper<B>>,
+    /// Silero model used instead of the energy based voice activity detection when
+    /// `DecodingOptions::vad` is set.
+    pub vad: Option<&'a SileroVad<B>>,
+    /// Speaker embedding model telling the speakers apart when `DecodingOptions::diarization`
+    /// is set.
+    pub speaker: Option<&'a SpeakerEncoder<B>>,
+}
+
+impl<'a, B: Backend> Default for ExternalModels<'a, B> {
+    fn default() -> Self {
+        Self {
+            rescorer: None,
+            draft: None,
+            vad: None,
+            speaker: None,
+        }
+    }
+}
+
+/// A span of transcribed speech delimited by timestamp tokens.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    /// Start time in seconds from the beginning of the waveform.
+    pub start: f64,
+    /// End time in seconds from the beginning of the waveform.
+    pub end: f64,
+    pub text: String,
+    /// Text token ids of the segment, without timestamp tokens.
+    pub tokens: Vec<usize>,
+    /// Log probability each token of `tokens` was decoded with.
+    pub token_logprobs: Vec<f64>,
+    /// Mean probability of the segment tokens, useful to highlight uncertain transcriptions.
+    pub confidence: f64,
+    /// Gzip compression ratio of the segment text, see `compression_ratio`.
+    pub compression_ratio: f64,
+    /// Average token log probability of the window the segment was decoded in.
+    pub avg_logprob: f64,
+    /// Probability of the `<|nospeech|>` token for the window the segment was decoded in.
+    pub no_speech_prob: f64,
+    /// Temperature the window was successfully decoded at.
+    pub temperature: f64,
+    /// Words of the segment, only populated when `word_timestamps` is enabled.
+    pub words: Vec<WordTiming>,
+    /// Speaker of the segment as numbered by speaker diarization, unset without it. See
+    /// `output::speakers` for how speakers are named.
+    pub speaker: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TranscriptionResult {
+    pub text: String,
+    pub segments: Vec<Segment>,
+    /// Token ids of the whole transcription including timestamp tokens.
+    pub tokens: Vec<usize>,
+    /// Average token log probability over all decoded windows.
+    pub avg_logprob: f64,
+    /// Average `<|nospeech|>` probability over all decoded windows.
+    pub no_speech_prob: f64,
+    /// Language the audio was decoded as.
+    pub language: Language,
+    /// Whether the speech was transcribed or translated.
+    pub task: Task,
+    /// Length in seconds of the transcribed audio.
+    pub duration: f64,
+}
+
+/// Work done for a window as part of a batch of windows, see `batched_first_pass`.
+struct FirstPass<B: Backend> {
+    encoder_output: Tensor<B, 3>,
+    /// Decode at the first temperature, if it could be batched.
+    hypothesis: Option<Hypothesis>,
+}
+
+/// How far a transcription got, reported after every decoded window.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// Seconds of audio transcribed so far.
+    pub processed: f64,
+    /// Seconds of audio to transcribe, only the speech when voice activity detection is on.
+    pub total: f64,
+}
+
+/// Output of decoding a single 30 second window.
+struct DecodedWindow {
+    tokens: Vec<usize>,
+    segments: Vec<Segment>,
+    avg_logprob: f64,
+    no_speech_prob: f64,
+    temperature: f64,
+}
+
+pub fn waveform_to_text<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    waveform: Vec<f32>,
+    sample_rate: usize,
+    options: &DecodingOptions,
+) -> token::Result<TranscriptionResult> {
+    waveform_to_text_with_models(
+        whisper,
+        bpe,
+        waveform,
+        sample_rate,
+        options,
+        &ExternalModels::default(),
+    )
+}
+
+/// Same as `waveform_to_text` but lets external models take part in decoding, see
+/// `ExternalModels`.
+pub fn waveform_to_text_with_models<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    waveform: Vec<f32>,
+    sample_rate: usize,
+    options: &DecodingOptions,
+    models: &ExternalModels<B>,
+) -> token::Result<TranscriptionResult> {
+    waveform_to_text_incremental(
+        whisper,
+        bpe,
+        waveform,
+        sample_rate,
+        options,
+        models,
+        |_| Ok(()),
+        |_| (),
+    )
+}
+
+/// Same as `waveform_to_text_with_models` but hands the new segments of every decoded window to
+/// `on_segments` as soon as they are final, e.g. to write them out while the rest of a long
+/// recording is still being transcribed. An error returned by `on_segments` stops the
+/// transcription. `on_progress` is called after every window with how much of the audio is
+/// transcribed.
+pub fn waveform_to_text_incremental<B, F, P>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    waveform: Vec<f32>,
+    sample_rate: usize,
+    options: &DecodingOptions,
+    models: &ExternalModels<B>,
+    mut on_segments: F,
+    mut on_progress: P,
+) -> token::Result<TranscriptionResult>
+where
+    B: Backend,
+    F: FnMut(&[Segment]) -> token::Result<()>,
+    P: FnMut(Progress),
+{
+    let device = whisper.devices()[0].clone();
+    let duration = waveform.len() as f64 / sample_rate as f64;
+
+    let diarization = match (&options.diarization, models.speaker) {
+        (Some(config), Some(encoder)) => {
+            if sample_rate != SAMPLE_RATE {
+                return Err(format!("Speaker diarization needs {} Hz audio", SAMPLE_RATE).into());
+            }
+            Some((config, encoder, waveform.clone()))
+        }
+        _ => None,
+    };
+
+    // the speech is transcribed as one waveform and timestamps are mapped back at the end
+    let speech = match (&options.vad, models.vad) {
+        (Some(config), Some(silero)) => {
+            Some(silero.speech_segments(&waveform, sample_rate, config)?)
+        }
+        (Some(config), None) => Some(vad::speech_segments(&waveform, sample_rate, config)),
+        (None, _) => None,
+    };
+    let waveform = match &speech {
+        Some(speech) => speech
+            .iter()
+            .flat_map(|range| waveform[range.clone()].iter().cloned())
+            .collect(),
+        None => waveform,
+    };
+
+    // e.g. no speech was found, there is no audio to detect the language from either
+    if waveform.is_empty() {
+        return Ok(TranscriptionResult {
+            text: String::new(),
+            segments: Vec::new(),
+            tokens: Vec::new(),
+            avg_logprob: 0.0,
+            no_speech_prob: 1.0,
+            language: options.language.unwrap_or(Language::English),
+            task: options.task,
+            duration,
+        });
+    }
+
+    let lang = match options.language {
+        Some(lang) => lang,
+        None => detect_waveform_language(whisper, bpe, &waveform, sample_rate)?[0].0,
+    };
+    let total = waveform.len() as f64 / sample_rate as f64;
+
+    let n_ctx_max_encoder = whisper.encoder_ctx_size();
+    let n_mels = whisper.encoder_mel_size();
+    let mut mel_iter: Box<dyn Iterator<Item = MelWindow<B>>> = if options.reference_frontend {
+        Box::new(reference_mel_windows(
+            waveform,
+            sample_rate,
+            2 * n_ctx_max_encoder,
+            device,
+            n_mels,
+            options.silence_rms_threshold,
+        ))
+    } else {
+        let padding = 200; //ADJUST THIS IF CHINKS ARE REPEATING THEMSELVES ENDLESSLY
+        let n_waveform_samples_per_window = max_waveform_samples(n_ctx_max_encoder - padding);
+
+        Box::new(waveform_to_mel_tensor(
+            waveform,
+            sample_rate,
+            n_waveform_samples_per_window,
+            device,
+            n_mels,
+            options.silence_rms_threshold,
+            padding,
+            n_ctx_max_encoder,
+        ))
+    };
+
+    let mut tokens: Vec<usize> = Vec::new();
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut avg_logprobs: Vec<f64> = Vec::new();
+    let mut no_speech_probs: Vec<f64> = Vec::new();
+    let mut prompt_reset_since = 0;
+    // the initial prompt goes as the text decoded so far does
+    let mut use_initial_prompt = true;
+    // segments handed to `on_segments` already, which merging the next window leaves alone
+    let mut n_final = 0;
+    let mut rng = decoding_rng::<B>(options);
+
+    let batch_size = if options.condition_on_previous_text {
+        1
+    } else {
+        options.batch_size.max(1)
+    };
+
+    let to_source_time = |time: f64, is_end: bool| match &speech {
+        Some(speech) => speech_to_source_time(time, speech, sample_rate, is_end),
+        None => time,
+    };
+
+    // a short clip, such as a streamed utterance, fits in a single window and loops once
+    loop {
+        let windows: Vec<MelWindow<B>> = mel_iter.by_ref().take(batch_size).collect();
+        if windows.is_empty() {
+            break;
+        }
+
+        // the last window of the audio is usually shorter and can't be stacked with the others
+        let same_length = windows
+            .iter()
+            .all(|window| window.mels.dims() == windows[0].mels.dims());
+        let first_passes: Vec<Option<FirstPass<B>>> = if windows.len() > 1 && same_length {
+            let mels = windows.iter().map(|window| window.mels.clone()).collect();
+            batched_first_pass(whisper, bpe, lang, mels, options, models)?
+                .into_iter()
+                .map(Some)
+                .collect()
+        } else {
+            windows.iter().map(|_| None).collect()
+        };
+
+        for (mel_window, first_pass) in windows.into_iter().zip(first_passes) {
+            let mut prompt = Vec::new();
+            if use_initial_prompt {
+                prompt.extend(&options.initial_prompt);
+            }
+            if options.condition_on_previous_text {
+                prompt.extend(&tokens[prompt_reset_since.min(tokens.len())..]);
+            }
+            let window_start = to_source_time(mel_window.time_offset, false);
+            let window_end = mel_window.time_offset
+                + (mel_window.n_frames * HOP_LENGTH) as f64 / sample_rate as f64;
+
+            let window = mels_to_text(
+                whisper,
+                bpe,
+                lang,
+                mel_window.mels,
+                mel_window.n_frames,
+                mel_window.time_offset,
+                &prompt,
+                options,
+                &mut rng,
+                models,
+                first_pass,
+            )?;
+
+            // skipped windows have no tokens to average over
+            if !window.tokens.is_empty() {
+                avg_logprobs.push(window.avg_logprob);
+            }
+            no_speech_probs.push(window.no_speech_prob);
+
+            let window_segments = window
+                .segments
+                .into_iter()
+                .map(|mut segment| {
+                    segment.start = to_source_time(segment.start, false);
+                    segment.end = to_source_time(segment.end, true);
+                    for word in &mut segment.words {
+                        word.start = to_source_time(word.start, false);
+                        word.end = to_source_time(word.end, true);
+                    }
+                    segment
+                })
+                .collect();
+            merge_window_segments(&mut segments, n_final, window_segments, window_start, bpe)?;
+
+            // segments reaching into the next window may still change when it is merged
+            let next_window_start = to_source_time(window_end - WINDOW_OVERLAP, false);
+            let n_unchanging = segments[n_final..]
+                .iter()
+                .take_while(|segment| segment.end <= next_window_start)
+                .count();
+            on_segments(&segments[n_final..n_final + n_unchanging])?;
+            n_final += n_unchanging;
+
+            let new_tokens = window.tokens;
+            if let Some((prev_index, curr_index)) =
+                find_chunk_overlap(&tokens[..], &new_tokens[..], 40, 3)
+            {
+                tokens.truncate(prev_index);
+                tokens.extend(&new_tokens[curr_index..]);
+            } else {
+                tokens.extend(new_tokens);
+            }
+
+            // a high temperature decode is likely off so don't let it steer the next windows
+            if window.temperature > PROMPT_RESET_TEMPERATURE {
+                prompt_reset_since = tokens.len();
+                use_initial_prompt = false;
+            }
+            if !options.condition_on_previous_text {
+                use_initial_prompt = false;
+            }
+
+            on_progress(Progress {
+                processed: window_end.min(total),
+                total,
+            });
+        }
+    }
+    on_segments(&segments[n_final..])?;
+    on_progress(Progress {
+        processed: total,
+        total,
+    });
+
+    if let Some((config, encoder, waveform)) = diarization {
+        diarize(encoder, &waveform, &mut segments, config);
+    }
+
+    Ok(TranscriptionResult {
+        text: segments.iter().map(|segment| segment.text.as_str()).collect(),
+        segments,
+        tokens,
+        avg_logprob: mean(&avg_logprobs),
+        no_speech_prob: mean(&no_speech_probs),
+        language: lang,
+        task: options.task,
+        duration,
+    })
+}
+
+/// Transcribes a clip of at most one window from its mel spectrogram of size
+/// (1, n_mels, n_frame), normalized as `prep_audio` returns it, e.g. taken from a `MelStream`
+/// the audio was pushed to as it was captured. Unlike `waveform_to_text` the clip isn't
+/// searched for speech, and frames beyond the window are clipped.
+pub fn spectrogram_to_text<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    mels: Tensor<B, 3>,
+    options: &DecodingOptions,
+) -> token::Result<TranscriptionResult> {
+    let [_, _, n_frames] = mels.dims();
+    let duration = n_frames as f64 * HOP_DURATION;
+
+    let lang = match options.language {
+        Some(lang) => lang,
+        None => detect_language(whisper, bpe, mels.clone())?[0].0,
+    };
+
+    let padding = 200;
+    let (mels, n_frames) = pad_mels(mels, padding, whisper.encoder_ctx_size());
+    let window = mels_to_text(
+        whisper,
+        bpe,
+        lang,
+        mels,
+        n_frames,
+        0.0,
+        &options.initial_prompt,
+        options,
+        &mut decoding_rng::<B>(options),
+        &ExternalModels::default(),
+        None,
+    )?;
+
+    Ok(TranscriptionResult {
+        text: window.segments.iter().map(|segment| segment.text.as_str()).collect(),
+        segments: window.segments,
+        tokens: window.tokens,
+        avg_logprob: window.avg_logprob,
+        no_speech_prob: window.no_speech_prob,
+        language: lang,
+        task: options.task,
+        duration,
+    })
+}
+
+/// Random number generator sampling at temperatures above zero, seeded by `options.seed`.
+fn decoding_rng<B: Backend>(options: &DecodingOptions) -> StdRng {
+    match options.seed {
+        Some(seed) => {
+            // also seed the backend in case any of its operations draw random numbers
+            B::seed(seed);
+            StdRng::seed_from_u64(seed)
+        }
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Adds the segments of a window to those of the windows before it. The start of the window
+/// overlaps the end of the previous one, whose last words may be cut off there. The segments
+/// of both windows in the overlap are aligned on the longest run of tokens they share and
+/// joined at its start, taking the run and what follows from the new window. Without such a
+/// run the segments are joined in the middle of the overlap. The first `n_final` segments are
+/// left as they are.
+fn merge_window_segments(
+    segments: &mut Vec<Segment>,
+    n_final: usize,
+    window_segments: Vec<Segment>,
+    window_start: f64,
+    bpe: &Gpt2Tokenizer,
+) -> token::Result<()> {
+    let previous_end = segments.last().map_or(window_start, |segment| segment.end);
+    let first_previous = segments
+        .iter()
+        .position(|segment| segment.end > window_start)
+        .unwrap_or(segments.len())
+        .max(n_final);
+    let n_new = window_segments
+        .iter()
+        .take_while(|segment| segment.start < previous_end)
+        .count();
+
+    // segment and token index of every token in the overlap
+    let positions = |segments: &[Segment], first: usize| -> Vec<(usize, usize)> {
+        segments
+            .iter()
+            .enumerate()
+            .skip(first)
+            .flat_map(|(i, segment)| (0..segment.tokens.len()).map(move |j| (i, j)))
+            .collect()
+    };
+    let previous_positions = positions(&segments[..], first_previous);
+    let new_positions = positions(&window_segments[..n_new], 0);
+    let token_at = |segments: &[Segment], (i, j): (usize, usize)| segments[i].tokens[j];
+    let previous_tokens: Vec<usize> =
+        previous_positions.iter().map(|&p| token_at(&segments[..], p)).collect();
+    let new_tokens: Vec<usize> =
+        new_positions.iter().map(|&p| token_at(&window_segments, p)).collect();
+
+    let (previous_run, new_run, run_length) = longest_common_run(&previous_tokens, &new_tokens);
+    let mut window_segments = window_segments;
+    if run_length >= MIN_ALIGNED_TOKENS {
+        let (previous_segment, previous_token) = previous_positions[previous_run];
+        let (new_segment, new_token) = new_positions[new_run];
+        let boundary = token_time(&window_segments[new_segment], new_token)
+            .max(segments[previous_segment].start);
+
+        segments.truncate(previous_segment + 1);
+        if previous_token == 0 {
+            segments.pop();
+        } else {
+            let segment = segments.last_mut().expect("The segment was just kept");
+            keep_tokens(segment, 0..previous_token, bpe)?;
+            segment.end = boundary;
+        }
+
+        window_segments.drain(..new_segment);
+        let segment = &mut window_segments[0];
+        let n_tokens = segment.tokens.len();
+        keep_tokens(segment, new_token..n_tokens, bpe)?;
+        segment.start = boundary;
+    } else {
+        let middle = (window_start + previous_end) / 2.0;
+        let n_kept = segments[first_previous..]
+            .iter()
+            .take_while(|segment| segment.start < middle)
+            .count();
+        segments.truncate(first_previous + n_kept);
+        let last_end = segments.last().map_or(0.0, |segment| segment.end);
+        window_segments.retain(|segment| segment.start >= last_end);
+    }
+
+    segments.extend(window_segments);
+    Ok(())
+}
+
+/// Start and length of the longest run of tokens both `a` and `b` contain, as the index of its
+/// start in `a` and in `b` and its length.
+fn longest_common_run(a: &[usize], b: &[usize]) -> (usize, usize, usize) {
+    let mut best = (0, 0, 0);
+    // length of the common run ending at the previous token of `a` and every token of `b`
+    let mut previous = vec![0; b.len() + 1];
+    for i in 0..a.len() {
+        let mut current = vec![0; b.len() + 1];
+        for j in 0..b.len() {
+            if a[i] == b[j] {
+                current[j + 1] = previous[j] + 1;
+                if current[j + 1] > best.2 {
+                    best = (i + 1 - current[j + 1], j + 1 - current[j + 1], current[j + 1]);
+                }
+            }
+        }
+        previous = current;
+    }
+    best
+}
+
+/// Time the token at `index` of the segment starts at, of its word if the words are aligned
+/// and spread evenly over the segment otherwise.
+fn token_time(segment: &Segment, index: usize) -> f64 {
+    let mut n_tokens = 0;
+    for word in &segment.words {
+        if index < n_tokens + word.tokens.len() {
+            return word.start;
+        }
+        n_tokens += word.tokens.len();
+    }
+    let fraction = index as f64 / segment.tokens.len().max(1) as f64;
+    segment.start + (segment.end - segment.start) * fraction
+}
+
+/// Cuts the segment down to the tokens in `range`, along with their text and words.
+fn keep_tokens(
+    segment: &mut Segment,
+    range: Range<usize>,
+    bpe: &Gpt2Tokenizer,
+) -> token::Result<()> {
+    let mut n_tokens = 0;
+    segment.words.retain(|word| {
+        let word_start = n_tokens;
+        n_tokens += word.tokens.len();
+        word_start >= range.start && n_tokens <= range.end
+    });
+    segment.tokens = segment.tokens[range.clone()].to_vec();
+    segment.token_logprobs = segment.token_logprobs[range].to_vec();
+    segment.text = bpe.decode(&segment.tokens, true)?;
+    segment.confidence =
+        mean(&segment.token_logprobs.iter().map(|l| l.exp()).collect::<Vec<_>>());
+    segment.compression_ratio = compression_ratio(&segment.text);
+    Ok(())
+}
+
+/// Maps a time in the concatenated `speech` ranges back to the waveform they were cut from.
+/// A time on the border of two ranges belongs to the earlier one if it is an end time.
+fn speech_to_source_time(
+    time: f64,
+    speech: &[Range<usize>],
+    sample_rate: usize,
+    is_end: bool,
+) -> f64 {
+    let sample = (time * sample_rate as f64).round() as usize;
+
+    let mut offset = 0;
+    for range in speech {
+        let end = offset + range.len();
+        if sample < end || (is_end && sample == end) {
+            return (range.start + sample - offset) as f64 / sample_rate as f64;
+        }
+        offset = end;
+    }
+
+    // past the end of the speech, e.g. a rounded up timestamp
+    let last_end = speech.last().map_or(0, |range| range.end);
+    (last_end + sample - offset) as f64 / sample_rate as f64
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+/// Runs a single decoder step after `<|startoftranscript|>` and returns the probability of each
+/// language supported by the tokenizer, sorted from most to least likely.
+pub fn detect_language<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    mels: Tensor<B, 3>,
+) -> token::Result<Vec<(Language, f64)>> {
+    let [_, n_mel, n_ctx] = mels.dims();
+    let n_frames = n_ctx.min(whisper.encoder_ctx_size());
+    let encoder_output = whisper.forward_encoder(mels.slice([0..1, 0..n_mel, 0..n_frames]));
+    let cross_attention = whisper.cross_attention_cache(encoder_output);
+
+    let logits = start_of_transcript_logits(whisper, bpe, &cross_attention)?;
+
+    let language_tokens: Vec<(Language, usize)> = Language::iter()
+        .filter_map(|lang| {
+            bpe.special_token(SpecialToken::Language(lang))
+                .map(|token| (lang, token))
+        })
+        .collect();
+    if language_tokens.is_empty() {
+        return Err("Tokenizer has no language tokens".into());
+    }
+
+    let language_logits: Vec<f32> = language_tokens
+        .iter()
+        .map(|(_, token)| logits.get(*token).cloned().unwrap_or(f32::NEG_INFINITY))
+        .collect();
+
+    let mut probs: Vec<(Language, f64)> = language_tokens
+        .into_iter()
+        .zip(log_softmax(&language_logits))
+        .map(|((lang, _), logprob)| (lang, logprob.exp()))
+        .collect();
+    probs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+
+    Ok(probs)
+}
+
+/// Logits of the token following `<|startoftranscript|>`, which carry the language and no speech
+/// predictions of the model.
+fn start_of_transcript_logits<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    cross_attention: &CrossAttentionCache<B>,
+) -> token::Result<Vec<f32>> {
+    let start_token = bpe
+        .special_token(SpecialToken::StartofTranscript)
+        .ok_or("Tokenizer has no start of transcript token")?;
+
+    let prompt: &[usize] = &[start_token];
+    let logits = next_token_logits(whisper, cross_attention, &[prompt], &mut DecoderCache::new())
+        .pop()
+        .unwrap_or_default();
+
+    Ok(logits)
+}
+
+/// Detects the spoken language from the first window of a waveform. See `detect_language`.
+pub fn detect_waveform_language<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    waveform: &[f32],
+    sample_rate: usize,
+) -> token::Result<Vec<(Language, f64)>> {
+    let device = whisper.devices()[0].clone();
+
+    let n_samples = max_waveform_samples(whisper.encoder_ctx_size()).min(waveform.len());
+    let waveform = Tensor::from_floats(
+        tensor::Data::new(waveform[..n_samples].to_vec(), [n_samples].into()),
+        &device,
+    );
+    let mels = prep_audio(waveform.unsqueeze(), sample_rate as f64, whisper.encoder_mel_size());
+
+    detect_language(whisper, bpe, mels)
+}
+
+/// Aligns an existing transcript to the audio without transcribing it, returning the timing of
+/// every word. Audio longer than a window is aligned window by window, each window starting
+/// at the end of the last word aligned in the previous one.
+pub fn align<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    lang: Language,
+    waveform: &[f32],
+    sample_rate: usize,
+    transcript: &str,
+) -> token::Result<Vec<WordTiming>> {
+    let device = whisper.devices()[0].clone();
+
+    let n_ctx_max_encoder = whisper.encoder_ctx_size();
+    let padding = 200;
+    let n_samples_per_window = max_waveform_samples(n_ctx_max_encoder - padding);
+    let n_mels = whisper.encoder_mel_size();
+
+    let start_token = bpe
+        .special_token(SpecialToken::StartofTranscript)
+        .ok_or("Tokenizer has no start of transcript token")?;
+    let task_token = bpe
+        .special_token(SpecialToken::Transcribe)
+        .ok_or("Tokenizer has no transcribe token")?;
+    let lang_token = bpe
+        .special_token(SpecialToken::Language(lang))
+        .ok_or_else(|| format!("Tokenizer has no token for language {}", lang.as_str()))?;
+    let end_token = bpe
+        .special_token(SpecialToken::EndofText)
+        .ok_or("Tokenizer has no end of text token")?;
+    let notimestamp = bpe
+        .special_token(SpecialToken::NoTimeStamps)
+        .ok_or("Tokenizer has no <|notimestamps|> token")?;
+
+    let sot_sequence = vec![start_token, lang_token, task_token];
+    let max_text_tokens = whisper.decoder_ctx_size() - sot_sequence.len() - 2;
+
+    let mut remaining = bpe.encode_ordinary(&format!(" {}", transcript.trim()));
+    let mut words = Vec::new();
+    let mut start = 0;
+    while !remaining.is_empty() && start < waveform.len() {
+        let end = (start + n_samples_per_window).min(waveform.len());
+        let window = Tensor::from_floats(
+            tensor::Data::new(waveform[start..end].to_vec(), [end - start].into()),
+            &device,
+        );
+        let mels = prep_audio(window.unsqueeze(), sample_rate as f64, n_mels);
+        let (mels, n_frames) = pad_mels(mels, padding, n_ctx_max_encoder);
+        let encoder_output = whisper.forward_encoder(mels);
+
+        let n_tokens = remaining.len().min(max_text_tokens);
+        let mut window_words = timestamps::find_alignment(
+            whisper,
+            bpe,
+            encoder_output,
+            &sot_sequence,
+            notimestamp,
+            end_token,
+            &remaining[..n_tokens],
+            None,
+            n_frames,
+            start as f64 / sample_rate as f64,
+            WordSplitting::for_language(lang),
+        )?;
+
+        if end < waveform.len() {
+            // always keep the first word so that every window makes progress
+            let window_end = end as f64 / sample_rate as f64;
+            let n_keep = window_words
+                .iter()
+                .take_while(|word| word.end < window_end - ALIGNMENT_MARGIN)
+                .count()
+                .max(1);
+            window_words.truncate(n_keep);
+        }
+
+        let n_aligned: usize = window_words.iter().map(|word| word.tokens.len()).sum();
+        remaining.drain(..n_aligned.min(remaining.len()));
+
+        if let Some(last_word) = window_words.last() {
+            start = start.max((last_word.end * sample_rate as f64) as usize);
+        }
+        words.extend(window_words);
+    }
+
+    Ok(words)
+}
+
+/// Root mean square amplitude of a waveform.
+fn rms(waveform: &[f32]) -> f64 {
+    if waveform.is_empty() {
+        return 0.0;
+    }
+
+    let sum_squares: f64 = waveform.iter().map(|&x| (x as f64) * (x as f64)).sum();
+    (sum_squares / waveform.len() as f64).sqrt()
+}
+
+/// Mel spectrogram of a window of audio, ready to be passed to the encoder.
+struct MelWindow<B: Backend> {
+    /// Start of the window in seconds.
+    time_offset: f64,
+    mels: Tensor<B, 3>,
+    /// Number of frames holding audio, the rest is padding.
+    n_frames: usize,
+}
+
+/// Splits the waveform into overlapping windows and yields the mel spectrogram of each, padded
+/// with `padding` zero frames. Windows quieter than `silence_rms_threshold` are left out.
+fn waveform_to_mel_tensor<B: Backend>(
+    waveform: Vec<f32>,
+    sample_rate: usize,
+    window_length_samples: usize,
+    device: B::Device,
+    n_mels: usize,
+    silence_rms_threshold: Option<f64>,
+    padding: usize,
+    n_ctx_max_encoder: usize,
+) -> impl Iterator<Item = MelWindow<B>> {
+    let chunk_overlap = (WINDOW_OVERLAP * sample_rate as f64) as usize;
+    let n_samples_per_tensor = window_length_samples;
+    let shift = n_samples_per_tensor.saturating_sub(chunk_overlap).max(1);
+    let iter_len = waveform.len().saturating_sub(1).div(shift) + 1;
+
+    (0..iter_len).into_iter().filter_map(move |i| {
+        let start = i * shift;
+        let end = (start + n_samples_per_tensor).min(waveform.len());
+
+        let slice = &waveform[start..end];
+        if silence_rms_threshold.map_or(false, |threshold| rms(slice) < threshold) {
+            return None;
+        }
+
+        let waveform = Tensor::from_floats(
+            tensor::Data::new(slice.to_vec(), [slice.len()].into()),
+            &device,
+        );
+
+        let mels = prep_audio(waveform.unsqueeze(), sample_rate as f64, n_mels);
+        let (mels, n_frames) = pad_mels(mels, padding, n_ctx_max_encoder);
+
+        Some(MelWindow {
+            time_offset: start as f64 / sample_rate as f64,
+            mels,
+            n_frames,
+        })
+    })
+}
+
+/// Computes the mel spectrogram of the whole waveform followed by 30 seconds of silence at
+/// once, as openai/whisper does, and yields overlapping windows of `n_window_frames` frames.
+/// Normalization then depends on the loudest part of the whole recording and windows are
+/// padded with the spectrum of silence instead of zeros. See `waveform_to_mel_tensor`.
+fn reference_mel_windows<B: Backend>(
+    waveform: Vec<f32>,
+    sample_rate: usize,
+    n_window_frames: usize,
+    device: B::Device,
+    n_mels: usize,
+    silence_rms_threshold: Option<f64>,
+) -> impl Iterator<Item = MelWindow<B>> {
+    let hop_length = (HOP_DURATION * sample_rate as f64).round() as usize;
+    let n_audio_frames = waveform.len() / hop_length;
+
+    let mut padded = waveform.clone();
+    padded.resize(waveform.len() + n_window_frames * hop_length, 0.0);
+    let n_padded = padded.len();
+    let padded = Tensor::from_floats(tensor::Data::new(padded, [n_padded].into()), &device);
+    let mels: Tensor<B, 3> = prep_audio(padded.unsqueeze(), sample_rate as f64, n_mels);
+
+    let overlap = (WINDOW_OVERLAP / HOP_DURATION) as usize;
+    let shift = n_window_frames.saturating_sub(overlap).max(1);
+    let n_windows = n_audio_frames.saturating_sub(1) / shift + 1;
+
+    (0..n_windows).filter_map(move |i| {
+        let start = i * shift;
+        let n_frames = (n_audio_frames - start.min(n_audio_frames)).min(n_window_frames);
+
+        let samples = &waveform[(start * hop_length).min(waveform.len())
+            ..((start + n_frames) * hop_length).min(waveform.len())];
+        if silence_rms_threshold.map_or(false, |threshold| rms(samples) < threshold) {
+            return None;
+        }
+
+        Some(MelWindow {
+            time_offset: start as f64 * HOP_DURATION,
+            mels: mels
+                .clone()
+                .slice([0..1, 0..n_mels, start..(start + n_window_frames)]),
+            n_frames,
+        })
+    })
+}
+
+fn mels_to_text<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    lang: Language,
+    mels: Tensor<B, 3>,
+    n_frames: usize,
+    time_offset: f64,
+    prompt: &[usize],
+    options: &DecodingOptions,
+    rng: &mut StdRng,
+    models: &ExternalModels<B>,
+    first_pass: Option<FirstPass<B>>,
+) -> token::Result<DecodedWindow> {
+    let n_ctx_max_decoder = whisper.decoder_ctx_size();
+
+    let [_, n_mel, _] = mels.dims();
+
+    let draft_cross_attention = match models.draft {
+        Some(draft) if draft.encoder_mel_size() != n_mel => {
+            return Err("Draft model and main model use a different number of mel bins".into());
+        }
+        Some(draft) => Some(draft.cross_attention_cache(draft.forward_encoder(mels.clone()))),
+        None => None,
+    };
+    let (encoder_output, mut first_hypothesis) = match first_pass {
+        Some(first_pass) => (first_pass.encoder_output, first_pass.hypothesis),
+        None => (whisper.forward_encoder(mels), None),
+    };
+    let cross_attention = whisper.cross_attention_cache(encoder_output.clone());
+
+    let end_token = bpe
+        .special_token(SpecialToken::EndofText)
+        .ok_or("Tokenizer has no end of text token")?;
+    let notimestamp = bpe
+        .special_token(SpecialToken::NoTimeStamps)
+        .ok_or("Tokenizer has no <|notimestamps|> token")?;
+    let first_timestamp_token = bpe
+        .special_token(SpecialToken::Timestamp(0.0))
+        .ok_or("Tokenizer has no timestamp tokens")?;
+
+    let no_speech_prob = match bpe.special_token(SpecialToken::NoSpeech) {
+        Some(no_speech_token) => {
+            let logits = start_of_transcript_logits(whisper, bpe, &cross_attention)?;
+            log_softmax(&logits)
+                .get(no_speech_token)
+                .map_or(0.0, |logprob| logprob.exp())
+        }
+        None => 0.0,
+    };
+
+    if options
+        .no_speech_skip_threshold
+        .map_or(false, |threshold| no_speech_prob > threshold)
+    {
+        return Ok(DecodedWindow {
+            tokens: Vec::new(),
+            segments: Vec::new(),
+            avg_logprob: 0.0,
+            no_speech_prob,
+            temperature: 0.0,
+        });
+    }
+
+    let (sot_sequence, initial_tokens) =
+        decoding_prompt(bpe, lang, prompt, options, n_ctx_max_decoder)?;
+    let filters = logit_filters(bpe, options, initial_tokens.len())?;
+
+    let temperatures = if options.temperatures.is_empty() {
+        vec![0.0]
+    } else {
+        options.temperatures.clone()
+    };
+
+    let deadline = options
+        .max_decode_time
+        .map(|seconds| Instant::now() + Duration::from_secs_f64(seconds));
+
+    let mut result = None;
+    for temperature in temperatures {
+        // keep the last decode instead of retrying once the time is up
+        if result.is_some() && deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            break;
+        }
+
+        let hypothesis = if let Some(hypothesis) = first_hypothesis.take() {
+            hypothesis
+        } else if temperature > 0.0 {
+            sample_with_temperature(
+                whisper,
+                &cross_attention,
+                initial_tokens.clone(),
+                end_token,
+                &filters,
+                temperature,
+                options,
+                deadline,
+                rng,
+            )
+        } else if let (Some(draft), Some(draft_cross_attention)) =
+            (models.draft, &draft_cross_attention)
+        {
+            speculative_decode(
+                whisper,
+                &cross_attention,
+                draft,
+                draft_cross_attention,
+                initial_tokens.clone(),
+                end_token,
+                &filters,
+                options,
+                deadline,
+            )
+        } else {
+            beam_search(
+                whisper,
+                &cross_attention,
+                initial_tokens.clone(),
+                end_token,
+                &filters,
+                models.rescorer,
+                options,
+                deadline,
+            )
+        };
+
+        let text = bpe.decode(&hypothesis.tokens[..], true)?;
+        let avg_logprob = hypothesis.sum_logprob / (hypothesis.tokens.len() + 1) as f64;
+
+        // a loop can be confined to a single segment so check each of them as well
+        let too_compressible = match options.compression_ratio_threshold {
+            Some(threshold) => {
+                compression_ratio(&text) > threshold
+                    || max_segment_compression_ratio(
+                        bpe,
+                        &hypothesis.tokens,
+                        end_token,
+                        first_timestamp_token,
+                    )? > threshold
+            }
+            None => false,
+        };
+        let too_repetitive = hypothesis.repetitive || too_compressible;
+        let too_unlikely = options
+            .logprob_threshold
+            .map_or(false, |threshold| avg_logprob < threshold);
+
+        result = Some((
+            hypothesis.tokens,
+            hypothesis.token_logprobs,
+            avg_logprob,
+            temperature,
+        ));
+        if !too_repetitive && !too_unlikely {
+            break;
+        }
+    }
+
+    let (tokens, token_logprobs, avg_logprob, temperature) =
+        result.unwrap_or((Vec::new(), Vec::new(), 0.0, 0.0));
+
+    let (text_tokens, text_logprobs): (Vec<usize>, Vec<f64>) = tokens
+        .iter()
+        .cloned()
+        .zip(token_logprobs)
+        .filter(|&(token, _)| token < end_token)
+        .unzip();
+
+    let words = if options.word_timestamps {
+        timestamps::find_alignment(
+            whisper,
+            bpe,
+            encoder_output,
+            &sot_sequence,
+            notimestamp,
+            end_token,
+            &text_tokens,
+            Some(&text_logprobs),
+            n_frames,
+            time_offset,
+            options
+                .word_splitting
+                .unwrap_or_else(|| WordSplitting::for_language(lang)),
+        )?
+    } else {
+        Vec::new()
+    };
+
+    let window_duration = n_frames as f64 * HOP_DURATION;
+    let spans = if options.without_timestamps {
+        vec![(0.0, window_duration, text_tokens)]
+    } else {
+        split_segments(&tokens, end_token, first_timestamp_token, window_duration)
+    };
+
+    // words were aligned over the text tokens of the whole window so hand them out in order
+    let mut words = words.into_iter().peekable();
+    let mut segments = Vec::with_capacity(spans.len());
+    let mut text_offset = 0;
+    for (start, end, segment_tokens) in spans {
+        let segment_logprobs =
+            text_logprobs[text_offset..text_offset + segment_tokens.len()].to_vec();
+        text_offset += segment_tokens.len();
+
+        let mut segment_words = Vec::new();
+        let mut n_word_tokens = 0;
+        while let Some(word) =
+            words.next_if(|word| n_word_tokens + word.tokens.len() <= segment_tokens.len())
+        {
+            n_word_tokens += word.tokens.len();
+            segment_words.push(word);
+        }
+
+        let text = bpe.decode(&segment_tokens, true)?;
+        let segment_compression_ratio = compression_ratio(&text);
+
+        // segments still looping after every fallback temperature are dropped
+        if options
+            .compression_ratio_threshold
+            .map_or(false, |threshold| segment_compression_ratio > threshold)
+        {
+            continue;
+        }
+
+        segments.push(Segment {
+            start: time_offset + start,
+            end: time_offset + end,
+            text,
+            tokens: segment_tokens,
+            confidence: mean(&segment_logprobs.iter().map(|l| l.exp()).collect::<Vec<_>>()),
+            token_logprobs: segment_logprobs,
+            compression_ratio: segment_compression_ratio,
+            avg_logprob,
+            no_speech_prob,
+            temperature,
+            words: segment_words,
+            speaker: None,
+        });
+    }
+
+    Ok(DecodedWindow {
+        tokens,
+        segments,
+        avg_logprob,
+        no_speech_prob,
+        temperature,
+    })
+}
+
+/// Runs the encoder over a batch of windows at once and, when the first temperature is zero,
+/// a batched beam search as well. Windows that need a fallback temperature are retried one by
+/// one in `mels_to_text`.
+fn batched_first_pass<B: Backend>(
+    whisper: &Whisper<B>,
+    bpe: &Gpt2Tokenizer,
+    lang: Language,
+    mels: Vec<Tensor<B, 3>>,
+    options: &DecodingOptions,
+    models: &ExternalModels<B>,
+) -> token::Result<Vec<FirstPass<B>>> {
+    let n_windows = mels.len();
+    let encoder_output = whisper.forward_encoder(Tensor::cat(mels, 0));
+    let [_, n_audio_ctx, n_state] = encoder_output.dims();
+
+    // speculative decoding and sampling only work on a single window
+    let first_temperature = options.temperatures.first().cloned().unwrap_or(0.0);
+    let batch_decode = first_temperature == 0.0 && models.draft.is_none();
+    let hypotheses: Vec<Option<Hypothesis>> = if batch_decode {
+        let end_token = bpe
+            .special_token(SpecialToken::EndofText)
+            .ok_or("Tokenizer has no end of text token")?;
+        let (_, initial_tokens) =
+            decoding_prompt(bpe, lang, &[], options, whisper.decoder_ctx_size())?;
+        let filters = logit_filters(bpe, options, initial_tokens.len())?;
+        let deadline = options.max_decode_time.map(|seconds| {
+            Instant::now() + Duration::from_secs_f64(seconds * n_windows as f64)
+        });
+
+        batched_beam_search(
+            whisper,
+            &whisper.cross_attention_cache(encoder_output.clone()),
+            initial_tokens,
+            n_windows,
+            end_token,
+            &filters,
+            models.rescorer,
+            options,
+            deadline,
+        )
+        .into_iter()
+        .map(Some)
+        .collect()
+    } else {
+        vec![None; n_windows]
+    };
+
+    Ok(hypotheses
+        .into_iter()
+        .enumerate()
+        .map(|(i, hypothesis)| FirstPass {
+            encoder_output: encoder_output
+                .clone()
+                .slice([i..i + 1, 0..n_audio_ctx, 0..n_state]),
+            hypothesis,
+        })
+        .collect())
+}
+
+/// Pads the mel spectrogram of a window with zero frames, clipping it to the encoder context
+/// if needed. Returns the padded spectrogram and the number of frames of actual audio.
+fn pad_mels<B: Backend>(
+    mels: Tensor<B, 3>,
+    padding: usize,
+    n_ctx_max_encoder: usize,
+) -> (Tensor<B, 3>, usize) {
+    let device = mels.device();
+
+    let [_, n_mel, n_ctx] = mels.dims();
+    if n_ctx + padding > n_ctx_max_encoder {
+        log::warn!(
+            "Audio has length of {} which exceeds maximum length {}. It will be clipped.",
+            n_ctx + padding,
+            n_ctx_max_encoder
+        );
+    }
+
+    // the zero padding helps whisper determine end of text
+    let n_frames = n_ctx.min(n_ctx_max_encoder - padding);
+    let mels = Tensor::cat(
+        vec![
+            mels.slice([0..1, 0..n_mel, 0..n_frames]),
+            Tensor::zeros([1, n_mel, padding], &device),
+        ],
+        2,
+    );
+
+    (mels, n_frames)
+}
+
+/// Start of transcript sequence of a window and the full prompt the decoder starts from, which
+/// also holds the previously decoded text.
+fn decoding_prompt(
+    bpe: &Gpt2Tokenizer,
+    lang: Language,
+    prompt: &[usize],
+    options: &DecodingOptions,
+    n_ctx_max_decoder: usize,
+) -> token::Result<(Vec<usize>, Vec<usize>)> {
+    let start_token = bpe
+        .special_token(SpecialToken::StartofTranscript)
+        .ok_or("Tokenizer has no start of transcript token")?;
+    let task_token = bpe
+        .special_token(options.task.special_token())
+        .ok_or("Tokenizer has no token for the task")?;
+    let lang_token = bpe
+        .special_token(SpecialToken::Language(lang))
+        .ok_or_else(|| format!("Tokenizer has no token for language {}", lang.as_str()))?;
+    let notimestamp = bpe
+        .special_token(SpecialToken::NoTimeStamps)
+        .ok_or("Tokenizer has no <|notimestamps|> token")?;
+
+    let sot_sequence = vec![start_token, lang_token, task_token];
+
+    // previous text goes before the start of transcript, limited to half the decoder context
+    let mut initial_tokens = Vec::new();
+    if let Some(start_of_prev) = bpe.special_token(SpecialToken::StartofPrev) {
+        let n_prompt = prompt.len().min(n_ctx_max_decoder / 2 - 1);
+        if n_prompt > 0 {
+            initial_tokens.push(start_of_prev);
+            initial_tokens.extend(&prompt[prompt.len() - n_prompt..]);
+        }
+    }
+    initial_tokens.extend(&sot_sequence);
+    if options.without_timestamps {
+        initial_tokens.push(notimestamp);
+    }
+
+    Ok((sot_sequence, initial_tokens))
+}
+
+/// Logit filters applied at every decoding step, in order. `sample_begin` is the length of the
+/// prompt.
+fn logit_filters(
+    bpe: &Gpt2Tokenizer,
+    options: &DecodingOptions,
+    sample_begin: usize,
+) -> token::Result<Vec<Box<dyn LogitFilter>>> {
+    let end_token = bpe
+        .special_token(SpecialToken::EndofText)
+        .ok_or("Tokenizer has no end of text token")?;
+    let notimestamp = bpe
+        .special_token(SpecialToken::NoTimeStamps)
+        .ok_or("Tokenizer has no <|notimestamps|> token")?;
+    let first_timestamp_token = bpe
+        .special_token(SpecialToken::Timestamp(0.0))
+        .ok_or("Tokenizer has no timestamp tokens")?;
+
+    let neg_infty = -f32::INFINITY;
+
+    let vocab_size = bpe.vocab_size();
+    let special_tokens_maskout: Vec<f32> = (0..vocab_size)
+        .into_iter()
+        .map(|token| {
+            if bpe.is_special(token) && token < first_timestamp_token {
+                neg_infty
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    let mut filters: Vec<Box<dyn LogitFilter>> = vec![Box::new(SuppressInitialSpecialTokens {
+        maskout: special_tokens_maskout,
+        sample_begin,
+        n_steps: 2,
+    })];
+    if options.suppress_blank {
+        let mut blank_tokens = bpe.encode_ordinary(" ");
+        blank_tokens.push(end_token);
+        filters.push(Box::new(SuppressBlank {
+            blank_tokens,
+            sample_begin,
+        }));
+    }
+    filters.push(Box::new(SuppressTokens {
+        tokens: suppressed_tokens(bpe, options),
+    }));
+    if !options.hotwords.is_empty() {
+        // a phrase can start a sentence or follow a space so boost both encodings
+        let phrases = options
+            .hotwords
+            .iter()
+            .map(|phrase| phrase.trim())
+            .filter(|phrase| !phrase.is_empty())
+            .flat_map(|phrase| {
+                [
+                    bpe.encode_ordinary(phrase),
+                    bpe.encode_ordinary(&format!(" {}", phrase)),
+                ]
+            })
+            .filter(|tokens| !tokens.is_empty())
+            .collect();
+        filters.push(Box::new(BoostPhrases {
+            phrases,
+            bias: options.hotword_bias as f32,
+            sample_begin,
+        }));
+    }
+    if options.max_tokens_per_segment.is_some() || options.max_segment_duration.is_some() {
+        filters.push(Box::new(LimitSegmentLength {
+            max_tokens: options.max_tokens_per_segment,
+            max_timestamp_span: options
+                .max_segment_duration
+                .map(|seconds| (seconds / TIME_PRECISION).round() as usize),
+            sample_begin,
+            timestamp_begin: first_timestamp_token,
+            end_token,
+            without_timestamps: options.without_timestamps,
+        }));
+    }
+    if let Some(pattern) = &options.regex_constraint {
+        let token_bytes = (0..end_token).map(|token| bpe.token_bytes(token)).collect();
+        filters.push(Box::new(ConstrainToRegex::new(
+            pattern,
+            token_bytes,
+            sample_begin,
+            end_token,
+        )?));
+    }
+    if !options.without_timestamps {
+        filters.push(Box::new(ApplyTimestampRules {
+            sample_begin,
+            timestamp_begin: first_timestamp_token,
+            end_token,
+            no_timestamps_token: notimestamp,
+            max_initial_timestamp_index: options
+                .max_initial_timestamp
+                .map(|seconds| (seconds / TIME_PRECISION).round() as usize),
+        }));
+    }
+
+    Ok(filters)
+}
+
+/// Tokens that are never sampled: the prompt special tokens, the non-speech symbols if enabled
+/// and the user provided list.
+fn suppressed_tokens(bpe: &Gpt2Tokenizer, options: &DecodingOptions) -> Vec<usize> {
+    let mut tokens = options.suppress_tokens.clone();
+    if options.suppress_non_speech {
+        tokens.extend(bpe.non_speech_tokens());
+    }
+    tokens.extend(
+        [
+            SpecialToken::Transcribe,
+            SpecialToken::Translate,
+            SpecialToken::StartofTranscript,
+            SpecialToken::StartofPrev,
+            SpecialToken::StartofLM,
+            SpecialToken::NoSpeech,
+        ]
+        .into_iter()
+        .filter_map(|token| bpe.special_token(token)),
+    );
+
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+/// Splits decoded tokens into `(start, end, text tokens)` spans delimited by timestamp token
+/// pairs. Text after the last timestamp is assumed to run until the end of the window.
+fn split_segments(
+    tokens: &[usize],
+    end_token: usize,
+    timestamp_begin: usize,
+    window_duration: f64,
+) -> Vec<(f64, f64, Vec<usize>)> {
+    let mut segments = Vec::new();
+    let mut start: Option<f64> = None;
+    let mut text_tokens: Vec<usize> = Vec::new();
+
+    for &token in tokens {
+        if token >= timestamp_begin {
+            let time = (token - timestamp_begin) as f64 * TIME_PRECISION;
+            match start {
+                Some(segment_start) if !text_tokens.is_empty() => {
+                    segments.push((segment_start, time, std::mem::take(&mut text_tokens)));
+                    start = None;
+                }
+                _ => start = Some(time),
+            }
+        } else if token < end_token {
+            text_tokens.push(token);
+        }
+    }
+
+    if !text_tokens.is_empty() {
+        segments.push((start.unwrap_or(0.0), window_duration, text_tokens));
+    }
+
+    segments
+}
+
+/// Highest compression ratio over the segments of a decoded window.
+fn max_segment_compression_ratio(
+    bpe: &Gpt2Tokenizer,
+    tokens: &[usize],
+    end_token: usize,
+    timestamp_begin: usize,
+) -> token::Result<f64> {
+    let mut max_ratio: f64 = 0.0;
+    for (_, _, segment_tokens) in split_segments(tokens, end_token, timestamp_begin, 0.0) {
+        let text = bpe.decode(&segment_tokens, true)?;
+        max_ratio = max_ratio.max(compression_ratio(&text));
+    }
+
+    Ok(max_ratio)
+}
+
+/// Ratio between the size of the utf-8 text and its gzip compressed size. Highly repetitive
+/// text compresses well so a high ratio indicates the decoder got stuck in a loop.
+pub fn compression_ratio(text: &str) -> f64 {
+    if text.is_empty() {
+        return 0.0;
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let compressed_len = encoder
+        .write_all(text.as_bytes())
+        .and_then(|_| encoder.finish())
+        .map(|compressed| compressed.len())
+        .unwrap_or(text.len());
+
+    text.len() as f64 / compressed_len as f64
+}
 
-pub fn waveform_to_text<B: Backend>(
+/// A candidate token sequence together with its cumulative log probability.
+#[derive(Clone, Debug)]
+struct Hypothesis {
+    tokens: Vec<usize>,
+    sum_logprob: f64,
+    /// Log probability of every sampled token, excluding the prompt.
+    token_logprobs: Vec<f64>,
+    /// Cumulative weighted external language model score and end of text bonus, only used to
+    /// rank hypotheses.
+    score_bonus: f64,
+    /// Decoding was aborted because the sequence got stuck in a repetition loop.
+    repetitive: bool,
+}
+
+/// Beam search over the decoder following openai/whisper's `BeamSearchDecoder`.
+///
+/// Returns the sampled tokens of the best finished hypothesis, excluding the prompt and the
+/// end of text token.
+fn beam_search<B: Backend>(
     whisper: &Whisper<B>,
-    bpe: &Gpt2Tokenizer,
-    lang: Language,
-    waveform: Vec<f32>,
-    sample_rate: usize,
-    streaming_mode: bool,
-) -> token::Result<(String, Vec<usize>)> {
-    let device = whisper.devices()[0].clone();
+    cross_attention: &CrossAttentionCache<B>,
+    initial_tokens: Vec<usize>,
+    end_token: usize,
+    filters: &[Box<dyn LogitFilter>],
+    rescorer: Option<&dyn Rescorer>,
+    options: &DecodingOptions,
+    deadline: Option<Instant>,
+) -> Hypothesis {
+    batched_beam_search(
+        whisper,
+        cross_attention,
+        initial_tokens,
+        1,
+        end_token,
+        filters,
+        rescorer,
+        options,
+        deadline,
+    )
+    .pop()
+    .unwrap()
+}
 
-    let n_ctx_max_encoder = whisper.encoder_ctx_size();
-    let padding = 200; //ADJUST THIS IF CHINKS ARE REPEATING THEMSELVES ENDLESSLY
-    let n_waveform_samples_per_window = max_waveform_samples(n_ctx_max_encoder - padding);
+/// Runs an independent beam search for each of `n_windows` windows in lockstep, decoding the
+/// beams of every window in a single batched forward pass. Row `i` of `cross_attention`
+/// belongs to window `i` and every window starts from `initial_tokens`.
+fn batched_beam_search<B: Backend>(
+    whisper: &Whisper<B>,
+    cross_attention: &CrossAttentionCache<B>,
+    initial_tokens: Vec<usize>,
+    n_windows: usize,
+    end_token: usize,
+    filters: &[Box<dyn LogitFilter>],
+    rescorer: Option<&dyn Rescorer>,
+    options: &DecodingOptions,
+    deadline: Option<Instant>,
+) -> Vec<Hypothesis> {
+    let beam_size = options.beam_size.max(1);
+    let max_candidates = ((beam_size as f64 * options.patience).round() as usize).max(1);
+    let n_prompt = initial_tokens.len();
+    let n_ctx_max_decoder = whisper.decoder_ctx_size();
 
-    let n_mels = whisper.encoder_mel_size();
-    let mel_iter =
-        waveform_to_mel_tensor(waveform, sample_rate, n_waveform_samples_per_window, device, n_mels);
+    let initial = Hypothesis {
+        tokens: initial_tokens,
+        sum_logprob: 0.0,
+        token_logprobs: Vec::new(),
+        score_bonus: 0.0,
+        repetitive: false,
+    };
+    let mut beams: Vec<Vec<Hypothesis>> = vec![vec![initial]; n_windows];
+    let mut finished: Vec<Vec<Hypothesis>> = vec![Vec::with_capacity(max_candidates); n_windows];
+    let mut cache = DecoderCache::new();
 
-    let mut text = String::new();
-    let mut tokens: Vec<usize> = Vec::new();
+    for i in 0..options.sample_len {
+        if n_prompt + i >= n_ctx_max_decoder
+            || deadline.map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            break;
+        }
 
-    //IN THE FOLLOWING CODE, WE WILL PRETTY MUCH ALWAYS ITERATE JUST ONCE, SINCE WE ARE SENDING SUCH SHORT CLIPS OF AUDIO. THIS MEANS FIND CHUNK OVERLAP IS NOT NECESSARY BUT CAN LEAVE IT FOR THE FUTURE
-    for (i, mel) in mel_iter.enumerate() {
-        let (new_text, new_tokens) = mels_to_text(
-            whisper,
-            bpe,
-            lang,
-            mel,
-            padding,
-            streaming_mode,
-        )?;
+        let sequences: Vec<&[usize]> = beams
+            .iter()
+            .flatten()
+            .map(|beam| &beam.tokens[..])
+            .collect();
+        if sequences.is_empty() {
+            break;
+        }
 
-        if let Some((prev_index, curr_index)) =
-            find_chunk_overlap(&tokens[..], &new_tokens[..], 40, 3)
-        {
-            tokens.truncate(prev_index);
-            tokens.extend(&new_tokens[curr_index..]);
+        let logits = if n_windows > 1 {
+            let windows: Vec<usize> = beams
+                .iter()
+                .enumerate()
+                .flat_map(|(window, window_beams)| iter::repeat(window).take(window_beams.len()))
+                .collect();
+            let cross_attention = cross_attention.select(&windows);
+            next_token_logits(whisper, &cross_attention, &sequences, &mut cache)
         } else {
-            tokens.extend(new_tokens);
+            next_token_logits(whisper, cross_attention, &sequences, &mut cache)
+        };
+
+        let parents = batched_beam_step(
+            &mut beams,
+            &mut finished,
+            logits,
+            n_prompt,
+            end_token,
+            filters,
+            rescorer,
+            options,
+        );
+        if parents.is_empty() {
+            break;
         }
+        cache.reorder(&parents);
+    }
+
+    beams
+        .into_iter()
+        .zip(finished)
+        .map(|(window_beams, mut window_finished)| {
+            // fall back to the unfinished beams if nothing reached the end of text token
+            if window_finished.is_empty() {
+                window_finished = window_beams;
+            }
+
+            select_best_hypothesis(window_finished, n_prompt, end_token, options.length_penalty)
+        })
+        .collect()
+}
+
+/// Extends the beams of every window by one token, `logits` holding a row for each beam of
+/// each window in order. Windows with `max_candidates` finished hypotheses drop out of the
+/// batch. Returns the row each new beam was extended from, to reorder the decoder cache with,
+/// so the rows of the windows that dropped out are left out of it too.
+fn batched_beam_step(
+    beams: &mut [Vec<Hypothesis>],
+    finished: &mut [Vec<Hypothesis>],
+    logits: Vec<Vec<f32>>,
+    n_prompt: usize,
+    end_token: usize,
+    filters: &[Box<dyn LogitFilter>],
+    rescorer: Option<&dyn Rescorer>,
+    options: &DecodingOptions,
+) -> Vec<usize> {
+    let beam_size = options.beam_size.max(1);
+    let max_candidates = ((beam_size as f64 * options.patience).round() as usize).max(1);
+
+    let mut logits = logits.into_iter();
+    let mut parents = Vec::with_capacity(logits.len());
+    let mut row = 0;
+    for (window_beams, window_finished) in beams.iter_mut().zip(finished.iter_mut()) {
+        let n_beams = window_beams.len();
+        let window_logits: Vec<Vec<f32>> = logits.by_ref().take(n_beams).collect();
+
+        let (next_beams, window_parents) = beam_step(
+            std::mem::take(window_beams),
+            window_logits,
+            window_finished,
+            n_prompt,
+            end_token,
+            filters,
+            rescorer,
+            options,
+        );
 
-        text = bpe.decode(&tokens[..], true)?;
+        if window_finished.len() < max_candidates {
+            parents.extend(window_parents.into_iter().map(|parent| row + parent));
+            *window_beams = next_beams;
+        }
+        row += n_beams;
     }
 
-    Ok((text, tokens))
+    parents
 }
 
+/// Extends the beams of a single window by one token. Hypotheses ending with the end of text
+/// token are moved to `finished`. Returns the new beams together with the index of the beam
+/// each of them was extended from.
+fn beam_step(
+    beams: Vec<Hypothesis>,
+    logits: Vec<Vec<f32>>,
+    finished: &mut Vec<Hypothesis>,
+    n_prompt: usize,
+    end_token: usize,
+    filters: &[Box<dyn LogitFilter>],
+    rescorer: Option<&dyn Rescorer>,
+    options: &DecodingOptions,
+) -> (Vec<Hypothesis>, Vec<usize>) {
+    let beam_size = options.beam_size.max(1);
+    let max_candidates = ((beam_size as f64 * options.patience).round() as usize).max(1);
 
-fn waveform_to_mel_tensor<B: Backend>(
-    waveform: Vec<f32>,
-    sample_rate: usize,
-    window_length_samples: usize,
-    device: B::Device,
-    n_mels: usize
-) -> impl Iterator<Item = Tensor<B, 3>> {
-    let chunk_overlap = sample_rate * 3;
-    let n_samples_per_tensor = window_length_samples;
-    let shift = n_samples_per_tensor.saturating_sub(chunk_overlap).max(1);
-    let iter_len = waveform.len().saturating_sub(1).div(shift) + 1;
+    // (parent beam, token, cumulative log probability, token log probability,
+    // cumulative score bonus)
+    let mut candidates: Vec<(usize, usize, f64, f64, f64)> = Vec::new();
+    for (parent, (beam, mut logits)) in beams.iter().zip(logits).enumerate() {
+        apply_filters(filters, &mut logits, &beam.tokens);
 
-    (0..iter_len).into_iter().map(move |i| {
-        let start = i * shift;
-        let end = (start + n_samples_per_tensor).min(waveform.len());
+        // the language model only knows about text so special tokens are left out
+        let context: Vec<usize> = beam.tokens[n_prompt..]
+            .iter()
+            .cloned()
+            .filter(|&token| token < end_token)
+            .collect();
 
-        let slice = &waveform[start..end];
+        let logprobs = log_softmax(&logits);
+        for token in top_k_indices(&logprobs, beam_size + 1) {
+            let bonus = match rescorer {
+                Some(rescorer) if token < end_token => {
+                    options.lm_weight * rescorer.score(&context, token)
+                }
+                _ if token == end_token => options.eot_bonus,
+                _ => 0.0,
+            };
 
-        let waveform = Tensor::from_floats(
-            tensor::Data::new(slice.to_vec(), [slice.len()].into()),
-            &device,
-        );
+            candidates.push((
+                parent,
+                token,
+                beam.sum_logprob + logprobs[token],
+                logprobs[token],
+                beam.score_bonus + bonus,
+            ));
+        }
+    }
 
-        let mels = prep_audio(waveform.unsqueeze(), sample_rate as f64, n_mels);
+    candidates.sort_by(|a, b| {
+        (b.2 + b.4)
+            .partial_cmp(&(a.2 + a.4))
+            .unwrap_or(Ordering::Equal)
+    });
 
-        mels
-    })
-}
+    let mut next_beams = Vec::with_capacity(beam_size);
+    let mut parents = Vec::with_capacity(beam_size);
+    for (parent, token, sum_logprob, logprob, score_bonus) in candidates {
+        let mut tokens = beams[parent].tokens.clone();
+        tokens.push(token);
+        let mut token_logprobs = beams[parent].token_logprobs.clone();
+        token_logprobs.push(logprob);
 
-#[derive(Clone)]
-struct BeamSearchToken {
-    token: usize,
-    log_prob: f64,
+        let hypothesis = Hypothesis {
+            tokens,
+            sum_logprob,
+            token_logprobs,
+            score_bonus,
+            repetitive: false,
+        };
+
+        if token == end_token {
+            if finished.len() < max_candidates {
+                finished.push(hypothesis);
+            }
+        } else {
+            next_beams.push(hypothesis);
+            parents.push(parent);
+            if next_beams.len() == beam_size {
+                break;
+            }
+        }
+    }
+
+    // give up on the whole search once the most likely beam is looping
+    if let Some(best) = next_beams.first_mut() {
+        if is_repetition_loop(&best.tokens[n_prompt..], options.max_ngram_repeats) {
+            best.repetitive = true;
+            *finished = vec![best.clone()];
+            return (Vec::new(), Vec::new());
+        }
+    }
+
+    (next_beams, parents)
 }
 
-fn mels_to_text<B: Backend>(
+/// Greedy decoding sped up by a draft model (Leviathan et al., 2023). The draft greedily
+/// proposes `draft_tokens` tokens which the main model scores in a single forward pass. The
+/// proposals the main model agrees with are kept together with its own next token, so the
+/// output is identical to greedy decoding with the main model alone.
+fn speculative_decode<B: Backend>(
     whisper: &Whisper<B>,
-    bpe: &Gpt2Tokenizer,
-    lang: Language,
-    mels: Tensor<B, 3>,
-    padding: usize,
-    streaming_mode: bool,
-) -> token::Result<(String, Vec<usize>)> {
-    let device = mels.device();
+    cross_attention: &CrossAttentionCache<B>,
+    draft: &Whisper<B>,
+    draft_cross_attention: &CrossAttentionCache<B>,
+    initial_tokens: Vec<usize>,
+    end_token: usize,
+    filters: &[Box<dyn LogitFilter>],
+    options: &DecodingOptions,
+    deadline: Option<Instant>,
+) -> Hypothesis {
+    let n_prompt = initial_tokens.len();
+    let n_ctx_max_decoder = whisper.decoder_ctx_size().min(draft.decoder_ctx_size());
+    let max_len = (n_prompt + options.sample_len).min(n_ctx_max_decoder);
 
-    let n_ctx_max_encoder = whisper.encoder_ctx_size();
-    let n_ctx_max_decoder = whisper.decoder_ctx_size();
+    let mut hypothesis = Hypothesis {
+        tokens: initial_tokens,
+        sum_logprob: 0.0,
+        token_logprobs: Vec::new(),
+        score_bonus: 0.0,
+        repetitive: false,
+    };
+    // both caches hold every accepted token except the last one
+    let mut cache = DecoderCache::new();
+    let mut draft_cache = DecoderCache::new();
 
-    let [n_channel, n_mel, n_ctx] = mels.dims();
-    if n_ctx + padding > n_ctx_max_encoder {
-        println!(
-            "Audio has length of {} which exceeds maximum length {}. It will be clipped.",
-            n_ctx + padding,
-            n_ctx_max_encoder
-        );
+    while hypothesis.tokens.len() < max_len && hypothesis.tokens.last() != Some(&end_token) {
+        if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            break;
+        }
+
+        let n_accepted = hypothesis.tokens.len();
+        let n_draft = options.draft_tokens.max(1).min(max_len - n_accepted);
+
+        let mut proposed = hypothesis.tokens.clone();
+        for _ in 0..n_draft {
+            let mut logits = next_token_logits(
+                draft,
+                draft_cross_attention,
+                &[&proposed[..]],
+                &mut draft_cache,
+            )
+            .pop()
+            .unwrap_or_default();
+            apply_filters(filters, &mut logits, &proposed);
+
+            let token = top_k_indices(&log_softmax(&logits), 1)[0];
+            proposed.push(token);
+            if token == end_token {
+                break;
+            }
+        }
+
+        // row i holds the logits following token `offset + i` of the proposed sequence
+        let offset = cache.len();
+        let rows = sequence_logits(whisper, cross_attention, &proposed, &mut cache);
+        for (j, mut logits) in rows.into_iter().skip(n_accepted - 1 - offset).enumerate() {
+            apply_filters(filters, &mut logits, &hypothesis.tokens);
+
+            let logprobs = log_softmax(&logits);
+            let token = top_k_indices(&logprobs, 1)[0];
+
+            hypothesis.sum_logprob += logprobs[token];
+            hypothesis.tokens.push(token);
+            hypothesis.token_logprobs.push(logprobs[token]);
+
+            let agrees = proposed.get(n_accepted + j) == Some(&token);
+            if !agrees || token == end_token || hypothesis.tokens.len() >= max_len {
+                break;
+            }
+        }
+
+        // forget the rejected proposals
+        cache.truncate(hypothesis.tokens.len() - 1);
+        draft_cache.truncate(hypothesis.tokens.len() - 1);
+
+        if is_repetition_loop(&hypothesis.tokens[n_prompt..], options.max_ngram_repeats) {
+            hypothesis.repetitive = true;
+            break;
+        }
     }
 
-    // the zero padding helps whisper determine end of text
-    let mels = Tensor::cat(
-        vec![
-            mels.slice([0..1, 0..n_mel, 0..(n_ctx).min(n_ctx_max_encoder - padding)]),
-            Tensor::zeros([1, n_mel, padding], &device),
-        ],
-        2,
-    );
-    let encoder_output = whisper.forward_encoder(mels);
+    select_best_hypothesis(vec![hypothesis], n_prompt, end_token, options.length_penalty)
+}
 
-    let start_token = bpe.special_token(SpecialToken::StartofTranscript).unwrap();
-    let transcription_token = bpe.special_token(SpecialToken::Transcribe).unwrap();
-    let start_of_prev_token = bpe.special_token(SpecialToken::StartofPrev).unwrap();
-    let lang_token = bpe.special_token(SpecialToken::Language(lang)).unwrap();
-    let first_timestamp_token = bpe.special_token(SpecialToken::Timestamp(0.0)).unwrap();
-    let end_token = bpe.special_token(SpecialToken::EndofText).unwrap();
-    let notimestamp = bpe.special_token(SpecialToken::NoTimeStamps).unwrap();
+/// Draws `best_of` independent samples from the temperature scaled token distribution.
+fn sample_with_temperature<B: Backend, R: Rng>(
+    whisper: &Whisper<B>,
+    cross_attention: &CrossAttentionCache<B>,
+    initial_tokens: Vec<usize>,
+    end_token: usize,
+    filters: &[Box<dyn LogitFilter>],
+    temperature: f64,
+    options: &DecodingOptions,
+    deadline: Option<Instant>,
+    rng: &mut R,
+) -> Hypothesis {
+    let n_prompt = initial_tokens.len();
+    let n_ctx_max_decoder = whisper.decoder_ctx_size();
 
-    let mut initial_tokens = Vec::new();
-    initial_tokens.extend([start_token, lang_token, transcription_token, notimestamp]);
-
-    type BeamNode = beam::BeamNode<BeamSearchToken>;
-    let initial_tokens = BeamNode {
-        seq: initial_tokens
-            .into_iter()
-            .map(|tok| BeamSearchToken {
-                token: tok,
-                log_prob: 0.0,
-            })
-            .collect(),
-        log_prob: 0.0,
-    };
+    let mut active = vec![
+        Hypothesis {
+            tokens: initial_tokens,
+            sum_logprob: 0.0,
+            token_logprobs: Vec::new(),
+            score_bonus: 0.0,
+            repetitive: false,
+        };
+        options.best_of.max(1)
+    ];
+    let mut finished = Vec::with_capacity(active.len());
+    let mut cache = DecoderCache::new();
 
-    let neg_infty = -f32::INFINITY;
+    for i in 0..options.sample_len {
+        if active.is_empty()
+            || n_prompt + i >= n_ctx_max_decoder
+            || deadline.map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            break;
+        }
 
-    let vocab_size = bpe.vocab_size();
-    let mut special_tokens_maskout: Vec<f32> = (0..vocab_size)
-        .into_iter()
-        .map(|token| {
-            if bpe.is_special(token) {
-                neg_infty
+        let n_active = active.len();
+        let sequences: Vec<&[usize]> = active.iter().map(|hyp| &hyp.tokens[..]).collect();
+        let logits = next_token_logits(whisper, cross_attention, &sequences, &mut cache);
+
+        let mut still_active = Vec::with_capacity(active.len());
+        let mut kept = Vec::with_capacity(active.len());
+        for (index, (mut hypothesis, mut logits)) in active.into_iter().zip(logits).enumerate() {
+            apply_filters(filters, &mut logits, &hypothesis.tokens);
+
+            let logprobs = log_softmax(&logits);
+            let token = sample_token(&logprobs, temperature, options.top_k, options.top_p, rng);
+
+            hypothesis.sum_logprob += logprobs[token];
+            hypothesis.tokens.push(token);
+            hypothesis.token_logprobs.push(logprobs[token]);
+
+            if is_repetition_loop(&hypothesis.tokens[n_prompt..], options.max_ngram_repeats) {
+                hypothesis.repetitive = true;
+            }
+
+            if token == end_token || hypothesis.repetitive {
+                finished.push(hypothesis);
             } else {
-                0.0
+                still_active.push(hypothesis);
+                kept.push(index);
+            }
+        }
+
+        // drop the finished samples from the cache
+        if kept.len() < n_active {
+            cache.reorder(&kept);
+        }
+        active = still_active;
+    }
+
+    finished.extend(active);
+
+    select_best_hypothesis(finished, n_prompt, end_token, options.length_penalty)
+}
+
+/// Strips the prompt and end of text token from each candidate and returns the highest scoring one.
+/// Repetitive candidates are only returned if every candidate is repetitive.
+fn select_best_hypothesis(
+    candidates: Vec<Hypothesis>,
+    n_prompt: usize,
+    end_token: usize,
+    length_penalty: Option<f64>,
+) -> Hypothesis {
+    let all_repetitive = candidates.iter().all(|hypothesis| hypothesis.repetitive);
+    let best = candidates
+        .into_iter()
+        .filter(|hypothesis| all_repetitive || !hypothesis.repetitive)
+        .map(|mut hypothesis| {
+            hypothesis.tokens.drain(..n_prompt);
+            if hypothesis.tokens.last() == Some(&end_token) {
+                hypothesis.tokens.pop();
+                hypothesis.token_logprobs.pop();
             }
+            hypothesis
         })
+        .max_by(|a, b| {
+            let score_a = length_normalized_score(a, length_penalty);
+            let score_b = length_normalized_score(b, length_penalty);
+            score_a.partial_cmp(&score_b).unwrap_or(Ordering::Equal)
+        });
+
+    best.unwrap_or(Hypothesis {
+        tokens: Vec::new(),
+        sum_logprob: 0.0,
+        token_logprobs: Vec::new(),
+        score_bonus: 0.0,
+        repetitive: false,
+    })
+}
+
+/// Whether `tokens` ends with an n-gram of any size in `REPETITION_NGRAM_SIZES` repeated more
+/// than `max_repeats` times in a row.
+fn is_repetition_loop(tokens: &[usize], max_repeats: Option<usize>) -> bool {
+    let max_repeats = match max_repeats {
+        Some(max_repeats) => max_repeats,
+        None => return false,
+    };
+
+    REPETITION_NGRAM_SIZES.any(|n| {
+        let span = n * (max_repeats + 1);
+        if tokens.len() < span {
+            return false;
+        }
+
+        let tail = &tokens[tokens.len() - span..];
+        let ngram = &tail[span - n..];
+        tail.chunks(n).all(|chunk| chunk == ngram)
+    })
+}
+
+/// Score used to rank finished hypotheses. Without a length penalty the score is the mean log
+/// probability per token, otherwise the Google NMT penalty `((5 + length) / 6) ^ alpha` is used.
+/// The score bonus of language model rescoring and `eot_bonus` is added to the log probability.
+fn length_normalized_score(hypothesis: &Hypothesis, length_penalty: Option<f64>) -> f64 {
+    let length = hypothesis.tokens.len().max(1) as f64;
+    let penalty = match length_penalty {
+        Some(alpha) => ((5.0 + length) / 6.0).powf(alpha),
+        None => length,
+    };
+
+    (hypothesis.sum_logprob + hypothesis.score_bonus) / penalty
+}
+
+/// Runs the decoder over a batch of equal length token sequences and returns the logits of the
+/// last position of each sequence. Row `i` of `cache` must hold the keys and values of the
+/// leading tokens of `sequences[i]`, only the remaining tokens are fed through the decoder.
+fn next_token_logits<B: Backend>(
+    whisper: &Whisper<B>,
+    cross_attention: &CrossAttentionCache<B>,
+    sequences: &[&[usize]],
+    cache: &mut DecoderCache<B>,
+) -> Vec<Vec<f32>> {
+    let device = whisper.devices()[0].clone();
+
+    let n_batch = sequences.len();
+    let offset = cache.len();
+    let n_new = sequences[0].len() - offset;
+    let flattened_tokens: Vec<usize> = sequences
+        .iter()
+        .flat_map(|seq| seq[offset..].iter().cloned())
         .collect();
-    //special_tokens_maskout[end_token] = 1.0;
 
-    let special_tokens_maskout = Tensor::from_data(
-        Data::new(special_tokens_maskout, [vocab_size].into()).convert(),
+    let token_tensor = Tensor::from_ints(
+        Data::from_usize(Data::new(flattened_tokens, [n_batch, n_new].into())),
         &device,
     );
 
-    let beamsearch_next = |beams: &[BeamNode]| {
-        // convert tokens into tensor
-        let max_seq_len = beams.iter().map(|beam| beam.seq.len()).max().unwrap_or(0);
-        let flattened_tokens: Vec<_> = beams
-            .iter()
-            .flat_map(|beam| {
-                let additional_tokens = max_seq_len - beam.seq.len();
-                beam.seq
-                    .iter()
-                    .map(|btok| btok.token)
-                    .chain(iter::once(0).cycle().take(additional_tokens))
-            })
-            .collect();
+    let logits = whisper.forward_decoder_cached(token_tensor, cross_attention, cache);
+    let [_, _, n_vocab] = logits.dims();
 
-        let token_tensor = Tensor::from_ints(
-            Data::from_usize(Data::new(
-                flattened_tokens,
-                [beams.len(), max_seq_len].into(),
-            )),
-            &device,
-        );
+    let logits: Vec<f32> = logits
+        .slice([0..n_batch, (n_new - 1)..n_new, 0..n_vocab])
+        .reshape([n_batch, n_vocab])
+        .into_data()
+        .convert::<f32>()
+        .value;
 
-        let logits =
-            whisper.forward_decoder(token_tensor, encoder_output.clone().repeat(0, beams.len()));
-        let logits = if max_seq_len > 5 {
-            logits
-        } else {
-            logits + special_tokens_maskout.clone().unsqueeze()
-        };
-        let log_probs = log_softmax(logits, 2);
+    logits
+        .chunks(n_vocab)
+        .map(|row| row.to_vec())
+        .collect()
+}
 
-        let [n_batch, n_token, n_dict] = log_probs.dims();
-        let beam_log_probs = beams.iter().enumerate().map(|(i, beam)| {
-            let batch = i;
-            let token_index = beam.seq.len() - 1;
+/// Runs the decoder over the tokens of `sequence` not yet covered by `cache` and returns the
+/// logits of each of those positions.
+fn sequence_logits<B: Backend>(
+    whisper: &Whisper<B>,
+    cross_attention: &CrossAttentionCache<B>,
+    sequence: &[usize],
+    cache: &mut DecoderCache<B>,
+) -> Vec<Vec<f32>> {
+    let device = whisper.devices()[0].clone();
 
-            log_probs
-                .clone()
-                .slice([batch..batch + 1, token_index..token_index + 1])
-                .flatten::<1>(0, 2)
-                .into_data()
-                .value
-        });
+    let new_tokens = sequence[cache.len()..].to_vec();
+    let n_new = new_tokens.len();
+    let token_tensor = Tensor::from_ints(
+        Data::from_usize(Data::new(new_tokens, [1, n_new].into())),
+        &device,
+    );
 
-        let continuations = beam_log_probs
-            .zip(beams)
-            .map(|(log_probs, beam)| {
-                log_probs
-                    .into_iter()
-                    .map(|log_prob| log_prob.elem::<f64>())
-                    .enumerate()
-                    .map(|(token_id, log_prob)| {
-                        (
-                            BeamSearchToken {
-                                token: token_id,
-                                log_prob: log_prob,
-                            },
-                            beam.log_prob + log_prob,
-                        )
-                    })
-                    .collect()
-            })
-            .collect();
+    let logits = whisper.forward_decoder_cached(token_tensor, cross_attention, cache);
+    let [_, _, n_vocab] = logits.dims();
 
-        continuations
-    };
+    let logits: Vec<f32> = logits.into_data().convert::<f32>().value;
 
-    let beamsearch_is_finished = |toks: &[BeamSearchToken]| {
-        if let Some(btok) = toks.last() {
-            btok.token == end_token
-        } else {
-            false
-        }
-    };
+    logits
+        .chunks(n_vocab)
+        .map(|row| row.to_vec())
+        .collect()
+}
 
-    let beam_size = 5;
-    let max_depth = 30;
-    let mut tokens: Vec<_> = beam::beam_search(
-        vec![initial_tokens],
-        beamsearch_next,
-        beamsearch_is_finished,
-        beam_size,
-        max_depth,
-    )
-    .into_iter()
-    .map(|btok| btok.token)
-    .collect();
+fn apply_filters(filters: &[Box<dyn LogitFilter>], logits: &mut [f32], tokens: &[usize]) {
+    for filter in filters {
+        filter.apply(logits, tokens);
+    }
+}
+
+/// Samples a token from the temperature scaled distribution, optionally truncated to the
+/// `top_k` most likely tokens and to the nucleus of cumulative probability `top_p`.
+fn sample_token<R: Rng>(
+    logprobs: &[f64],
+    temperature: f64,
+    top_k: Option<usize>,
+    top_p: Option<f64>,
+    rng: &mut R,
+) -> usize {
+    let max = logprobs
+        .iter()
+        .cloned()
+        .fold(f64::NEG_INFINITY, f64::max);
+    let mut weights: Vec<f64> = logprobs
+        .iter()
+        .map(|&logprob| ((logprob - max) / temperature).exp())
+        .collect();
+
+    if top_k.is_some() || top_p.is_some() {
+        let mut order: Vec<usize> = (0..weights.len()).collect();
+        order.sort_by(|&a, &b| weights[b].partial_cmp(&weights[a]).unwrap_or(Ordering::Equal));
+
+        let total: f64 = weights.iter().sum();
+        let mut cumulative = 0.0;
+        let mut n_keep = top_k.unwrap_or(order.len()).clamp(1, order.len().max(1));
+        if let Some(top_p) = top_p {
+            for (rank, &token) in order.iter().take(n_keep).enumerate() {
+                cumulative += weights[token] / total;
+                if cumulative >= top_p {
+                    n_keep = rank + 1;
+                    break;
+                }
+            }
+        }
 
-    let text = bpe.decode(&tokens[..], false)?;
+        for &token in &order[n_keep.min(order.len())..] {
+            weights[token] = 0.0;
+        }
+    }
 
-    return Ok((text, tokens));
+    match WeightedIndex::new(weights) {
+        Ok(distribution) => distribution.sample(rng),
+        Err(_) => top_k_indices(logprobs, 1)[0],
+    }
 }
 
+fn log_softmax(logits: &[f32]) -> Vec<f64> {
+    let max = logits
+        .iter()
+        .cloned()
+        .fold(f32::NEG_INFINITY, f32::max) as f64;
+    let log_sum_exp = logits
+        .iter()
+        .map(|&logit| (logit as f64 - max).exp())
+        .sum::<f64>()
+        .ln()
+        + max;
+
+    logits
+        .iter()
+        .map(|&logit| logit as f64 - log_sum_exp)
+        .collect()
+}
 
+fn top_k_indices(values: &[f64], k: usize) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..values.len()).collect();
+    let k = k.min(indices.len());
+    if k == 0 {
+        return Vec::new();
+    }
 
+    let by_value_desc =
+        |a: &usize, b: &usize| values[*b].partial_cmp(&values[*a]).unwrap_or(Ordering::Equal);
+    indices.select_nth_unstable_by(k - 1, by_value_desc);
+    indices.truncate(k);
+    indices.sort_by(by_value_desc);
 
+    indices
+}
 
 //HELPERS
 fn find_chunk_overlap(
@@ -308,3 +2346,257 @@ fn find_chunk_overlap(
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokenizers::{models::wordlevel::WordLevel, Tokenizer};
+
+    fn hypothesis(tokens: Vec<usize>) -> Hypothesis {
+        Hypothesis {
+            tokens,
+            sum_logprob: 0.0,
+            token_logprobs: Vec::new(),
+            score_bonus: 0.0,
+            repetitive: false,
+        }
+    }
+
+    /// Tokenizer of the words `w0` to `w9`, token `i` being `wi`.
+    fn tokenizer() -> Gpt2Tokenizer {
+        let vocab: HashMap<String, u32> = (0..10).map(|i| (format!("w{}", i), i)).collect();
+        let model = WordLevel::builder()
+            .vocab(vocab)
+            .unk_token("w0".to_string())
+            .build()
+            .unwrap();
+        Gpt2Tokenizer::from_tokenizer(Tokenizer::new(model))
+    }
+
+    fn segment(start: f64, end: f64, tokens: Vec<usize>) -> Segment {
+        Segment {
+            start,
+            end,
+            text: String::new(),
+            token_logprobs: vec![0.0; tokens.len()],
+            tokens,
+            confidence: 1.0,
+            compression_ratio: 0.0,
+            avg_logprob: 0.0,
+            no_speech_prob: 0.0,
+            temperature: 0.0,
+            words: Vec::new(),
+            speaker: None,
+        }
+    }
+
+    #[test]
+    fn finished_window_drops_out_of_the_cache() {
+        let end_token = 3;
+        let options = DecodingOptions::new().with_beam_size(1);
+        let mut beams = vec![vec![hypothesis(vec![0])], vec![hypothesis(vec![0])]];
+        let mut finished = vec![Vec::new(), Vec::new()];
+        // the first window reaches the end of text token, the second one goes on
+        let logits = vec![vec![0.0, 0.0, 0.0, 10.0], vec![10.0, 0.0, 0.0, 0.0]];
+
+        let parents = batched_beam_step(
+            &mut beams,
+            &mut finished,
+            logits,
+            1,
+            end_token,
+            &[],
+            None,
+            &options,
+        );
+
+        assert_eq!(parents, vec![1]);
+        assert!(beams[0].is_empty());
+        assert_eq!(finished[0].len(), 1);
+        assert_eq!(beams[1].len(), 1);
+        assert_eq!(beams[1][0].tokens, vec![0, 0]);
+    }
+
+    #[test]
+    fn beam_step_moves_finished_hypotheses_out() {
+        let end_token = 3;
+        let options = DecodingOptions::new().with_beam_size(2);
+        let mut finished = Vec::new();
+
+        let (beams, parents) = beam_step(
+            vec![hypothesis(vec![0])],
+            vec![vec![1.0, 3.0, 0.0, 2.0]],
+            &mut finished,
+            1,
+            end_token,
+            &[],
+            None,
+            &options,
+        );
+
+        let tokens: Vec<_> = beams.iter().map(|beam| beam.tokens.clone()).collect();
+        assert_eq!(tokens, vec![vec![0, 1], vec![0, 0]]);
+        assert_eq!(parents, vec![0, 0]);
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].tokens, vec![0, end_token]);
+        let logprobs = log_softmax(&[1.0, 3.0, 0.0, 2.0]);
+        assert_eq!(beams[0].sum_logprob, logprobs[1]);
+        assert_eq!(beams[0].token_logprobs, vec![logprobs[1]]);
+    }
+
+    #[test]
+    fn beam_step_gives_up_on_a_loop() {
+        let options = DecodingOptions::new()
+            .with_beam_size(2)
+            .with_max_ngram_repeats(Some(1));
+        let mut finished = Vec::new();
+
+        let (beams, parents) = beam_step(
+            vec![hypothesis(vec![0, 1, 2, 0, 1, 2])],
+            vec![vec![5.0, 0.0, 0.0, 0.0]],
+            &mut finished,
+            1,
+            3,
+            &[],
+            None,
+            &options,
+        );
+
+        assert!(beams.is_empty());
+        assert!(parents.is_empty());
+        assert_eq!(finished.len(), 1);
+        assert!(finished[0].repetitive);
+        assert_eq!(finished[0].tokens, vec![0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn select_best_hypothesis_skips_repetitive_ones() {
+        let end_token = 3;
+        let candidate = |tokens: Vec<usize>, sum_logprob: f64, repetitive: bool| Hypothesis {
+            sum_logprob,
+            repetitive,
+            ..hypothesis(tokens)
+        };
+
+        let best = select_best_hypothesis(
+            vec![
+                candidate(vec![9, 1, 2, end_token], -3.0, false),
+                candidate(vec![9, 1, end_token], -0.1, true),
+            ],
+            1,
+            end_token,
+            None,
+        );
+        assert_eq!(best.tokens, vec![1, 2]);
+
+        // the mean log probability without a length penalty
+        let best = select_best_hypothesis(
+            vec![
+                candidate(vec![9, 1, 2, 0, end_token], -2.0, true),
+                candidate(vec![9, 1, end_token], -1.0, true),
+            ],
+            1,
+            end_token,
+            None,
+        );
+        assert_eq!(best.tokens, vec![1, 2, 0]);
+
+        assert!(select_best_hypothesis(Vec::new(), 1, end_token, None).tokens.is_empty());
+    }
+
+    #[test]
+    fn compression_ratio_of_repetitive_text() {
+        assert_eq!(compression_ratio(""), 0.0);
+        assert!(compression_ratio(&"so so so ".repeat(50)) > 2.4);
+        assert!(compression_ratio("The quick brown fox jumps over the lazy dog.") < 1.5);
+    }
+
+    #[test]
+    fn repetition_loop_needs_enough_repeats() {
+        let tokens = [7, 1, 2, 3, 1, 2, 3];
+        assert!(is_repetition_loop(&tokens, Some(1)));
+        assert!(!is_repetition_loop(&tokens, Some(2)));
+        assert!(!is_repetition_loop(&tokens, None));
+        assert!(!is_repetition_loop(&[1, 2, 3, 4, 5, 6], Some(1)));
+    }
+
+    #[test]
+    fn longest_common_run_of_overlap() {
+        assert_eq!(longest_common_run(&[1, 2, 3, 4, 5], &[9, 3, 4, 5, 7]), (2, 1, 3));
+        assert_eq!(longest_common_run(&[1, 2], &[3, 4]), (0, 0, 0));
+        assert_eq!(longest_common_run(&[], &[1]), (0, 0, 0));
+    }
+
+    #[test]
+    fn merge_window_segments_on_common_run() {
+        let bpe = tokenizer();
+        let mut segments = vec![segment(0.0, 10.0, vec![1, 2, 3, 4, 5])];
+        let window_segments = vec![segment(8.5, 12.0, vec![3, 4, 5, 6, 7])];
+
+        merge_window_segments(&mut segments, 0, window_segments, 8.0, &bpe).unwrap();
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].tokens, vec![1, 2]);
+        assert_eq!(segments[0].text, "w1 w2");
+        assert_eq!(segments[0].end, 8.5);
+        assert_eq!(segments[1].tokens, vec![3, 4, 5, 6, 7]);
+        assert_eq!(segments[1].start, 8.5);
+    }
+
+    #[test]
+    fn merge_window_segments_in_the_middle_without_run() {
+        let bpe = tokenizer();
+        let mut segments = vec![segment(0.0, 10.0, vec![1, 2])];
+        let window_segments = vec![segment(9.0, 10.0, vec![8]), segment(10.0, 12.0, vec![9])];
+
+        merge_window_segments(&mut segments, 0, window_segments, 8.0, &bpe).unwrap();
+
+        let tokens: Vec<_> = segments.iter().map(|segment| segment.tokens.clone()).collect();
+        assert_eq!(tokens, vec![vec![1, 2], vec![9]]);
+    }
+
+    #[test]
+    fn split_segments_on_timestamp_pairs() {
+        let (end_token, timestamp_begin) = (5, 10);
+        let time = |token: usize| (token - timestamp_begin) as f64 * TIME_PRECISION;
+        let tokens = [10, 1, 2, 20, 20, 3, 30, end_token];
+
+        assert_eq!(
+            split_segments(&tokens, end_token, timestamp_begin, 30.0),
+            vec![
+                (time(10), time(20), vec![1, 2]),
+                (time(20), time(30), vec![3])
+            ]
+        );
+        // text without a closing timestamp runs until the end of the window
+        assert_eq!(
+            split_segments(&[10, 1, 2], end_token, timestamp_begin, 30.0),
+            vec![(0.0, 30.0, vec![1, 2])]
+        );
+    }
+
+    #[test]
+    fn sample_token_within_top_k() {
+        let logprobs = [0.5f64.ln(), 0.3f64.ln(), 0.2f64.ln()];
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert!(sample_token(&logprobs, 1.0, Some(2), None, &mut rng) < 2);
+            assert_eq!(sample_token(&logprobs, 1.0, None, Some(0.1), &mut rng), 0);
+        }
+        // tokens of zero probability are never sampled
+        let logprobs = [0.0, f64::NEG_INFINITY];
+        assert_eq!(sample_token(&logprobs, 1.0, None, None, &mut rng), 0);
+    }
+
+    #[test]
+    fn speech_time_maps_to_the_source() {
+        let speech = [100..200, 500..600];
+        assert_eq!(speech_to_source_time(0.5, &speech, 100, false), 1.5);
+        // the border of two ranges
+        assert_eq!(speech_to_source_time(1.0, &speech, 100, true), 2.0);
+        assert_eq!(speech_to_source_time(1.0, &speech, 100, false), 5.0);
+        // past the end of the speech
+        assert_eq!(speech_to_source_time(2.5, &speech, 100, false), 6.5);
+    }
+}